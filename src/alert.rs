@@ -0,0 +1,239 @@
+use crate::host::Host;
+use crate::probe::Probe;
+use crate::silence::Silenced;
+use chrono::DateTime;
+use chrono::Utc;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Deserialize, Debug, Hash, Eq, PartialEq, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Firing,
+    Resolved,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Status::Firing => write!(f, "Firing"),
+            Status::Resolved => write!(f, "Resolved"),
+        }
+    }
+}
+
+/// The 64-bit FNV-1a hash Prometheus and Alertmanager fingerprint with.
+pub fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Computes the fingerprint of a label set the way Prometheus and
+/// Alertmanager do, an FNV-1a hash of the sorted label pairs.
+pub fn fingerprint(labels: &HashMap<String, String>) -> String {
+    let mut pairs: Vec<_> = labels.iter().collect();
+    pairs.sort();
+    let bytes = pairs.into_iter().flat_map(|(name, value)| {
+        name.bytes()
+            .chain([0xff])
+            .chain(value.bytes())
+            .chain([0xff])
+            .collect::<Vec<_>>()
+    });
+    let hash = fnv1a(bytes);
+    format!("{:016x}", hash)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Annotations {
+    pub summary: String,
+    pub description: Option<String>,
+}
+
+impl Annotations {
+    /// Picks the annotations the bridge renders out of all of an alert's,
+    /// as sent by sources other than Alertmanager notifications.
+    pub fn from_map(
+        mut annotations: HashMap<String, String>,
+    ) -> Option<Annotations> {
+        let summary = annotations.remove("summary")?;
+        Some(Annotations {
+            summary,
+            description: annotations.remove("description"),
+        })
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+#[allow(dead_code)]
+pub struct Alert {
+    pub status: Status,
+    pub labels: HashMap<String, String>,
+    pub annotations: Option<Annotations>,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    pub fingerprint: String,
+    /// Values of the queries that triggered the alert, sent by Grafana.
+    #[serde(default)]
+    pub values: Option<HashMap<String, f64>>,
+    #[serde(default)]
+    pub value_string: Option<String>,
+    /// Link to the issue opened for the alert, see `tickets`.
+    #[serde(skip)]
+    pub ticket: Option<String>,
+    /// Silenced or inhibited in Alertmanager, see `pull.suppressed`.
+    #[serde(skip)]
+    pub suppressed: bool,
+    /// The active silences matching it, see `silences`.
+    #[serde(skip)]
+    pub silences: Option<Silenced>,
+    /// The last result of its blackbox probe, see `probes`.
+    #[serde(skip)]
+    pub probe: Option<Probe>,
+    /// What was looked up about its address, see `hosts`.
+    #[serde(skip)]
+    pub host: Option<Host>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+#[allow(dead_code)]
+pub struct AlertGroup {
+    pub version: String,
+    #[serde(default)]
+    pub group_key: String,
+    #[serde(default)]
+    pub receiver: String,
+    #[serde(rename = "externalURL", default)]
+    pub external_url: String,
+    pub status: Status,
+    pub alerts: Vec<Alert>,
+    pub group_labels: HashMap<String, String>,
+    pub common_labels: HashMap<String, String>,
+    pub common_annotations: Option<Annotations>,
+    pub truncated_alerts: i32,
+}
+
+impl AlertGroup {
+    /// Builds groups out of alerts that did not come grouped, such as the
+    /// ones of the Alertmanager API, one per `alertname`.
+    pub fn by_alertname(
+        receiver: String,
+        external_url: String,
+        alerts: Vec<Alert>,
+    ) -> Vec<AlertGroup> {
+        let group = AlertGroup {
+            version: String::from("4"),
+            group_key: String::new(),
+            receiver,
+            external_url,
+            status: Status::Firing,
+            alerts,
+            group_labels: HashMap::new(),
+            common_labels: HashMap::new(),
+            common_annotations: None,
+            truncated_alerts: 0,
+        };
+        group.regroup(&[String::from("alertname")])
+    }
+
+    /// Identifies the payload in logs: its group key or, for senders not
+    /// setting one, the fingerprints of its alerts.
+    pub fn fingerprint(&self) -> String {
+        if !self.group_key.is_empty() {
+            return self.group_key.clone();
+        }
+        let fingerprints: Vec<_> =
+            self.alerts.iter().map(|a| a.fingerprint.as_str()).collect();
+        fingerprints.join(",")
+    }
+
+    /// A short hash of the group key, to tell groups apart in messages.
+    pub fn short_key(&self) -> String {
+        format!("{:016x}", fnv1a(self.group_key.bytes()))[..8].to_string()
+    }
+
+    /// Splits the group into one group per distinct value of `labels`,
+    /// recomputing the common labels and annotations of each bucket.
+    pub fn regroup(self, labels: &[String]) -> Vec<AlertGroup> {
+        if labels.is_empty() {
+            return vec![self];
+        }
+        let buckets = self.partition(|alert| {
+            labels
+                .iter()
+                .map(|l| alert.labels.get(l).cloned())
+                .collect::<Vec<_>>()
+        });
+        buckets
+            .into_iter()
+            .map(|(key, mut group)| {
+                for (label, value) in labels.iter().zip(key) {
+                    if let Some(value) = value {
+                        group.group_labels.insert(label.clone(), value);
+                    }
+                }
+                group
+            })
+            .collect()
+    }
+
+    /// Splits the group by the key of each alert, recomputing the common
+    /// labels and annotations of each part.
+    pub fn partition<K: PartialEq>(
+        mut self,
+        key: impl Fn(&Alert) -> K,
+    ) -> Vec<(K, AlertGroup)> {
+        let mut buckets: Vec<(K, Vec<Alert>)> = Vec::new();
+        for alert in std::mem::take(&mut self.alerts) {
+            let key = key(&alert);
+            match buckets.iter_mut().find(|(k, _)| k == &key) {
+                Some((_, alerts)) => alerts.push(alert),
+                None => buckets.push((key, vec![alert])),
+            }
+        }
+
+        buckets
+            .into_iter()
+            .map(|(key, alerts)| {
+                let mut common_labels = alerts[0].labels.clone();
+                common_labels.retain(|name, value| {
+                    alerts.iter().all(|a| a.labels.get(name) == Some(value))
+                });
+                let common_annotations =
+                    alerts[0].annotations.clone().filter(|first| {
+                        alerts
+                            .iter()
+                            .all(|a| a.annotations.as_ref() == Some(first))
+                    });
+                let status =
+                    if alerts.iter().any(|a| a.status == Status::Firing) {
+                        Status::Firing
+                    } else {
+                        Status::Resolved
+                    };
+
+                let group = AlertGroup {
+                    version: self.version.clone(),
+                    group_key: self.group_key.clone(),
+                    receiver: self.receiver.clone(),
+                    external_url: self.external_url.clone(),
+                    status,
+                    alerts,
+                    group_labels: self.group_labels.clone(),
+                    common_labels,
+                    common_annotations,
+                    truncated_alerts: self.truncated_alerts,
+                };
+                (key, group)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,171 @@
+use crate::log;
+use crate::App;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use chrono::Utc;
+use openssl::hash::hash;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use url::Url;
+
+/// Uploads the history of resolved alerts to a bucket of an S3-compatible
+/// object storage every `interval`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ArchiveConfig {
+    /// e.g. https://s3.eu-west-1.amazonaws.com or
+    /// https://storage.googleapis.com, buckets being addressed by path.
+    pub endpoint: String,
+    #[serde(default = "default_region")]
+    pub region: String,
+    pub bucket: String,
+    /// Prepended to the names of the objects.
+    #[serde(default)]
+    pub prefix: String,
+    pub access_key: String,
+    pub secret_key: String,
+    #[serde(with = "humantime_serde", default = "default_interval")]
+    pub interval: Duration,
+}
+
+fn default_region() -> String {
+    String::from("us-east-1")
+}
+
+fn default_interval() -> Duration {
+    Duration::from_secs(3600)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn sha256(data: &[u8]) -> Result<String> {
+    Ok(hex(&hash(MessageDigest::sha256(), data)?))
+}
+
+fn hmac(key: &[u8], data: &str) -> Result<Vec<u8>> {
+    let key = PKey::hmac(key)?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    signer.update(data.as_bytes())?;
+    Ok(signer.sign_to_vec()?)
+}
+
+/// Percent-encodes a path the way signature version 4 expects it.
+fn encode_path(path: &str) -> String {
+    let mut encoded = String::new();
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' => {
+                encoded.push(byte as char)
+            }
+            b'-' | b'.' | b'_' | b'~' | b'/' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+impl ArchiveConfig {
+    /// Puts the object, signed with AWS signature version 4.
+    fn put(&self, app: &App, name: &str, body: Vec<u8>) -> Result<()> {
+        let endpoint = Url::parse(self.endpoint.trim_end_matches('/'))
+            .with_context(|| format!("invalid endpoint {}", self.endpoint))?;
+        let Some(host) = endpoint.host_str() else {
+            bail!("endpoint {} has no host", self.endpoint);
+        };
+        let host = match endpoint.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        };
+        let path = encode_path(&format!(
+            "{}/{}/{}{}",
+            endpoint.path().trim_end_matches('/'),
+            self.bucket,
+            self.prefix,
+            name
+        ));
+        let now = Utc::now();
+        let time = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let payload = sha256(&body)?;
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical = format!(
+            "PUT\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            path, host, payload, time, signed_headers, payload
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            time,
+            scope,
+            sha256(canonical.as_bytes())?
+        );
+        let mut key = format!("AWS4{}", self.secret_key).into_bytes();
+        for part in [date.as_str(), &self.region, "s3", "aws4_request"] {
+            key = hmac(&key, part)?;
+        }
+        let signature = hex(&hmac(&key, &to_sign)?);
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, \
+             Signature={}",
+            self.access_key, scope, signed_headers, signature
+        );
+
+        let url = format!("{}://{}{}", endpoint.scheme(), host, path);
+        app.client
+            .put(&url)
+            .header("Authorization", authorization)
+            .header("x-amz-content-sha256", payload)
+            .header("x-amz-date", time)
+            .header("Content-Type", "application/x-ndjson")
+            .body(body)
+            .send()
+            .and_then(|r| r.error_for_status())
+            .with_context(|| format!("cannot upload {}", url))?;
+        Ok(())
+    }
+}
+
+/// Uploads the alerts resolved since the last upload as JSON lines.
+fn archive(app: &App, config: &ArchiveConfig) -> Result<()> {
+    let (history, until) = app.store.unarchived();
+    if history.is_empty() {
+        return Ok(());
+    }
+    let mut body = Vec::new();
+    for alert in &history {
+        let mut alert = alert.clone();
+        app.redactor.redact_resolved(&mut alert);
+        serde_json::to_writer(&mut body, &alert)?;
+        body.push(b'\n');
+    }
+    let name = format!("{}.jsonl", Utc::now().format("%Y/%m/%d/%H%M%S"));
+    config.put(app, &name, body)?;
+    app.store.archived(until);
+    log::info(format!(
+        "archived {} resolved alerts as {}",
+        history.len(),
+        name
+    ));
+    Ok(())
+}
+
+pub fn start(app: Arc<App>) {
+    let Some(config) = app.config.archive.clone() else {
+        return;
+    };
+    thread::spawn(move || loop {
+        thread::sleep(config.interval);
+        // Kept for the next attempt meanwhile.
+        if let Err(e) = archive(&app, &config) {
+            log::error(format!("cannot archive alert history: {:#}", e));
+        }
+    });
+}
@@ -0,0 +1,139 @@
+use crate::log;
+use crate::metrics;
+use crate::metrics::Metrics;
+use crate::sync::Recover;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How long the chunks sent by a partially delivered notification are
+/// remembered, covering Alertmanager's retries of it.
+const TTL: Duration = Duration::from_secs(3600);
+
+/// The chunks sent of notifications that were only partially delivered,
+/// by notification.
+#[derive(Default)]
+pub struct Partials(Mutex<HashMap<String, (Instant, HashSet<u64>)>>);
+
+impl Partials {
+    /// The chunks already sent if the notification was partially
+    /// delivered, forgetting it: the new batch takes over.
+    pub fn take(&self, key: &str) -> HashSet<u64> {
+        let mut partials = self.0.lock().recover();
+        partials.retain(|_, (at, _)| at.elapsed() < TTL);
+        partials
+            .remove(key)
+            .map(|(_, sent)| sent)
+            .unwrap_or_default()
+    }
+
+    fn insert(&self, key: String, sent: HashSet<u64>) {
+        self.0.lock().recover().insert(key, (Instant::now(), sent));
+    }
+}
+
+/// The messages a notification was split into for one route, each chunk
+/// identified by its `dedup::key` rather than by its text, which changes
+/// with the time shown. Each is retried on its own, and once the last one
+/// is settled a batch that was only partially sent is reported and
+/// remembered, so that a retry of the notification sends only the chunks
+/// that failed.
+pub struct Batch {
+    key: String,
+    label: String,
+    chunks: HashSet<u64>,
+    sent: Mutex<HashSet<u64>>,
+    partials: Arc<Partials>,
+    metrics: Arc<Metrics>,
+}
+
+impl Batch {
+    /// `already_sent` are the chunks sent by the previous, partial, batch
+    /// of the notification.
+    pub fn new(
+        key: String,
+        label: String,
+        already_sent: HashSet<u64>,
+        partials: Arc<Partials>,
+        metrics: Arc<Metrics>,
+    ) -> Batch {
+        Batch {
+            key,
+            label,
+            chunks: HashSet::new(),
+            sent: Mutex::new(already_sent),
+            partials,
+            metrics,
+        }
+    }
+
+    pub fn add(&mut self, chunk: u64) {
+        self.chunks.insert(chunk);
+    }
+
+    /// Marks a chunk as sent, or handed to the rate limiter's rollup.
+    pub fn sent(&self, chunk: u64) {
+        self.sent.lock().recover().insert(chunk);
+    }
+}
+
+impl Drop for Batch {
+    /// Runs once no job of the batch is left.
+    fn drop(&mut self) {
+        let sent = std::mem::take(self.sent.get_mut().unwrap());
+        let sent_count = self.chunks.intersection(&sent).count();
+        if sent_count == self.chunks.len() || sent_count == 0 {
+            return;
+        }
+        metrics::inc(&self.metrics.partial_deliveries);
+        log::warning(format!(
+            "partial delivery of {}: {} of {} messages sent, \
+             a retry sends the others",
+            self.label,
+            sent_count,
+            self.chunks.len()
+        ));
+        self.partials.insert(std::mem::take(&mut self.key), sent);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remembers_the_chunks_of_partial_deliveries() {
+        let partials = Arc::new(Partials::default());
+        let metrics = Arc::new(Metrics::default());
+        let batch = |already_sent| {
+            let mut batch = Batch::new(
+                String::from("key"),
+                String::from("group"),
+                already_sent,
+                partials.clone(),
+                metrics.clone(),
+            );
+            (1..=3).for_each(|chunk| batch.add(chunk));
+            batch
+        };
+        batch(HashSet::new()).sent(1);
+        assert_eq!(metrics::get(&metrics.partial_deliveries), 1);
+        let sent = partials.take("key");
+        assert_eq!(sent, HashSet::from([1]));
+        assert!(partials.take("key").is_empty());
+
+        // The retry sends the other chunks, completing the notification.
+        let retry = batch(sent);
+        retry.sent(2);
+        retry.sent(3);
+        drop(retry);
+        assert!(partials.take("key").is_empty());
+        // Nothing sent at all is retried whole.
+        drop(batch(HashSet::new()));
+        assert!(partials.take("key").is_empty());
+        assert_eq!(metrics::get(&metrics.partial_deliveries), 1);
+    }
+}
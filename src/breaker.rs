@@ -0,0 +1,203 @@
+use crate::log;
+use crate::sync::Recover;
+use serde::Deserialize;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+/// What the bridge does with new alerts while the circuit is open.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WhenOpen {
+    /// Answer 503 so that Alertmanager retries later.
+    Reject,
+    /// Keep accepting and queueing notifications.
+    Queue,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct BreakerConfig {
+    /// Consecutive delivery failures opening the circuit.
+    pub failures: u32,
+    #[serde(with = "humantime_serde")]
+    pub cooldown: Duration,
+    pub when_open: WhenOpen,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        BreakerConfig {
+            failures: 5,
+            cooldown: Duration::from_secs(30),
+            when_open: WhenOpen::Queue,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Closed,
+    Open(Instant),
+    /// The cooldown elapsed and a single trial delivery is in flight.
+    HalfOpen,
+}
+
+impl State {
+    /// Value of the `bridge_circuit_state` metric.
+    pub fn gauge(&self) -> u8 {
+        match self {
+            State::Closed => 0,
+            State::Open(_) => 1,
+            State::HalfOpen => 2,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            State::Closed => "closed",
+            State::Open(_) => "open",
+            State::HalfOpen => "half-open",
+        }
+    }
+}
+
+struct Inner {
+    state: State,
+    failures: u32,
+}
+
+pub struct Breaker {
+    config: BreakerConfig,
+    inner: Mutex<Inner>,
+    changed: Condvar,
+}
+
+impl Breaker {
+    pub fn new(config: BreakerConfig) -> Breaker {
+        Breaker {
+            config,
+            inner: Mutex::new(Inner {
+                state: State::Closed,
+                failures: 0,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.inner.lock().recover().state
+    }
+
+    /// Whether the ingest endpoint should turn alerts away. Once the
+    /// cooldown elapsed they are let in again to provide the trial delivery.
+    pub fn rejects(&self) -> bool {
+        if self.config.when_open != WhenOpen::Reject {
+            return false;
+        }
+        match self.state() {
+            State::Closed => false,
+            State::Open(since) => since.elapsed() < self.config.cooldown,
+            State::HalfOpen => true,
+        }
+    }
+
+    /// Blocks until a delivery may be attempted: right away while closed,
+    /// as the single trial once the cooldown of an open circuit elapsed.
+    pub fn wait(&self) {
+        let mut inner = self.inner.lock().recover();
+        loop {
+            match inner.state {
+                State::Closed => return,
+                State::Open(since) => {
+                    let elapsed = since.elapsed();
+                    if elapsed >= self.config.cooldown {
+                        inner.state = State::HalfOpen;
+                        return;
+                    }
+                    inner = self
+                        .changed
+                        .wait_timeout(inner, self.config.cooldown - elapsed)
+                        .recover()
+                        .0;
+                }
+                State::HalfOpen => {
+                    inner = self.changed.wait(inner).recover();
+                }
+            }
+        }
+    }
+
+    pub fn success(&self) {
+        let mut inner = self.inner.lock().recover();
+        inner.failures = 0;
+        if inner.state != State::Closed {
+            inner.state = State::Closed;
+            log::info("discord circuit closed");
+            self.changed.notify_all();
+        }
+    }
+
+    pub fn failure(&self) {
+        let mut inner = self.inner.lock().recover();
+        inner.failures += 1;
+        let trip = match inner.state {
+            State::Closed => inner.failures >= self.config.failures,
+            State::HalfOpen => true,
+            State::Open(_) => false,
+        };
+        if trip {
+            inner.state = State::Open(Instant::now());
+            log::warning(format!(
+                "discord circuit open for {}",
+                humantime::format_duration(self.config.cooldown)
+            ));
+            self.changed.notify_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with(when_open: WhenOpen) -> Breaker {
+        Breaker::new(BreakerConfig {
+            failures: 2,
+            cooldown: Duration::from_millis(50),
+            when_open,
+        })
+    }
+
+    #[test]
+    fn opens_after_consecutive_failures() {
+        let breaker = with(WhenOpen::Reject);
+        breaker.failure();
+        breaker.success();
+        breaker.failure();
+        assert_eq!(breaker.state(), State::Closed);
+        assert!(!breaker.rejects());
+        breaker.failure();
+        assert_eq!(breaker.state().name(), "open");
+        assert!(breaker.rejects());
+        assert!(!with(WhenOpen::Queue).rejects());
+    }
+
+    #[test]
+    fn tries_once_after_the_cooldown() {
+        let breaker = with(WhenOpen::Reject);
+        breaker.failure();
+        breaker.failure();
+        let started = Instant::now();
+        breaker.wait();
+        assert!(started.elapsed() >= Duration::from_millis(40));
+        assert_eq!(breaker.state(), State::HalfOpen);
+        // The trial failing opens the circuit again right away.
+        breaker.failure();
+        assert_eq!(breaker.state().gauge(), 1);
+        breaker.wait();
+        breaker.success();
+        assert_eq!(breaker.state(), State::Closed);
+    }
+}
@@ -0,0 +1,165 @@
+use crate::discord::DeliveryError;
+use crate::log;
+use anyhow::bail;
+use anyhow::Result;
+use serde::Deserialize;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+
+/// Failures injected into the deliveries to Discord, to see retries, the
+/// queue, the circuit breaker and the meta channel at work. Rates are
+/// percentages of the attempts.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChaosConfig {
+    /// Attempts failing as if Discord answered 500.
+    pub failures: f64,
+    /// Attempts answered 429, to be retried after `retry_after`.
+    pub rate_limits: f64,
+    #[serde(with = "humantime_serde")]
+    pub retry_after: Duration,
+    /// Attempts delayed by `latency` before being made.
+    pub slow: f64,
+    #[serde(with = "humantime_serde")]
+    pub latency: Duration,
+}
+
+impl Default for ChaosConfig {
+    fn default() -> Self {
+        ChaosConfig {
+            failures: 0.0,
+            rate_limits: 0.0,
+            retry_after: Duration::from_secs(1),
+            slow: 0.0,
+            latency: Duration::from_secs(1),
+        }
+    }
+}
+
+impl ChaosConfig {
+    pub fn validate(&self) -> Result<()> {
+        for rate in [self.failures, self.rate_limits, self.slow] {
+            if !(0.0..=100.0).contains(&rate) {
+                bail!("chaos rates are percentages, not {}", rate);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct Chaos {
+    config: Option<ChaosConfig>,
+    random: RandomState,
+    next: AtomicU64,
+}
+
+impl Chaos {
+    pub fn new(config: Option<ChaosConfig>) -> Chaos {
+        if config.is_some() {
+            log::warning("chaos: injecting failures into deliveries");
+        }
+        Chaos {
+            config,
+            ..Chaos::default()
+        }
+    }
+
+    /// Whether an event happening `rate` percent of the time happens.
+    fn roll(&self, rate: f64) -> bool {
+        let n = self
+            .random
+            .hash_one(self.next.fetch_add(1, Ordering::Relaxed));
+        ((n % 10000) as f64) < rate * 100.0
+    }
+
+    /// Delays the attempt or fails it, as configured.
+    pub fn inject(&self) -> Result<(), DeliveryError> {
+        let Some(config) = &self.config else {
+            return Ok(());
+        };
+        if self.roll(config.slow) {
+            thread::sleep(config.latency);
+        }
+        if self.roll(config.rate_limits) {
+            return Err(DeliveryError::Transient {
+                error: String::from(
+                    "chaos: discord answered 429 Too Many Requests",
+                ),
+                retry_after: Some(config.retry_after),
+            });
+        }
+        if self.roll(config.failures) {
+            return Err(DeliveryError::Transient {
+                error: String::from(
+                    "chaos: discord answered 500 Internal Server Error",
+                ),
+                retry_after: None,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_rates_out_of_percentages() {
+        for rate in [-1.0, 100.5, f64::NAN] {
+            let config = ChaosConfig {
+                slow: rate,
+                ..ChaosConfig::default()
+            };
+            assert!(config.validate().is_err(), "{}", rate);
+        }
+        let config = ChaosConfig {
+            failures: 100.0,
+            ..ChaosConfig::default()
+        };
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn rolls_at_the_rate() {
+        let chaos = Chaos::default();
+        let count = |rate| (0..10000).filter(|_| chaos.roll(rate)).count();
+        assert_eq!(count(0.0), 0);
+        assert_eq!(count(100.0), 10000);
+        assert!((4500..5500).contains(&count(50.0)));
+    }
+
+    #[test]
+    fn fails_deliveries_as_configured() {
+        let chaos = |config| Chaos::new(Some(config)).inject();
+        let e = chaos(ChaosConfig {
+            rate_limits: 100.0,
+            ..ChaosConfig::default()
+        });
+        assert!(matches!(
+            e,
+            Err(DeliveryError::Transient {
+                retry_after: Some(_),
+                ..
+            })
+        ));
+        let e = chaos(ChaosConfig {
+            failures: 100.0,
+            ..ChaosConfig::default()
+        });
+        assert!(matches!(
+            e,
+            Err(DeliveryError::Transient {
+                retry_after: None,
+                ..
+            })
+        ));
+        assert!(chaos(ChaosConfig::default()).is_ok());
+        assert!(Chaos::new(None).inject().is_ok());
+    }
+}
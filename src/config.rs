@@ -0,0 +1,1258 @@
+use crate::admin::AdminConfig;
+use crate::admin::AuthConfig;
+use crate::alert::AlertGroup;
+use crate::alert::Status;
+#[cfg(feature = "archive")]
+use crate::archive::ArchiveConfig;
+use crate::breaker::BreakerConfig;
+use crate::chaos::ChaosConfig;
+use crate::console::ConsoleLink;
+use crate::debug::DebugConfig;
+use crate::dedup::DedupConfig;
+use crate::deliveries::DeliveriesConfig;
+use crate::discord;
+use crate::discord::AllowedMentions;
+use crate::discord::EmbedStyle;
+use crate::escalate::Escalation;
+use crate::flap::FlapConfig;
+use crate::heartbeat::HeartbeatConfig;
+use crate::history::HistoryConfig;
+use crate::host::HostConfig;
+use crate::i18n;
+use crate::idempotency::IdempotencyConfig;
+use crate::incident::IncidentThreads;
+use crate::inhibit::InhibitConfig;
+use crate::input::Parsing;
+#[cfg(feature = "interactions")]
+use crate::interaction::InteractionConfig;
+use crate::log::LogConfig;
+use crate::maintenance::MaintenanceConfig;
+use crate::matcher::Matchers;
+use crate::meta::MetaConfig;
+use crate::oncall::ScheduleConfig;
+use crate::outbound::OutboundConfig;
+use crate::preset::KubernetesConfig;
+use crate::preset::Preset;
+use crate::probe::ProbeConfig;
+use crate::pull::PullConfig;
+use crate::pull::ReconcileConfig;
+use crate::push::PushConfig;
+use crate::queue::QueueConfig;
+use crate::redact::RedactConfig;
+use crate::remind::ReminderConfig;
+use crate::severity::Severity;
+use crate::severity::SeverityRule;
+use crate::silence::SilenceConfig;
+use crate::sink;
+#[cfg(feature = "twilio")]
+use crate::sink::twilio::SmsConfig;
+use crate::sink::SinkConfig;
+use crate::theme::Theme;
+use crate::ticket::TicketConfig;
+#[cfg(feature = "tls")]
+use crate::tls::TlsConfig;
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use chrono_tz::Tz;
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// TCP address to listen on, empty to only use `unix_socket`.
+    pub listen: String,
+    pub unix_socket: Option<String>,
+    pub unix_socket_mode: u32,
+    /// TCP address serving the metrics, status and admin endpoints, which
+    /// the other listeners then stop serving.
+    pub admin_listen: Option<String>,
+    #[cfg(feature = "tls")]
+    pub tls: Option<TlsConfig>,
+    /// How long a notification may take to be answered, lowered by its
+    /// `X-Request-Timeout` header.
+    #[serde(with = "humantime_serde")]
+    pub request_timeout: Option<Duration>,
+    /// How long queued notifications may take to be delivered on shutdown.
+    #[serde(with = "humantime_serde")]
+    pub shutdown_grace: Duration,
+    pub webhook_url: Option<String>,
+    pub locale: String,
+    pub timezone: Tz,
+    pub regroup_by: Vec<String>,
+    pub allowed_mentions: Option<AllowedMentions>,
+    pub rate_limit: Option<u32>,
+    /// Sends up to 10 embeds per message rather than one message each.
+    pub pack_embeds: bool,
+    pub embed: EmbedStyle,
+    /// Embed colors and title emoji, a preset name or a palette.
+    pub theme: Theme,
+    pub layout: Layout,
+    /// Alerts listed per embed, the rest being counted in a last field or,
+    /// with `follow_up_messages`, sent in further messages.
+    pub max_alerts_per_message: Option<usize>,
+    pub follow_up_messages: bool,
+    pub statuses: Statuses,
+    pub messages: Messages,
+    /// Named alternatives to some of the message templates, picked by
+    /// routes or by the `template` query parameter of a notification.
+    pub template_sets: HashMap<String, TemplateSet>,
+    pub severity: Severity,
+    /// Rewrites of the severity, before routing and rendering.
+    pub severity_rules: Vec<SeverityRule>,
+    pub escalation: Vec<Escalation>,
+    /// Exporters whose labels are shown as `details` in `field_value`.
+    pub presets: Vec<Preset>,
+    pub kubernetes: Option<KubernetesConfig>,
+    /// Links to the cloud consoles, added to `details`.
+    pub console_links: Vec<ConsoleLink>,
+    pub flapping: Option<FlapConfig>,
+    pub dedup: Option<DedupConfig>,
+    pub idempotency: Option<IdempotencyConfig>,
+    /// Answer notifications with a delivery ID, see `/api/deliveries`.
+    pub deliveries: Option<DeliveriesConfig>,
+    pub reminders: Option<ReminderConfig>,
+    pub incident_threads: Option<IncidentThreads>,
+    /// On-call schedules by name, see `Route::oncall`.
+    pub schedules: HashMap<String, ScheduleConfig>,
+    pub oncall: Option<String>,
+    /// Calendar of the maintenance windows, see `Route::maintenance`.
+    pub maintenance: Option<MaintenanceConfig>,
+    pub tts: Option<String>,
+    pub tickets: Option<TicketConfig>,
+    /// Alertmanager whose silences are shown on the alerts they match.
+    pub silences: Option<SilenceConfig>,
+    /// Discord application whose buttons and slash commands are answered.
+    #[cfg(feature = "interactions")]
+    pub interactions: Option<InteractionConfig>,
+    pub probes: Option<ProbeConfig>,
+    /// Lookups of the addresses alerts have as instance.
+    pub hosts: Option<HostConfig>,
+    /// Pushgateway the self-metrics are pushed to.
+    pub push: Option<PushConfig>,
+    /// Failures injected into the deliveries, for testing.
+    pub chaos: Option<ChaosConfig>,
+    #[cfg(feature = "twilio")]
+    pub sms: Option<SmsConfig>,
+    #[cfg(feature = "archive")]
+    pub archive: Option<ArchiveConfig>,
+    pub history: Option<HistoryConfig>,
+    pub inhibition: InhibitConfig,
+    pub redaction: RedactConfig,
+    pub debug: DebugConfig,
+    /// Directory of templates replacing the configured ones, reloaded
+    /// when they change.
+    pub templates_dir: Option<String>,
+    pub admin: AdminConfig,
+    pub auth: AuthConfig,
+    pub meta: Option<MetaConfig>,
+    /// Where firing alerts are remembered across restarts.
+    pub state_file: Option<String>,
+    pub pull: Option<PullConfig>,
+    pub heartbeats: Vec<HeartbeatConfig>,
+    pub reconcile: Option<ReconcileConfig>,
+    pub outbound: OutboundConfig,
+    pub log: LogConfig,
+    pub queue: QueueConfig,
+    pub circuit_breaker: BreakerConfig,
+    pub catalog: HashMap<String, i18n::Locale>,
+    pub discord: bool,
+    #[serde(deserialize_with = "sink::deserialize_list")]
+    pub sinks: Vec<SinkConfig>,
+    pub routes: Vec<Route>,
+    pub routing: Routing,
+    pub unmatched: Unmatched,
+    /// Strict parsing rejects payloads with unknown or missing fields.
+    pub parsing: Parsing,
+    /// Largest request body accepted, in bytes once decompressed.
+    pub max_body_size: usize,
+    #[serde(skip)]
+    pub default_route: Route,
+    /// The file as loaded, shown by `/api/config`.
+    #[serde(skip)]
+    pub source: serde_yaml::Value,
+}
+
+/// Whether routes match the common labels of a group, or the labels of
+/// each alert, the group being split between the routes they match.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Routing {
+    Group,
+    Alert,
+}
+
+/// What happens to groups matching no route.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Unmatched {
+    /// Send them with the top-level settings.
+    Default,
+    Drop,
+    /// Answer the notification with an error.
+    Reject,
+}
+
+/// The error of a group matching no route with `unmatched: reject`.
+#[derive(Debug)]
+pub struct Unroutable(String);
+
+impl fmt::Display for Unroutable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Unroutable {}
+
+/// A destination for alert groups whose common labels match all of its
+/// matchers and, when set, that were sent to the given Alertmanager
+/// receiver. Unset options are inherited from the top-level config.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Route {
+    pub name: String,
+    pub receiver: Option<String>,
+    pub matchers: Matchers,
+    pub webhook_url: Option<String>,
+    pub locale: Option<String>,
+    pub timezone: Option<Tz>,
+    /// Labels to split groups by before rendering, one message per
+    /// distinct set of values.
+    pub regroup_by: Option<Vec<String>>,
+    /// Template for the message content, replacing `messages.content`.
+    pub content: Option<String>,
+    /// Templates of text put before and after the content.
+    pub content_prefix: Option<String>,
+    pub content_suffix: Option<String>,
+    pub allowed_mentions: Option<AllowedMentions>,
+    /// Messages per minute sent to the webhook, the excess being rolled up.
+    pub rate_limit: Option<u32>,
+    pub embed: Option<EmbedStyle>,
+    /// Name of the entry of `template_sets` the route renders with.
+    pub template: Option<String>,
+    /// Whether messages go to the Discord webhook, sinks receiving the
+    /// alerts in addition or instead.
+    pub discord: Option<bool>,
+    #[serde(deserialize_with = "sink::deserialize_optional_list")]
+    pub sinks: Option<Vec<SinkConfig>>,
+    pub theme: Option<Theme>,
+    pub layout: Option<Layout>,
+    pub incident_threads: Option<IncidentThreads>,
+    /// Name of the entry of `schedules` whose on-call is mentioned.
+    pub oncall: Option<String>,
+    pub maintenance: Option<MaintenanceConfig>,
+    /// Least severe level whose firing alerts are read aloud.
+    pub tts: Option<String>,
+    pub console_links: Option<Vec<ConsoleLink>>,
+    /// IDs of the Discord roles whose members may act on the alerts of the
+    /// route, replacing `interactions.allowed_roles`.
+    #[cfg(feature = "interactions")]
+    pub allowed_roles: Option<Vec<String>>,
+}
+
+impl Route {
+    pub fn matches(&self, group: &AlertGroup) -> bool {
+        self.matches_labels(&group.receiver, &group.common_labels)
+    }
+
+    pub fn matches_labels(
+        &self,
+        receiver: &str,
+        labels: &HashMap<String, String>,
+    ) -> bool {
+        if let Some(expected) = &self.receiver {
+            if expected != receiver {
+                return false;
+            }
+        }
+        self.matchers.matches(labels)
+    }
+
+    /// The configured webhook, falling back to `DISCORD_WEBHOOK_URL`.
+    pub fn webhook_url(&self) -> Result<String> {
+        let url = match &self.webhook_url {
+            Some(url) => url.clone(),
+            None => std::env::var("DISCORD_WEBHOOK_URL")
+                .context("DISCORD_WEBHOOK_URL is not set")?,
+        };
+        Ok(discord::expand_url(&url))
+    }
+
+    pub fn locale(&self) -> &str {
+        self.locale.as_deref().unwrap_or(i18n::DEFAULT_LOCALE)
+    }
+
+    pub fn timezone(&self) -> Tz {
+        self.timezone.unwrap_or(Tz::UTC)
+    }
+
+    pub fn regroup_by(&self) -> &[String] {
+        self.regroup_by.as_deref().unwrap_or_default()
+    }
+
+    pub fn console_links(&self) -> &[ConsoleLink] {
+        self.console_links.as_deref().unwrap_or_default()
+    }
+
+    pub fn embed(&self) -> EmbedStyle {
+        self.embed.clone().unwrap_or_default()
+    }
+
+    pub fn theme(&self) -> Theme {
+        self.theme.clone().unwrap_or_default()
+    }
+
+    pub fn discord(&self) -> bool {
+        self.discord.unwrap_or(true)
+    }
+
+    pub fn sinks(&self) -> &[SinkConfig] {
+        self.sinks.as_deref().unwrap_or_default()
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout.unwrap_or(Layout::Fields)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listen: String::from("[::]:9094"),
+            unix_socket: None,
+            unix_socket_mode: 0o660,
+            admin_listen: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+            request_timeout: None,
+            shutdown_grace: Duration::from_secs(30),
+            webhook_url: None,
+            locale: String::from(i18n::DEFAULT_LOCALE),
+            timezone: Tz::UTC,
+            regroup_by: Vec::new(),
+            allowed_mentions: None,
+            rate_limit: None,
+            pack_embeds: true,
+            embed: EmbedStyle::default(),
+            theme: Theme::default(),
+            layout: Layout::Fields,
+            max_alerts_per_message: None,
+            follow_up_messages: false,
+            statuses: Statuses::Combined,
+            messages: Messages::default(),
+            template_sets: HashMap::new(),
+            severity: Severity::default(),
+            severity_rules: Vec::new(),
+            escalation: Vec::new(),
+            presets: Vec::new(),
+            kubernetes: None,
+            console_links: Vec::new(),
+            flapping: None,
+            dedup: None,
+            idempotency: None,
+            deliveries: None,
+            reminders: None,
+            incident_threads: None,
+            schedules: HashMap::new(),
+            oncall: None,
+            maintenance: None,
+            tts: None,
+            tickets: None,
+            silences: None,
+            #[cfg(feature = "interactions")]
+            interactions: None,
+            probes: None,
+            hosts: None,
+            push: None,
+            chaos: None,
+            #[cfg(feature = "twilio")]
+            sms: None,
+            #[cfg(feature = "archive")]
+            archive: None,
+            history: None,
+            inhibition: InhibitConfig::default(),
+            redaction: RedactConfig::default(),
+            debug: DebugConfig::default(),
+            templates_dir: None,
+            admin: AdminConfig::default(),
+            auth: AuthConfig::default(),
+            meta: None,
+            state_file: None,
+            pull: None,
+            heartbeats: Vec::new(),
+            reconcile: None,
+            outbound: OutboundConfig::default(),
+            log: LogConfig::default(),
+            queue: QueueConfig::default(),
+            circuit_breaker: BreakerConfig::default(),
+            catalog: HashMap::new(),
+            discord: true,
+            sinks: Vec::new(),
+            routes: Vec::new(),
+            routing: Routing::Group,
+            unmatched: Unmatched::Default,
+            parsing: Parsing::Lenient,
+            max_body_size: 16 << 20,
+            default_route: Route::default(),
+            source: serde_yaml::Value::Mapping(Default::default()),
+        }
+    }
+}
+
+/// Templates and fallback text used when rendering a notification.
+///
+/// The templates are rendered with minijinja; fallbacks are plain text
+/// substituted for labels or annotations missing from the payload.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Messages {
+    pub title: String,
+    pub content: String,
+    pub field_name: String,
+    pub field_value: String,
+    pub unnamed: String,
+    pub unknown_alertname: String,
+    pub unknown_instance: String,
+    pub no_summary: String,
+    pub no_description: String,
+    /// Template of the summary of alerts without annotations, which sees
+    /// their `labels` and `extra_labels`. `no_description` is used when
+    /// it renders empty.
+    pub no_annotations: String,
+    pub no_job: String,
+    pub group_labels: GroupLabels,
+    pub group_labels_name: String,
+    pub alertmanager_link: AlertmanagerLink,
+    pub alertmanager_link_text: String,
+    /// Heads messages holding both firing and resolved alerts.
+    pub transition: String,
+    /// Line heading the description of embeds of at least
+    /// `group_summary_min` alerts, 0 disabling it.
+    pub group_summary: String,
+    pub group_summary_min: usize,
+    /// Name of the field counting the alerts beyond
+    /// `max_alerts_per_message`.
+    pub more_alerts: String,
+    pub flapping_title: String,
+    pub flapping_field: String,
+    pub reminder_title: String,
+    pub reminder_field: String,
+    /// Name of the incident threads.
+    pub thread_name: String,
+    /// Heads messages sent during a maintenance window.
+    pub maintenance: String,
+    pub ticket_title: String,
+    pub ticket_body: String,
+    /// Notes the silences matching a firing alert, in `field_value`.
+    pub silence: String,
+    /// Appends the short group key hash and the alert fingerprints to the
+    /// embed footer, to find the alerts in the Alertmanager API and logs.
+    pub trace_footer: bool,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct TemplateSet {
+    pub title: Option<String>,
+    pub content: Option<String>,
+    pub field_name: Option<String>,
+    pub field_value: Option<String>,
+}
+
+/// Where the link to the group in the Alertmanager UI is shown, if at all.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertmanagerLink {
+    None,
+    Title,
+    Field,
+}
+
+/// How the alerts of an embed are listed: a field each or a table in the
+/// description.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    Fields,
+    Table,
+}
+
+/// Whether the firing and resolved alerts of a notification share its
+/// messages or are sent separately, and which come first.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Statuses {
+    Combined,
+    SeparateFiringFirst,
+    SeparateResolvedFirst,
+}
+
+impl Statuses {
+    /// The parts of the group rendered and packed on their own, in order.
+    pub fn split(self, group: AlertGroup) -> Vec<AlertGroup> {
+        let first = match self {
+            Statuses::Combined => return vec![group],
+            Statuses::SeparateFiringFirst => Status::Firing,
+            Statuses::SeparateResolvedFirst => Status::Resolved,
+        };
+        let mut parts = group.partition(|alert| alert.status != first);
+        parts.sort_by_key(|(later, _)| *later);
+        parts
+            .into_iter()
+            .map(|(_, mut part)| {
+                part.status = part.alerts[0].status;
+                part
+            })
+            .collect()
+    }
+}
+
+/// Where the group labels of a notification are shown, if at all.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupLabels {
+    None,
+    Author,
+    Field,
+}
+
+impl Default for Messages {
+    fn default() -> Self {
+        Messages {
+            title: String::from("[{{ status }}:{{ count }}] {{ alertname }}"),
+            content: String::from(
+                "{{ mention }}{% if mention and summary %} {% endif %}\
+                {{ summary }}",
+            ),
+            field_name: String::from(
+                "[{{ status }}]: {{ alertname }} on {{ instance }}",
+            ),
+            field_value: String::from(
+                "{{ severity }} {{ job }} {{ summary }}\
+                {% if details %} · {{ details }}{% endif %}\
+                {% if values %} ({{ values }}){% endif %}\
+                {% if ticket %} [ticket]({{ ticket }}){% endif %}\
+                {% if silence %} {{ silence }}{% endif %}",
+            ),
+            unnamed: String::from("unnamed"),
+            unknown_alertname: String::from("unknown"),
+            unknown_instance: String::from("unknown"),
+            no_summary: String::from("no summary"),
+            no_description: String::from("-"),
+            no_annotations: String::new(),
+            no_job: String::from("-"),
+            group_labels: GroupLabels::None,
+            group_labels_name: String::from("Group"),
+            alertmanager_link: AlertmanagerLink::Title,
+            alertmanager_link_text: String::from("View in Alertmanager"),
+            transition: String::from(
+                "{{ resolved }} resolved, {{ firing }} still firing",
+            ),
+            group_summary: String::from(
+                "{{ count }} alerts: {{ breakdown }}\
+                 {% if namespaces > 1 %} across {{ namespaces }} namespaces\
+                 {% endif %}{% if jobs > 1 %} ({{ jobs }} jobs){% endif %}",
+            ),
+            group_summary_min: 5,
+            more_alerts: String::from("…and {{ count }} more"),
+            flapping_title: String::from("ALERT FLAPPING: {{ alertname }}"),
+            flapping_field: String::from(
+                "{{ status }} now, changed status {{ flips }} times \
+                in {{ window }}; further notifications are suppressed \
+                until it stabilizes",
+            ),
+            reminder_title: String::from("STILL FIRING: {{ alertname }}"),
+            reminder_field: String::from(
+                "firing for {{ age }}{% if summary %}: {{ summary }}{% endif %}",
+            ),
+            thread_name: String::from("{{ alertname }}"),
+            maintenance: String::from(
+                "\u{1F6E0} maintenance window{% if event %}: {{ event }}{% endif %}",
+            ),
+            ticket_title: String::from("{{ alertname }} on {{ instance }}"),
+            ticket_body: String::from(
+                "{{ summary }}\n\n\
+                 {% if description %}{{ description }}\n\n{% endif %}\
+                 Firing since {{ starts_at }}.\n\n\
+                 {% for name, value in labels | items %}\
+                 - {{ name }}: {{ value }}\n{% endfor %}",
+            ),
+            silence: String::from(
+                "\u{1F515} {% if count == 1 %}1 matching silence expires\
+                 {% else %}{{ count }} matching silences, the last expires\
+                 {% endif %} in {{ expires_in }}",
+            ),
+            trace_footer: false,
+        }
+    }
+}
+
+impl Config {
+    /// Loads the file named by `CONFIG_FILE`, or the defaults if it is unset,
+    /// adding the destinations given as shorthand URLs: a Discord webhook
+    /// replaces `webhook_url`, others are appended to `sinks`.
+    pub fn load(destinations: &[String]) -> Result<Config> {
+        let mut config = match std::env::var("CONFIG_FILE") {
+            Ok(path) if !path.is_empty() => Config::from_file(&path)?,
+            _ => Config::default(),
+        };
+        for url in destinations {
+            let discord = ["discord://", "https://", "http://"]
+                .iter()
+                .any(|scheme| url.starts_with(scheme));
+            if discord {
+                config.webhook_url = Some(url.clone());
+            } else {
+                config.sinks.push(SinkConfig::from_url(url)?);
+            }
+        }
+        config.resolve_routes();
+        Ok(config)
+    }
+
+    fn resolve_routes(&mut self) {
+        let expand = |url: &mut Option<String>| {
+            if let Some(url) = url {
+                *url = discord::expand_url(url);
+            }
+        };
+        expand(&mut self.webhook_url);
+        let expand_threads = |threads: &mut Option<IncidentThreads>| {
+            if let Some(threads) = threads {
+                threads.webhook_url = discord::expand_url(&threads.webhook_url);
+            }
+        };
+        expand_threads(&mut self.incident_threads);
+        for route in &mut self.routes {
+            expand(&mut route.webhook_url);
+            expand_threads(&mut route.incident_threads);
+        }
+        if let Some(meta) = &mut self.meta {
+            meta.webhook_url = discord::expand_url(&meta.webhook_url);
+        }
+        self.default_route = Route {
+            name: String::from("default"),
+            receiver: None,
+            matchers: Matchers::default(),
+            webhook_url: self.webhook_url.clone(),
+            locale: Some(self.locale.clone()),
+            timezone: Some(self.timezone),
+            regroup_by: Some(self.regroup_by.clone()),
+            content: None,
+            content_prefix: None,
+            content_suffix: None,
+            allowed_mentions: self.allowed_mentions.clone(),
+            rate_limit: self.rate_limit,
+            embed: Some(self.embed.clone()),
+            template: None,
+            discord: Some(self.discord),
+            sinks: Some(self.sinks.clone()),
+            theme: Some(self.theme.clone()),
+            layout: Some(self.layout),
+            incident_threads: self.incident_threads.clone(),
+            oncall: self.oncall.clone(),
+            maintenance: self.maintenance.clone(),
+            tts: self.tts.clone(),
+            console_links: Some(self.console_links.clone()),
+            #[cfg(feature = "interactions")]
+            allowed_roles: None,
+        };
+        for route in &mut self.routes {
+            if route.webhook_url.is_none() {
+                route.webhook_url = self.webhook_url.clone();
+            }
+            if route.locale.is_none() {
+                route.locale = Some(self.locale.clone());
+            }
+            if route.timezone.is_none() {
+                route.timezone = Some(self.timezone);
+            }
+            if route.regroup_by.is_none() {
+                route.regroup_by = Some(self.regroup_by.clone());
+            }
+            if route.allowed_mentions.is_none() {
+                route.allowed_mentions = self.allowed_mentions.clone();
+            }
+            if route.rate_limit.is_none() {
+                route.rate_limit = self.rate_limit;
+            }
+            if route.embed.is_none() {
+                route.embed = Some(self.embed.clone());
+            }
+            if route.theme.is_none() {
+                route.theme = Some(self.theme.clone());
+            }
+            if route.discord.is_none() {
+                route.discord = Some(self.discord);
+            }
+            if route.sinks.is_none() {
+                route.sinks = Some(self.sinks.clone());
+            }
+            if route.layout.is_none() {
+                route.layout = Some(self.layout);
+            }
+            if route.incident_threads.is_none() {
+                route.incident_threads = self.incident_threads.clone();
+            }
+            if route.oncall.is_none() {
+                route.oncall = self.oncall.clone();
+            }
+            if route.maintenance.is_none() {
+                route.maintenance = self.maintenance.clone();
+            }
+            if route.tts.is_none() {
+                route.tts = self.tts.clone();
+            }
+            if route.console_links.is_none() {
+                route.console_links = Some(self.console_links.clone());
+            }
+        }
+        #[cfg(feature = "interactions")]
+        if let Some(interactions) = &self.interactions {
+            let routes =
+                self.routes.iter_mut().chain([&mut self.default_route]);
+            for route in routes {
+                if route.allowed_roles.is_none() {
+                    route.allowed_roles =
+                        Some(interactions.allowed_roles.clone());
+                }
+            }
+        }
+        if self
+            .kubernetes
+            .as_ref()
+            .is_some_and(|k| k.group_by_namespace)
+        {
+            let routes =
+                self.routes.iter_mut().chain([&mut self.default_route]);
+            for route in routes {
+                let regroup_by = route.regroup_by.get_or_insert_with(Vec::new);
+                if !regroup_by.iter().any(|l| l == "namespace") {
+                    regroup_by.push(String::from("namespace"));
+                }
+            }
+        }
+    }
+
+    /// Checks the settings that can only be validated once the message
+    /// catalog is built.
+    pub fn validate(&self, catalog: &i18n::Catalog) -> Result<()> {
+        self.severity.validate()?;
+        for rule in &self.severity_rules {
+            if self.severity.position(&rule.severity).is_none() {
+                bail!("severity_rules: unknown severity {}", rule.severity);
+            }
+        }
+        if self.max_body_size == 0 {
+            bail!("max_body_size must be at least 1");
+        }
+        if self.max_alerts_per_message == Some(0) {
+            bail!("max_alerts_per_message must be at least 1");
+        }
+        if self.queue.workers == 0 {
+            bail!("queue.workers must be at least 1");
+        }
+        if self.queue.capacity == 0 {
+            bail!("queue.capacity must be at least 1");
+        }
+        if self.queue.max_memory.is_some() != self.queue.spill_dir.is_some() {
+            bail!("queue.max_memory and queue.spill_dir go together");
+        }
+        for route in &self.routes {
+            if route.name.is_empty() {
+                bail!("every route needs a name");
+            }
+        }
+        if let Some(meta) = &self.meta {
+            self.outbound
+                .check_url(meta.webhook_url.trim())
+                .context("meta")?;
+        }
+        if let Some(silences) = &self.silences {
+            self.outbound.check_url(&silences.url).context("silences")?;
+        }
+        if self.kubernetes.is_some()
+            && !self.presets.contains(&Preset::Kubernetes)
+        {
+            bail!("kubernetes needs the kubernetes preset");
+        }
+        if let Some(probes) = &self.probes {
+            self.outbound
+                .check_url(&probes.prometheus_url)
+                .context("probes")?;
+        }
+        if let Some(hosts) = &self.hosts {
+            hosts.validate().context("hosts")?;
+            if let Some(geoip) = &hosts.geoip {
+                self.outbound
+                    .check_url(&geoip.url.replace("{ip}", "127.0.0.1"))
+                    .context("hosts.geoip")?;
+            }
+        }
+        if let Some(chaos) = &self.chaos {
+            chaos.validate().context("chaos")?;
+        }
+        #[cfg(feature = "interactions")]
+        if let Some(interactions) = &self.interactions {
+            interactions.validate().context("interactions")?;
+        }
+        #[cfg(feature = "twilio")]
+        if let Some(sms) = &self.sms {
+            sms.validate(&self.severity).context("sms")?;
+            self.outbound.check_url(&sms.url).context("sms")?;
+        }
+        if let Some(push) = &self.push {
+            self.outbound
+                .check_url(push.group_url()?.as_str())
+                .context("push")?;
+        }
+        #[cfg(feature = "archive")]
+        if let Some(archive) = &self.archive {
+            self.outbound
+                .check_url(&archive.endpoint)
+                .context("archive")?;
+        }
+        if let Some(tickets) = &self.tickets {
+            if tickets.matchers.is_empty() {
+                bail!("tickets: matchers must not be empty");
+            }
+            self.outbound
+                .check_url(tickets.tracker.url())
+                .context("tickets")?;
+        }
+        for (name, schedule) in &self.schedules {
+            if let Some(url) = schedule.url() {
+                self.outbound
+                    .check_url(url)
+                    .with_context(|| format!("schedule {}", name))?;
+            }
+        }
+        for route in self.routes.iter().chain([&self.default_route]) {
+            if let Some(url) = &route.webhook_url {
+                self.outbound
+                    .check_url(url.trim())
+                    .with_context(|| format!("route {}", route.name))?;
+            }
+            for sink in route.sinks() {
+                sink.validate()
+                    .and_then(|_| {
+                        sink.urls()
+                            .iter()
+                            .try_for_each(|url| self.outbound.check_url(url))
+                    })
+                    .with_context(|| format!("route {} sink", route.name))?;
+            }
+            if let Some(threads) = &route.incident_threads {
+                self.outbound
+                    .check_url(&threads.webhook_url)
+                    .with_context(|| format!("route {}", route.name))?;
+                if self.severity.position(&threads.severity).is_none() {
+                    bail!(
+                        "route {}: incident_threads: unknown severity {}",
+                        route.name,
+                        threads.severity
+                    );
+                }
+            }
+            if let Some(maintenance) = &route.maintenance {
+                self.outbound
+                    .check_url(&maintenance.url())
+                    .with_context(|| format!("route {}", route.name))?;
+            }
+            if let Some(tts) = &route.tts {
+                if self.severity.position(tts).is_none() {
+                    bail!(
+                        "route {}: tts: unknown severity {}",
+                        route.name,
+                        tts
+                    );
+                }
+            }
+            if let Some(oncall) = &route.oncall {
+                if !self.schedules.contains_key(oncall) {
+                    bail!("route {}: unknown schedule {}", route.name, oncall);
+                }
+            }
+            if let Some(set) = &route.template {
+                if !self.template_sets.contains_key(set) {
+                    bail!("route {}: unknown template set {}", route.name, set);
+                }
+            }
+            if !catalog.contains(route.locale()) {
+                bail!(
+                    "route {}: unknown locale {}",
+                    route.name,
+                    route.locale()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// The route of that name, `default` being the top-level settings.
+    pub fn named_route(&self, name: &str) -> Option<&Route> {
+        self.routes
+            .iter()
+            .chain([&self.default_route])
+            .find(|r| r.name == name)
+    }
+
+    /// The first route matching the group.
+    pub fn route(&self, group: &AlertGroup) -> Option<&Route> {
+        self.routes.iter().find(|r| r.matches(group))
+    }
+
+    /// Where a group matching no route goes, if anywhere.
+    pub fn unmatched_route(
+        &self,
+        group: &AlertGroup,
+    ) -> Result<Option<&Route>> {
+        match self.unmatched {
+            Unmatched::Default => Ok(Some(&self.default_route)),
+            Unmatched::Drop => Ok(None),
+            Unmatched::Reject => Err(Unroutable(format!(
+                "no route matches receiver {} and labels {:?}",
+                group.receiver, group.common_labels
+            ))
+            .into()),
+        }
+    }
+
+    pub fn from_file(path: &str) -> Result<Config> {
+        let raw = std::fs::read_to_string(path)
+            .with_context(|| format!("cannot read config file {}", path))?;
+        let invalid = || format!("invalid config file {}", path);
+        let mut parsed: serde_yaml::Value =
+            serde_yaml::from_str(&raw).with_context(invalid)?;
+        // Shown by /api/config, without the secrets of the environment.
+        let source = parsed.clone();
+        interpolate(&mut parsed).with_context(invalid)?;
+        // Read back as text so that plain scalars convert as in the file.
+        let text = serde_yaml::to_string(&parsed)?;
+        let mut config: Config = serde_yaml::from_str(&text).map_err(|e| {
+            // Lines are those of the text read back, not of the file: the
+            // file has them unless the error is in a variable's value.
+            let e = match serde_yaml::from_str::<Config>(&raw) {
+                Err(original)
+                    if without_location(&original) == without_location(&e) =>
+                {
+                    original.to_string()
+                }
+                _ => without_location(&e),
+            };
+            let message = match suggestion(&e) {
+                Some(name) => format!("{}, did you mean `{}`?", e, name),
+                None => e,
+            };
+            anyhow!(message).context(invalid())
+        })?;
+        config.source = source;
+        Ok(config)
+    }
+}
+
+fn without_location(e: &serde_yaml::Error) -> String {
+    let e = e.to_string();
+    match e.rsplit_once(" at line ") {
+        Some((message, _)) => message.to_string(),
+        None => e,
+    }
+}
+
+/// The expected name closest to a misspelt field or variant, from an error
+/// like "routes[2]: unknown field `severiry`, expected one of ...".
+fn suggestion(error: &str) -> Option<String> {
+    let re = Regex::new(r"unknown (?:field|variant) `([^`]*)`, expected (.*)")
+        .unwrap();
+    let captures = re.captures(error)?;
+    let unknown = &captures[1];
+    Regex::new(r"`([^`]*)`")
+        .unwrap()
+        .captures_iter(&captures[2])
+        .map(|c| c[1].to_string())
+        .map(|name| (edit_distance(unknown, &name), name))
+        .filter(|(distance, name)| *distance <= (name.len() / 3).max(2))
+        .min()
+        .map(|(_, name)| name)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Replaces `${NAME}` in string values by the value of the environment
+/// variable, so that secrets can stay out of the config file. `$${` stands
+/// for a literal `${`.
+fn interpolate(value: &mut serde_yaml::Value) -> Result<()> {
+    let re = Regex::new(r"\$(\$?)\{([A-Za-z_][A-Za-z0-9_]*)\}").unwrap();
+    let mut missing = Vec::new();
+    substitute(&re, value, &mut missing);
+    if !missing.is_empty() {
+        bail!("environment variables not set: {}", missing.join(", "));
+    }
+    Ok(())
+}
+
+fn substitute(
+    re: &Regex,
+    value: &mut serde_yaml::Value,
+    missing: &mut Vec<String>,
+) {
+    use serde_yaml::Value;
+    match value {
+        Value::String(s) => {
+            let whole = re.find(s).is_some_and(|m| m.len() == s.len());
+            let replaced = re.replace_all(s, |c: &regex::Captures| {
+                if !c[1].is_empty() {
+                    return format!("${{{}}}", &c[2]);
+                }
+                std::env::var(&c[2]).unwrap_or_else(|_| {
+                    missing.push(c[2].to_string());
+                    String::new()
+                })
+            });
+            let replaced = replaced.into_owned();
+            // `port: ${PORT}` is a number, as it would be written out.
+            let typed = match serde_yaml::from_str(&replaced) {
+                Ok(typed @ (Value::Number(_) | Value::Bool(_))) if whole => {
+                    typed
+                }
+                _ => Value::String(replaced),
+            };
+            *value = typed;
+        }
+        Value::Sequence(items) => {
+            for item in items {
+                substitute(re, item, missing);
+            }
+        }
+        Value::Mapping(fields) => {
+            for (_, field) in fields.iter_mut() {
+                substitute(re, field, missing);
+            }
+        }
+        Value::Tagged(tagged) => substitute(re, &mut tagged.value, missing),
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+
+    fn load(yaml: &str) -> Result<Config> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "prometheus-discord-alert-config-{}-{}.yaml",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&path, yaml)?;
+        let config = Config::from_file(path.to_str().unwrap());
+        std::fs::remove_file(&path)?;
+        config
+    }
+
+    #[test]
+    fn interpolates_string_values() {
+        std::env::set_var("CONFIG_TEST_URL", "https://x\nmax_body_size: 1");
+        std::env::set_var("CONFIG_TEST_SIZE", "2048");
+        let config = load(
+            "# ${CONFIG_TEST_UNSET}\n\
+             webhook_url: ${CONFIG_TEST_URL}\n\
+             max_body_size: ${CONFIG_TEST_SIZE}\n\
+             messages:\n  transition: \"$${literal}\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.webhook_url.as_deref(),
+            Some("https://x\nmax_body_size: 1")
+        );
+        assert_eq!(config.max_body_size, 2048);
+        assert_eq!(config.messages.transition, "${literal}");
+        assert_eq!(
+            config.source["webhook_url"].as_str(),
+            Some("${CONFIG_TEST_URL}")
+        );
+    }
+
+    #[test]
+    fn keeps_numbers_in_string_fields() {
+        std::env::set_var("CONFIG_TEST_ID", "123456");
+        let config = load("webhook_url: ${CONFIG_TEST_ID}\n").unwrap();
+        assert_eq!(config.webhook_url.as_deref(), Some("123456"));
+    }
+
+    #[test]
+    fn rejects_empty_messages() {
+        let catalog = i18n::Catalog::new(&HashMap::new()).unwrap();
+        let config = Config {
+            max_alerts_per_message: Some(0),
+            follow_up_messages: true,
+            ..Config::default()
+        };
+        let e = config.validate(&catalog).unwrap_err();
+        assert_eq!(e.to_string(), "max_alerts_per_message must be at least 1");
+    }
+
+    #[test]
+    fn rejects_unset_variables() {
+        let e = load("webhook_url: ${CONFIG_TEST_UNSET}\n").unwrap_err();
+        assert!(format!("{:#}", e).contains("not set: CONFIG_TEST_UNSET"));
+    }
+
+    #[test]
+    fn suggests_misspelt_fields() {
+        let e = load("queue:\n  wokers: 2\n").unwrap_err();
+        assert!(format!("{:#}", e).ends_with("did you mean `workers`?"));
+    }
+
+    #[test]
+    fn tells_where_errors_are_in_the_file() {
+        std::env::set_var("CONFIG_TEST_HOOK", "https://x");
+        let e = load(
+            "# Discord\n\
+             webhook_url: ${CONFIG_TEST_HOOK}\n\n\
+             queue: {workers: 2}\n\
+             max_body_size: many\n",
+        )
+        .unwrap_err();
+        assert!(
+            format!("{:#}", e).contains("at line 5 column 16"),
+            "{:#}",
+            e
+        );
+
+        // Values of variables are not in the file.
+        std::env::set_var("CONFIG_TEST_WORKERS", "many");
+        let e =
+            load("queue:\n  workers: ${CONFIG_TEST_WORKERS}\n").unwrap_err();
+        assert!(!format!("{:#}", e).contains(" at line "), "{:#}", e);
+    }
+
+    #[test]
+    fn groups_every_route_by_namespace() {
+        let mut config = load(
+            "presets: [kubernetes]\n\
+             kubernetes:\n  group_by_namespace: true\n\
+             routes:\n  - name: team\n    regroup_by: [job]\n",
+        )
+        .unwrap();
+        config.resolve_routes();
+        assert_eq!(config.routes[0].regroup_by(), ["job", "namespace"]);
+        assert_eq!(config.default_route.regroup_by(), ["namespace"]);
+    }
+
+    /// The fields of `T`, as listed by the error about an unknown one.
+    fn fields<T: serde::de::DeserializeOwned + fmt::Debug>() -> Vec<String> {
+        let e = serde_yaml::from_str::<T>("not_a_field: 0").unwrap_err();
+        Regex::new(r"`([^`]*)`")
+            .unwrap()
+            .captures_iter(&e.to_string())
+            .skip(1)
+            .map(|c| c[1].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn documents_every_field_in_the_default_config() {
+        let yaml = include_str!("default-config.yaml");
+        // The lines of the section, set or commented out.
+        let section = |key: &str| -> Vec<&str> {
+            let header = format!("{}:", key);
+            yaml.lines()
+                .skip_while(|l| {
+                    !l.trim_start_matches("# ").starts_with(&header)
+                })
+                .skip(1)
+                .take_while(|l| l.starts_with("  ") || l.starts_with("#   "))
+                .collect()
+        };
+        let documented = |lines: &[&str], field: &str| {
+            lines.iter().any(|line| {
+                let line = line.trim_start();
+                let line = line.strip_prefix('#').unwrap_or(line);
+                line.trim_start()
+                    .strip_prefix(field)
+                    .is_some_and(|rest| rest.starts_with(':'))
+            })
+        };
+        let top: Vec<&str> = yaml.lines().collect();
+        let sections = [
+            ("messages", fields::<Messages>()),
+            ("severity", fields::<Severity>()),
+            ("kubernetes", fields::<KubernetesConfig>()),
+            ("hosts", fields::<HostConfig>()),
+            ("probes", fields::<ProbeConfig>()),
+            ("flapping", fields::<FlapConfig>()),
+            ("reminders", fields::<ReminderConfig>()),
+            ("incident_threads", fields::<IncidentThreads>()),
+            ("maintenance", fields::<MaintenanceConfig>()),
+            ("tickets", fields::<TicketConfig>()),
+            ("history", fields::<HistoryConfig>()),
+            ("dedup", fields::<DedupConfig>()),
+            ("idempotency", fields::<IdempotencyConfig>()),
+            ("deliveries", fields::<DeliveriesConfig>()),
+            ("inhibition", fields::<InhibitConfig>()),
+            ("redaction", fields::<RedactConfig>()),
+            ("debug", fields::<DebugConfig>()),
+            ("auth", fields::<AuthConfig>()),
+            ("admin", fields::<AdminConfig>()),
+            ("meta", fields::<MetaConfig>()),
+            ("pull", fields::<PullConfig>()),
+            ("silences", fields::<SilenceConfig>()),
+            ("chaos", fields::<ChaosConfig>()),
+            ("push", fields::<PushConfig>()),
+            ("reconcile", fields::<ReconcileConfig>()),
+            ("log", fields::<LogConfig>()),
+            ("outbound", fields::<OutboundConfig>()),
+            ("queue", fields::<QueueConfig>()),
+            ("circuit_breaker", fields::<BreakerConfig>()),
+        ];
+        let mut missing: Vec<String> = fields::<Config>()
+            .into_iter()
+            .filter(|field| {
+                !top.iter().any(|l| {
+                    let l = l.strip_prefix("# ").unwrap_or(l);
+                    l.strip_prefix(field.as_str())
+                        .is_some_and(|rest| rest.starts_with(':'))
+                })
+            })
+            .collect();
+        for (key, fields) in sections {
+            let lines = section(key);
+            missing.extend(
+                fields
+                    .into_iter()
+                    .filter(|field| !documented(&lines, field))
+                    .map(|field| format!("{}.{}", key, field)),
+            );
+        }
+        assert!(
+            missing.is_empty(),
+            "not in default-config.yaml: {:?}",
+            missing
+        );
+        // Nor keys that are not fields.
+        serde_yaml::from_str::<Config>(yaml).unwrap();
+    }
+}
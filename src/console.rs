@@ -0,0 +1,53 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// A link to the cloud console page of the resource an alert is about.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ConsoleLink {
+    pub text: String,
+    /// Labels the alert needs for the link to be shown.
+    pub labels: Vec<String>,
+    /// minijinja template of the URL, seeing the alert's labels.
+    pub url: String,
+}
+
+impl ConsoleLink {
+    pub fn applies(&self, labels: &HashMap<String, String>) -> bool {
+        self.labels
+            .iter()
+            .all(|name| labels.get(name).is_some_and(|v| !v.is_empty()))
+    }
+}
+
+/// Name of the template of the route's link at `index`.
+pub fn template_name(route: &str, index: usize) -> String {
+    format!("console/{}/{}", route, index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_when_the_labels_are_set() {
+        let link = ConsoleLink {
+            text: String::from("EC2"),
+            labels: vec![String::from("instance_id"), String::from("region")],
+            url: String::new(),
+        };
+        let labels = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<HashMap<_, _>>()
+        };
+        assert!(link.applies(&labels(&[
+            ("instance_id", "i-0abc"),
+            ("region", "eu-west-1"),
+        ])));
+        assert!(!link.applies(&labels(&[("instance_id", "i-0abc")])));
+        assert!(!link
+            .applies(&labels(&[("instance_id", "i-0abc"), ("region", ""),])));
+    }
+}
@@ -0,0 +1,265 @@
+use crate::admin;
+use crate::alert::Alert;
+use crate::alert::AlertGroup;
+use crate::alert::Annotations;
+use crate::alert::Status;
+use crate::config::Route;
+use crate::config::Routing;
+use crate::discord::DiscordContent;
+use crate::input;
+use crate::maintenance;
+use crate::sync::Recover;
+use crate::App;
+use anyhow::Result;
+use chrono::DateTime;
+use chrono::Utc;
+use rouille::Request;
+use rouille::Response;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::io::Read;
+use std::sync::Mutex;
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct DebugConfig {
+    /// How many of the latest payloads `/api/debug/payloads` returns,
+    /// 0 disabling the endpoint.
+    pub payloads: usize,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Captured {
+    pub received_at: DateTime<Utc>,
+    pub remote_addr: String,
+    pub payload: Value,
+}
+
+/// Keeps the latest raw payloads received, already redacted.
+pub struct Capture {
+    config: DebugConfig,
+    payloads: Mutex<VecDeque<Captured>>,
+}
+
+impl Capture {
+    pub fn new(config: DebugConfig) -> Capture {
+        Capture {
+            config,
+            payloads: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.payloads > 0
+    }
+
+    pub fn record(&self, captured: Captured) {
+        if !self.enabled() {
+            return;
+        }
+        let mut payloads = self.payloads.lock().recover();
+        if payloads.len() >= self.config.payloads {
+            payloads.pop_front();
+        }
+        payloads.push_back(captured);
+    }
+
+    /// The captured payloads, most recent first.
+    pub fn payloads(&self) -> Vec<Captured> {
+        self.payloads
+            .lock()
+            .recover()
+            .iter()
+            .rev()
+            .cloned()
+            .collect()
+    }
+}
+
+/// A sample alert to trace through the configuration.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Sample {
+    #[serde(default)]
+    receiver: String,
+    labels: HashMap<String, String>,
+    #[serde(default)]
+    annotations: HashMap<String, String>,
+    #[serde(default = "firing")]
+    status: Status,
+}
+
+fn firing() -> Status {
+    Status::Firing
+}
+
+#[derive(Serialize)]
+struct MatcherTrace {
+    matcher: String,
+    matched: bool,
+}
+
+#[derive(Serialize)]
+struct RouteTrace {
+    name: String,
+    receiver_matched: bool,
+    matchers: Vec<MatcherTrace>,
+    matched: bool,
+}
+
+#[derive(Serialize)]
+struct Inhibition {
+    rule: usize,
+    source: HashMap<String, String>,
+}
+
+#[derive(Serialize, Default)]
+struct Trace {
+    routes: Vec<RouteTrace>,
+    /// The route the alert goes to, none if it would be dropped.
+    route: Option<String>,
+    paused: bool,
+    /// The maintenance event of the route, and whether it mutes the alert.
+    maintenance: Option<String>,
+    muted: bool,
+    severity: Option<String>,
+    inhibited_by: Vec<Inhibition>,
+    content_template: Option<String>,
+    messages: Vec<DiscordContent>,
+    error: Option<String>,
+}
+
+/// How the route matches the group or, with `routing: alert`, its alert.
+fn trace_route(
+    route: &Route,
+    group: &AlertGroup,
+    routing: Routing,
+) -> RouteTrace {
+    let labels = match (routing, group.alerts.first()) {
+        (Routing::Alert, Some(alert)) => &alert.labels,
+        _ => &group.common_labels,
+    };
+    RouteTrace {
+        name: route.name.clone(),
+        receiver_matched: route
+            .receiver
+            .as_ref()
+            .is_none_or(|r| r == &group.receiver),
+        matchers: route
+            .matchers
+            .0
+            .iter()
+            .map(|m| MatcherTrace {
+                matcher: m.to_string(),
+                matched: m.matches(labels),
+            })
+            .collect(),
+        matched: route.matches_labels(&group.receiver, labels),
+    }
+}
+
+/// Tells what the bridge would do with an alert, without sending or
+/// remembering anything.
+pub fn route_test(
+    app: &App,
+    request: &Request,
+    body: &mut dyn Read,
+) -> Response {
+    if let Some(response) = admin::guard(app, request) {
+        return response;
+    }
+    let sample: Sample =
+        match input::json_input(request, body, app.config.max_body_size) {
+            Ok(sample) => sample,
+            Err(e) => return input::error(e),
+        };
+    let alert = Alert {
+        status: sample.status,
+        labels: sample.labels,
+        annotations: Annotations::from_map(sample.annotations),
+        starts_at: Utc::now(),
+        ends_at: DateTime::default(),
+        fingerprint: String::from("route-test"),
+        values: None,
+        value_string: None,
+        ticket: None,
+        silences: None,
+        probe: None,
+        host: None,
+        suppressed: false,
+    };
+    let Some(mut group) =
+        AlertGroup::by_alertname(sample.receiver, String::new(), vec![alert])
+            .pop()
+    else {
+        return Response::text("No alert").with_status_code(400);
+    };
+
+    // As forward does, without remembering anything.
+    let rules = &app.config.severity_rules;
+    app.config.severity.rewrite(rules, None, &mut group);
+    let routing = app.config.routing;
+    let mut trace = Trace {
+        routes: app
+            .config
+            .routes
+            .iter()
+            .map(|r| trace_route(r, &group, routing))
+            .collect(),
+        ..Trace::default()
+    };
+    let matched = trace.routes.iter().position(|r| r.matched);
+    let route = match matched {
+        Some(index) => Ok(Some(&app.config.routes[index])),
+        None => app.config.unmatched_route(&group),
+    };
+    let route = match route {
+        Ok(Some(route)) => route,
+        Ok(None) => return Response::json(&trace),
+        Err(e) => {
+            trace.error = Some(format!("{:#}", e));
+            return Response::json(&trace);
+        }
+    };
+    trace.route = Some(route.name.clone());
+    trace.paused = app.store.is_paused(&route.name);
+    trace.severity = app
+        .config
+        .severity
+        .level(&group.common_labels)
+        .map(|l| l.name.clone());
+    trace.inhibited_by = app
+        .inhibitor
+        .inhibitors(&group.common_labels)
+        .into_iter()
+        .map(|(rule, source)| Inhibition { rule, source })
+        .collect();
+    if let Some(maintenance) = &route.maintenance {
+        trace.maintenance = app.calendars.ongoing(maintenance);
+        trace.muted = trace.maintenance.is_some()
+            && maintenance.action == maintenance::Action::Mute;
+    }
+    if trace.muted {
+        return Response::json(&trace);
+    }
+
+    app.redactor.redact(&mut group);
+    let renderer = app.renderer.read().recover().clone();
+    trace.content_template = Some(renderer.content_template(route));
+    let oncall = app.schedules.current(route);
+    let messages: Result<Vec<_>> = group
+        .regroup(route.regroup_by())
+        .into_iter()
+        .map(|g| renderer.render(route, g, &oncall))
+        .collect();
+    match messages {
+        Ok(messages) => {
+            trace.messages = messages.into_iter().flatten().collect()
+        }
+        Err(e) => trace.error = Some(format!("{:#}", e)),
+    }
+    Response::json(&trace)
+}
@@ -0,0 +1,130 @@
+use crate::alert::AlertGroup;
+use crate::alert::Status;
+use crate::sync::Recover;
+use serde::Deserialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DedupConfig {
+    #[serde(with = "humantime_serde")]
+    pub window: Duration,
+}
+
+/// Remembers the messages recently sent to drop identical ones, as sent by
+/// Alertmanager retrying or several replicas posting the same notification.
+/// A message is identified by the alerts of its notification, their status
+/// and its place among its messages rather than by its text, which changes
+/// with the time shown.
+pub struct Dedup {
+    config: DedupConfig,
+    sent: Mutex<HashMap<u64, Instant>>,
+}
+
+/// Identifies the alerts of a notification to a route and their status.
+pub fn notification(route: &str, group: &AlertGroup) -> u64 {
+    let mut alerts: Vec<_> = group
+        .alerts
+        .iter()
+        .map(|a| (a.fingerprint.as_str(), a.status == Status::Firing))
+        .collect();
+    alerts.sort_unstable();
+    let mut hasher = DefaultHasher::new();
+    route.hash(&mut hasher);
+    alerts.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Identifies the `index`th message of the notification to the webhook.
+pub fn key(notification: u64, url: &str, index: usize) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (notification, url, index).hash(&mut hasher);
+    hasher.finish()
+}
+
+impl Dedup {
+    pub fn new(config: DedupConfig) -> Dedup {
+        Dedup {
+            config,
+            sent: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether the same message was sent within the window.
+    pub fn is_sent(&self, key: u64) -> bool {
+        let mut sent = self.sent.lock().recover();
+        sent.retain(|_, at| at.elapsed() < self.config.window);
+        sent.contains_key(&key)
+    }
+
+    /// Records a message once delivered, so that one that failed goes
+    /// through on the retry of its notification.
+    pub fn record(&self, key: u64) {
+        self.sent.lock().recover().insert(key, Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::StoredAlert;
+    use chrono::Utc;
+
+    fn group(statuses: &[(&str, Status)]) -> AlertGroup {
+        let stored = StoredAlert {
+            receiver: String::new(),
+            labels: HashMap::from([("alertname".into(), "Down".into())]),
+            annotations: None,
+            starts_at: Utc::now(),
+        };
+        let alerts = statuses
+            .iter()
+            .map(|(fingerprint, status)| match status {
+                Status::Firing => stored.firing(fingerprint),
+                Status::Resolved => stored.resolved(fingerprint, Utc::now()),
+            })
+            .collect();
+        AlertGroup::by_alertname(String::new(), String::new(), alerts).remove(0)
+    }
+
+    #[test]
+    fn identifies_notifications_by_their_alerts() {
+        let a = group(&[("a", Status::Firing), ("b", Status::Firing)]);
+        let b = group(&[("b", Status::Firing), ("a", Status::Firing)]);
+        let c = group(&[("a", Status::Firing), ("b", Status::Resolved)]);
+        assert_eq!(notification("r", &a), notification("r", &b));
+        assert_ne!(notification("r", &a), notification("r", &c));
+        assert_ne!(notification("r", &a), notification("other", &a));
+        let n = notification("r", &a);
+        assert_ne!(key(n, "https://x", 0), key(n, "https://x", 1));
+        assert_ne!(key(n, "https://x", 0), key(n, "https://y", 0));
+    }
+
+    #[test]
+    fn drops_messages_once_sent() {
+        let dedup = Dedup::new(DedupConfig {
+            window: Duration::from_secs(60),
+        });
+        assert!(!dedup.is_sent(1));
+        assert!(!dedup.is_sent(1));
+        dedup.record(1);
+        assert!(dedup.is_sent(1));
+        assert!(!dedup.is_sent(2));
+    }
+
+    #[test]
+    fn forgets_messages_after_the_window() {
+        let dedup = Dedup::new(DedupConfig {
+            window: Duration::from_millis(20),
+        });
+        dedup.record(1);
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(!dedup.is_sent(1));
+    }
+}
@@ -0,0 +1,144 @@
+use crate::receipt::Receipt;
+use crate::sync::Recover;
+use crate::App;
+use rouille::Response;
+use serde::Deserialize;
+use std::collections::hash_map::RandomState;
+use std::collections::VecDeque;
+use std::hash::BuildHasher;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Answers notifications with a 202 and the ID their delivery can be
+/// looked up by.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeliveriesConfig {
+    /// How long deliveries can be looked up.
+    #[serde(with = "humantime_serde")]
+    pub retention: Duration,
+    /// Deliveries kept at most, the oldest being forgotten first.
+    pub capacity: usize,
+}
+
+impl Default for DeliveriesConfig {
+    fn default() -> Self {
+        DeliveriesConfig {
+            retention: Duration::from_secs(3600),
+            capacity: 10000,
+        }
+    }
+}
+
+struct Delivery {
+    id: String,
+    created: Instant,
+    receipt: Receipt,
+}
+
+/// The deliveries of the notifications answered with an ID.
+#[derive(Default)]
+pub struct Deliveries {
+    deliveries: Mutex<VecDeque<Delivery>>,
+    random: RandomState,
+    next: AtomicU64,
+}
+
+impl Deliveries {
+    /// Keeps the receipt, returning the new delivery's ID.
+    pub fn insert(
+        &self,
+        config: &DeliveriesConfig,
+        receipt: Receipt,
+    ) -> String {
+        let mut deliveries = self.deliveries.lock().recover();
+        while deliveries.front().is_some_and(|d| {
+            d.created.elapsed() > config.retention
+                || deliveries.len() >= config.capacity
+        }) {
+            deliveries.pop_front();
+        }
+        // Unguessable, deliveries not being behind the admin token.
+        let id = format!(
+            "{:016x}",
+            self.random
+                .hash_one(self.next.fetch_add(1, Ordering::Relaxed))
+        );
+        deliveries.push_back(Delivery {
+            id: id.clone(),
+            created: Instant::now(),
+            receipt,
+        });
+        id
+    }
+}
+
+/// The receipt of a delivery, with the outcomes reported so far.
+pub fn get(app: &App, id: &str) -> Response {
+    let Some(config) = &app.config.deliveries else {
+        return Response::empty_404();
+    };
+    let mut deliveries = app.deliveries.deliveries.lock().recover();
+    let delivery = deliveries
+        .iter_mut()
+        .find(|d| d.id == id && d.created.elapsed() <= config.retention);
+    let Some(delivery) = delivery else {
+        return Response::text("No such delivery").with_status_code(404);
+    };
+    delivery.receipt.poll();
+    Response::json(&delivery.receipt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receipt(repeated: usize) -> Receipt {
+        Receipt {
+            repeated,
+            ..Receipt::default()
+        }
+    }
+
+    fn kept(deliveries: &Deliveries) -> Vec<(String, usize)> {
+        let deliveries = deliveries.deliveries.lock().recover();
+        deliveries
+            .iter()
+            .map(|d| (d.id.clone(), d.receipt.repeated))
+            .collect()
+    }
+
+    #[test]
+    fn forgets_the_oldest_deliveries_past_the_capacity() {
+        let config = DeliveriesConfig {
+            capacity: 2,
+            ..DeliveriesConfig::default()
+        };
+        let deliveries = Deliveries::default();
+        let ids: Vec<_> = (1..=3)
+            .map(|n| deliveries.insert(&config, receipt(n)))
+            .collect();
+        assert!(ids.iter().all(|id| id.len() == 16));
+        assert_ne!(ids[1], ids[2]);
+        assert_eq!(
+            kept(&deliveries),
+            [(ids[1].clone(), 2), (ids[2].clone(), 3)]
+        );
+    }
+
+    #[test]
+    fn forgets_deliveries_past_the_retention() {
+        let config = DeliveriesConfig {
+            retention: Duration::from_millis(10),
+            ..DeliveriesConfig::default()
+        };
+        let deliveries = Deliveries::default();
+        deliveries.insert(&config, receipt(1));
+        std::thread::sleep(Duration::from_millis(20));
+        let id = deliveries.insert(&config, receipt(2));
+        assert_eq!(kept(&deliveries), [(id, 2)]);
+    }
+}
@@ -0,0 +1,427 @@
+use crate::mock;
+use reqwest::blocking::Client;
+use reqwest::header::RETRY_AFTER;
+use reqwest::StatusCode;
+use serde::de;
+use serde::Deserialize;
+use serde::Deserializer;
+use serde::Serialize;
+use serde::Serializer;
+use std::fmt;
+use std::time::Duration;
+
+/// Expands the `discord://id/token` shorthand to the webhook URL, other
+/// URLs being returned as they are.
+pub fn expand_url(url: &str) -> String {
+    let url = url.trim();
+    match url.strip_prefix("discord://") {
+        Some(rest) => format!("https://discord.com/api/webhooks/{}", rest),
+        None => url.to_string(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum Color {
+    Red,
+    Green,
+    Grey,
+    Orange,
+    Custom(u32),
+}
+
+impl Color {
+    pub fn value(&self) -> u32 {
+        match self {
+            Color::Red => 0x992D22,
+            Color::Green => 0x2ECC71,
+            Color::Grey => 0x95A5A6,
+            Color::Orange => 0xE67E22,
+            Color::Custom(value) => *value,
+        }
+    }
+}
+
+impl Serialize for Color {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_u32(self.value())
+    }
+}
+
+/// Colors are configured as an integer or a `#RRGGBB` string.
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Color, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Int(u32),
+            Hex(String),
+        }
+        match Raw::deserialize(d)? {
+            Raw::Int(value) => Ok(Color::Custom(value)),
+            Raw::Hex(hex) => hex
+                .strip_prefix('#')
+                .and_then(|h| u32::from_str_radix(h, 16).ok())
+                .map(Color::Custom)
+                .ok_or_else(|| {
+                    de::Error::custom(format!("invalid color {}", hex))
+                }),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DiscordEmbedField {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscordEmbedAuthor {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon_url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DiscordEmbedThumbnail {
+    pub url: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DiscordEmbedFooter {
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon_url: Option<String>,
+}
+
+/// Decorations of the embeds of a route or severity level.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct EmbedStyle {
+    pub author: Option<DiscordEmbedAuthor>,
+    /// URL of the image shown in the top right corner.
+    pub thumbnail: Option<String>,
+    pub footer: Option<DiscordEmbedFooter>,
+}
+
+impl EmbedStyle {
+    /// This style, completed with the settings of `other`.
+    pub fn or(&self, other: &EmbedStyle) -> EmbedStyle {
+        EmbedStyle {
+            author: self.author.clone().or_else(|| other.author.clone()),
+            thumbnail: self
+                .thumbnail
+                .clone()
+                .or_else(|| other.thumbnail.clone()),
+            footer: self.footer.clone().or_else(|| other.footer.clone()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DiscordEmbed {
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    pub description: String,
+    pub color: Color,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<DiscordEmbedAuthor>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnail: Option<DiscordEmbedThumbnail>,
+    pub fields: Vec<DiscordEmbedField>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub footer: Option<DiscordEmbedFooter>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MentionType {
+    Roles,
+    Users,
+    Everyone,
+}
+
+/// Which mentions in the content actually ping, see
+/// <https://discord.com/developers/docs/resources/message#allowed-mentions-object>.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AllowedMentions {
+    pub parse: Vec<MentionType>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub users: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DiscordContent {
+    pub content: Option<String>,
+    pub embeds: Vec<DiscordEmbed>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_mentions: Option<AllowedMentions>,
+    /// Read aloud to those with the channel open.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub tts: bool,
+    /// Rows of buttons, see
+    /// <https://discord.com/developers/docs/components/reference>.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub components: Vec<serde_json::Value>,
+}
+
+/// Limits of a single message, see
+/// <https://discord.com/developers/docs/resources/message#embed-object-embed-limits>.
+const MAX_EMBEDS: usize = 10;
+const MAX_EMBED_CHARS: usize = 6000;
+const MAX_CONTENT_CHARS: usize = 2000;
+/// Longest `Retry-After` honored, longer ones being taken as that.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(300);
+
+impl DiscordEmbed {
+    /// Characters counted towards the limit of all embeds of a message.
+    fn chars(&self) -> usize {
+        let count = |s: &str| s.chars().count();
+        count(&self.title)
+            + count(&self.description)
+            + self.author.as_ref().map_or(0, |a| count(&a.name))
+            + self.footer.as_ref().map_or(0, |f| count(&f.text))
+            + self
+                .fields
+                .iter()
+                .map(|f| count(&f.name) + count(&f.value))
+                .sum::<usize>()
+    }
+}
+
+impl DiscordContent {
+    fn chars(&self) -> usize {
+        self.embeds.iter().map(DiscordEmbed::chars).sum()
+    }
+
+    /// Moves the embeds of `other` into this message if they fit. Both
+    /// being for the same route, they share their allowed mentions.
+    pub fn absorb(&mut self, other: DiscordContent) -> Option<DiscordContent> {
+        let content = match (&self.content, &other.content) {
+            (Some(a), Some(b)) if a != b => Some(format!("{}\n{}", a, b)),
+            (a, b) => a.clone().or_else(|| b.clone()),
+        };
+        let fits = self.embeds.len() + other.embeds.len() <= MAX_EMBEDS
+            && self.chars() + other.chars() <= MAX_EMBED_CHARS
+            && content.as_ref().map_or(0, |c| c.chars().count())
+                <= MAX_CONTENT_CHARS;
+        if !fits {
+            return Some(other);
+        }
+        self.content = content;
+        self.embeds.extend(other.embeds);
+        if self.allowed_mentions.is_none() {
+            self.allowed_mentions = other.allowed_mentions;
+        }
+        self.tts |= other.tts;
+        self.components.extend(other.components);
+        None
+    }
+}
+
+/// Packs messages into as few webhook calls as Discord's limits allow.
+pub fn pack(messages: Vec<DiscordContent>) -> Vec<DiscordContent> {
+    let mut packed: Vec<DiscordContent> = Vec::new();
+    for message in messages {
+        let rest = match packed.last_mut() {
+            Some(last) => last.absorb(message),
+            None => Some(message),
+        };
+        packed.extend(rest);
+    }
+    packed
+}
+
+pub enum DeliveryError {
+    /// Worth retrying: network errors, rate limits and server errors.
+    Transient {
+        error: String,
+        retry_after: Option<Duration>,
+    },
+    /// Discord refused the message itself, retrying will not help.
+    Permanent(String),
+}
+
+impl fmt::Display for DeliveryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeliveryError::Transient { error, .. } => write!(f, "{}", error),
+            DeliveryError::Permanent(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+/// Posts the message, returning its ID when `wait` asks Discord for it.
+pub fn send(
+    client: &Client,
+    url: &str,
+    content: &DiscordContent,
+    wait: bool,
+) -> Result<Option<String>, DeliveryError> {
+    let query: &[(&str, &str)] = if wait { &[("wait", "true")] } else { &[] };
+    let message = post(client, url, content, query)?;
+    Ok(message.and_then(|m| m["id"].as_str().map(String::from)))
+}
+
+/// Posts the message in a thread of the forum channel of the webhook,
+/// creating one named `name` unless `thread` is given. Returns the IDs of
+/// the message and of the thread.
+pub fn send_in_thread(
+    client: &Client,
+    url: &str,
+    content: &DiscordContent,
+    thread: Option<&str>,
+    name: &str,
+) -> Result<(Option<String>, String), DeliveryError> {
+    let message = match thread {
+        Some(thread) => post(
+            client,
+            url,
+            content,
+            &[("wait", "true"), ("thread_id", thread)],
+        )?,
+        None => {
+            let mut body = serde_json::to_value(content)
+                .map_err(|e| DeliveryError::Permanent(e.to_string()))?;
+            body["thread_name"] = name.into();
+            post(client, url, &body, &[("wait", "true")])?
+        }
+    };
+    let field = |name: &str| {
+        message
+            .as_ref()
+            .and_then(|m| m[name].as_str())
+            .map(String::from)
+    };
+    let thread = thread.map(String::from).or_else(|| field("channel_id"));
+    match thread {
+        Some(thread) => Ok((field("id"), thread)),
+        None => Err(DeliveryError::Permanent(String::from(
+            "discord answered without the ID of the thread",
+        ))),
+    }
+}
+
+/// Seconds, possibly fractional, as Discord puts in `Retry-After`.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let seconds = value.trim().parse::<f64>().ok()?;
+    Duration::try_from_secs_f64(seconds)
+        .ok()
+        .or((seconds > 0.0).then_some(MAX_RETRY_AFTER))
+        .map(|d| d.min(MAX_RETRY_AFTER))
+}
+
+/// Returns the message Discord answered with, if any.
+fn post(
+    client: &Client,
+    url: &str,
+    body: &impl Serialize,
+    query: &[(&str, &str)],
+) -> Result<Option<serde_json::Value>, DeliveryError> {
+    if let Some(message) = mock::intercept(url, query, body) {
+        return Ok(Some(message));
+    }
+    let response =
+        client
+            .post(url)
+            .json(body)
+            .query(query)
+            .send()
+            .map_err(|e| DeliveryError::Transient {
+                error: e.to_string(),
+                retry_after: None,
+            })?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response.json().ok());
+    }
+
+    let retry_after = response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_retry_after);
+    let error = format!(
+        "discord answered {}: {}",
+        status,
+        response.text().unwrap_or_default()
+    );
+    if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+        Err(DeliveryError::Transient { error, retry_after })
+    } else {
+        Err(DeliveryError::Permanent(error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_discord_urls() {
+        assert_eq!(
+            expand_url(" discord://123/abc "),
+            "https://discord.com/api/webhooks/123/abc"
+        );
+        assert_eq!(
+            expand_url("https://example.com/x"),
+            "https://example.com/x"
+        );
+    }
+
+    #[test]
+    fn parses_retry_after() {
+        assert_eq!(parse_retry_after("2"), Some(Duration::from_secs(2)));
+        assert_eq!(
+            parse_retry_after(" 0.25 "),
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(parse_retry_after("1e12"), Some(MAX_RETRY_AFTER));
+        assert_eq!(parse_retry_after("inf"), Some(MAX_RETRY_AFTER));
+        assert_eq!(parse_retry_after("-1"), None);
+        assert_eq!(parse_retry_after("NaN"), None);
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    fn message(embeds: usize, description: usize) -> DiscordContent {
+        let embed = || DiscordEmbed {
+            title: String::from("Down"),
+            url: None,
+            description: "x".repeat(description),
+            color: Color::Red,
+            author: None,
+            thumbnail: None,
+            fields: Vec::new(),
+            footer: None,
+        };
+        DiscordContent {
+            content: None,
+            embeds: (0..embeds).map(|_| embed()).collect(),
+            allowed_mentions: None,
+            tts: false,
+            components: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn absorbs_what_fits() {
+        let mut first = message(4, 1000);
+        assert!(first.absorb(message(1, 1000)).is_none());
+        assert_eq!(first.embeds.len(), 5);
+        let rest = first.absorb(message(1, 1000)).unwrap();
+        assert_eq!(rest.embeds.len(), 1);
+        assert_eq!(first.embeds.len(), 5);
+        let mut first = message(9, 10);
+        assert!(first.absorb(message(2, 10)).is_some());
+        assert_eq!(pack(vec![message(9, 10), message(2, 10)]).len(), 2);
+    }
+}
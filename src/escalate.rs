@@ -0,0 +1,41 @@
+use crate::alert::Alert;
+use crate::alert::Status;
+use crate::discord::Color;
+use chrono::Utc;
+use serde::Deserialize;
+use std::time::Duration;
+
+/// Marks alerts that have been firing for longer than `after`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Escalation {
+    #[serde(with = "humantime_serde")]
+    pub after: Duration,
+    /// Prepended to the field name, "⏰ firing <after>+" by default.
+    pub label: Option<String>,
+    /// Embed color once an alert of the message reached this age.
+    pub color: Option<Color>,
+}
+
+impl Escalation {
+    pub fn label(&self) -> String {
+        self.label.clone().unwrap_or_else(|| {
+            format!("⏰ firing {}+", humantime::format_duration(self.after))
+        })
+    }
+}
+
+/// The escalation with the longest threshold the alert passed, if any.
+pub fn escalation<'a>(
+    escalations: &'a [Escalation],
+    alert: &Alert,
+) -> Option<&'a Escalation> {
+    if alert.status != Status::Firing {
+        return None;
+    }
+    let age = (Utc::now() - alert.starts_at).to_std().ok()?;
+    escalations
+        .iter()
+        .filter(|e| age >= e.after)
+        .max_by_key(|e| e.after)
+}
@@ -0,0 +1,146 @@
+use crate::alert::Status;
+use crate::sync::Recover;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct FlapConfig {
+    /// An alert flaps once its status changed more than this many times
+    /// within `window`.
+    pub max_flips: usize,
+    #[serde(with = "humantime_serde")]
+    pub window: Duration,
+}
+
+pub enum Verdict {
+    Notify,
+    /// The alert just started flapping, after that many status changes.
+    Flapping(usize),
+    Suppress,
+}
+
+struct History {
+    status: Status,
+    flips: VecDeque<Instant>,
+    flapping: bool,
+    last_seen: Instant,
+}
+
+/// Tracks status changes by fingerprint. A flapping alert stays
+/// suppressed until its status has not changed for a full window.
+pub struct FlapDetector {
+    config: FlapConfig,
+    history: Mutex<HashMap<String, History>>,
+}
+
+impl FlapDetector {
+    pub fn new(config: FlapConfig) -> FlapDetector {
+        FlapDetector {
+            config,
+            history: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.config.window
+    }
+
+    pub fn observe(&self, fingerprint: &str, status: Status) -> Verdict {
+        let now = Instant::now();
+        let window = self.config.window;
+        let mut history = self.history.lock().recover();
+        // Alerts unseen for a window are stable, flapping or not.
+        history.retain(|_, h| now - h.last_seen < window);
+
+        let h =
+            history
+                .entry(fingerprint.to_string())
+                .or_insert_with(|| History {
+                    status,
+                    flips: VecDeque::new(),
+                    flapping: false,
+                    last_seen: now,
+                });
+        h.last_seen = now;
+        if h.status != status {
+            h.status = status;
+            h.flips.push_back(now);
+        }
+        while h.flips.front().is_some_and(|t| now - *t > window)
+            || h.flips.len() > self.config.max_flips + 1
+        {
+            h.flips.pop_front();
+        }
+
+        if h.flapping {
+            if h.flips.is_empty() {
+                h.flapping = false;
+                return Verdict::Notify;
+            }
+            return Verdict::Suppress;
+        }
+        if h.flips.len() > self.config.max_flips {
+            h.flapping = true;
+            return Verdict::Flapping(h.flips.len());
+        }
+        Verdict::Notify
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn detector(window: Duration) -> FlapDetector {
+        FlapDetector::new(FlapConfig {
+            max_flips: 2,
+            window,
+        })
+    }
+
+    #[test]
+    fn flapping_is_suppressed() {
+        let flaps = detector(Duration::from_secs(60));
+        assert!(matches!(
+            flaps.observe("a", Status::Firing),
+            Verdict::Notify
+        ));
+        assert!(matches!(
+            flaps.observe("a", Status::Resolved),
+            Verdict::Notify
+        ));
+        assert!(matches!(
+            flaps.observe("a", Status::Firing),
+            Verdict::Notify
+        ));
+        assert!(matches!(
+            flaps.observe("a", Status::Resolved),
+            Verdict::Flapping(3)
+        ));
+        assert!(matches!(
+            flaps.observe("a", Status::Firing),
+            Verdict::Suppress
+        ));
+        assert!(flaps.history.lock().recover()["a"].flips.len() <= 3);
+    }
+
+    #[test]
+    fn unseen_alerts_are_forgotten() {
+        let flaps = detector(Duration::from_millis(20));
+        for status in [Status::Firing, Status::Resolved, Status::Firing] {
+            flaps.observe("a", status);
+        }
+        flaps.observe("a", Status::Resolved);
+        thread::sleep(Duration::from_millis(30));
+        flaps.observe("b", Status::Firing);
+        let history = flaps.history.lock().recover();
+        assert!(!history.contains_key("a"));
+        assert_eq!(history.len(), 1);
+    }
+}
@@ -0,0 +1,94 @@
+//! Helpers named and behaving like the functions of Alertmanager's
+//! templates, to ease porting them. Go's `{{ .X | toUpper }}` becomes
+//! `{{ x | toUpper }}` and `{{ reReplaceAll "a" "b" .X }}` becomes
+//! `{{ x | reReplaceAll("a", "b") }}`.
+
+use minijinja::Environment;
+use minijinja::Error;
+use minijinja::ErrorKind;
+use minijinja::Value;
+use regex::Regex;
+
+fn invalid(e: impl ToString) -> Error {
+    Error::new(ErrorKind::InvalidOperation, e.to_string())
+}
+
+/// Formats seconds like Prometheus' `humanizeDuration`: "1d 2h 3m 4s".
+fn humanize_duration(seconds: f64) -> String {
+    if seconds == 0.0 || !seconds.is_finite() {
+        return format!("{}s", seconds);
+    }
+    let sign = if seconds < 0.0 { "-" } else { "" };
+    let v = seconds.abs();
+    if v >= 1.0 {
+        let s = v as u64;
+        let (days, hours) = (s / 86400, s / 3600 % 24);
+        let (minutes, secs) = (s / 60 % 60, s % 60);
+        return match (days, hours, minutes) {
+            (0, 0, 0) => format!("{}{}s", sign, (v * 1e3).round() / 1e3),
+            (0, 0, _) => format!("{}{}m {}s", sign, minutes, secs),
+            (0, _, _) => format!("{}{}h {}m {}s", sign, hours, minutes, secs),
+            _ => format!("{}{}d {}h {}m {}s", sign, days, hours, minutes, secs),
+        };
+    }
+    let (value, unit) = if v >= 1e-3 {
+        (v * 1e3, "ms")
+    } else if v >= 1e-6 {
+        (v * 1e6, "us")
+    } else {
+        (v * 1e9, "ns")
+    };
+    format!("{}{}{}", sign, (value * 1e3).round() / 1e3, unit)
+}
+
+/// The name and value pairs of a map sorted by name, like `SortedPairs`.
+fn sorted_pairs(labels: Value) -> Result<Vec<(String, Value)>, Error> {
+    let mut pairs = Vec::new();
+    for key in labels.try_iter()? {
+        let value = labels.get_item(&key)?;
+        pairs.push((key.to_string(), value));
+    }
+    pairs.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(pairs)
+}
+
+pub fn register(env: &mut Environment) {
+    env.add_filter("toUpper", |s: String| s.to_uppercase());
+    env.add_filter("toLower", |s: String| s.to_lowercase());
+    env.add_filter("trimSpace", |s: String| s.trim().to_string());
+    env.add_filter("title", |s: String| {
+        s.split(' ')
+            .map(|word| {
+                let mut chars = word.chars();
+                match chars.next() {
+                    Some(first) => first.to_uppercase().chain(chars).collect(),
+                    None => String::new(),
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(" ")
+    });
+    env.add_filter(
+        "reReplaceAll",
+        |s: String, pattern: String, replacement: String| {
+            let re = Regex::new(&pattern).map_err(invalid)?;
+            Ok::<_, Error>(
+                re.replace_all(&s, replacement.as_str()).into_owned(),
+            )
+        },
+    );
+    env.add_function("match", |pattern: String, s: String| {
+        let re = Regex::new(&pattern).map_err(invalid)?;
+        Ok::<_, Error>(re.is_match(&s))
+    });
+    env.add_filter("humanizeDuration", humanize_duration);
+    env.add_filter("sortedPairs", sorted_pairs);
+    env.add_filter("names", |labels: Value| {
+        sorted_pairs(labels)
+            .map(|p| p.into_iter().map(|(name, _)| name).collect::<Vec<_>>())
+    });
+    env.add_filter("values", |labels: Value| {
+        sorted_pairs(labels)
+            .map(|p| p.into_iter().map(|(_, value)| value).collect::<Vec<_>>())
+    });
+}
@@ -0,0 +1,214 @@
+use crate::alert::Alert;
+use crate::alert::AlertGroup;
+use crate::alert::Annotations;
+use crate::alert::Status;
+use crate::log;
+use crate::state::Store;
+use crate::sync::Recover;
+use crate::App;
+use chrono::DateTime;
+use chrono::Utc;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+/// A service expected to request `/heartbeat/<name>` at least every
+/// `interval`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct HeartbeatConfig {
+    pub name: String,
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+    /// Added to the labels of the alert, to route or set its severity.
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+}
+
+struct Beat {
+    last: Instant,
+    /// Start of the missing heartbeat alert, if it is firing.
+    missing_since: Option<DateTime<Utc>>,
+}
+
+pub struct Heartbeats {
+    configs: Vec<HeartbeatConfig>,
+    beats: Mutex<HashMap<String, Beat>>,
+}
+
+impl Heartbeats {
+    pub fn new(configs: Vec<HeartbeatConfig>) -> Heartbeats {
+        // Every heartbeat gets a full interval after startup.
+        let beats = configs
+            .iter()
+            .map(|c| {
+                let beat = Beat {
+                    last: Instant::now(),
+                    missing_since: None,
+                };
+                (c.name.clone(), beat)
+            })
+            .collect();
+        Heartbeats {
+            configs,
+            beats: Mutex::new(beats),
+        }
+    }
+
+    /// Takes the missing heartbeat alerts notified before a restart back,
+    /// returning those of heartbeats no longer configured to resolve.
+    fn restore(&self, store: &Store) -> Vec<Alert> {
+        let mut beats = self.beats.lock().recover();
+        let mut gone = Vec::new();
+        for (fingerprint, alert) in store.firing() {
+            let Some(name) = fingerprint.strip_prefix("heartbeat/") else {
+                continue;
+            };
+            match beats.get_mut(name) {
+                Some(beat) => beat.missing_since = Some(alert.starts_at),
+                None => gone.push(alert.resolved(&fingerprint, Utc::now())),
+            }
+        }
+        gone
+    }
+
+    fn config(&self, name: &str) -> Option<&HeartbeatConfig> {
+        self.configs.iter().find(|c| c.name == name)
+    }
+
+    /// Records a ping, returning the alert to resolve if the heartbeat
+    /// was missing, or `None` if no such heartbeat is configured.
+    pub fn ping(&self, name: &str) -> Option<Option<Alert>> {
+        let config = self.config(name)?;
+        let mut beats = self.beats.lock().recover();
+        let beat = beats.get_mut(name)?;
+        beat.last = Instant::now();
+        Some(
+            beat.missing_since
+                .take()
+                .map(|since| alert(config, Status::Resolved, since)),
+        )
+    }
+
+    /// The alerts of the heartbeats that just went silent.
+    fn missing(&self) -> Vec<Alert> {
+        let mut beats = self.beats.lock().recover();
+        let mut alerts = Vec::new();
+        for config in &self.configs {
+            let Some(beat) = beats.get_mut(&config.name) else {
+                continue;
+            };
+            if beat.missing_since.is_none()
+                && beat.last.elapsed() > config.interval
+            {
+                let since = Utc::now();
+                beat.missing_since = Some(since);
+                alerts.push(alert(config, Status::Firing, since));
+            }
+        }
+        alerts
+    }
+}
+
+fn alert(
+    config: &HeartbeatConfig,
+    status: Status,
+    since: DateTime<Utc>,
+) -> Alert {
+    let mut labels = config.labels.clone();
+    labels.insert(String::from("alertname"), String::from("HeartbeatMissing"));
+    labels.insert(String::from("heartbeat"), config.name.clone());
+    labels
+        .entry(String::from("instance"))
+        .or_insert_with(|| config.name.clone());
+    let ends_at = match status {
+        Status::Firing => DateTime::default(),
+        Status::Resolved => Utc::now(),
+    };
+    Alert {
+        status,
+        labels,
+        annotations: Some(Annotations {
+            summary: format!(
+                "No heartbeat from {} for {}",
+                config.name,
+                humantime::format_duration(config.interval)
+            ),
+            description: None,
+        }),
+        starts_at: since,
+        ends_at,
+        fingerprint: format!("heartbeat/{}", config.name),
+        values: None,
+        value_string: None,
+        ticket: None,
+        silences: None,
+        probe: None,
+        host: None,
+        suppressed: false,
+    }
+}
+
+pub fn notify(app: &App, alerts: Vec<Alert>) {
+    for group in AlertGroup::by_alertname(String::new(), String::new(), alerts)
+    {
+        if let Err(e) = crate::forward_alert(app, group) {
+            log::error(format!("cannot notify heartbeat: {:#}", e));
+        }
+    }
+}
+
+pub fn start(app: Arc<App>) {
+    let gone = app.heartbeats.restore(&app.store);
+    if !gone.is_empty() {
+        notify(&app, gone);
+    }
+    if app.heartbeats.configs.is_empty() {
+        return;
+    }
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs(1));
+        let missing = app.heartbeats.missing();
+        if !missing.is_empty() {
+            notify(&app, missing);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::HistoryPolicy;
+
+    fn config(name: &str) -> HeartbeatConfig {
+        HeartbeatConfig {
+            name: name.to_string(),
+            interval: Duration::from_secs(60),
+            labels: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn resolves_alerts_notified_before_a_restart() {
+        let store = Store::open(None, HistoryPolicy::default()).unwrap();
+        let since = Utc::now() - chrono::TimeDelta::hours(1);
+        let alerts = [
+            alert(&config("backup"), Status::Firing, since),
+            alert(&config("removed"), Status::Firing, since),
+        ];
+        store.observe("", &alerts);
+        let heartbeats = Heartbeats::new(vec![config("backup")]);
+        let gone = heartbeats.restore(&store);
+        assert_eq!(gone.len(), 1);
+        assert_eq!(gone[0].fingerprint, "heartbeat/removed");
+        assert_eq!(gone[0].status, Status::Resolved);
+        let resolved = heartbeats.ping("backup").unwrap().unwrap();
+        assert_eq!(resolved.status, Status::Resolved);
+        assert_eq!(resolved.starts_at, since);
+        assert!(heartbeats.missing().is_empty());
+    }
+}
@@ -0,0 +1,266 @@
+use crate::admin;
+use crate::state::ResolvedAlert;
+use crate::App;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use chrono::DateTime;
+use chrono::NaiveDate;
+use chrono::TimeDelta;
+use chrono::Utc;
+use rouille::Request;
+use rouille::Response;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Keeps resolved alerts in the state store for the `/api/history`
+/// endpoints.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct HistoryConfig {
+    #[serde(with = "humantime_serde")]
+    pub retention: Duration,
+}
+
+/// An alert of the history as exported.
+#[derive(Serialize)]
+struct Row<'a> {
+    fingerprint: &'a str,
+    alertname: &'a str,
+    receiver: &'a str,
+    severity: String,
+    starts_at: String,
+    ends_at: String,
+    duration_seconds: i64,
+    summary: &'a str,
+    labels: String,
+}
+
+impl Row<'_> {
+    fn new<'a>(app: &App, alert: &'a ResolvedAlert) -> Row<'a> {
+        let mut labels: Vec<_> = alert
+            .labels
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect();
+        labels.sort();
+        Row {
+            fingerprint: &alert.fingerprint,
+            alertname: alert.labels.get("alertname").map_or("", |a| a),
+            receiver: &alert.receiver,
+            severity: app.config.severity.display(&alert.labels),
+            starts_at: alert.starts_at.to_rfc3339(),
+            ends_at: alert.ends_at.to_rfc3339(),
+            duration_seconds: (alert.ends_at - alert.starts_at).num_seconds(),
+            summary: alert.annotations.as_ref().map_or("", |a| &a.summary),
+            labels: labels.join(","),
+        }
+    }
+
+    const HEADER: &'static str = "fingerprint,alertname,receiver,severity,\
+        starts_at,ends_at,duration_seconds,summary,labels";
+
+    fn csv(&self) -> String {
+        let duration = self.duration_seconds.to_string();
+        [
+            self.fingerprint,
+            self.alertname,
+            self.receiver,
+            &self.severity,
+            &self.starts_at,
+            &self.ends_at,
+            &duration,
+            self.summary,
+            &self.labels,
+        ]
+        .iter()
+        .map(|field| csv_field(field))
+        .collect::<Vec<_>>()
+        .join(",")
+    }
+}
+
+/// Quotes the field if it holds a separator, a quote or a line break, and
+/// keeps spreadsheets from taking it for a formula.
+fn csv_field(field: &str) -> String {
+    let field = match field.starts_with(['=', '+', '-', '@']) {
+        true => format!("'{}", field),
+        false => field.to_string(),
+    };
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+/// Parses RFC 3339 times and `YYYY-MM-DD` dates, taken at midnight UTC.
+pub fn parse_time(value: &str) -> Result<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    match NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        Ok(date) => Ok(date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc()),
+        Err(_) => bail!("invalid time {}", value),
+    }
+}
+
+/// The alerts of the history that were firing at some point between the
+/// `from` and `to` query parameters, both optional, redacted.
+fn range(app: &App, request: &Request) -> Result<Vec<ResolvedAlert>> {
+    let from = request
+        .get_param("from")
+        .map(|f| parse_time(&f))
+        .transpose()?;
+    let to = request
+        .get_param("to")
+        .map(|t| parse_time(&t))
+        .transpose()?;
+    let mut alerts = app.store.history_between(from, to);
+    for alert in &mut alerts {
+        app.redactor.redact_resolved(alert);
+    }
+    Ok(alerts)
+}
+
+/// `GET /api/history/export?from=…&to=…&format=csv|json`.
+pub fn export(app: &App, request: &Request) -> Response {
+    if let Some(response) = admin::check(app, request) {
+        return response;
+    }
+    let alerts = match range(app, request) {
+        Ok(alerts) => alerts,
+        Err(e) => return Response::text(e.to_string()).with_status_code(400),
+    };
+    let rows: Vec<Row> = alerts.iter().map(|a| Row::new(app, a)).collect();
+    match request.get_param("format").as_deref() {
+        None | Some("json") => Response::json(&rows),
+        Some("csv") => {
+            let mut csv = String::from(Row::HEADER);
+            csv.push('\n');
+            for row in &rows {
+                csv.push_str(&row.csv());
+                csv.push('\n');
+            }
+            Response::from_data("text/csv; charset=utf-8", csv)
+                .with_content_disposition_attachment("alert-history.csv")
+        }
+        Some(format) => Response::text(format!("unknown format {}", format))
+            .with_status_code(400),
+    }
+}
+
+/// A firing alert counts as a flap when it fired again within that long
+/// of resolving, unless `flapping.window` is configured.
+const FLAP_WINDOW: Duration = Duration::from_secs(600);
+
+/// The statistics of the alerts sharing the value of a label.
+#[derive(Serialize, Default)]
+struct Stats {
+    value: String,
+    count: usize,
+    /// The mean time to resolve.
+    mttr_seconds: i64,
+    flaps: usize,
+}
+
+#[derive(Serialize)]
+struct StatsResponse {
+    group_by: String,
+    from: String,
+    to: String,
+    groups: Vec<Stats>,
+}
+
+/// The statistics of the alerts that resolved within `range` of now, by
+/// value of the `group_by` label.
+fn stats_of(
+    history: &[ResolvedAlert],
+    group_by: &str,
+    range: Duration,
+    flap_window: Duration,
+) -> Result<StatsResponse> {
+    let to = Utc::now();
+    let from = TimeDelta::from_std(range)
+        .ok()
+        .and_then(|range| to.checked_sub_signed(range))
+        .context("range too long")?;
+    let flap_window = TimeDelta::from_std(flap_window).unwrap_or_default();
+    let mut groups: HashMap<&str, (Stats, i64)> = HashMap::new();
+    // When each alert last resolved, the history being oldest first.
+    let mut resolved: HashMap<&str, DateTime<Utc>> = HashMap::new();
+    for alert in history {
+        let flapped = resolved
+            .insert(&alert.fingerprint, alert.ends_at)
+            .is_some_and(|at| alert.starts_at - at <= flap_window);
+        if alert.ends_at < from {
+            continue;
+        }
+        let value = alert.labels.get(group_by).map_or("", |v| v);
+        let (stats, total) = groups.entry(value).or_default();
+        stats.count += 1;
+        stats.flaps += usize::from(flapped);
+        *total += (alert.ends_at - alert.starts_at).num_seconds();
+    }
+    let mut groups: Vec<Stats> = groups
+        .into_iter()
+        .map(|(value, (stats, total))| Stats {
+            value: value.to_string(),
+            mttr_seconds: total / stats.count as i64,
+            ..stats
+        })
+        .collect();
+    groups.sort_by(|a, b| b.count.cmp(&a.count).then(a.value.cmp(&b.value)));
+    Ok(StatsResponse {
+        group_by: group_by.to_string(),
+        from: from.to_rfc3339(),
+        to: to.to_rfc3339(),
+        groups,
+    })
+}
+
+/// `GET /api/stats?group_by=…&range=…`, by `alertname` over 7 days by
+/// default.
+pub fn stats(app: &App, request: &Request) -> Response {
+    if let Some(response) = admin::check(app, request) {
+        return response;
+    }
+    let group_by = request
+        .get_param("group_by")
+        .unwrap_or("alertname".to_string());
+    let range = match request.get_param("range") {
+        None => Ok(Duration::from_secs(7 * 86400)),
+        Some(range) => humantime::parse_duration(&range)
+            .with_context(|| format!("invalid range {}", range)),
+    };
+    let flap_window = app.flaps.as_ref().map_or(FLAP_WINDOW, |f| f.window());
+    let mut history = app.store.history();
+    for alert in &mut history {
+        app.redactor.redact_resolved(alert);
+    }
+    match range.and_then(|r| stats_of(&history, &group_by, r, flap_window)) {
+        Ok(stats) => Response::json(&stats),
+        Err(e) => Response::text(format!("{:#}", e)).with_status_code(400),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_csv_fields() {
+        assert_eq!(csv_field("disk full"), "disk full");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(
+            csv_field("=HYPERLINK(\"x\")"),
+            "\"'=HYPERLINK(\"\"x\"\")\""
+        );
+        assert_eq!(csv_field("+1"), "'+1");
+        assert_eq!(csv_field("-1"), "'-1");
+        assert_eq!(csv_field("@SUM(A1)"), "'@SUM(A1)");
+    }
+}
@@ -0,0 +1,374 @@
+use crate::alert::AlertGroup;
+use crate::log;
+use crate::sync::Recover;
+use crate::App;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use minijinja::Environment;
+use minijinja::Value;
+use reqwest::blocking::Client;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::mem;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::sync::mpsc;
+use std::sync::mpsc::SyncSender;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+/// What is looked up about the IP addresses alerts have as instance.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct HostConfig {
+    /// Shows the host name of the address instead.
+    #[serde(default)]
+    pub reverse_dns: bool,
+    pub geoip: Option<GeoIpConfig>,
+    /// How long lookups, failed ones included, are remembered.
+    #[serde(with = "humantime_serde", default = "default_ttl")]
+    pub ttl: Duration,
+}
+
+fn default_ttl() -> Duration {
+    Duration::from_secs(3600)
+}
+
+/// A GeoIP service answering JSON about an address.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct GeoIpConfig {
+    /// `{ip}` is replaced by the address.
+    pub url: String,
+    /// minijinja template of the location, seeing the answer's fields.
+    pub text: String,
+}
+
+impl HostConfig {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(geoip) = &self.geoip {
+            if !geoip.url.contains("{ip}") {
+                bail!("geoip.url has no {{ip}}");
+            }
+            Environment::new()
+                .template_from_str(&geoip.text)
+                .context("geoip.text")?;
+        }
+        Ok(())
+    }
+}
+
+/// What was found about an address.
+#[derive(Debug, Clone)]
+pub struct Host {
+    pub ip: IpAddr,
+    pub name: Option<String>,
+    pub location: Option<String>,
+}
+
+impl Host {
+    /// The instance with its address replaced by the host name.
+    pub fn display(&self, instance: &str) -> String {
+        match &self.name {
+            Some(name) => instance.replacen(&self.ip.to_string(), name, 1),
+            None => instance.to_string(),
+        }
+    }
+}
+
+/// Addresses waiting to be looked up, at most.
+const CAPACITY: usize = 256;
+
+/// The lookups of the last `ttl`, None while one is under way.
+type Cache = HashMap<IpAddr, (Instant, Option<Host>)>;
+
+/// What was found about addresses, looked up on a thread so that
+/// notifications only ever read what is known already.
+#[derive(Default)]
+pub struct Hosts {
+    cache: Arc<Mutex<Cache>>,
+    sender: Option<SyncSender<IpAddr>>,
+    ttl: Duration,
+}
+
+/// The address of an instance like `10.0.0.1:9100`, if it is one.
+fn address(instance: &str) -> Option<IpAddr> {
+    instance
+        .parse::<IpAddr>()
+        .ok()
+        .or_else(|| instance.parse::<SocketAddr>().ok().map(|a| a.ip()))
+}
+
+/// Whether GeoIP can know about the address: not a private, shared, link
+/// local or documentation one.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || (a == 100 && b & 0xc0 == 64)
+                || matches!(
+                    (a, b, c),
+                    (192, 0, 2) | (198, 51, 100) | (203, 0, 113)
+                ))
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // Unique local fc00::/7 and link local fe80::/10.
+                || segments[0] & 0xfe00 == 0xfc00
+                || segments[0] & 0xffc0 == 0xfe80
+                || (segments[0], segments[1]) == (0x2001, 0xdb8))
+        }
+    }
+}
+
+/// Whether a name found by reverse DNS is a host name, letters, digits,
+/// hyphens and underscores in labels of 63 bytes at most, and nothing
+/// that would be read as markdown.
+fn is_host_name(name: &str) -> bool {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    !name.is_empty()
+        && name.len() <= 253
+        && name.split('.').all(|label| {
+            (1..=63).contains(&label.len())
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.bytes().all(|b| {
+                    b.is_ascii_alphanumeric() || b == b'-' || b == b'_'
+                })
+        })
+}
+
+/// Room for the longest name `getnameinfo` returns, NI_MAXHOST with glibc.
+const NAME_LENGTH: usize = 1025;
+
+/// Asks the resolver of the system for the name of the address, as
+/// configured by `/etc/nsswitch.conf`.
+fn reverse_dns(ip: IpAddr) -> Option<String> {
+    let mut name = [0 as libc::c_char; NAME_LENGTH];
+    let mut lookup = |address: *const libc::sockaddr, length: usize| unsafe {
+        libc::getnameinfo(
+            address,
+            length as libc::socklen_t,
+            name.as_mut_ptr(),
+            NAME_LENGTH as libc::socklen_t,
+            std::ptr::null_mut(),
+            0,
+            libc::NI_NAMEREQD,
+        )
+    };
+    let result = match ip {
+        IpAddr::V4(ip) => {
+            // Zeroed for the fields some systems have beyond these.
+            let mut address: libc::sockaddr_in = unsafe { mem::zeroed() };
+            address.sin_family = libc::AF_INET as libc::sa_family_t;
+            address.sin_addr.s_addr = u32::from_ne_bytes(ip.octets());
+            lookup(
+                &address as *const _ as *const libc::sockaddr,
+                mem::size_of_val(&address),
+            )
+        }
+        IpAddr::V6(ip) => {
+            let mut address: libc::sockaddr_in6 = unsafe { mem::zeroed() };
+            address.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            address.sin6_addr.s6_addr = ip.octets();
+            lookup(
+                &address as *const _ as *const libc::sockaddr,
+                mem::size_of_val(&address),
+            )
+        }
+    };
+    if result != 0 {
+        if result != libc::EAI_NONAME {
+            let error = unsafe { CStr::from_ptr(libc::gai_strerror(result)) };
+            log::warning(format!(
+                "cannot look up {}: {}",
+                ip,
+                error.to_string_lossy()
+            ));
+        }
+        return None;
+    }
+    let name = unsafe { CStr::from_ptr(name.as_ptr()) }.to_str().ok()?;
+    if !is_host_name(name) {
+        log::warning(format!("{} has an invalid host name, ignored", ip));
+        return None;
+    }
+    Some(name.trim_end_matches('.').to_string())
+}
+
+fn to_value(value: serde_json::Value) -> Value {
+    match value {
+        serde_json::Value::Null => Value::from(()),
+        serde_json::Value::Bool(b) => Value::from(b),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(n) => Value::from(n),
+            None => Value::from(n.as_f64().unwrap_or_default()),
+        },
+        serde_json::Value::String(s) => Value::from(s),
+        serde_json::Value::Array(items) => {
+            Value::from(items.into_iter().map(to_value).collect::<Vec<_>>())
+        }
+        serde_json::Value::Object(fields) => Value::from(
+            fields
+                .into_iter()
+                .map(|(k, v)| (k, to_value(v)))
+                .collect::<BTreeMap<_, _>>(),
+        ),
+    }
+}
+
+fn geoip(client: &Client, config: &GeoIpConfig, ip: IpAddr) -> Result<String> {
+    let url = config.url.replace("{ip}", &ip.to_string());
+    let answer: serde_json::Value = client
+        .get(&url)
+        .send()
+        .and_then(|r| r.error_for_status())
+        .and_then(|r| r.json())
+        .with_context(|| format!("cannot query {}", url))?;
+    let text = Environment::new().render_str(&config.text, to_value(answer))?;
+    Ok(text.trim().to_string())
+}
+
+fn lookup(client: &Client, config: &HostConfig, ip: IpAddr) -> Host {
+    let name = if config.reverse_dns {
+        reverse_dns(ip)
+    } else {
+        None
+    };
+    let location = match &config.geoip {
+        Some(geoip) if is_public(ip) => match self::geoip(client, geoip, ip) {
+            Ok(location) => Some(location).filter(|l| !l.is_empty()),
+            Err(e) => {
+                log::warning(format!("{:#}", e));
+                None
+            }
+        },
+        _ => None,
+    };
+    Host { ip, name, location }
+}
+
+impl Hosts {
+    /// Starts the thread looking addresses up, if enabled.
+    pub fn start(config: Option<HostConfig>, client: Client) -> Hosts {
+        let Some(config) = config else {
+            return Hosts::default();
+        };
+        let cache: Arc<Mutex<Cache>> = Arc::default();
+        let (sender, receiver) = mpsc::sync_channel::<IpAddr>(CAPACITY);
+        let hosts = Hosts {
+            cache: cache.clone(),
+            sender: Some(sender),
+            ttl: config.ttl,
+        };
+        thread::spawn(move || {
+            for ip in receiver {
+                let host = lookup(&client, &config, ip);
+                let now = Instant::now();
+                let mut cache = cache.lock().recover();
+                cache.retain(|_, (at, _)| now - *at < config.ttl);
+                cache.insert(ip, (now, Some(host)));
+            }
+        });
+        hosts
+    }
+
+    /// What is known about the address, having it looked up when nothing
+    /// is, for the next notifications.
+    fn get(&self, ip: IpAddr) -> Option<Host> {
+        let sender = self.sender.as_ref()?;
+        let mut cache = self.cache.lock().recover();
+        if let Some((at, host)) = cache.get(&ip) {
+            if at.elapsed() < self.ttl {
+                return host.clone();
+            }
+        }
+        if sender.try_send(ip).is_ok() {
+            cache.insert(ip, (Instant::now(), None));
+        }
+        None
+    }
+}
+
+/// Notes what is known about the addresses the group's alerts are about.
+pub fn attach(app: &App, group: &mut AlertGroup) {
+    for alert in &mut group.alerts {
+        let ip = alert.labels.get("instance").and_then(|i| address(i));
+        if let Some(ip) = ip {
+            alert.host = app.hosts.get(ip);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_what_geoip_knows_about() {
+        let public = ["8.8.8.8", "100.128.0.1", "2606:4700::1111"];
+        let local = [
+            "10.1.2.3",
+            "100.64.0.1",
+            "100.127.255.255",
+            "169.254.1.1",
+            "192.0.2.10",
+            "198.51.100.1",
+            "203.0.113.7",
+            "fd00::1",
+            "fe80::1",
+            "2001:db8::1",
+        ];
+        for ip in public {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in local {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn accepts_host_names_only() {
+        for name in ["host", "db-1.example.com.", "_srv.example", "a.b9"] {
+            assert!(is_host_name(name), "{}", name);
+        }
+        let invalid = [
+            "",
+            ".",
+            "a..b",
+            "-a.example",
+            "[a](https://evil.example)",
+            "a*b",
+            "host name",
+            "hôte.example",
+        ];
+        for name in invalid {
+            assert!(!is_host_name(name), "{}", name);
+        }
+        assert!(!is_host_name(&"a".repeat(64)));
+        assert!(!is_host_name(&["a"; 128].join(".")));
+    }
+
+    #[test]
+    fn looks_loopback_up() {
+        // Whatever the resolver finds, never a number.
+        if let Some(name) = reverse_dns("127.0.0.1".parse().unwrap()) {
+            assert!(is_host_name(&name), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use crate::alert::Status;
+use anyhow::anyhow;
+use anyhow::Result;
+use chrono::format::Item;
+use chrono::format::StrftimeItems;
+use chrono::DateTime;
+use chrono::Utc;
+use chrono_tz::Tz;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+pub const DEFAULT_LOCALE: &str = "en";
+
+/// How a count selects between the `one` and `other` forms of a message.
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum PluralRule {
+    /// Only 1 is singular (English, German, ...).
+    One,
+    /// 0 and 1 are singular (French, ...).
+    ZeroOne,
+    /// No plural forms, `other` is always used (Japanese, Chinese, ...).
+    None,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Message {
+    Text(String),
+    Plural {
+        zero: Option<String>,
+        one: String,
+        other: String,
+    },
+}
+
+/// One entry of the message catalog. Entries given in the config are
+/// merged over the built-in ones, so only the differences need to be set.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Locale {
+    pub firing: Option<String>,
+    pub resolved: Option<String>,
+    /// strftime-style format used by the `datetime` filter.
+    pub date_format: Option<String>,
+    /// POSIX locale used for month and day names, e.g. `de_DE`.
+    pub date_locale: Option<String>,
+    pub plural_rule: Option<PluralRule>,
+    pub messages: HashMap<String, Message>,
+}
+
+impl Locale {
+    fn merge(&mut self, other: &Locale) {
+        if other.firing.is_some() {
+            self.firing = other.firing.clone();
+        }
+        if other.resolved.is_some() {
+            self.resolved = other.resolved.clone();
+        }
+        if other.date_format.is_some() {
+            self.date_format = other.date_format.clone();
+        }
+        if other.date_locale.is_some() {
+            self.date_locale = other.date_locale.clone();
+        }
+        if other.plural_rule.is_some() {
+            self.plural_rule = other.plural_rule;
+        }
+        for (key, message) in &other.messages {
+            self.messages.insert(key.clone(), message.clone());
+        }
+    }
+}
+
+fn builtin(
+    firing: &str,
+    resolved: &str,
+    date_format: &str,
+    date_locale: &str,
+    plural_rule: PluralRule,
+    alerts: (&str, &str),
+    alert_on: &str,
+) -> Locale {
+    let mut messages = HashMap::new();
+    // The name of the fields of flapping notices and reminders.
+    messages.insert(
+        String::from("alert_on"),
+        Message::Text(alert_on.to_string()),
+    );
+    messages.insert(
+        String::from("alerts"),
+        Message::Plural {
+            zero: None,
+            one: alerts.0.to_string(),
+            other: alerts.1.to_string(),
+        },
+    );
+    Locale {
+        firing: Some(firing.to_string()),
+        resolved: Some(resolved.to_string()),
+        date_format: Some(date_format.to_string()),
+        date_locale: Some(date_locale.to_string()),
+        plural_rule: Some(plural_rule),
+        messages,
+    }
+}
+
+pub struct Catalog {
+    locales: HashMap<String, Locale>,
+}
+
+impl Catalog {
+    pub fn new(overrides: &HashMap<String, Locale>) -> Result<Catalog> {
+        let mut locales = HashMap::new();
+        locales.insert(
+            String::from("en"),
+            builtin(
+                "Firing",
+                "Resolved",
+                "%Y-%m-%d %H:%M:%S %Z",
+                "en_US",
+                PluralRule::One,
+                ("{{ count }} alert", "{{ count }} alerts"),
+                "{{ alertname }} on {{ instance }}",
+            ),
+        );
+        locales.insert(
+            String::from("de"),
+            builtin(
+                "Ausgelöst",
+                "Behoben",
+                "%d.%m.%Y %H:%M:%S %Z",
+                "de_DE",
+                PluralRule::One,
+                ("{{ count }} Alarm", "{{ count }} Alarme"),
+                "{{ alertname }} auf {{ instance }}",
+            ),
+        );
+        locales.insert(
+            String::from("fr"),
+            builtin(
+                "En cours",
+                "Résolu",
+                "%d/%m/%Y %H:%M:%S %Z",
+                "fr_FR",
+                PluralRule::ZeroOne,
+                ("{{ count }} alerte", "{{ count }} alertes"),
+                "{{ alertname }} sur {{ instance }}",
+            ),
+        );
+
+        for (name, locale) in overrides {
+            locales.entry(name.clone()).or_default().merge(locale);
+        }
+        for (name, locale) in &locales {
+            if let Some(format) = &locale.date_format {
+                if StrftimeItems::new(format).any(|i| i == Item::Error) {
+                    return Err(anyhow!(
+                        "locale {}: invalid date_format {}",
+                        name,
+                        format
+                    ));
+                }
+            }
+            if let Some(date_locale) = &locale.date_locale {
+                chrono::Locale::try_from(date_locale.as_str()).map_err(
+                    |_| {
+                        anyhow!(
+                            "locale {}: unknown date_locale {}",
+                            name,
+                            date_locale
+                        )
+                    },
+                )?;
+            }
+        }
+        Ok(Catalog { locales })
+    }
+
+    pub fn contains(&self, locale: &str) -> bool {
+        self.locales.contains_key(locale)
+    }
+
+    fn lookup<T>(
+        &self,
+        locale: &str,
+        field: impl Fn(&Locale) -> Option<T>,
+    ) -> Option<T> {
+        self.locales
+            .get(locale)
+            .and_then(&field)
+            .or_else(|| self.locales.get(DEFAULT_LOCALE).and_then(&field))
+    }
+
+    pub fn status(&self, locale: &str, status: Status) -> String {
+        let word = match status {
+            Status::Firing => self.lookup(locale, |l| l.firing.clone()),
+            Status::Resolved => self.lookup(locale, |l| l.resolved.clone()),
+        };
+        word.unwrap_or_else(|| status.to_string())
+    }
+
+    /// Returns the message template for `key`, picking the plural form
+    /// matching `count` when one is given.
+    pub fn message(
+        &self,
+        locale: &str,
+        key: &str,
+        count: Option<i64>,
+    ) -> Option<String> {
+        let message = self.lookup(locale, |l| l.messages.get(key).cloned())?;
+        match message {
+            Message::Text(text) => Some(text),
+            Message::Plural { zero, one, other } => {
+                let count = count.unwrap_or(0);
+                let rule = self
+                    .lookup(locale, |l| l.plural_rule)
+                    .unwrap_or(PluralRule::One);
+                let singular = match rule {
+                    PluralRule::One => count == 1,
+                    PluralRule::ZeroOne => count == 0 || count == 1,
+                    PluralRule::None => false,
+                };
+                match zero {
+                    Some(zero) if count == 0 => Some(zero),
+                    _ if singular => Some(one),
+                    _ => Some(other),
+                }
+            }
+        }
+    }
+
+    pub fn format_datetime(
+        &self,
+        locale: &str,
+        timezone: Tz,
+        time: &DateTime<Utc>,
+    ) -> String {
+        let format = self
+            .lookup(locale, |l| l.date_format.clone())
+            .unwrap_or_else(|| String::from("%Y-%m-%d %H:%M:%S %Z"));
+        let date_locale = self
+            .lookup(locale, |l| l.date_locale.clone())
+            .and_then(|l| chrono::Locale::try_from(l.as_str()).ok())
+            .unwrap_or(chrono::Locale::en_US);
+        time.with_timezone(&timezone)
+            .format_localized(&format, date_locale)
+            .to_string()
+    }
+}
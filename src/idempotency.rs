@@ -0,0 +1,171 @@
+use crate::alert::fnv1a;
+use crate::alert::AlertGroup;
+use crate::state::Store;
+use serde::Deserialize;
+use std::time::Duration;
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct IdempotencyConfig {
+    /// How long handled notifications are remembered: longer than
+    /// Alertmanager takes to retry, shorter than its `repeat_interval`.
+    #[serde(with = "humantime_serde")]
+    pub window: Duration,
+}
+
+/// Identifies a notification by its group key and a hash of its alerts,
+/// the same for Alertmanager's retries of it. Groups without a group key
+/// have none.
+pub fn key(group: &AlertGroup) -> Option<String> {
+    if group.group_key.is_empty() {
+        return None;
+    }
+    let mut alerts: Vec<String> = group
+        .alerts
+        .iter()
+        .map(|alert| {
+            format!(
+                "{}:{:?}:{}:{}",
+                alert.fingerprint,
+                alert.status,
+                alert.starts_at.timestamp(),
+                alert.ends_at.timestamp()
+            )
+        })
+        .collect();
+    alerts.sort();
+    let content = format!(
+        "{}\n{:?}\n{}",
+        group.receiver,
+        group.status,
+        alerts.join("\n")
+    );
+    Some(format!(
+        "{:016x}{:016x}",
+        fnv1a(group.group_key.bytes()),
+        fnv1a(content.bytes())
+    ))
+}
+
+/// The notifications a request claimed, released when dropped unless
+/// kept, so that Alertmanager's retry of a failed request goes through.
+pub struct Claims<'a> {
+    store: &'a Store,
+    window: Option<Duration>,
+    keys: Vec<String>,
+    /// Whether the last group claimed has its key last in `keys`.
+    keyed: bool,
+}
+
+impl<'a> Claims<'a> {
+    pub fn new(store: &'a Store, config: Option<&IdempotencyConfig>) -> Self {
+        Claims {
+            store,
+            window: config.map(|c| c.window),
+            keys: Vec::new(),
+            keyed: false,
+        }
+    }
+
+    /// Whether the group is to be handled, not having been already.
+    pub fn claim(&mut self, group: &AlertGroup) -> bool {
+        self.keyed = false;
+        let (Some(window), Some(key)) = (self.window, key(group)) else {
+            return true;
+        };
+        if !self.store.claim(&key, window) {
+            return false;
+        }
+        self.keys.push(key);
+        self.keyed = true;
+        true
+    }
+
+    /// Releases the claim of the last group claimed, which could not be
+    /// handled, keeping those of the groups handled before it.
+    pub fn release_last(&mut self) {
+        if !std::mem::take(&mut self.keyed) {
+            return;
+        }
+        if let Some(key) = self.keys.pop() {
+            self.store.release(&[key]);
+        }
+    }
+
+    pub fn keep(mut self) {
+        self.keys.clear();
+    }
+}
+
+impl Drop for Claims<'_> {
+    fn drop(&mut self) {
+        if !self.keys.is_empty() {
+            self.store.release(&self.keys);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::HistoryPolicy;
+    use crate::state::StoredAlert;
+    use chrono::Utc;
+    use std::collections::HashMap;
+
+    fn group(group_key: &str) -> AlertGroup {
+        let stored = StoredAlert {
+            receiver: String::new(),
+            labels: HashMap::from([("alertname".into(), "Down".into())]),
+            annotations: None,
+            starts_at: Utc::now(),
+        };
+        let mut group = AlertGroup::by_alertname(
+            String::from("team"),
+            String::new(),
+            vec![stored.firing("a")],
+        )
+        .remove(0);
+        group.group_key = group_key.to_string();
+        group
+    }
+
+    const CONFIG: IdempotencyConfig = IdempotencyConfig {
+        window: Duration::from_secs(60),
+    };
+
+    #[test]
+    fn claims_each_notification_once() {
+        let store = Store::open(None, HistoryPolicy::default()).unwrap();
+        let mut claims = Claims::new(&store, Some(&CONFIG));
+        assert!(claims.claim(&group("{}:{a}")));
+        assert!(!claims.claim(&group("{}:{a}")));
+        assert!(claims.claim(&group("{}:{b}")));
+        claims.keep();
+        let mut again = Claims::new(&store, Some(&CONFIG));
+        assert!(!again.claim(&group("{}:{a}")));
+        // Groups without a key are always handled.
+        assert!(again.claim(&group("")));
+        assert!(again.claim(&group("")));
+        assert_eq!(key(&group("")), None);
+    }
+
+    #[test]
+    fn releases_the_claims_of_failed_requests() {
+        let store = Store::open(None, HistoryPolicy::default()).unwrap();
+        {
+            let mut claims = Claims::new(&store, Some(&CONFIG));
+            assert!(claims.claim(&group("{}:{a}")));
+        }
+        let mut retry = Claims::new(&store, Some(&CONFIG));
+        assert!(retry.claim(&group("{}:{a}")));
+        retry.claim(&group("{}:{b}"));
+        retry.release_last();
+        retry.keep();
+        let mut again = Claims::new(&store, Some(&CONFIG));
+        assert!(!again.claim(&group("{}:{a}")));
+        assert!(again.claim(&group("{}:{b}")));
+        let mut disabled = Claims::new(&store, None);
+        assert!(disabled.claim(&group("{}:{a}")));
+    }
+}
@@ -0,0 +1,68 @@
+use crate::alert::AlertGroup;
+use crate::alert::Status;
+use crate::config::Route;
+use crate::severity::Severity;
+use crate::state::Store;
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Longest name Discord accepts for a thread.
+const NAME_LIMIT: usize = 100;
+
+/// Posts groups as severe as `severity` in a thread of their own, created
+/// in the forum channel of `webhook_url`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct IncidentThreads {
+    pub webhook_url: String,
+    /// Least severe level opening a thread.
+    pub severity: String,
+}
+
+/// The incident thread a message is posted in.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Thread {
+    /// Under which the ID of the thread is stored once created.
+    pub key: String,
+    /// Used when the thread is created.
+    pub name: String,
+    /// Set on the last message of a resolved incident, after which the
+    /// next notification of the group opens a new thread.
+    pub close: bool,
+}
+
+/// The key of the thread of the group on the route.
+pub fn key(route: &Route, group: &AlertGroup) -> String {
+    format!("{}\n{}", route.name, group.fingerprint())
+}
+
+/// Whether the group is posted in a thread: it either has one already or
+/// fires alerts severe enough to open one.
+pub fn applies(
+    threads: &IncidentThreads,
+    severity: &Severity,
+    store: &Store,
+    key: &str,
+    group: &AlertGroup,
+) -> bool {
+    if store.thread(key).is_some() {
+        return true;
+    }
+    let Some(least) = severity.position(&threads.severity) else {
+        return false;
+    };
+    group.alerts.iter().any(|a| {
+        a.status == Status::Firing && severity.rank(&a.labels) <= least
+    })
+}
+
+/// Cuts the rendered name to the length Discord allows.
+pub fn name(rendered: String) -> String {
+    let rendered = rendered.trim();
+    if rendered.chars().count() <= NAME_LIMIT {
+        return rendered.to_string();
+    }
+    let mut cut: String = rendered.chars().take(NAME_LIMIT - 1).collect();
+    cut.push('…');
+    cut
+}
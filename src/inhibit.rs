@@ -0,0 +1,197 @@
+use crate::alert::Alert;
+use crate::alert::Status;
+use crate::matcher::Matchers;
+use crate::sync::Recover;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Mutes firing alerts matching `target_matchers` while an alert matching
+/// `source_matchers` fires with the same values for the `equal` labels.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct InhibitRule {
+    pub source_matchers: Matchers,
+    pub target_matchers: Matchers,
+    pub equal: Vec<String>,
+}
+
+impl InhibitRule {
+    fn inhibits(
+        &self,
+        source: &HashMap<String, String>,
+        target: &HashMap<String, String>,
+    ) -> bool {
+        self.source_matchers.matches(source)
+            && self.target_matchers.matches(target)
+            && self.equal.iter().all(|l| source.get(l) == target.get(l))
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct InhibitConfig {
+    pub rules: Vec<InhibitRule>,
+    /// How long a firing source alert is remembered without being seen
+    /// again, in case its resolution never reaches the bridge.
+    #[serde(with = "humantime_serde")]
+    pub source_ttl: Duration,
+}
+
+impl Default for InhibitConfig {
+    fn default() -> Self {
+        InhibitConfig {
+            rules: Vec::new(),
+            source_ttl: Duration::from_secs(4 * 60 * 60),
+        }
+    }
+}
+
+struct Active {
+    labels: HashMap<String, String>,
+    last_seen: Instant,
+}
+
+/// Remembers the firing alerts of every group so that rules can match
+/// sources and targets sent in different notifications.
+pub struct Inhibitor {
+    config: InhibitConfig,
+    active: Mutex<HashMap<String, Active>>,
+}
+
+impl Inhibitor {
+    pub fn new(config: InhibitConfig) -> Inhibitor {
+        Inhibitor {
+            config,
+            active: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The rules and active sources that would mute an alert with these
+    /// labels.
+    pub fn inhibitors(
+        &self,
+        labels: &HashMap<String, String>,
+    ) -> Vec<(usize, HashMap<String, String>)> {
+        let active = self.active.lock().recover();
+        let mut inhibitors = Vec::new();
+        for (index, rule) in self.config.rules.iter().enumerate() {
+            for source in active.values() {
+                if rule.inhibits(&source.labels, labels) {
+                    inhibitors.push((index, source.labels.clone()));
+                }
+            }
+        }
+        inhibitors
+    }
+
+    pub fn filter(&self, alerts: Vec<Alert>) -> Vec<Alert> {
+        if self.config.rules.is_empty() {
+            return alerts;
+        }
+        let now = Instant::now();
+        let mut active = self.active.lock().recover();
+        active.retain(|_, a| now - a.last_seen < self.config.source_ttl);
+        for alert in &alerts {
+            match alert.status {
+                Status::Firing => {
+                    active.insert(
+                        alert.fingerprint.clone(),
+                        Active {
+                            labels: alert.labels.clone(),
+                            last_seen: now,
+                        },
+                    );
+                }
+                Status::Resolved => {
+                    active.remove(&alert.fingerprint);
+                }
+            }
+        }
+
+        alerts
+            .into_iter()
+            .filter(|alert| {
+                alert.status == Status::Resolved
+                    || !self.inhibits(&active, alert)
+            })
+            .collect()
+    }
+
+    /// Whether a firing alert seen lately inhibits the alert.
+    pub fn is_inhibited(&self, alert: &Alert) -> bool {
+        if self.config.rules.is_empty() {
+            return false;
+        }
+        let now = Instant::now();
+        let mut active = self.active.lock().recover();
+        active.retain(|_, a| now - a.last_seen < self.config.source_ttl);
+        self.inhibits(&active, alert)
+    }
+
+    fn inhibits(
+        &self,
+        active: &HashMap<String, Active>,
+        alert: &Alert,
+    ) -> bool {
+        active.iter().any(|(fingerprint, source)| {
+            fingerprint != &alert.fingerprint
+                && self
+                    .config
+                    .rules
+                    .iter()
+                    .any(|r| r.inhibits(&source.labels, &alert.labels))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::Matcher;
+    use crate::state::StoredAlert;
+    use chrono::Utc;
+
+    fn alert(fingerprint: &str, labels: &[(&str, &str)]) -> Alert {
+        StoredAlert {
+            receiver: String::new(),
+            labels: labels
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            annotations: None,
+            starts_at: Utc::now(),
+        }
+        .firing(fingerprint)
+    }
+
+    #[test]
+    fn inhibits_targets_of_firing_sources() {
+        let inhibitor = Inhibitor::new(InhibitConfig {
+            rules: vec![InhibitRule {
+                source_matchers: Matchers(vec![Matcher::parse(
+                    "severity=critical",
+                )
+                .unwrap()]),
+                target_matchers: Matchers(vec![Matcher::parse(
+                    "severity=warning",
+                )
+                .unwrap()]),
+                equal: vec![String::from("instance")],
+            }],
+            ..InhibitConfig::default()
+        });
+        let source = alert("s", &[("severity", "critical"), ("instance", "a")]);
+        let target = alert("t", &[("severity", "warning"), ("instance", "a")]);
+        let other = alert("o", &[("severity", "warning"), ("instance", "b")]);
+        assert!(!inhibitor.is_inhibited(&target));
+        let kept = inhibitor.filter(vec![source, target]);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].fingerprint, "s");
+        let target = alert("t", &[("severity", "warning"), ("instance", "a")]);
+        assert!(inhibitor.is_inhibited(&target));
+        assert!(!inhibitor.is_inhibited(&other));
+    }
+}
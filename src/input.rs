@@ -0,0 +1,683 @@
+use crate::alert::fingerprint;
+use crate::alert::Alert;
+use crate::alert::AlertGroup;
+use crate::alert::Status;
+use crate::prometheus::PostableAlert;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use chrono::DateTime;
+use chrono::Utc;
+use flate2::read::GzDecoder;
+use flate2::read::ZlibDecoder;
+use rouille::input::json::JsonError;
+use rouille::try_or_400::ErrJson;
+use rouille::Request;
+use rouille::Response;
+use serde::de;
+use serde::de::DeserializeOwned;
+use serde::de::DeserializeSeed;
+use serde::de::MapAccess;
+use serde::de::SeqAccess;
+use serde::de::Visitor;
+use serde::Deserialize;
+use serde::Deserializer;
+use serde_json::Map;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::BufReader;
+use std::io::Error;
+use std::io::Read;
+
+/// The error reading a body past `max_body_size` fails with.
+#[derive(Debug)]
+struct TooLarge(usize);
+
+impl fmt::Display for TooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "body larger than {} bytes", self.0)
+    }
+}
+
+impl std::error::Error for TooLarge {}
+
+/// Reads at most `left` bytes, failing when there are more.
+struct Limited<R> {
+    inner: R,
+    left: usize,
+    limit: usize,
+}
+
+impl<R: Read> Read for Limited<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.left == 0 {
+            return match self.inner.read(&mut [0])? {
+                0 => Ok(0),
+                _ => Err(Error::other(TooLarge(self.limit))),
+            };
+        }
+        let length = buf.len().min(self.left);
+        let read = self.inner.read(&mut buf[..length])?;
+        self.left -= read;
+        Ok(read)
+    }
+}
+
+/// Answers a body that could not be parsed like `try_or_400!`, or with a
+/// 413 when it was too large.
+pub fn error(e: JsonError) -> Response {
+    // Parse errors wrap the errors of the reader they come from.
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(&e);
+    while let Some(e) = source {
+        let io = e.downcast_ref::<Error>().and_then(|io| io.get_ref());
+        if let Some(too_large) = io.and_then(|e| e.downcast_ref::<TooLarge>()) {
+            return Response::text(too_large.to_string()).with_status_code(413);
+        }
+        source = e.source();
+    }
+    Response::json(&ErrJson::from_err(&e)).with_status_code(400)
+}
+
+/// Like `rouille::input::json_input`, but parses the `body` as it is read
+/// off the connection, also accepts bodies compressed with
+/// `Content-Encoding: gzip` or `deflate`, and fails with bodies over
+/// `limit` once decompressed, see `error`.
+pub fn json_input<O: DeserializeOwned>(
+    request: &Request,
+    body: &mut dyn Read,
+    limit: usize,
+) -> std::result::Result<O, JsonError> {
+    let body = reader(request, body, limit)?;
+    Ok(serde_json::from_reader(body)?)
+}
+
+/// The body, decompressed and limited, as `json_input` reads it.
+fn reader<'a>(
+    request: &Request,
+    body: &'a mut dyn Read,
+    limit: usize,
+) -> std::result::Result<impl Read + 'a, JsonError> {
+    match request.header("Content-Type") {
+        Some(header) if header.starts_with("application/json") => {}
+        _ => return Err(JsonError::WrongContentType),
+    }
+    let length = request
+        .header("Content-Length")
+        .and_then(|l| l.parse::<usize>().ok());
+    if length.is_some_and(|l| l > limit) {
+        return Err(JsonError::IoError(Error::other(TooLarge(limit))));
+    }
+
+    let encoding = request
+        .header("Content-Encoding")
+        .unwrap_or("identity")
+        .trim()
+        .to_ascii_lowercase();
+    let body: Box<dyn Read> = match encoding.as_str() {
+        "identity" | "" => Box::new(body),
+        "gzip" | "x-gzip" => Box::new(GzDecoder::new(body)),
+        "deflate" => Box::new(ZlibDecoder::new(body)),
+        other => {
+            return Err(JsonError::IoError(Error::other(format!(
+                "unsupported content encoding {}",
+                other
+            ))))
+        }
+    };
+    let body = Limited {
+        inner: body,
+        left: limit,
+        limit,
+    };
+    Ok(BufReader::new(body))
+}
+
+/// How payloads straying from the format of the senders are handled.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Parsing {
+    /// Rejects unknown and missing fields, to catch integration mistakes.
+    Strict,
+    /// Ignores unknown fields and fills in missing ones.
+    #[default]
+    Lenient,
+}
+
+/// The fields of an Alertmanager notification, all of them required.
+const GROUP_FIELDS: [&str; 10] = [
+    "version",
+    "groupKey",
+    "truncatedAlerts",
+    "status",
+    "receiver",
+    "groupLabels",
+    "commonLabels",
+    "commonAnnotations",
+    "externalURL",
+    "alerts",
+];
+const ALERT_FIELDS: [&str; 7] = [
+    "status",
+    "labels",
+    "annotations",
+    "startsAt",
+    "endsAt",
+    "generatorURL",
+    "fingerprint",
+];
+/// The fields Grafana adds to notifications, all of them required.
+const GRAFANA_GROUP_FIELDS: [&str; 4] = ["orgId", "title", "state", "message"];
+/// The fields Grafana adds to alerts, `values` and `valueString` being
+/// rendered.
+const GRAFANA_ALERT_FIELDS: [&str; 5] = [
+    "silenceURL",
+    "dashboardURL",
+    "panelURL",
+    "values",
+    "valueString",
+];
+/// The fields of an alert posted by Prometheus: only `labels` is required.
+const POSTABLE_FIELDS: [&str; 5] = [
+    "labels",
+    "annotations",
+    "startsAt",
+    "endsAt",
+    "generatorURL",
+];
+
+/// Checks that the object has the required fields and no others.
+fn check_fields(
+    value: &Value,
+    path: &str,
+    required: &[&str],
+    optional: &[&str],
+) -> Result<()> {
+    let Some(object) = value.as_object() else {
+        bail!("{}: not an object", path);
+    };
+    if let Some(name) = required.iter().find(|f| !object.contains_key(**f)) {
+        bail!("{}: missing field {}", path, name);
+    }
+    let known = |name: &String| {
+        required.contains(&name.as_str()) || optional.contains(&name.as_str())
+    };
+    if let Some(name) = object.keys().find(|name| !known(name)) {
+        bail!("{}: unknown field {}", path, name);
+    }
+    Ok(())
+}
+
+/// Turns the values of a label map into strings, creating it if missing.
+fn complete_labels(object: &mut Map<String, Value>, field: &str) {
+    let labels = object
+        .entry(field)
+        .or_insert_with(|| Value::Object(Map::new()));
+    if let Some(labels) = labels.as_object_mut() {
+        labels.retain(|_, value| !value.is_null());
+        for value in labels.values_mut() {
+            if !value.is_string() {
+                *value = Value::String(value.to_string());
+            }
+        }
+    }
+}
+
+/// Takes the description of annotations without a summary as the summary,
+/// dropping them when they have neither.
+fn complete_annotations(object: &mut Map<String, Value>, field: &str) {
+    let Some(annotations) = object.get_mut(field) else {
+        return;
+    };
+    let Some(map) = annotations.as_object_mut() else {
+        return;
+    };
+    if !map.get("summary").is_some_and(Value::is_string) {
+        match map.get("description").filter(|d| d.is_string()).cloned() {
+            Some(description) => {
+                map.insert(String::from("summary"), description);
+            }
+            None => *annotations = Value::Null,
+        }
+    }
+}
+
+fn complete_alert(alert: &mut Value) {
+    let Some(alert) = alert.as_object_mut() else {
+        return;
+    };
+    complete_labels(alert, "labels");
+    complete_annotations(alert, "annotations");
+    alert
+        .entry("startsAt")
+        .or_insert_with(|| Value::String(Utc::now().to_rfc3339()));
+    let ends_at = alert
+        .entry("endsAt")
+        .or_insert_with(|| Value::String(String::from("0001-01-01T00:00:00Z")));
+    let ended = ends_at
+        .as_str()
+        .and_then(|e| DateTime::parse_from_rfc3339(e).ok())
+        .is_some_and(|e| e.to_utc() > DateTime::UNIX_EPOCH && e <= Utc::now());
+    alert.entry("status").or_insert_with(|| {
+        Value::from(if ended { "resolved" } else { "firing" })
+    });
+    if !alert.contains_key("fingerprint") {
+        let labels: HashMap<String, String> =
+            serde_json::from_value(alert["labels"].clone()).unwrap_or_default();
+        alert.insert(String::from("fingerprint"), fingerprint(&labels).into());
+    }
+}
+
+/// Fills in the fields a notification lacks from its alerts, and turns
+/// non-string label values into strings.
+fn complete_group(group: &mut Map<String, Value>, alerts: &[Alert]) {
+    let firing = alerts.iter().any(|a| a.status == Status::Firing);
+    let status = if firing { "firing" } else { "resolved" };
+    let mut common =
+        alerts.first().map(|a| a.labels.clone()).unwrap_or_default();
+    common.retain(|name, value| {
+        alerts.iter().all(|a| a.labels.get(name) == Some(value))
+    });
+    group.entry("status").or_insert_with(|| Value::from(status));
+    group
+        .entry("commonLabels")
+        .or_insert_with(|| serde_json::json!(common));
+    complete_labels(group, "commonLabels");
+    complete_labels(group, "groupLabels");
+    complete_annotations(group, "commonAnnotations");
+    group.entry("version").or_insert_with(|| Value::from("4"));
+    group
+        .entry("truncatedAlerts")
+        .or_insert_with(|| Value::from(0));
+}
+
+/// A notification as read: an Alertmanager group or the alerts Prometheus
+/// posts.
+pub enum Notification {
+    Group(Box<AlertGroup>),
+    Alerts(Vec<PostableAlert>),
+}
+
+/// Why a notification could not be read.
+pub enum Unreadable {
+    /// Not JSON of the right shape, or too large, see `error`.
+    Json(JsonError),
+    /// Straying from the format of the sender.
+    Invalid(anyhow::Error),
+}
+
+/// Checks or completes each alert as its element of `alerts` is read,
+/// turning it into a `T` right away so that the JSON of a single alert is
+/// held at a time.
+struct Alerts<T> {
+    parsing: Parsing,
+    prometheus: bool,
+    read: Vec<T>,
+    /// Why the last alert was refused, the parser being stopped by it.
+    invalid: Option<anyhow::Error>,
+    /// Why the first alert with Grafana's fields is refused should the
+    /// notification turn out not to be Grafana's.
+    grafana: Option<anyhow::Error>,
+}
+
+impl<T: DeserializeOwned> Alerts<T> {
+    fn read(&mut self, mut alert: Value) -> Result<()> {
+        let path = format!("alerts[{}]", self.read.len());
+        match (self.parsing, self.prometheus) {
+            (Parsing::Strict, true) => {
+                check_fields(&alert, &path, &["labels"], &POSTABLE_FIELDS)?
+            }
+            (Parsing::Strict, false) => {
+                if let Err(e) = check_fields(&alert, &path, &ALERT_FIELDS, &[])
+                {
+                    let grafana = GRAFANA_ALERT_FIELDS;
+                    check_fields(&alert, &path, &ALERT_FIELDS, &grafana)?;
+                    self.grafana.get_or_insert(e);
+                }
+            }
+            (Parsing::Lenient, true) => {
+                if let Some(alert) = alert.as_object_mut() {
+                    complete_labels(alert, "labels");
+                }
+            }
+            (Parsing::Lenient, false) => complete_alert(&mut alert),
+        }
+        let alert = serde_json::from_value(alert).context(path)?;
+        self.read.push(alert);
+        Ok(())
+    }
+}
+
+impl<'de, T: DeserializeOwned> Visitor<'de> for &mut Alerts<T> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an array of alerts")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(alert) = seq.next_element::<Value>()? {
+            if let Err(e) = self.read(alert) {
+                let message = format!("{:#}", e);
+                self.invalid = Some(e);
+                return Err(de::Error::custom(message));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'de, T: DeserializeOwned> DeserializeSeed<'de> for &mut Alerts<T> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, d: D) -> Result<(), D::Error> {
+        d.deserialize_seq(self)
+    }
+}
+
+/// The fields of a notification, its alerts going to `alerts` as they are
+/// read and being left as an empty array.
+struct Group<'a>(&'a mut Alerts<Alert>);
+
+impl<'de> Visitor<'de> for Group<'_> {
+    type Value = Map<String, Value>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a notification")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(
+        self,
+        mut map: A,
+    ) -> Result<Self::Value, A::Error> {
+        let mut fields = Map::new();
+        while let Some(name) = map.next_key::<String>()? {
+            if name == "alerts" {
+                map.next_value_seed(&mut *self.0)?;
+                fields.insert(name, Value::Array(Vec::new()));
+            } else {
+                fields.insert(name, map.next_value()?);
+            }
+        }
+        Ok(fields)
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for Group<'_> {
+    type Value = Map<String, Value>;
+
+    fn deserialize<D: Deserializer<'de>>(
+        self,
+        d: D,
+    ) -> Result<Self::Value, D::Error> {
+        d.deserialize_map(self)
+    }
+}
+
+/// Reads an Alertmanager notification or, with `prometheus`, the alerts
+/// Prometheus posts off the body as `json_input` does, checking or
+/// completing each alert as it is read.
+///
+/// The group itself is only built once the body was read entirely, as
+/// its `groupLabels`, `commonLabels` and `externalURL` may come after the
+/// alerts.
+pub fn notification(
+    request: &Request,
+    body: &mut dyn Read,
+    limit: usize,
+    parsing: Parsing,
+    prometheus: bool,
+) -> std::result::Result<Notification, Unreadable> {
+    let body = reader(request, body, limit).map_err(Unreadable::Json)?;
+    let mut parser = serde_json::Deserializer::from_reader(body);
+    let notification = parse(&mut parser, parsing, prometheus)?;
+    parser.end().map_err(|e| Unreadable::Json(e.into()))?;
+    Ok(notification)
+}
+
+/// Like `notification`, for a payload already read whole.
+pub fn parse_value(
+    payload: Value,
+    parsing: Parsing,
+    prometheus: bool,
+) -> std::result::Result<Notification, Unreadable> {
+    parse(payload, parsing, prometheus)
+}
+
+fn parse<'de, D>(
+    d: D,
+    parsing: Parsing,
+    prometheus: bool,
+) -> std::result::Result<Notification, Unreadable>
+where
+    D: Deserializer<'de, Error = serde_json::Error>,
+{
+    let json = |e: serde_json::Error| Unreadable::Json(e.into());
+    if prometheus {
+        let mut alerts = Alerts {
+            parsing,
+            prometheus,
+            read: Vec::new(),
+            invalid: None,
+            grafana: None,
+        };
+        let result = (&mut alerts).deserialize(d);
+        if let Some(e) = alerts.invalid {
+            return Err(Unreadable::Invalid(e));
+        }
+        result.map_err(json)?;
+        return Ok(Notification::Alerts(alerts.read));
+    }
+
+    let mut alerts = Alerts {
+        parsing,
+        prometheus,
+        read: Vec::new(),
+        invalid: None,
+        grafana: None,
+    };
+    let result = Group(&mut alerts).deserialize(d);
+    if let Some(e) = alerts.invalid {
+        return Err(Unreadable::Invalid(e));
+    }
+    let mut fields = result.map_err(json)?;
+    if parsing == Parsing::Lenient {
+        complete_group(&mut fields, &alerts.read);
+    }
+    let fields = Value::Object(fields);
+    if parsing == Parsing::Strict {
+        // Grafana tells itself apart by its organization.
+        let checked = if fields.get("orgId").is_some() {
+            let required = [&GROUP_FIELDS[..], &GRAFANA_GROUP_FIELDS].concat();
+            check_fields(&fields, "notification", &required, &[])
+        } else {
+            check_fields(&fields, "notification", &GROUP_FIELDS, &[])
+                .and(alerts.grafana.map_or(Ok(()), Err))
+        };
+        checked.map_err(Unreadable::Invalid)?;
+    }
+    let mut group: AlertGroup = serde_json::from_value(fields).map_err(json)?;
+    group.alerts = alerts.read;
+    Ok(Notification::Group(Box::new(group)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn request(headers: &[(&str, &str)]) -> Request {
+        let headers = headers
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Request::fake_http("POST", "/", headers, Vec::new())
+    }
+
+    #[test]
+    fn decompresses_gzip() {
+        let request = request(&[
+            ("Content-Type", "application/json"),
+            ("Content-Encoding", "gzip"),
+        ]);
+        let body = gzip(br#"{"status": "firing"}"#);
+        let value: Value =
+            json_input(&request, &mut body.as_slice(), 1024).unwrap();
+        assert_eq!(value["status"], "firing");
+    }
+
+    #[test]
+    fn caps_decompressed_size() {
+        let mut bomb = vec![b' '; 1 << 20];
+        bomb.extend_from_slice(b"{}");
+        let body = gzip(&bomb);
+        assert!(body.len() < 4096);
+        let request = request(&[
+            ("Content-Type", "application/json"),
+            ("Content-Encoding", "gzip"),
+        ]);
+        let e = json_input::<Value>(&request, &mut body.as_slice(), 4096)
+            .unwrap_err();
+        assert_eq!(error(e).status_code, 413);
+    }
+
+    #[test]
+    fn rejects_large_content_length() {
+        let request = request(&[
+            ("Content-Type", "application/json"),
+            ("Content-Length", "100000"),
+        ]);
+        let e = json_input::<Value>(&request, &mut "".as_bytes(), 1024)
+            .unwrap_err();
+        assert_eq!(error(e).status_code, 413);
+    }
+
+    fn read(
+        body: &str,
+        parsing: Parsing,
+        prometheus: bool,
+    ) -> std::result::Result<Notification, Unreadable> {
+        let request = request(&[("Content-Type", "application/json")]);
+        let mut body = body.as_bytes();
+        notification(&request, &mut body, 1 << 20, parsing, prometheus)
+    }
+
+    #[test]
+    fn completes_notifications_after_their_alerts() {
+        let body = r#"{"alerts": [{"labels": {"alertname": "A", "n": 1}}],
+            "groupLabels": {"alertname": "A"}}"#;
+        let Ok(Notification::Group(group)) =
+            read(body, Parsing::Lenient, false)
+        else {
+            panic!("not read");
+        };
+        assert_eq!(group.status, Status::Firing);
+        assert_eq!(group.alerts.len(), 1);
+        assert_eq!(group.alerts[0].labels["n"], "1");
+        assert_eq!(group.common_labels["alertname"], "A");
+    }
+
+    #[test]
+    fn tells_which_alert_is_invalid() {
+        let alert = r#"{"status": "firing", "labels": {}, "annotations": {"summary": "S"},
+            "startsAt": "2024-01-01T00:00:00Z",
+            "endsAt": "0001-01-01T00:00:00Z", "generatorURL": "",
+            "fingerprint": "0"}"#;
+        let unknown = alert.replace("\"labels\"", "\"extra\": 1, \"labels\"");
+        let body = format!(r#"{{"alerts": [{}, {}]}}"#, alert, unknown);
+        match read(&body, Parsing::Strict, false) {
+            Err(Unreadable::Invalid(e)) => {
+                assert_eq!(format!("{:#}", e), "alerts[1]: unknown field extra")
+            }
+            _ => panic!("not refused"),
+        }
+        let body = format!(r#"{{"alerts": [{}]}}"#, alert);
+        match read(&body, Parsing::Strict, false) {
+            Err(Unreadable::Invalid(e)) => {
+                assert_eq!(e.to_string(), "notification: missing field version")
+            }
+            _ => panic!("not refused"),
+        }
+    }
+
+    #[test]
+    fn checks_the_fields_of_each_sender() {
+        let alert = r#"{"status": "firing", "labels": {},
+            "annotations": {"summary": "S"},
+            "startsAt": "2024-01-01T00:00:00Z",
+            "endsAt": "0001-01-01T00:00:00Z", "generatorURL": "",
+            "fingerprint": "0""#;
+        let group = r#""version": "4", "groupKey": "{}", "truncatedAlerts": 0,
+            "status": "firing", "receiver": "r", "groupLabels": {},
+            "commonLabels": {}, "commonAnnotations": {"summary": "S"},
+            "externalURL": """#;
+        let grafana = r#""orgId": 1, "title": "T", "state": "alerting",
+            "message": "M""#;
+        let grafana_alert = r#""silenceURL": "", "dashboardURL": "",
+            "panelURL": "", "values": {"B": 1}, "valueString": "B=1""#;
+        let body = |group: &str, alert: &str| {
+            format!(r#"{{{}, "alerts": [{}}}]}}"#, group, alert)
+        };
+        let check = |body: String| match read(&body, Parsing::Strict, false) {
+            Ok(_) => String::new(),
+            Err(Unreadable::Invalid(e)) => format!("{:#}", e),
+            Err(Unreadable::Json(_)) => panic!("not read: {}", body),
+        };
+
+        let alertmanager_alert = body(group, alert);
+        assert_eq!(check(alertmanager_alert), "");
+        let both = format!("{}, {}", group, grafana);
+        let grafana_alert = format!("{}, {}", alert, grafana_alert);
+        assert_eq!(check(body(&both, &grafana_alert)), "");
+        assert_eq!(check(body(&both, alert)), "");
+        assert_eq!(
+            check(body(group, &grafana_alert)),
+            "alerts[0]: unknown field dashboardURL"
+        );
+        let partial = both.replace(r#""state": "alerting","#, "");
+        assert_eq!(
+            check(body(&partial, alert)),
+            "notification: missing field state"
+        );
+    }
+
+    #[test]
+    fn reads_alerts_posted_by_prometheus() {
+        let body = r#"[{"labels": {"alertname": "A"}}, {"labels": {}}]"#;
+        let Ok(Notification::Alerts(alerts)) =
+            read(body, Parsing::Strict, true)
+        else {
+            panic!("not read");
+        };
+        assert_eq!(alerts.len(), 2);
+        assert!(matches!(
+            read("[{}]", Parsing::Strict, true),
+            Err(Unreadable::Invalid(_))
+        ));
+        assert!(matches!(
+            read("[] []", Parsing::Lenient, true),
+            Err(Unreadable::Json(_))
+        ));
+    }
+
+    #[test]
+    fn accepts_bodies_at_the_limit() {
+        let body = br#"{"a": 1}"#.to_vec();
+        let limit = body.len();
+        let request = request(&[("Content-Type", "application/json")]);
+        let value: Value =
+            json_input(&request, &mut body.as_slice(), limit).unwrap();
+        assert_eq!(value["a"], 1);
+        let e = json_input::<Value>(&request, &mut body.as_slice(), limit - 1)
+            .unwrap_err();
+        assert_eq!(error(e).status_code, 413);
+    }
+}
@@ -0,0 +1,534 @@
+use crate::alert::fnv1a;
+use crate::alert::Alert;
+use crate::alert::AlertGroup;
+use crate::alert::Status;
+use crate::config::Route;
+use crate::log;
+use crate::remind;
+use crate::silence;
+use crate::silence::Matcher;
+use crate::state::AuditEntry;
+use crate::state::StoredAlert;
+use crate::sync::Recover;
+use crate::App;
+use anyhow::bail;
+use anyhow::Result;
+use chrono::Utc;
+use openssl::pkey::Id;
+use openssl::pkey::PKey;
+use openssl::sign::Verifier;
+use rouille::Request;
+use rouille::Response;
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::io::Read;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Groups whose buttons are remembered at most, the oldest being
+/// forgotten beyond.
+const POSTED_LIMIT: usize = 10_000;
+/// How far the timestamp of an interaction may be from now, so that a
+/// request captured on the way cannot be replayed later.
+const MAX_SKEW: i64 = 300;
+
+/// The Discord application whose interactions the bridge answers.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct InteractionConfig {
+    /// Public key of the application, in hex, interactions are signed with.
+    pub public_key: String,
+    /// IDs of the roles whose members may act on alerts, anyone when empty.
+    #[serde(default)]
+    pub allowed_roles: Vec<String>,
+    /// How long the Silence button silences for.
+    #[serde(with = "humantime_serde", default = "default_silence")]
+    pub silence_duration: Duration,
+}
+
+fn default_silence() -> Duration {
+    Duration::from_secs(2 * 3600)
+}
+
+impl InteractionConfig {
+    pub fn validate(&self) -> Result<()> {
+        match hex(&self.public_key) {
+            Some(key) if key.len() == 32 => Ok(()),
+            _ => bail!("public_key is not 32 bytes in hex"),
+        }
+    }
+}
+
+fn hex(value: &str) -> Option<Vec<u8>> {
+    let value = value.trim();
+    if !value.len().is_multiple_of(2) || !value.is_ascii() {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&value[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Whether `signature` is the application's signature of the timestamp
+/// followed by the body, see
+/// <https://discord.com/developers/docs/interactions/overview#setting-up-an-endpoint>.
+fn verify(
+    public_key: &str,
+    signature: &str,
+    timestamp: &str,
+    body: &[u8],
+) -> bool {
+    let (Some(key), Some(signature)) = (hex(public_key), hex(signature)) else {
+        return false;
+    };
+    let Ok(key) = PKey::public_key_from_raw_bytes(&key, Id::ED25519) else {
+        return false;
+    };
+    let message = [timestamp.as_bytes(), body].concat();
+    Verifier::new_without_digest(&key)
+        .and_then(|mut v| v.verify_oneshot(&signature, &message))
+        .unwrap_or(false)
+}
+
+/// The alerts of a message with buttons.
+struct Posted {
+    route: String,
+    fingerprints: Vec<String>,
+    /// The labels all of them have, which Silence matches.
+    labels: Vec<(String, String)>,
+    at: Instant,
+}
+
+/// The groups posted with buttons, by the key of their buttons, and the
+/// firing alerts acknowledged, which are no longer reminded of.
+#[derive(Default)]
+pub struct Interactions {
+    posted: Mutex<HashMap<String, Posted>>,
+    acknowledged: Mutex<HashSet<String>>,
+}
+
+impl Interactions {
+    pub fn is_acknowledged(&self, fingerprint: &str) -> bool {
+        self.acknowledged.lock().recover().contains(fingerprint)
+    }
+
+    /// Forgets the acknowledgements of the alerts no longer firing.
+    pub fn retain(&self, firing: &HashMap<String, StoredAlert>) {
+        self.acknowledged
+            .lock()
+            .recover()
+            .retain(|f| firing.contains_key(f));
+    }
+
+    fn acknowledge(&self, fingerprints: &[String]) {
+        let mut acknowledged = self.acknowledged.lock().recover();
+        acknowledged.extend(fingerprints.iter().cloned());
+    }
+
+    fn insert(&self, key: String, posted: Posted) {
+        let mut all = self.posted.lock().recover();
+        if all.len() >= POSTED_LIMIT && !all.contains_key(&key) {
+            let oldest = all.iter().min_by_key(|(_, p)| p.at);
+            if let Some(oldest) = oldest.map(|(k, _)| k.clone()) {
+                all.remove(&oldest);
+            }
+        }
+        all.insert(key, posted);
+    }
+}
+
+/// The row of buttons for the firing alerts of the group, remembering
+/// what they act on. None when the group has resolved.
+pub fn buttons(app: &App, route: &Route, group: &AlertGroup) -> Option<Value> {
+    app.config.interactions.as_ref()?;
+    let key = format!("{}\n{}", route.name, group.fingerprint());
+    let key = format!("{:016x}", fnv1a(key.bytes()));
+    let firing: Vec<&Alert> = group
+        .alerts
+        .iter()
+        .filter(|a| a.status == Status::Firing)
+        .collect();
+    if firing.is_empty() {
+        app.interactions.posted.lock().recover().remove(&key);
+        return None;
+    }
+    let mut labels: Vec<_> = firing[0]
+        .labels
+        .iter()
+        .filter(|(name, value)| {
+            firing.iter().all(|a| a.labels.get(*name) == Some(value))
+        })
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+    labels.sort();
+    app.interactions.insert(
+        key.clone(),
+        Posted {
+            route: route.name.clone(),
+            fingerprints: firing
+                .iter()
+                .map(|a| a.fingerprint.clone())
+                .collect(),
+            labels,
+            at: Instant::now(),
+        },
+    );
+    let mut buttons = vec![json!({
+        "type": 2,
+        "style": 2,
+        "label": "Ack",
+        "custom_id": format!("ack:{}", key),
+    })];
+    if app.config.silences.is_some() {
+        buttons.push(json!({
+            "type": 2,
+            "style": 4,
+            "label": "Silence",
+            "custom_id": format!("silence:{}", key),
+        }));
+    }
+    Some(json!({"type": 1, "components": buttons}))
+}
+
+#[derive(Deserialize)]
+struct Interaction {
+    #[serde(rename = "type")]
+    kind: u8,
+    data: Option<Data>,
+    /// Set in servers, where members have roles.
+    member: Option<Member>,
+}
+
+#[derive(Deserialize)]
+struct Data {
+    custom_id: Option<String>,
+    name: Option<String>,
+    #[serde(default)]
+    options: Vec<CommandOption>,
+}
+
+#[derive(Deserialize)]
+struct CommandOption {
+    name: String,
+    value: Value,
+}
+
+#[derive(Deserialize)]
+struct Member {
+    user: User,
+    roles: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct User {
+    id: String,
+    username: String,
+}
+
+impl Member {
+    fn actor(&self) -> String {
+        format!("{} ({})", self.user.username, self.user.id)
+    }
+
+    /// Whether the member may act on the alerts of the route.
+    fn may(&self, route: &Route) -> bool {
+        let allowed = route.allowed_roles.as_deref().unwrap_or_default();
+        allowed.is_empty() || self.roles.iter().any(|r| allowed.contains(r))
+    }
+}
+
+const PING: u8 = 1;
+const COMMAND: u8 = 2;
+const COMPONENT: u8 = 3;
+
+/// A message answering the interaction, shown to the member only when
+/// `private`.
+fn reply(content: String, private: bool) -> Response {
+    Response::json(&json!({
+        "type": 4,
+        "data": {
+            "content": content,
+            "flags": if private { 64 } else { 0 },
+            "allowed_mentions": {"parse": []},
+        },
+    }))
+}
+
+/// What an interaction acts on.
+struct Target {
+    routes: Vec<String>,
+    fingerprints: Vec<String>,
+    labels: Vec<(String, String)>,
+    /// How it is named in replies.
+    name: String,
+}
+
+/// `POST /api/discord/interactions`, the interactions endpoint of the
+/// Discord application.
+pub fn handle(app: &App, request: &Request, body: &mut dyn Read) -> Response {
+    let Some(config) = &app.config.interactions else {
+        return Response::empty_404();
+    };
+    let limit = app.config.max_body_size;
+    let mut bytes = Vec::new();
+    if let Err(e) = body.take(limit as u64 + 1).read_to_end(&mut bytes) {
+        return Response::text(e.to_string()).with_status_code(400);
+    }
+    if bytes.len() > limit {
+        return Response::text(format!("body larger than {} bytes", limit))
+            .with_status_code(413);
+    }
+    let signature = request.header("X-Signature-Ed25519").unwrap_or_default();
+    let timestamp = request.header("X-Signature-Timestamp").unwrap_or_default();
+    let fresh = timestamp
+        .parse::<i64>()
+        .is_ok_and(|t| (Utc::now().timestamp() - t).abs() <= MAX_SKEW);
+    if !fresh || !verify(&config.public_key, signature, timestamp, &bytes) {
+        return Response::text("invalid request signature")
+            .with_status_code(401);
+    }
+    let interaction: Interaction = match serde_json::from_slice(&bytes) {
+        Ok(interaction) => interaction,
+        Err(e) => return Response::text(e.to_string()).with_status_code(400),
+    };
+    if interaction.kind == PING {
+        return Response::json(&json!({"type": 1}));
+    }
+    let (Some(data), Some(member)) = (&interaction.data, &interaction.member)
+    else {
+        return reply(String::from("Only available in servers."), true);
+    };
+    let (action, target) = match interaction.kind {
+        COMPONENT => {
+            let custom_id = data.custom_id.as_deref().unwrap_or_default();
+            let Some((action, key)) = custom_id.split_once(':') else {
+                return reply(String::from("Unknown button."), true);
+            };
+            let target = posted(app, key).ok_or_else(|| {
+                String::from("These alerts are no longer firing.")
+            });
+            (action.to_string(), target)
+        }
+        COMMAND => {
+            let action = data.name.clone().unwrap_or_default();
+            let name = option(data, "alertname").unwrap_or_default();
+            let target = named(app, &name)
+                .ok_or_else(|| format!("No {} alert is firing.", name));
+            (action, target)
+        }
+        _ => {
+            return Response::text("unknown interaction").with_status_code(400)
+        }
+    };
+    let target = match target {
+        Ok(target) => target,
+        Err(text) => return reply(text, true),
+    };
+    let refused = target.routes.iter().any(|name| {
+        app.config.named_route(name).is_some_and(|r| !member.may(r))
+    });
+    if refused {
+        log::warning(format!(
+            "{} refused to {} {}: missing role",
+            member.actor(),
+            action,
+            target.name
+        ));
+        return reply(String::from("You may not act on these alerts."), true);
+    }
+    match action.as_str() {
+        "ack" => acknowledge(app, request, member, target),
+        "silence" => silence(app, request, config, member, data, target),
+        _ => reply(format!("Unknown action {}.", action), true),
+    }
+}
+
+/// The alerts of a message with buttons.
+fn posted(app: &App, key: &str) -> Option<Target> {
+    let (route, mut fingerprints, labels) = {
+        let posted = app.interactions.posted.lock().recover();
+        let posted = posted.get(key)?;
+        let route = posted.route.clone();
+        (route, posted.fingerprints.clone(), posted.labels.clone())
+    };
+    // Those resolved since are left alone.
+    let firing = app.store.firing();
+    fingerprints.retain(|f| firing.contains_key(f));
+    if fingerprints.is_empty() {
+        return None;
+    }
+    let name = labels
+        .iter()
+        .find(|(name, _)| name == "alertname")
+        .map_or_else(|| key.to_string(), |(_, value)| value.clone());
+    Some(Target {
+        routes: vec![route],
+        fingerprints,
+        labels,
+        name,
+    })
+}
+
+/// The firing alerts of an `alertname`, as named by slash commands, and
+/// the routes they go to.
+fn named(app: &App, name: &str) -> Option<Target> {
+    let name = name.to_string();
+    let mut target = Target {
+        routes: Vec::new(),
+        fingerprints: Vec::new(),
+        labels: vec![(String::from("alertname"), name.clone())],
+        name,
+    };
+    for (fingerprint, stored) in app.store.firing() {
+        if stored.labels.get("alertname") != Some(&target.name) {
+            continue;
+        }
+        let alert = stored.firing(&fingerprint);
+        if let Some(route) = remind::route(app, &stored.receiver, &alert) {
+            if !target.routes.contains(&route.name) {
+                target.routes.push(route.name.clone());
+            }
+        }
+        target.fingerprints.push(fingerprint);
+    }
+    (!target.fingerprints.is_empty()).then_some(target)
+}
+
+fn option(data: &Data, name: &str) -> Option<String> {
+    let option = data.options.iter().find(|o| o.name == name)?;
+    option.value.as_str().map(String::from)
+}
+
+/// Records the action in the audit log, on behalf of the member.
+fn audit(
+    app: &App,
+    request: &Request,
+    member: &Member,
+    action: &str,
+    target: &str,
+) {
+    app.store.audit(AuditEntry {
+        at: Utc::now(),
+        action: action.to_string(),
+        target: target.to_string(),
+        actor: Some(member.actor()),
+        address: Some(request.remote_addr().ip().to_canonical().to_string()),
+    });
+}
+
+fn acknowledge(
+    app: &App,
+    request: &Request,
+    member: &Member,
+    target: Target,
+) -> Response {
+    app.interactions.acknowledge(&target.fingerprints);
+    audit(app, request, member, "ack", &target.fingerprints.join(","));
+    log::notice(format!("{} acknowledged {}", member.actor(), target.name));
+    reply(
+        format!("{} acknowledged by <@{}>.", target.name, member.user.id),
+        false,
+    )
+}
+
+fn silence(
+    app: &App,
+    request: &Request,
+    config: &InteractionConfig,
+    member: &Member,
+    data: &Data,
+    target: Target,
+) -> Response {
+    if target.labels.is_empty() {
+        return reply(String::from("These alerts share no label."), true);
+    }
+    let duration = match option(data, "duration") {
+        None => config.silence_duration,
+        Some(d) => match humantime::parse_duration(&d) {
+            Ok(duration) => duration,
+            Err(e) => return reply(format!("Invalid duration: {}.", e), true),
+        },
+    };
+    let matchers = target
+        .labels
+        .iter()
+        .map(|(name, value)| Matcher::equal(name, value))
+        .collect();
+    let comment = format!("from Discord, by {}", member.actor());
+    let created =
+        silence::silence(app, &member.actor(), matchers, duration, comment);
+    let created = match created {
+        Ok(created) => created,
+        Err(e) => {
+            log::error(format!("{:#}", e));
+            let text = "Cannot create the silence, see the log of the bridge.";
+            return reply(String::from(text), true);
+        }
+    };
+    audit(app, request, member, "create_silence", &created.id);
+    reply(
+        format!(
+            "{} silenced for {} by <@{}>.",
+            target.name,
+            humantime::format_duration(duration),
+            member.user.id
+        ),
+        false,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::sign::Signer;
+
+    #[test]
+    fn verifies_signatures() {
+        let key = PKey::generate_ed25519().unwrap();
+        let public: String = key
+            .raw_public_key()
+            .unwrap()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let body = br#"{"type":1}"#;
+        let mut signer = Signer::new_without_digest(&key).unwrap();
+        let signature: String = signer
+            .sign_oneshot_to_vec(&[b"1700000000".as_slice(), body].concat())
+            .unwrap()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        assert!(verify(&public, &signature, "1700000000", body));
+        assert!(!verify(&public, &signature, "1700000001", body));
+        assert!(!verify(&public, &signature, "1700000000", b"{}"));
+        assert!(!verify(&public, "zz", "1700000000", body));
+    }
+
+    #[test]
+    fn checks_roles_of_the_route() {
+        let member = |roles: &[&str]| Member {
+            user: User {
+                id: String::from("1"),
+                username: String::from("alice"),
+            },
+            roles: roles.iter().map(|r| r.to_string()).collect(),
+        };
+        let route = Route {
+            allowed_roles: Some(vec![String::from("oncall")]),
+            ..Route::default()
+        };
+        assert!(member(&["dev", "oncall"]).may(&route));
+        assert!(!member(&["dev"]).may(&route));
+        let open = Route {
+            allowed_roles: Some(Vec::new()),
+            ..Route::default()
+        };
+        assert!(member(&[]).may(&open));
+    }
+}
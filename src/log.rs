@@ -0,0 +1,244 @@
+use anyhow::Context;
+use anyhow::Result;
+use serde::Deserialize;
+use std::net::UdpSocket;
+use std::os::unix::net::UnixDatagram;
+use std::sync::OnceLock;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Stderr,
+    Journald,
+    Syslog,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Facility {
+    User,
+    Daemon,
+    Local0,
+    Local1,
+    Local2,
+    Local3,
+    Local4,
+    Local5,
+    Local6,
+    Local7,
+}
+
+impl Facility {
+    fn code(self) -> u8 {
+        match self {
+            Facility::User => 1,
+            Facility::Daemon => 3,
+            local => 16 + local as u8 - Facility::Local0 as u8,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub backend: Backend,
+    /// Unix socket, or `host:port` for UDP, of the syslog daemon.
+    pub syslog_address: String,
+    pub facility: Facility,
+    /// Tag of the messages, `SYSLOG_IDENTIFIER` for journald.
+    pub ident: String,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            backend: Backend::Stderr,
+            syslog_address: String::from("/dev/log"),
+            facility: Facility::Daemon,
+            ident: String::from(env!("CARGO_PKG_NAME")),
+        }
+    }
+}
+
+/// The syslog(3) severities, as the journal's `PRIORITY` too.
+#[derive(Debug, Clone, Copy)]
+pub enum Priority {
+    Error = 3,
+    Warning = 4,
+    Notice = 5,
+    Info = 6,
+}
+
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+enum Socket {
+    Unix(UnixDatagram, String),
+    Udp(UdpSocket),
+}
+
+impl Socket {
+    fn send(&self, datagram: &[u8]) -> std::io::Result<()> {
+        match self {
+            Socket::Unix(socket, path) => socket.send_to(datagram, path),
+            Socket::Udp(socket) => socket.send(datagram),
+        }
+        .map(drop)
+    }
+}
+
+struct Logger {
+    config: LogConfig,
+    socket: Socket,
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/// Sends the messages logged from now on to the configured backend.
+pub fn init(config: &LogConfig) -> Result<()> {
+    let socket = match config.backend {
+        Backend::Stderr => return Ok(()),
+        Backend::Journald => {
+            Socket::Unix(UnixDatagram::unbound()?, JOURNAL_SOCKET.to_string())
+        }
+        Backend::Syslog if config.syslog_address.starts_with('/') => {
+            Socket::Unix(
+                UnixDatagram::unbound()?,
+                config.syslog_address.clone(),
+            )
+        }
+        Backend::Syslog => {
+            let socket = UdpSocket::bind("[::]:0")
+                .or_else(|_| UdpSocket::bind("0.0.0.0:0"))?;
+            socket.connect(&config.syslog_address).with_context(|| {
+                format!("cannot reach syslog at {}", config.syslog_address)
+            })?;
+            Socket::Udp(socket)
+        }
+    };
+    let logger = Logger {
+        config: config.clone(),
+        socket,
+    };
+    let _ = LOGGER.set(logger);
+    Ok(())
+}
+
+/// A message in the journal's native protocol, the binary form of the
+/// field keeping newlines in the message.
+fn journal_entry(
+    config: &LogConfig,
+    priority: Priority,
+    message: &str,
+) -> Vec<u8> {
+    let mut entry = format!(
+        "PRIORITY={}\nSYSLOG_IDENTIFIER={}\nMESSAGE\n",
+        priority as u8, config.ident
+    )
+    .into_bytes();
+    entry.extend((message.len() as u64).to_le_bytes());
+    entry.extend(message.as_bytes());
+    entry.push(b'\n');
+    entry
+}
+
+/// A message as read by syslog daemons, see RFC 3164.
+fn syslog_entry(
+    config: &LogConfig,
+    priority: Priority,
+    message: &str,
+) -> Vec<u8> {
+    format!(
+        "<{}>{} {}[{}]: {}",
+        config.facility.code() * 8 + priority as u8,
+        chrono::Local::now().format("%b %e %H:%M:%S"),
+        config.ident,
+        std::process::id(),
+        message
+    )
+    .into_bytes()
+}
+
+/// Logs to the backend, or to stderr if there is none or it fails.
+pub fn write(priority: Priority, message: impl AsRef<str>) {
+    let message = message.as_ref();
+    let Some(logger) = LOGGER.get() else {
+        eprintln!("{}", message);
+        return;
+    };
+    let entry = match logger.config.backend {
+        Backend::Journald => journal_entry(&logger.config, priority, message),
+        _ => syslog_entry(&logger.config, priority, message),
+    };
+    if let Err(e) = logger.socket.send(&entry) {
+        eprintln!(
+            "{} (cannot log to {:?}: {})",
+            message, logger.config.backend, e
+        );
+    }
+}
+
+pub fn error(message: impl AsRef<str>) {
+    write(Priority::Error, message);
+}
+
+pub fn warning(message: impl AsRef<str>) {
+    write(Priority::Warning, message);
+}
+
+pub fn notice(message: impl AsRef<str>) {
+    write(Priority::Notice, message);
+}
+
+pub fn info(message: impl AsRef<str>) {
+    write(Priority::Info, message);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(facility: Facility) -> LogConfig {
+        LogConfig {
+            facility,
+            ident: String::from("bridge"),
+            ..LogConfig::default()
+        }
+    }
+
+    #[test]
+    fn frames_journal_messages() {
+        let entry =
+            journal_entry(&config(Facility::Daemon), Priority::Warning, "a\nb");
+        let mut expected =
+            b"PRIORITY=4\nSYSLOG_IDENTIFIER=bridge\nMESSAGE\n".to_vec();
+        expected.extend([3, 0, 0, 0, 0, 0, 0, 0]);
+        expected.extend(b"a\nb\n");
+        assert_eq!(entry, expected);
+    }
+
+    #[test]
+    fn prefixes_syslog_messages_with_their_priority() {
+        let cases = [
+            (Facility::User, Priority::Error, "<11>"),
+            (Facility::Daemon, Priority::Info, "<30>"),
+            (Facility::Local0, Priority::Notice, "<133>"),
+            (Facility::Local7, Priority::Warning, "<188>"),
+        ];
+        for (facility, priority, pri) in cases {
+            let entry = syslog_entry(&config(facility), priority, "down");
+            let entry = String::from_utf8(entry).unwrap();
+            let (head, tail) = entry.split_at(pri.len());
+            assert_eq!(head, pri);
+            // Then comes the timestamp, like "Oct  4 09:05:00".
+            let (timestamp, tail) = tail.split_at(15);
+            assert!(
+                chrono::NaiveTime::parse_from_str(&timestamp[7..], "%H:%M:%S")
+                    .is_ok(),
+                "{}",
+                entry
+            );
+            let tag = format!(" bridge[{}]: down", std::process::id());
+            assert_eq!(tail, tag);
+        }
+    }
+}
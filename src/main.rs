@@ -1,173 +1,1071 @@
+mod admin;
+mod alert;
+#[cfg(feature = "archive")]
+mod archive;
+mod batch;
+mod breaker;
+mod chaos;
+mod config;
+mod console;
+mod debug;
+mod dedup;
+mod deliveries;
+mod discord;
+mod escalate;
+mod flap;
+mod gotmpl;
+mod heartbeat;
+mod history;
+mod host;
+mod i18n;
+mod idempotency;
+mod incident;
+mod inhibit;
+mod input;
+#[cfg(feature = "interactions")]
+mod interaction;
+mod log;
+mod maintenance;
+mod matcher;
+mod meta;
+mod metrics;
+mod mock;
+mod oncall;
+mod outbound;
+mod preset;
+mod probe;
+mod prometheus;
+mod pull;
+mod push;
+mod queue;
+mod ratelimit;
+mod receipt;
+mod redact;
+mod remind;
+mod render;
+mod server;
+mod severity;
+mod silence;
+mod sink;
+mod state;
+mod status;
+mod sync;
+mod systemd;
+mod table;
+mod templates;
+mod theme;
+mod ticket;
+#[cfg(feature = "tls")]
+mod tls;
+
+use alert::AlertGroup;
+use alert::Status;
+use anyhow::bail;
+use anyhow::Context;
 use anyhow::Result;
-use rouille::try_or_400;
+use batch::Batch;
+use batch::Partials;
+use breaker::Breaker;
+use chaos::Chaos;
+use chrono::Utc;
+use config::Config;
+use config::Route;
+use config::Routing;
+use config::Statuses;
+use config::Unroutable;
+use debug::Capture;
+use debug::Captured;
+use dedup::Dedup;
+use deliveries::Deliveries;
+use discord::AllowedMentions;
+use flap::FlapDetector;
+use flap::Verdict;
+use heartbeat::Heartbeats;
+use host::Hosts;
+use i18n::Catalog;
+use idempotency::Claims;
+use incident::Thread;
+use inhibit::Inhibitor;
+use input::Notification;
+use input::Unreadable;
+#[cfg(feature = "interactions")]
+use interaction::Interactions;
+use maintenance::Calendars;
+use metrics::Metrics;
+use oncall::Schedules;
+use probe::Probes;
+use queue::Job;
+use queue::Queue;
+use queue::Workers;
+use ratelimit::Limiter;
+use receipt::Receipt;
+use receipt::RouteReceipt;
+use redact::Redactor;
+use remind::Reminders;
+use render::Renderer;
+use rouille::router;
+use rouille::Request;
 use rouille::Response;
-use serde::Deserialize;
-use serde::Serialize;
-use serde_repr::Serialize_repr;
-use std::collections::HashMap;
-use std::io::Error;
-use std::io::ErrorKind;
+use serde_json::Value;
+use server::Endpoints;
+use server::Listener;
+use signal_hook::consts::SIGINT;
+use signal_hook::consts::SIGTERM;
+use signal_hook::iterator::Signals;
+use silence::Silences;
+#[cfg(feature = "twilio")]
+use sink::twilio::Sms;
+use sink::Sinks;
+use state::HistoryPolicy;
+use state::Store;
+use std::borrow::Cow;
+use std::io::Read;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::time::Duration;
+use std::time::Instant;
+use sync::Recover;
+use ticket::Tickets;
 
-#[derive(Serialize_repr, Debug)]
-#[repr(u32)]
-enum Color {
-    Red = 0x992D22,
-    Green = 0x2ECC71,
-    Grey = 0x95A5A6,
+struct App {
+    config: Config,
+    client: reqwest::blocking::Client,
+    store: Arc<Store>,
+    renderer: RwLock<Arc<Renderer>>,
+    catalog: Arc<Catalog>,
+    queue: Arc<Queue>,
+    limiter: Arc<Limiter>,
+    breaker: Arc<Breaker>,
+    metrics: Arc<Metrics>,
+    inhibitor: Inhibitor,
+    redactor: Redactor,
+    capture: Capture,
+    flaps: Option<FlapDetector>,
+    dedup: Option<Arc<Dedup>>,
+    heartbeats: Heartbeats,
+    sinks: Sinks,
+    tickets: Tickets,
+    partials: Arc<Partials>,
+    reminders: Reminders,
+    schedules: Schedules,
+    calendars: Calendars,
+    silences: Silences,
+    probes: Probes,
+    hosts: Hosts,
+    deliveries: Deliveries,
+    #[cfg(feature = "twilio")]
+    sms: Option<Arc<Sms>>,
+    #[cfg(feature = "interactions")]
+    interactions: Interactions,
 }
 
-#[derive(Deserialize, Debug, Hash, Eq, PartialEq, Copy, Clone)]
-#[serde(rename_all = "lowercase")]
-enum Status {
-    Firing,
-    Resolved,
+#[async_std::main]
+async fn main() -> Result<()> {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let mock_discord = args.iter().any(|a| a == "--mock-discord");
+    args.retain(|a| a != "--mock-discord");
+    match args.first().map(String::as_str) {
+        None => {}
+        Some("generate-config") => {
+            print!("{}", include_str!("default-config.yaml"));
+            return Ok(());
+        }
+        Some(url) if url.contains("://") => {}
+        Some(command) => bail!("unknown command {}", command),
+    }
+    if mock_discord {
+        mock::enable();
+        if std::env::var_os("DISCORD_WEBHOOK_URL").is_none() {
+            std::env::set_var("DISCORD_WEBHOOK_URL", mock::WEBHOOK_URL);
+        }
+    }
+    let config = Config::load(&args)?;
+    log::init(&config.log)?;
+    let catalog = Arc::new(Catalog::new(&config.catalog)?);
+    config.validate(&catalog)?;
+    let renderer = templates::renderer(&config, &catalog)?;
+    let queue = Arc::new(Queue::new(config.queue.clone())?);
+    let client = config.outbound.client()?;
+    let history = HistoryPolicy {
+        #[cfg(feature = "archive")]
+        archive: config.archive.is_some(),
+        #[cfg(not(feature = "archive"))]
+        archive: false,
+        retention: config.history.as_ref().map(|h| h.retention),
+    };
+    let store = Arc::new(Store::open(config.state_file.as_deref(), history)?);
+    state::start(store.clone());
+    let breaker = Arc::new(Breaker::new(config.circuit_breaker.clone()));
+    let metrics = Arc::new(Metrics::default());
+    let sinks =
+        Sinks::start(client.clone(), config.queue.clone(), metrics.clone());
+    let dedup = config.dedup.clone().map(|d| Arc::new(Dedup::new(d)));
+    let hosts = Hosts::start(config.hosts.clone(), client.clone());
+    let tickets = Tickets::start(
+        config.tickets.as_ref(),
+        client.clone(),
+        store.clone(),
+        metrics.clone(),
+    );
+    #[cfg(feature = "twilio")]
+    let sms = config
+        .sms
+        .clone()
+        .map(|sms| Arc::new(Sms::new(sms, sinks.clone(), metrics.clone())));
+    queue::start_workers(
+        queue.clone(),
+        Workers {
+            client: client.clone(),
+            breaker: breaker.clone(),
+            metrics: metrics.clone(),
+            store: store.clone(),
+            dedup: dedup.clone(),
+            chaos: Chaos::new(config.chaos.clone()),
+            #[cfg(feature = "twilio")]
+            sms: sms.clone(),
+        },
+    );
+    let limiter = Limiter::start(queue.clone());
+    let inhibitor = Inhibitor::new(config.inhibition.clone());
+    let schedules = Schedules::new(config.schedules.clone());
+    let redactor = Redactor::new(config.redaction.clone())?;
+    let capture = Capture::new(config.debug.clone());
+    let flaps = config.flapping.clone().map(FlapDetector::new);
+    let heartbeats = Heartbeats::new(config.heartbeats.clone());
+    // When socket activated, the unit's sockets replace the configured ones,
+    // those named "admin" taking the place of admin_listen.
+    let inherited = systemd::listeners()?;
+    let mut admin = Vec::new();
+    let mut servers = Vec::new();
+    for (name, server) in inherited {
+        match name.as_str() {
+            "admin" => admin.push(server),
+            _ => servers.push(server),
+        }
+    }
+    if servers.is_empty() && admin.is_empty() {
+        if !config.listen.is_empty() {
+            servers.push(server::bind_tcp(&config.listen)?);
+        }
+        if let Some(path) = &config.unix_socket {
+            servers.push(server::bind_unix(path, config.unix_socket_mode)?);
+        }
+        if let Some(addr) = &config.admin_listen {
+            admin.push(server::bind_tcp(addr)?);
+        }
+    }
+    // Decrypted connections are relayed to a private socket.
+    #[cfg(feature = "tls")]
+    let relay_socket = config
+        .tls
+        .as_ref()
+        .map(|_| tls::socket_path())
+        .transpose()?;
+    #[cfg(feature = "tls")]
+    let relayed = match (&config.tls, &relay_socket) {
+        (Some(tls), Some(socket)) => {
+            let server = server::bind_unix(&socket.to_string_lossy(), 0o600)?;
+            tls::start(tls, socket.clone())?;
+            Some(server)
+        }
+        _ => None,
+    };
+    #[cfg(not(feature = "tls"))]
+    let relayed: Option<tiny_http::Server> = None;
+    if servers.is_empty() && relayed.is_none() {
+        bail!("nothing to listen on, set listen or unix_socket");
+    }
+    let endpoints = match admin.is_empty() {
+        true => Endpoints::All,
+        false => Endpoints::Ingest,
+    };
+    let listener = |server, endpoints| Listener {
+        server,
+        endpoints,
+        relayed: false,
+    };
+    let listeners = servers
+        .into_iter()
+        .map(|server| listener(server, endpoints))
+        .chain(admin.into_iter().map(|s| listener(s, Endpoints::Admin)))
+        .chain(relayed.map(|server| Listener {
+            server,
+            endpoints,
+            relayed: true,
+        }))
+        .collect();
+    let app = Arc::new(App {
+        config,
+        client,
+        store,
+        renderer: RwLock::new(Arc::new(renderer)),
+        catalog,
+        queue,
+        limiter,
+        breaker,
+        metrics,
+        inhibitor,
+        redactor,
+        capture,
+        flaps,
+        dedup,
+        heartbeats,
+        sinks,
+        tickets,
+        partials: Arc::default(),
+        reminders: Reminders::default(),
+        schedules,
+        calendars: Calendars::default(),
+        silences: Silences::default(),
+        probes: Probes::default(),
+        hosts,
+        deliveries: Deliveries::default(),
+        #[cfg(feature = "twilio")]
+        sms,
+        #[cfg(feature = "interactions")]
+        interactions: Interactions::default(),
+    });
+    heartbeat::start(app.clone());
+    templates::watch(app.clone());
+    meta::start(app.clone());
+    remind::start(app.clone());
+    oncall::start(app.clone());
+    maintenance::start(app.clone());
+    silence::start(app.clone());
+    push::start(app.clone())?;
+    prometheus::start(app.clone());
+    #[cfg(feature = "archive")]
+    archive::start(app.clone());
+    if let Some(pull) = app.config.pull.clone() {
+        pull::start(app.clone(), pull);
+    }
+    if let Some(reconcile) = app.config.reconcile.clone() {
+        pull::start_reconcile(app.clone(), reconcile);
+    }
+
+    let mut signals = Signals::new([SIGTERM, SIGINT])?;
+    let server = app.clone();
+    let serving =
+        server::serve(
+            listeners,
+            move |request, body, endpoints| match endpoints {
+                Endpoints::All => admin_routes(&server, request, body)
+                    .unwrap_or_else(|| routes(&server, request, body)),
+                Endpoints::Ingest => routes(&server, request, body),
+                Endpoints::Admin => admin_routes(&server, request, body)
+                    .unwrap_or_else(Response::empty_404),
+            },
+        );
+    systemd::notify("READY=1");
+
+    signals.forever().next();
+    systemd::notify("STOPPING=1");
+    log::notice(format!(
+        "shutting down, delivering what is left within {}",
+        humantime::format_duration(app.config.shutdown_grace)
+    ));
+    // A second signal does not wait.
+    let store = app.store.clone();
+    #[cfg(feature = "tls")]
+    let socket = relay_socket.clone();
+    std::thread::spawn(move || {
+        signals.forever().next();
+        store.flush();
+        #[cfg(feature = "tls")]
+        if let Some(socket) = &socket {
+            tls::remove_socket(socket);
+        }
+        std::process::exit(1);
+    });
+    shut_down(&app, serving);
+    app.store.flush();
+    #[cfg(feature = "tls")]
+    if let Some(socket) = &relay_socket {
+        tls::remove_socket(socket);
+    }
+    Ok(())
 }
 
-#[derive(Deserialize, Debug)]
-struct Annotations {
-    summary: String,
-    description: Option<String>,
+/// Stops accepting requests, then delivers the notifications they left
+/// behind, up to `shutdown_grace`.
+fn shut_down(app: &App, serving: server::Serving) {
+    let deadline = Instant::now() + app.config.shutdown_grace;
+    if !serving.stop(deadline) {
+        log::warning("shutting down with requests still being handled");
+    }
+    app.limiter.release();
+    if !app.queue.drain(deadline) {
+        log::warning(format!(
+            "shutting down with {} notifications queued, {} of them \
+             spilled to disk",
+            app.queue.len(),
+            app.queue.spilled()
+        ));
+    }
+    if !app.sinks.drain(deadline) {
+        log::warning("shutting down with sink deliveries pending");
+    }
 }
 
-#[derive(Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-struct Alert {
-    status: Status,
-    labels: HashMap<String, String>,
-    annotations: Option<Annotations>,
-    fingerprint: String,
+/// The metrics, status, debugging and admin endpoints.
+// What router! expands to reads to clippy as a `?` when it yields options.
+#[allow(clippy::question_mark)]
+fn admin_routes(
+    app: &App,
+    request: &Request,
+    body: &mut dyn Read,
+) -> Option<Response> {
+    // router! does not take dots in paths.
+    if request.method() == "GET" && request.url() == "/api/status.json" {
+        return Some(status::json(app));
+    }
+    router!(request,
+        (GET) (/metrics) => { Some(status::metrics(app)) },
+        (GET) (/status) => { Some(status::page(app)) },
+        (GET) (/api/status/badge) => { Some(status::badge(app)) },
+        (GET) (/api/debug/payloads) => {
+            if !app.capture.enabled() {
+                return Some(Response::empty_404());
+            }
+            Some(admin::guard(app, request).unwrap_or_else(|| {
+                Response::json(&app.capture.payloads())
+            }))
+        },
+        (POST) (/api/route-test) => { Some(debug::route_test(app, request, body)) },
+        (GET) (/api/history/export) => { Some(history::export(app, request)) },
+        (GET) (/api/stats) => { Some(history::stats(app, request)) },
+        (GET) (/api/config) => { Some(admin::config(app, request)) },
+        (GET) (/api/audit) => { Some(admin::audit_log(app, request)) },
+        (GET) (/api/silences) => { Some(silence::list(app, request)) },
+        (POST) (/api/silences) => { Some(silence::create(app, request, body)) },
+        (DELETE) (/api/silences/{id: String}) => {
+            Some(silence::expire(app, request, &id))
+        },
+        (GET) (/api/mock/messages) => {
+            Some(admin::guard(app, request).unwrap_or_else(mock::messages))
+        },
+        (DELETE) (/api/mock/messages) => {
+            Some(admin::guard(app, request).unwrap_or_else(mock::clear))
+        },
+        (POST) (/api/routes/{name: String}/pause) => {
+            Some(admin::pause_route(app, request, &name, true))
+        },
+        (POST) (/api/routes/{name: String}/resume) => {
+            Some(admin::pause_route(app, request, &name, false))
+        },
+        _ => None
+    )
 }
 
-#[derive(Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-struct AlertGroup {
-    version: String,
-    status: Status,
-    alerts: Vec<Alert>,
-    group_labels: HashMap<String, String>,
-    common_labels: HashMap<String, String>,
-    common_annotations: Option<Annotations>,
-    truncated_alerts: i32,
+/// The endpoints alerts and heartbeats come in on.
+fn routes(app: &App, request: &Request, body: &mut dyn Read) -> Response {
+    router!(request,
+        (GET) (/api/deliveries/{id: String}) => { deliveries::get(app, &id) },
+        (POST) (/api/discord/interactions) => {
+            interactions(app, request, body)
+        },
+        (GET) (/heartbeat/{name: String}) => { heartbeat(app, &name) },
+        (POST) (/heartbeat/{name: String}) => { heartbeat(app, &name) },
+        (POST) (/api/v1/alerts) => { ingest(app, request, body, true) },
+        (POST) (/api/v2/alerts) => { ingest(app, request, body, true) },
+        _ => ingest(app, request, body, false)
+    )
 }
 
-#[derive(Serialize, Debug)]
-struct DiscordEmbedField {
-    name: String,
-    value: String,
+#[cfg(feature = "interactions")]
+fn interactions(app: &App, request: &Request, body: &mut dyn Read) -> Response {
+    interaction::handle(app, request, body)
 }
 
-#[derive(Serialize, Debug)]
-struct DiscordEmbed {
-    title: String,
-    description: String,
-    color: Color,
-    fields: Vec<DiscordEmbedField>,
+#[cfg(not(feature = "interactions"))]
+fn interactions(_: &App, _: &Request, _: &mut dyn Read) -> Response {
+    Response::empty_404()
 }
 
-#[derive(Serialize, Debug)]
-struct DiscordContent {
-    content: Option<String>,
-    embeds: Vec<DiscordEmbed>,
+fn heartbeat(app: &App, name: &str) -> Response {
+    match app.heartbeats.ping(name) {
+        None => Response::empty_404(),
+        Some(recovered) => {
+            if let Some(alert) = recovered {
+                heartbeat::notify(app, vec![alert]);
+            }
+            Response::text("OK")
+        }
+    }
 }
 
-#[async_std::main]
-async fn main() -> Result<()> {
-    rouille::start_server("[::]:9094", move |request| {
-        let group: AlertGroup =
-            try_or_400!(rouille::input::json_input(request));
-        try_or_400!(forward_alert(group)
-            .map_err(|e| { Error::new(ErrorKind::Other, e.to_string()) }));
-        Response::text("OK")
-    });
+/// Handles an Alertmanager notification or, with `prometheus`, the alerts
+/// Prometheus itself sends to Alertmanager.
+fn ingest(
+    app: &App,
+    request: &Request,
+    body: &mut dyn Read,
+    prometheus: bool,
+) -> Response {
+    metrics::inc(&app.metrics.requests);
+    if let Some(token) = &app.config.auth.token {
+        if let Some(response) = admin::authorize(request, token) {
+            return response;
+        }
+    }
+    let selection = match Selection::parse(app, request) {
+        Ok(selection) => selection,
+        Err(e) => return Response::text(e.to_string()).with_status_code(400),
+    };
+    let (parsing, limit) = (app.config.parsing, app.config.max_body_size);
+    // Captured payloads are read whole, to be kept as received.
+    let notification = if app.capture.enabled() {
+        let payload: Value = match input::json_input(request, body, limit) {
+            Ok(payload) => payload,
+            Err(e) => return input::error(e),
+        };
+        let mut captured = payload.clone();
+        app.redactor.redact_json(&mut captured);
+        app.capture.record(Captured {
+            received_at: Utc::now(),
+            remote_addr: request.remote_addr().to_string(),
+            payload: captured,
+        });
+        input::parse_value(payload, parsing, prometheus)
+    } else {
+        input::notification(request, body, limit, parsing, prometheus)
+    };
+    let groups = match notification {
+        Ok(Notification::Group(group)) => vec![*group],
+        Ok(Notification::Alerts(alerts)) => {
+            prometheus::groups(alerts, &app.store)
+        }
+        Err(Unreadable::Json(e)) => return input::error(e),
+        Err(Unreadable::Invalid(e)) => {
+            log::warning(format!("rejecting payload: {:#}", e));
+            return Response::text(format!("invalid payload: {:#}", e))
+                .with_status_code(400);
+        }
+    };
+    if app.breaker.rejects() {
+        metrics::inc(&app.metrics.rejected_requests);
+        #[cfg(feature = "twilio")]
+        for mut group in groups {
+            let rules = &app.config.severity_rules;
+            app.config
+                .severity
+                .rewrite(rules, Some(&app.store), &mut group);
+            escalate(app, sms_text(app, &group));
+        }
+        return Response::text("Discord is unavailable, retry later")
+            .with_status_code(503);
+    }
+    let mut receipt = Receipt::default();
+    let mut error_status = 400;
+    let mut claims = Claims::new(&app.store, app.config.idempotency.as_ref());
+    for group in groups {
+        if !claims.claim(&group) {
+            metrics::inc(&app.metrics.repeated_notifications);
+            receipt.repeated += 1;
+            continue;
+        }
+        let fingerprint = group.fingerprint();
+        let short_key = group.short_key();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            forward(app, group, &selection)
+        }));
+        match result {
+            Ok(Ok(routes)) => receipt.routes.extend(routes),
+            Ok(Err(e)) => {
+                // Alertmanager does not retry 4xx answers: a notification
+                // that cannot be routed is 422, one that cannot be
+                // rendered or sent for its configuration 400, while one
+                // the queue has no room for is retried after a 503. The
+                // groups handled before it are not handled again.
+                claims.release_last();
+                let status = if e.is::<Unroutable>() {
+                    422
+                } else if e.is::<queue::Full>() {
+                    503
+                } else {
+                    400
+                };
+                if !selection.receipt {
+                    claims.keep();
+                    return Response::text(format!("{:#}", e))
+                        .with_status_code(status);
+                }
+                receipt.error = Some(format!("{:#}", e));
+                error_status = status;
+                break;
+            }
+            Err(panic) => {
+                let message = panic
+                    .downcast_ref::<&str>()
+                    .copied()
+                    .or_else(|| {
+                        panic.downcast_ref::<String>().map(|s| s.as_str())
+                    })
+                    .unwrap_or("unknown panic");
+                log::error(format!(
+                    "panic while handling payload {} (group {}): {}",
+                    fingerprint, short_key, message
+                ));
+                return Response::text("Internal Server Error")
+                    .with_status_code(500);
+            }
+        }
+    }
+    let late = selection.deadline.is_some_and(|d| Instant::now() >= d);
+    if let (Some(config), true) = (&app.config.deliveries, selection.track) {
+        claims.keep();
+        let id = app.deliveries.insert(config, receipt);
+        let url = format!("/api/deliveries/{}", id);
+        return Response::json(&serde_json::json!({"id": id, "url": url}))
+            .with_status_code(202)
+            .with_additional_header("Location", url);
+    }
+    if !selection.receipt {
+        claims.keep();
+        if late {
+            return Response::text("Accepted").with_status_code(202);
+        }
+        return Response::text("OK");
+    }
+    receipt.wait(selection.deadline);
+    if !receipt.failed() {
+        claims.keep();
+    }
+    let status = if receipt.error.is_some() {
+        error_status
+    } else if receipt.failed() {
+        // Alertmanager retries, sending only the failed messages again.
+        502
+    } else if receipt.pending() {
+        202
+    } else {
+        200
+    };
+    Response::json(&receipt).with_status_code(status)
 }
 
-fn forward_alert(group: AlertGroup) -> Result<()> {
-    let hook_url = std::env::var("DISCORD_WEBHOOK_URL")?.trim().to_string();
-    let reqwest_client = reqwest::blocking::Client::new();
+/// The route and template set a notification asks for in its query, as in
+/// `POST /?route=oncall&template=compact`, and the webhook of its
+/// `X-Discord-Webhook` header when notifications are authenticated.
+#[derive(Default)]
+struct Selection<'a> {
+    route: Option<&'a Route>,
+    template: Option<String>,
+    webhook_url: Option<String>,
+    /// Answer with a receipt rather than `OK`.
+    receipt: bool,
+    /// When the notification must be answered by.
+    deadline: Option<Instant>,
+    /// Keep the receipt, answering with the ID to look it up by.
+    track: bool,
+}
 
-    let alert_name = group
-        .common_labels
-        .get("alertname")
-        .map_or(String::from("unnamed"), |l| l.clone());
+impl<'a> Selection<'a> {
+    fn parse(app: &'a App, request: &Request) -> Result<Selection<'a>> {
+        let route = match request.get_param("route") {
+            Some(name) => match app.config.named_route(&name) {
+                Some(route) => Some(route),
+                None => bail!("unknown route {}", name),
+            },
+            None => None,
+        };
+        let template = request.get_param("template");
+        if let Some(set) = &template {
+            if !app.config.template_sets.contains_key(set) {
+                bail!("unknown template set {}", set);
+            }
+        }
+        let webhook_url = request
+            .header("X-Discord-Webhook")
+            .filter(|_| app.config.auth.token.is_some())
+            .map(discord::expand_url);
+        if let Some(url) = &webhook_url {
+            app.config.outbound.check_url(url)?;
+        }
+        let receipt = request
+            .get_param("receipt")
+            .is_some_and(|v| v != "false" && v != "0");
+        let timeout = match request.header("X-Request-Timeout") {
+            Some(value) => Some(parse_timeout(value)?),
+            None => None,
+        };
+        let timeout = match (timeout, app.config.request_timeout) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        Ok(Selection {
+            route,
+            template,
+            webhook_url,
+            receipt,
+            deadline: timeout.map(|t| Instant::now() + t),
+            track: app.config.deliveries.is_some() && !receipt,
+        })
+    }
 
-    let has_summary = group.common_annotations.is_some();
-    let alert_summary = group
-        .common_annotations
-        .map_or(String::from("no summary"), |a| a.summary);
+    fn apply<'r>(&self, route: &'r Route) -> Cow<'r, Route> {
+        if self.template.is_none() && self.webhook_url.is_none() {
+            return Cow::Borrowed(route);
+        }
+        let mut route = route.clone();
+        if let Some(set) = &self.template {
+            route.template = Some(set.clone());
+        }
+        if let Some(url) = &self.webhook_url {
+            route.webhook_url = Some(url.clone());
+        }
+        Cow::Owned(route)
+    }
+}
 
-    let mut alert_by_status = HashMap::new();
-    for alert in group.alerts {
-        let list = alert_by_status
-            .entry(alert.status.clone())
-            .or_insert(Vec::new());
-        list.push(alert);
+/// Seconds, possibly fractional, or a duration like `10s`.
+fn parse_timeout(value: &str) -> Result<Duration> {
+    let value = value.trim();
+    match value.parse::<f64>() {
+        Ok(seconds) => Duration::try_from_secs_f64(seconds).ok(),
+        Err(_) => humantime::parse_duration(value).ok(),
     }
+    .with_context(|| format!("invalid X-Request-Timeout {}", value))
+}
 
-    for (status, alerts) in alert_by_status {
-        let title = format!("[{:?}:{}] {}", status, alerts.len(), alert_name);
-        let description = alert_summary.clone();
+fn forward_alert(app: &App, group: AlertGroup) -> Result<()> {
+    forward(app, group, &Selection::default()).map(|_| ())
+}
 
-        let color = match status {
-            Status::Firing => Color::Red,
-            Status::Resolved => Color::Green,
+/// Delivers the group to the routes it matches, reporting what was done
+/// for each.
+fn forward(
+    app: &App,
+    mut group: AlertGroup,
+    selection: &Selection,
+) -> Result<Vec<RouteReceipt>> {
+    let rules = &app.config.severity_rules;
+    app.config
+        .severity
+        .rewrite(rules, Some(&app.store), &mut group);
+    app.store.observe(&group.receiver, &group.alerts);
+    group.alerts = app.inhibitor.filter(group.alerts);
+    if let Some(route) = selection.route {
+        return Ok(vec![deliver(
+            app,
+            &selection.apply(route),
+            group,
+            selection,
+        )?]);
+    }
+    if app.config.routing == Routing::Group {
+        let route = match app.config.route(&group) {
+            Some(route) => Some(route),
+            None => unmatched(app, &group)?,
+        };
+        return match route {
+            Some(route) => Ok(vec![deliver(
+                app,
+                &selection.apply(route),
+                group,
+                selection,
+            )?]),
+            None => Ok(Vec::new()),
         };
+    }
 
-        let mut embed = DiscordEmbed {
-            title,
-            description,
-            color,
-            fields: Vec::new(),
+    let receiver = group.receiver.clone();
+    let parts = group.partition(|alert| {
+        app.config
+            .routes
+            .iter()
+            .position(|r| r.matches_labels(&receiver, &alert.labels))
+    });
+    let mut receipts = Vec::new();
+    for (index, group) in parts {
+        let route = match index {
+            Some(index) => Some(&app.config.routes[index]),
+            None => unmatched(app, &group)?,
         };
+        if let Some(route) = route {
+            receipts.push(deliver(
+                app,
+                &selection.apply(route),
+                group,
+                selection,
+            )?);
+        }
+    }
+    Ok(receipts)
+}
+
+/// The text escalating the group by SMS, if severe enough.
+#[cfg(feature = "twilio")]
+fn sms_text(app: &App, group: &AlertGroup) -> Option<String> {
+    let sms = app.config.sms.as_ref()?;
+    sms.text(&app.config.severity, group)
+}
+
+/// Escalates by SMS right away, leaving nothing to escalate once the
+/// messages are given up on.
+#[cfg(feature = "twilio")]
+fn escalate(app: &App, text: Option<String>) -> Option<String> {
+    if let (Some(sms), Some(text)) = (&app.sms, text) {
+        sms.escalate(&text);
+    }
+    None
+}
+
+/// Where a group matching no route goes, if anywhere.
+fn unmatched<'a>(
+    app: &'a App,
+    group: &AlertGroup,
+) -> Result<Option<&'a Route>> {
+    metrics::inc(&app.metrics.unmatched);
+    app.config.unmatched_route(group)
+}
+
+/// Sends the group to the route's sinks and queues its messages, asking
+/// the queue to report their delivery to the receipt if the selection
+/// wants one.
+fn deliver(
+    app: &App,
+    route: &Route,
+    mut group: AlertGroup,
+    selection: &Selection,
+) -> Result<RouteReceipt> {
+    let wait = selection.receipt || selection.track;
+    let mut receipt = RouteReceipt::new(&route.name, group.alerts.len());
+    app.reminders.routed(&route.name, &group.alerts);
+    if app.store.is_paused(&route.name) {
+        receipt.paused = true;
+        return Ok(receipt);
+    }
+    let maintenance = route
+        .maintenance
+        .as_ref()
+        .and_then(|m| app.calendars.ongoing(m).map(|event| (m.action, event)));
+    if let Some((action, event)) = &maintenance {
+        receipt.maintenance = Some(event.clone());
+        if *action == maintenance::Action::Mute {
+            metrics::inc(&app.metrics.maintenance_muted);
+            return Ok(receipt);
+        }
+    }
+    app.redactor.redact(&mut group);
+    ticket::attach(app, route, &mut group);
+    silence::attach(app, &mut group);
+    probe::attach(app, &mut group);
+    host::attach(app, &mut group);
+    #[cfg(feature = "interactions")]
+    let buttons = interaction::buttons(app, route, &group);
+    let renderer = app.renderer.read().recover().clone();
+    for sink in route.sinks() {
+        for delivery in sink::deliveries(sink, &group, route, &renderer)? {
+            app.sinks.push(delivery);
+            receipt.sinks += 1;
+        }
+    }
+    if !route.discord() {
+        return Ok(receipt);
+    }
+    // Severe incidents go to a thread of their own, until resolved.
+    let thread_key = incident::key(route, &group);
+    let thread = match &route.incident_threads {
+        Some(threads)
+            if incident::applies(
+                threads,
+                &app.config.severity,
+                &app.store,
+                &thread_key,
+                &group,
+            ) =>
+        {
+            let name = renderer.render_thread_name(route, &group)?;
+            Some((threads.webhook_url.clone(), incident::name(name)))
+        }
+        _ => None,
+    };
+    let close = group.status == Status::Resolved;
+    let hook_url = match &thread {
+        Some((url, _)) => url.clone(),
+        None => route.webhook_url()?,
+    };
+    app.config.outbound.check_url(&hook_url)?;
+    let batch_key =
+        format!("{}\n{}\n{}", route.name, hook_url, group.fingerprint());
+    let notification = dedup::notification(&route.name, &group);
+    let batch_label =
+        format!("group {} to route {}", group.short_key(), route.name);
+    let rank = group
+        .alerts
+        .iter()
+        .map(|a| app.config.severity.rank(&a.labels))
+        .min()
+        .unwrap_or(usize::MAX);
+    #[cfg(feature = "twilio")]
+    let sms = sms_text(app, &group);
+    // Queued messages wait for the circuit to close, which may take longer
+    // than the escalation should.
+    #[cfg(feature = "twilio")]
+    let sms = match app.breaker.state() {
+        breaker::State::Closed => sms,
+        _ => escalate(app, sms),
+    };
+    // The rollup of held messages could not go to the thread.
+    let rate_limit = match thread {
+        Some(_) => None,
+        None => route.rate_limit,
+    };
 
-        let content = if has_summary {
-            Some(alert_summary.clone())
+    // Packed on their own: the flapping alerts and each part of the group.
+    let mut sections = Vec::new();
+    let mut messages = Vec::new();
+    if let Some(flaps) = &app.flaps {
+        let mut flapping = Vec::new();
+        let mut alerts = Vec::new();
+        for alert in group.alerts {
+            match flaps.observe(&alert.fingerprint, alert.status) {
+                Verdict::Notify => alerts.push(alert),
+                Verdict::Flapping(flips) => flapping.push((alert, flips)),
+                Verdict::Suppress => {}
+            }
+        }
+        if !flapping.is_empty() {
+            sections.push(vec![renderer.render_flapping(
+                route,
+                &flapping,
+                flaps.window(),
+            )?]);
+        }
+        group.alerts = alerts;
+    }
+    let tts = app
+        .config
+        .severity
+        .audible(route.tts.as_deref(), &group.alerts);
+    if !group.alerts.is_empty() {
+        let oncall = app.schedules.current(route);
+        for part in app.config.statuses.split(group) {
+            let mut section = Vec::new();
+            for group in part.regroup(route.regroup_by()) {
+                section.extend(renderer.render(route, group, &oncall)?);
+            }
+            sections.push(section);
+        }
+    }
+    if app.config.statuses == Statuses::Combined {
+        sections = vec![sections.into_iter().flatten().collect()];
+    }
+    for section in sections {
+        if app.config.pack_embeds {
+            messages.extend(discord::pack(section));
         } else {
-            None
+            messages.extend(section);
+        }
+    }
+    match messages.first_mut() {
+        Some(first) if tts && maintenance.is_none() => first.tts = true,
+        _ => {}
+    }
+    if let Some((_, event)) = &maintenance {
+        let tag = renderer.render_maintenance(route, event)?;
+        for message in &mut messages {
+            message.content = Some(match message.content.take() {
+                Some(content) => format!("{}\n{}", tag, content),
+                None => tag.clone(),
+            });
+            // Nobody is paged during maintenance.
+            message.allowed_mentions = Some(AllowedMentions::default());
+        }
+    }
+    #[cfg(feature = "interactions")]
+    if let (Some(buttons), Some(last)) = (buttons, messages.last_mut()) {
+        last.components.push(buttons);
+    }
+    let already_sent = app.partials.take(&batch_key);
+    let mut batch = Batch::new(
+        batch_key,
+        batch_label,
+        already_sent.clone(),
+        app.partials.clone(),
+        app.metrics.clone(),
+    );
+    let chunks: Vec<u64> = (0..messages.len())
+        .map(|index| dedup::key(notification, &hook_url, index))
+        .collect();
+    for chunk in &chunks {
+        batch.add(*chunk);
+    }
+    let batch = Arc::new(batch);
+    let mut chain = Vec::new();
+    for (content, chunk) in messages.into_iter().zip(chunks) {
+        if already_sent.contains(&chunk) {
+            receipt.messages.push(receipt::Message::Skipped);
+            continue;
+        }
+        if let Some(dedup) = &app.dedup {
+            if dedup.is_sent(chunk) {
+                batch.sent(chunk);
+                receipt.messages.push(receipt::Message::Duplicate);
+                continue;
+            }
+        }
+        let job = Job {
+            url: hook_url.clone(),
+            content,
+            rank,
+            receipt: receipt.queued(wait),
+            batch: Some((batch.clone(), chunk)),
+            thread: thread.as_ref().map(|(_, name)| Thread {
+                key: thread_key.clone(),
+                name: name.clone(),
+                close: false,
+            }),
+            sms: None,
+            dedup: app.dedup.as_ref().map(|_| chunk),
+            then: Vec::new(),
         };
-
-        for alert in alerts {
-            let instance = alert
-                .labels
-                .get("instance")
-                .map_or(String::from("unknown"), |l| l.clone());
-            let exported_instance = alert.labels.get("exported_instance");
-
-            let instance = if (instance == "unknown" || instance == "localhost")
-                && exported_instance.is_some()
-            {
-                exported_instance.unwrap().to_string()
-            } else {
-                instance
-            };
-
-            let alert_name = alert
-                .labels
-                .get("alertname")
-                .map_or(String::from("unknown"), |l| l.clone());
-            let d = String::from("-");
-            let name =
-                format!("[{:?}]: {} on {}", status, alert_name, instance);
-
-            let summary = alert
-                .annotations
-                .map_or(d.clone(), |a| a.description.unwrap_or(a.summary));
-            let severity = alert
-                .labels
-                .get("severity")
-                .map_or(String::from("INFO"), |l| l.clone().to_uppercase());
-            let job = alert
-                .labels
-                .get("job")
-                .map_or(String::from("-"), |l| l.clone());
-            let value = format!("{} {} {}", severity, job, summary);
-
-            embed.fields.push(DiscordEmbedField { name, value });
-        }
-
-        let embeds = vec![embed];
-        let content = DiscordContent { content, embeds };
-
-        reqwest_client.post(&hook_url).json(&content).send()?;
+        let Some(job) = app.limiter.admit(job, rate_limit) else {
+            // Sent in the next rollup.
+            batch.sent(chunk);
+            receipt.set_last(receipt::Message::Held);
+            continue;
+        };
+        chain.push((receipt.messages.len() - 1, job));
     }
-    Ok(())
+    let (indices, mut chain): (Vec<usize>, Vec<Job>) =
+        chain.into_iter().unzip();
+    if let Some(thread) = chain.last_mut().and_then(|j| j.thread.as_mut()) {
+        thread.close = close;
+    }
+    // A single worker sends the messages, in order.
+    if !chain.is_empty() {
+        let mut job = chain.remove(0);
+        job.then = chain;
+        #[cfg(feature = "twilio")]
+        {
+            job.sms = sms;
+        }
+        let pushed = app.queue.push_until(job, selection.deadline);
+        // Alertmanager retries the notification.
+        let pushed = pushed.map_err(|_| queue::Full)?;
+        if let Some(shed) = pushed {
+            metrics::add(&app.metrics.messages_dropped, shed.messages() as u64);
+            log::warning(format!(
+                "queue full, dropped the least severe notification ({} \
+                messages)",
+                shed.messages()
+            ));
+            // Those of other notifications are reported dropped as their
+            // receipts stop waiting.
+            let own = shed
+                .batch
+                .as_ref()
+                .is_some_and(|(b, _)| Arc::ptr_eq(b, &batch));
+            if own {
+                for index in indices {
+                    receipt.set(index, receipt::Message::Dropped);
+                }
+            }
+        }
+    }
+    Ok(receipt)
 }
@@ -0,0 +1,719 @@
+use crate::log;
+use crate::sync::Recover;
+use crate::App;
+use anyhow::Context;
+use anyhow::Result;
+use chrono::DateTime;
+use chrono::Datelike;
+use chrono::Days;
+use chrono::Duration as TimeDelta;
+use chrono::NaiveDate;
+use chrono::NaiveDateTime;
+use chrono::NaiveTime;
+use chrono::TimeZone;
+use chrono::Utc;
+use chrono::Weekday;
+use chrono_tz::Tz;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+/// What happens to alerts arriving during a maintenance event.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// Nothing is sent, to Discord or to sinks.
+    Mute,
+    /// Messages are sent tagged, without pinging anyone.
+    Tag,
+}
+
+/// A calendar whose events are maintenance windows of the route.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct MaintenanceConfig {
+    /// Of the iCal feed, `webcal://` meaning `https://`.
+    pub url: String,
+    #[serde(default = "default_action")]
+    pub action: Action,
+    #[serde(with = "humantime_serde", default = "default_refresh")]
+    pub refresh: Duration,
+}
+
+fn default_action() -> Action {
+    Action::Tag
+}
+
+fn default_refresh() -> Duration {
+    Duration::from_secs(300)
+}
+
+impl MaintenanceConfig {
+    pub fn url(&self) -> String {
+        match self.url.trim().strip_prefix("webcal://") {
+            Some(rest) => format!("https://{}", rest),
+            None => self.url.trim().to_string(),
+        }
+    }
+}
+
+/// How far ahead the occurrences of recurring events are listed on each
+/// fetch, a calendar that keeps failing to refresh running out after that.
+const HORIZON: TimeDelta = TimeDelta::days(30);
+/// Days or weeks a recurrence is followed for at most.
+const MAX_STEPS: u64 = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Frequency {
+    Daily,
+    Weekly,
+}
+
+/// Recurrence of an event, daily or weekly rules being supported with
+/// `BYDAY` without ordinals.
+#[derive(Debug, Clone)]
+struct Recurrence {
+    frequency: Frequency,
+    interval: u64,
+    count: Option<u64>,
+    until: Option<DateTime<Utc>>,
+    days: Vec<Weekday>,
+    week_start: Weekday,
+}
+
+/// An event as written in the calendar, in its time zone.
+#[derive(Debug, Clone)]
+struct Event {
+    summary: String,
+    start: NaiveDateTime,
+    tz: Tz,
+    length: TimeDelta,
+    recurrence: Option<Recurrence>,
+    /// Starts of the occurrences left out or replaced by another event.
+    excluded: Vec<DateTime<Utc>>,
+    /// Days of all day occurrences left out.
+    excluded_days: Vec<NaiveDate>,
+}
+
+/// When an event takes place, as listed from the calendar.
+#[derive(Debug, Clone, PartialEq)]
+struct Occurrence {
+    summary: String,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+}
+
+impl Occurrence {
+    fn covers(&self, now: DateTime<Utc>) -> bool {
+        self.start <= now && now < self.end
+    }
+}
+
+/// The local time in the zone, the first of both when the clocks go back
+/// and an hour later when they skip it.
+fn to_utc(tz: Tz, time: NaiveDateTime) -> Option<DateTime<Utc>> {
+    tz.from_local_datetime(&time)
+        .earliest()
+        .or_else(|| {
+            tz.from_local_datetime(&(time + TimeDelta::hours(1)))
+                .earliest()
+        })
+        .map(|time| time.with_timezone(&Utc))
+}
+
+impl Event {
+    /// The occurrences ongoing after `from` that start before `to`, the
+    /// recurrence stepping through local days so that the local time of
+    /// the event stays the same across daylight saving changes.
+    fn occurrences(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Vec<Occurrence> {
+        let mut occurrences = Vec::new();
+        let mut add = |start: DateTime<Utc>| {
+            if start + self.length > from {
+                occurrences.push(Occurrence {
+                    summary: self.summary.clone(),
+                    start,
+                    end: start + self.length,
+                });
+            }
+        };
+        let Some(rule) = &self.recurrence else {
+            if let Some(start) = to_utc(self.tz, self.start) {
+                if start < to {
+                    add(start);
+                }
+            }
+            return occurrences;
+        };
+        let first = self.start.date();
+        let week = first.week(rule.week_start).first_day();
+        let mut days = match rule.days.is_empty() {
+            true => vec![first.weekday()],
+            false => rule.days.clone(),
+        };
+        days.sort_by_key(|d| d.days_since(rule.week_start));
+        let mut count = 0;
+        for step in 0..MAX_STEPS {
+            let candidates: Vec<NaiveDate> = match rule.frequency {
+                Frequency::Daily => {
+                    let day = first + Days::new(step * rule.interval);
+                    match rule.days.is_empty()
+                        || rule.days.contains(&day.weekday())
+                    {
+                        true => vec![day],
+                        false => Vec::new(),
+                    }
+                }
+                Frequency::Weekly => {
+                    let week = week + Days::new(7 * step * rule.interval);
+                    days.iter()
+                        .map(|d| {
+                            week + Days::new(
+                                d.days_since(rule.week_start).into(),
+                            )
+                        })
+                        .filter(|day| *day >= first)
+                        .collect()
+                }
+            };
+            for day in candidates {
+                if rule.count.is_some_and(|max| count >= max) {
+                    return occurrences;
+                }
+                let Some(start) =
+                    to_utc(self.tz, day.and_time(self.start.time()))
+                else {
+                    continue;
+                };
+                if start >= to || rule.until.is_some_and(|until| start > until)
+                {
+                    return occurrences;
+                }
+                // Left out occurrences still count.
+                count += 1;
+                if !self.excluded.contains(&start)
+                    && !self.excluded_days.contains(&day)
+                {
+                    add(start);
+                }
+            }
+        }
+        occurrences
+    }
+}
+
+/// Joins the folded lines of an iCal file, see RFC 5545 section 3.1.
+fn unfold(ical: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in ical.lines() {
+        let line = line.trim_end_matches('\r');
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+/// A time of the calendar: `20261014T100000Z`, `20261014T100000` in the
+/// `TZID` zone (UTC when floating) or a `20261014` date.
+#[derive(Debug, Clone, Copy)]
+struct Time {
+    local: NaiveDateTime,
+    tz: Tz,
+    date: bool,
+}
+
+impl Time {
+    fn parse(value: &str, tzid: Option<&str>) -> Result<Time, String> {
+        let invalid = || format!("invalid time {}", value);
+        if let Some(utc) = value.strip_suffix('Z') {
+            let local = NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S")
+                .map_err(|_| invalid())?;
+            return Ok(Time {
+                local,
+                tz: Tz::UTC,
+                date: false,
+            });
+        }
+        let (local, date) =
+            match NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S") {
+                Ok(time) => (time, false),
+                Err(_) => {
+                    let date = NaiveDate::parse_from_str(value, "%Y%m%d")
+                        .map_err(|_| invalid())?;
+                    (date.and_time(NaiveTime::MIN), true)
+                }
+            };
+        let tz = match tzid {
+            Some(tzid) => tzid
+                .parse()
+                .map_err(|_| format!("unknown time zone {}", tzid))?,
+            None => Tz::UTC,
+        };
+        Ok(Time { local, tz, date })
+    }
+
+    fn utc(&self) -> Result<DateTime<Utc>, String> {
+        to_utc(self.tz, self.local)
+            .ok_or_else(|| format!("invalid local time {}", self.local))
+    }
+}
+
+/// Parses the `P1DT2H30M` durations of iCal.
+fn parse_duration(value: &str) -> Option<TimeDelta> {
+    let value = value.strip_prefix('+').unwrap_or(value);
+    let mut rest = value.strip_prefix('P')?;
+    let mut seconds = 0;
+    let mut number = String::new();
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        let unit = match c {
+            '0'..='9' => {
+                number.push(c);
+                continue;
+            }
+            'T' => continue,
+            'W' => 604800,
+            'D' => 86400,
+            'H' => 3600,
+            'M' => 60,
+            'S' => 1,
+            _ => return None,
+        };
+        seconds += number.parse::<i64>().ok()? * unit;
+        number.clear();
+    }
+    Some(TimeDelta::seconds(seconds))
+}
+
+fn parse_weekday(value: &str) -> Option<Weekday> {
+    Some(match value {
+        "MO" => Weekday::Mon,
+        "TU" => Weekday::Tue,
+        "WE" => Weekday::Wed,
+        "TH" => Weekday::Thu,
+        "FR" => Weekday::Fri,
+        "SA" => Weekday::Sat,
+        "SU" => Weekday::Sun,
+        _ => return None,
+    })
+}
+
+fn parse_rule(value: &str, tzid: Option<&str>) -> Result<Recurrence, String> {
+    let unsupported = || format!("unsupported recurrence {}", value);
+    let mut rule = Recurrence {
+        frequency: Frequency::Daily,
+        interval: 1,
+        count: None,
+        until: None,
+        days: Vec::new(),
+        week_start: Weekday::Mon,
+    };
+    let mut frequency = None;
+    for part in value.split(';') {
+        let (name, value) = part.split_once('=').ok_or_else(unsupported)?;
+        match name {
+            "FREQ" => {
+                frequency = Some(match value {
+                    "DAILY" => Frequency::Daily,
+                    "WEEKLY" => Frequency::Weekly,
+                    _ => return Err(unsupported()),
+                })
+            }
+            "INTERVAL" => {
+                rule.interval = value.parse().map_err(|_| unsupported())?;
+                if rule.interval == 0 {
+                    return Err(unsupported());
+                }
+            }
+            "COUNT" => {
+                rule.count = Some(value.parse().map_err(|_| unsupported())?)
+            }
+            // A date or a UTC time, or floating with the start.
+            "UNTIL" => rule.until = Some(Time::parse(value, tzid)?.utc()?),
+            "BYDAY" => {
+                rule.days = value
+                    .split(',')
+                    .map(parse_weekday)
+                    .collect::<Option<_>>()
+                    .ok_or_else(unsupported)?
+            }
+            "WKST" => {
+                rule.week_start =
+                    parse_weekday(value).ok_or_else(unsupported)?
+            }
+            _ => return Err(unsupported()),
+        }
+    }
+    rule.frequency = frequency.ok_or_else(unsupported)?;
+    Ok(rule)
+}
+
+/// A property of an event, with its time zone if it has one.
+#[derive(Debug, Clone)]
+struct Property {
+    tzid: Option<String>,
+    value: String,
+}
+
+type Properties = HashMap<String, Vec<Property>>;
+
+fn value<'a>(properties: &'a Properties, name: &str) -> Option<&'a Property> {
+    properties.get(name)?.first()
+}
+
+fn time(properties: &Properties, name: &str) -> Result<Option<Time>, String> {
+    value(properties, name)
+        .map(|p| Time::parse(&p.value, p.tzid.as_deref()))
+        .transpose()
+}
+
+/// The occurrences of the events of an iCal file ongoing after `from` that
+/// start before `to`, unsupported events being skipped and logged.
+fn parse(
+    ical: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Vec<Occurrence> {
+    let mut components = Vec::new();
+    let mut event: Option<Properties> = None;
+    for line in unfold(ical) {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let mut params = name.split(';');
+        let name = params.next().unwrap_or_default().to_ascii_uppercase();
+        let tzid = params
+            .find_map(|p| p.strip_prefix("TZID="))
+            .map(|t| t.trim_matches('"').to_string());
+        match (name.as_str(), value, &mut event) {
+            ("BEGIN", "VEVENT", _) => event = Some(HashMap::new()),
+            ("END", "VEVENT", Some(_)) => components.extend(event.take()),
+            (_, _, Some(properties)) => {
+                properties.entry(name).or_default().push(Property {
+                    tzid,
+                    value: value.to_string(),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    // Occurrences moved or cancelled by events of the same UID.
+    let uid = |p: &Properties| value(p, "UID").map(|p| p.value.clone());
+    let mut replaced: HashMap<String, Vec<DateTime<Utc>>> = HashMap::new();
+    for properties in &components {
+        let Ok(Some(id)) = time(properties, "RECURRENCE-ID") else {
+            continue;
+        };
+        if let (Some(uid), Ok(start)) = (uid(properties), id.utc()) {
+            replaced.entry(uid).or_default().push(start);
+        }
+    }
+
+    let mut occurrences = Vec::new();
+    for properties in &components {
+        let cancelled = value(properties, "STATUS")
+            .is_some_and(|s| s.value.eq_ignore_ascii_case("CANCELLED"));
+        if cancelled {
+            continue;
+        }
+        match to_event(properties) {
+            Ok(mut event) => {
+                if event.recurrence.is_some() {
+                    let moved = uid(properties).and_then(|u| replaced.get(&u));
+                    event.excluded.extend(moved.into_iter().flatten());
+                }
+                occurrences.extend(event.occurrences(from, to));
+            }
+            Err(e) => log::warning(format!(
+                "skipping calendar event {}: {}",
+                value(properties, "SUMMARY").map_or("", |p| p.value.as_str()),
+                e
+            )),
+        }
+    }
+    occurrences.sort_by_key(|o| o.start);
+    occurrences
+}
+
+fn to_event(properties: &Properties) -> Result<Event, String> {
+    let start = time(properties, "DTSTART")?.ok_or("no DTSTART")?;
+    let length =
+        match (time(properties, "DTEND")?, value(properties, "DURATION")) {
+            (Some(end), _) => end.utc()? - start.utc()?,
+            (None, Some(duration)) => parse_duration(&duration.value)
+                .ok_or_else(|| {
+                    format!("invalid duration {}", duration.value)
+                })?,
+            // A date lasts the whole day, a time no time at all.
+            (None, None) if start.date => TimeDelta::days(1),
+            (None, None) => TimeDelta::zero(),
+        };
+    let tzid = value(properties, "DTSTART").and_then(|p| p.tzid.as_deref());
+    let recurrence = match value(properties, "RRULE") {
+        Some(rule) => Some(parse_rule(&rule.value, tzid)?),
+        None => None,
+    };
+    if properties.contains_key("RDATE") || properties.contains_key("EXRULE") {
+        return Err(String::from("RDATE and EXRULE are not supported"));
+    }
+    let mut excluded = Vec::new();
+    let mut excluded_days = Vec::new();
+    for exdate in properties.get("EXDATE").into_iter().flatten() {
+        for value in exdate.value.split(',') {
+            let time = Time::parse(value, exdate.tzid.as_deref())?;
+            match time.date {
+                true => excluded_days.push(time.local.date()),
+                false => excluded.push(time.utc()?),
+            }
+        }
+    }
+    let summary = value(properties, "SUMMARY")
+        .map(|p| p.value.replace("\\,", ",").replace("\\;", ";"))
+        .unwrap_or_default();
+    Ok(Event {
+        summary,
+        start: start.local,
+        tz: start.tz,
+        length,
+        recurrence,
+        excluded,
+        excluded_days,
+    })
+}
+
+/// The occurrences of the events of the maintenance calendars, by URL, as
+/// listed when last fetched.
+#[derive(Default)]
+pub struct Calendars(Mutex<HashMap<String, Vec<Occurrence>>>);
+
+impl Calendars {
+    /// The summary of the calendar's ongoing event, if any.
+    pub fn ongoing(&self, config: &MaintenanceConfig) -> Option<String> {
+        let now = Utc::now();
+        let calendars = self.0.lock().recover();
+        calendars
+            .get(&config.url())?
+            .iter()
+            .find(|occurrence| occurrence.covers(now))
+            .map(|occurrence| occurrence.summary.clone())
+    }
+}
+
+fn fetch(app: &App, url: &str) -> Result<()> {
+    let ical = app
+        .client
+        .get(url)
+        .send()
+        .and_then(|r| r.error_for_status())
+        .and_then(|r| r.text())
+        .with_context(|| format!("cannot fetch calendar {}", url))?;
+    let now = Utc::now();
+    let occurrences = parse(&ical, now, now + HORIZON);
+    app.calendars
+        .0
+        .lock()
+        .recover()
+        .insert(url.to_string(), occurrences);
+    Ok(())
+}
+
+/// Starts a thread per calendar of the routes, fetching it every
+/// `refresh`.
+pub fn start(app: Arc<App>) {
+    let mut started = HashSet::new();
+    let routes = app.config.routes.iter().chain([&app.config.default_route]);
+    for config in routes.filter_map(|r| r.maintenance.clone()) {
+        if !started.insert(config.url()) {
+            continue;
+        }
+        let app = app.clone();
+        thread::spawn(move || loop {
+            // The last events fetched stay in use meanwhile.
+            if let Err(e) = fetch(&app, &config.url()) {
+                log::error(format!("{:#}", e));
+            }
+            thread::sleep(config.refresh);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(time: &str) -> DateTime<Utc> {
+        time.parse().unwrap()
+    }
+
+    fn calendar(events: &[&str]) -> String {
+        let mut ical = String::from("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n");
+        for event in events {
+            ical += "BEGIN:VEVENT\r\n";
+            ical += &event.trim().replace('\n', "\r\n");
+            ical += "\r\nEND:VEVENT\r\n";
+        }
+        ical + "END:VCALENDAR\r\n"
+    }
+
+    fn starts(ical: &str, from: &str, to: &str) -> Vec<String> {
+        parse(ical, utc(from), utc(to))
+            .iter()
+            .map(|o| o.start.format("%Y-%m-%dT%H:%M").to_string())
+            .collect()
+    }
+
+    #[test]
+    fn expands_weekly_rules_by_day() {
+        let ical = calendar(&["
+UID:1
+SUMMARY:Patching\\, weekly
+DTSTART:20261005T220000Z
+DURATION:PT2H
+RRULE:FREQ=WEEKLY;BYDAY=MO,TH;COUNT=5"]);
+        assert_eq!(
+            starts(&ical, "2026-10-01T00:00:00Z", "2026-12-01T00:00:00Z"),
+            [
+                "2026-10-05T22:00",
+                "2026-10-08T22:00",
+                "2026-10-12T22:00",
+                "2026-10-15T22:00",
+                "2026-10-19T22:00",
+            ]
+        );
+        let occurrences = parse(
+            &ical,
+            utc("2026-10-08T23:00:00Z"),
+            utc("2026-10-09T00:00:00Z"),
+        );
+        assert_eq!(occurrences.len(), 1);
+        assert_eq!(occurrences[0].summary, "Patching, weekly");
+        assert!(occurrences[0].covers(utc("2026-10-08T23:59:00Z")));
+        assert!(!occurrences[0].covers(utc("2026-10-09T00:00:00Z")));
+    }
+
+    #[test]
+    fn expands_daily_rules_on_week_days() {
+        let ical = calendar(&["
+UID:1
+DTSTART:20261009T060000Z
+DTEND:20261009T070000Z
+RRULE:FREQ=DAILY;BYDAY=MO,TU,WE,TH,FR;UNTIL=20261014T060000Z"]);
+        assert_eq!(
+            starts(&ical, "2026-10-01T00:00:00Z", "2026-12-01T00:00:00Z"),
+            [
+                "2026-10-09T06:00",
+                "2026-10-12T06:00",
+                "2026-10-13T06:00",
+                "2026-10-14T06:00",
+            ]
+        );
+    }
+
+    #[test]
+    fn leaves_excluded_dates_out() {
+        let ical = calendar(&["
+UID:1
+DTSTART;TZID=Europe/Paris:20261005T090000
+DURATION:PT1H
+RRULE:FREQ=DAILY;COUNT=4
+EXDATE;TZID=Europe/Paris:20261006T090000,20261007T090000
+EXDATE:20261008T070000Z"]);
+        assert_eq!(
+            starts(&ical, "2026-10-01T00:00:00Z", "2026-12-01T00:00:00Z"),
+            ["2026-10-05T07:00"]
+        );
+    }
+
+    #[test]
+    fn moves_and_cancels_overridden_occurrences() {
+        let ical = calendar(&[
+            "
+UID:1
+DTSTART:20261005T220000Z
+DURATION:PT2H
+RRULE:FREQ=WEEKLY;COUNT=3",
+            "
+UID:1
+RECURRENCE-ID:20261012T220000Z
+DTSTART:20261013T200000Z
+DURATION:PT1H",
+            "
+UID:1
+RECURRENCE-ID:20261019T220000Z
+DTSTART:20261019T220000Z
+STATUS:CANCELLED",
+        ]);
+        assert_eq!(
+            starts(&ical, "2026-10-01T00:00:00Z", "2026-12-01T00:00:00Z"),
+            ["2026-10-05T22:00", "2026-10-13T20:00"]
+        );
+    }
+
+    #[test]
+    fn keeps_the_local_time_across_daylight_saving_changes() {
+        let ical = calendar(&["
+UID:1
+DTSTART;TZID=Europe/Paris:20261019T090000
+DURATION:PT1H
+RRULE:FREQ=WEEKLY;INTERVAL=1"]);
+        // Paris goes from UTC+2 to UTC+1 on October 25.
+        assert_eq!(
+            starts(&ical, "2026-10-19T00:00:00Z", "2026-11-03T00:00:00Z"),
+            ["2026-10-19T07:00", "2026-10-26T08:00", "2026-11-02T08:00"]
+        );
+    }
+
+    #[test]
+    fn skips_unknown_time_zones_and_unsupported_rules() {
+        let ical = calendar(&[
+            "
+UID:1
+DTSTART;TZID=Mars/Olympus:20261014T090000
+DURATION:PT1H",
+            "
+UID:2
+DTSTART:20261014T090000Z
+DURATION:PT1H
+RRULE:FREQ=MONTHLY;BYMONTHDAY=14",
+            "
+UID:3
+DTSTART:20261014T090000Z
+DURATION:PT1H
+RRULE:FREQ=WEEKLY;BYDAY=1MO",
+            "
+UID:4
+DTSTART:20261014T100000Z
+DURATION:PT1H",
+        ]);
+        assert_eq!(
+            starts(&ical, "2026-10-01T00:00:00Z", "2026-12-01T00:00:00Z"),
+            ["2026-10-14T10:00"]
+        );
+        let properties = Properties::from([(
+            String::from("RRULE"),
+            vec![Property {
+                tzid: None,
+                value: String::from("FREQ=YEARLY"),
+            }],
+        )]);
+        assert!(to_event(&properties).is_err());
+        assert_eq!(
+            parse_rule("FREQ=MONTHLY", None).unwrap_err(),
+            "unsupported recurrence FREQ=MONTHLY"
+        );
+        assert_eq!(
+            Time::parse("20261014T090000", Some("Mars/Olympus")).unwrap_err(),
+            "unknown time zone Mars/Olympus"
+        );
+    }
+}
@@ -0,0 +1,264 @@
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use regex::Regex;
+use serde::de;
+use serde::Deserialize;
+use serde::Deserializer;
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Equal,
+    NotEqual,
+    Regex,
+    NotRegex,
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Op::Equal => "=",
+            Op::NotEqual => "!=",
+            Op::Regex => "=~",
+            Op::NotRegex => "!~",
+        })
+    }
+}
+
+/// A label matcher with Alertmanager semantics: regular expressions are
+/// anchored at both ends and a missing label matches like an empty one.
+#[derive(Debug, Clone)]
+pub struct Matcher {
+    pub name: String,
+    pub op: Op,
+    pub value: String,
+    regex: Option<Regex>,
+}
+
+impl Matcher {
+    pub fn new(name: &str, op: Op, value: &str) -> Result<Matcher> {
+        let regex = match op {
+            Op::Regex | Op::NotRegex => Some(
+                Regex::new(&format!("^(?:{})$", value))
+                    .with_context(|| format!("invalid regex {}", value))?,
+            ),
+            Op::Equal | Op::NotEqual => None,
+        };
+        Ok(Matcher {
+            name: name.to_string(),
+            op,
+            value: value.to_string(),
+            regex,
+        })
+    }
+
+    /// Parses `name="value"`, `name!=value`, `name=~"regex"` or
+    /// `name!~regex`, the value being optionally quoted.
+    pub fn parse(s: &str) -> Result<Matcher> {
+        let s = s.trim();
+        let end = s
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(s.len());
+        let (name, rest) = s.split_at(end);
+        if name.is_empty() {
+            bail!("invalid matcher {}: no label name", s);
+        }
+        let rest = rest.trim_start();
+        let (op, value) = [
+            ("=~", Op::Regex),
+            ("!~", Op::NotRegex),
+            ("!=", Op::NotEqual),
+            ("=", Op::Equal),
+        ]
+        .into_iter()
+        .find_map(|(token, op)| rest.strip_prefix(token).map(|v| (op, v)))
+        .with_context(|| format!("invalid matcher {}: no operator", s))?;
+        let value = unquote(value.trim())
+            .with_context(|| format!("invalid matcher {}", s))?;
+        Matcher::new(name, op, &value)
+    }
+
+    pub fn matches(&self, labels: &HashMap<String, String>) -> bool {
+        let value = labels.get(&self.name).map_or("", String::as_str);
+        let is_match = |r: &Regex| r.is_match(value);
+        match self.op {
+            Op::Equal => value == self.value,
+            Op::NotEqual => value != self.value,
+            Op::Regex => self.regex.as_ref().is_some_and(is_match),
+            Op::NotRegex => !self.regex.as_ref().is_some_and(is_match),
+        }
+    }
+}
+
+impl fmt::Display for Matcher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}{}", self.name, self.op, quote(&self.value))
+    }
+}
+
+/// The value quoted as Alertmanager does, which `parse` reads back.
+pub fn quote(value: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in value.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn unquote(value: &str) -> Result<String> {
+    let Some(inner) = value.strip_prefix('"') else {
+        return Ok(value.to_string());
+    };
+    let Some(inner) = inner.strip_suffix('"') else {
+        bail!("unterminated quote");
+    };
+    let mut unquoted = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        unquoted.push(match c {
+            '\\' => match chars.next() {
+                Some('n') => '\n',
+                Some(c) => c,
+                None => bail!("trailing backslash"),
+            },
+            c => c,
+        });
+    }
+    Ok(unquoted)
+}
+
+/// Matchers that must all match, configured either as a map of label
+/// values or as a list of Alertmanager-style matchers:
+///
+/// ```yaml
+/// matchers:
+///   - namespace =~ "team-a-.*"
+///   - severity != info
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Matchers(pub Vec<Matcher>);
+
+impl Matchers {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn matches(&self, labels: &HashMap<String, String>) -> bool {
+        self.0.iter().all(|m| m.matches(labels))
+    }
+}
+
+impl<'de> Deserialize<'de> for Matchers {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Matchers, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Map(HashMap<String, String>),
+            List(Vec<String>),
+        }
+        let matchers = match Raw::deserialize(d)? {
+            Raw::Map(map) => {
+                let mut map: Vec<_> = map.into_iter().collect();
+                map.sort();
+                map.iter()
+                    .map(|(name, value)| Matcher::new(name, Op::Equal, value))
+                    .collect::<Result<_>>()
+            }
+            Raw::List(list) => list.iter().map(|m| Matcher::parse(m)).collect(),
+        };
+        matchers
+            .map(Matchers)
+            .map_err(|e| de::Error::custom(format!("{:#}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_each_operator() {
+        let cases = [
+            ("severity=critical", Op::Equal, "critical"),
+            (r#"severity != "info""#, Op::NotEqual, "info"),
+            (r#"namespace=~"team-a-.*""#, Op::Regex, "team-a-.*"),
+            ("job !~ node|kube", Op::NotRegex, "node|kube"),
+            (r#"summary="say \"hi\"\n""#, Op::Equal, "say \"hi\"\n"),
+        ];
+        for (input, op, value) in cases {
+            let matcher = Matcher::parse(input).unwrap();
+            assert_eq!((matcher.op, matcher.value.as_str()), (op, value));
+        }
+    }
+
+    #[test]
+    fn formats_like_alertmanager() {
+        let cases = [
+            ("severity=critical", r#"severity="critical""#),
+            ("job !~ node|kube", r#"job!~"node|kube""#),
+            (r#"summary="say \"hi\"\n""#, r#"summary="say \"hi\"\n""#),
+            (r#"path="C:\\été""#, r#"path="C:\\été""#),
+        ];
+        for (input, formatted) in cases {
+            let matcher = Matcher::parse(input).unwrap();
+            assert_eq!(matcher.to_string(), formatted);
+            let parsed = Matcher::parse(formatted).unwrap();
+            assert_eq!((parsed.op, parsed.value), (matcher.op, matcher.value));
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_matchers() {
+        for input in [
+            "=critical",
+            "severity",
+            "severity>critical",
+            r#"severity="critical"#,
+            r#"severity="critical\"#,
+            "job=~(",
+        ] {
+            assert!(Matcher::parse(input).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn anchors_regexes_and_treats_missing_labels_as_empty() {
+        let regex = Matcher::parse("job=~node").unwrap();
+        assert!(regex.matches(&labels(&[("job", "node")])));
+        assert!(!regex.matches(&labels(&[("job", "node-exporter")])));
+        let empty = Matcher::parse(r#"team="""#).unwrap();
+        assert!(empty.matches(&labels(&[])));
+        let not_empty = Matcher::parse("team!~").unwrap();
+        assert!(!not_empty.matches(&labels(&[])));
+        assert!(not_empty.matches(&labels(&[("team", "a")])));
+    }
+
+    #[test]
+    fn deserializes_maps_and_lists() {
+        let map: Matchers = serde_yaml::from_str("severity: critical").unwrap();
+        let list: Matchers =
+            serde_yaml::from_str("[\"severity = critical\", \"job!=node\"]")
+                .unwrap();
+        let alert = labels(&[("severity", "critical"), ("job", "kube")]);
+        assert!(map.matches(&alert) && list.matches(&alert));
+        assert!(!list
+            .matches(&labels(&[("severity", "critical"), ("job", "node")])));
+        let e = serde_yaml::from_str::<Matchers>("[\"job=~(\"]").unwrap_err();
+        assert!(e.to_string().contains("invalid regex"), "{}", e);
+    }
+}
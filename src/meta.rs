@@ -0,0 +1,159 @@
+use crate::alert::Status;
+use crate::breaker::State;
+use crate::discord;
+use crate::discord::DiscordContent;
+use crate::discord::DiscordEmbed;
+use crate::log;
+use crate::metrics;
+use crate::App;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// A webhook, separate from the alert channels, where the bridge reports
+/// its own problems.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct MetaConfig {
+    pub webhook_url: String,
+    /// Share of the queue capacity above which the queue is saturated.
+    #[serde(default = "default_saturation")]
+    pub queue_saturation: f64,
+}
+
+fn default_saturation() -> f64 {
+    0.9
+}
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+fn report(app: &App, url: &str, title: &str, description: String, ok: bool) {
+    let content = DiscordContent {
+        content: None,
+        embeds: vec![DiscordEmbed {
+            title: format!("prometheus-discord-alert: {}", title),
+            url: None,
+            description,
+            color: if ok {
+                app.config.theme.color(Status::Resolved)
+            } else {
+                app.config.theme.warning()
+            },
+            author: None,
+            thumbnail: None,
+            fields: Vec::new(),
+            footer: None,
+        }],
+        allowed_mentions: None,
+        tts: false,
+        components: Vec::new(),
+    };
+    if let Err(e) = discord::send(&app.client, url, &content, false) {
+        log::error(format!("cannot report to the meta channel: {}", e));
+    }
+}
+
+/// Whether the circuit is reported open, half open counting as it was
+/// since the trial delivery may still fail.
+fn still_open(state: State, was_open: bool) -> bool {
+    match state {
+        State::Open(_) => true,
+        State::HalfOpen => was_open,
+        State::Closed => false,
+    }
+}
+
+/// Watches the circuit breaker, the queue and template reloads, and
+/// reports when they turn bad and back to normal.
+pub fn start(app: Arc<App>) {
+    let Some(config) = app.config.meta.clone() else {
+        return;
+    };
+    thread::spawn(move || {
+        let url = &config.webhook_url;
+        let mut circuit_open = false;
+        let mut saturated = false;
+        let mut reload_failures =
+            metrics::get(&app.metrics.template_reload_failures);
+        loop {
+            thread::sleep(CHECK_INTERVAL);
+
+            let open = still_open(app.breaker.state(), circuit_open);
+            if open != circuit_open {
+                circuit_open = open;
+                if open {
+                    let failures = metrics::get(&app.metrics.delivery_failures);
+                    report(
+                        &app,
+                        url,
+                        "Discord deliveries failing",
+                        format!(
+                            "The circuit breaker opened after repeated \
+                            failures ({} failed attempts so far), alert \
+                            notifications are on hold.",
+                            failures
+                        ),
+                        false,
+                    );
+                } else {
+                    report(
+                        &app,
+                        url,
+                        "Discord deliveries recovered",
+                        String::from("The circuit breaker closed again."),
+                        true,
+                    );
+                }
+            }
+
+            let (depth, capacity) = (app.queue.len(), app.queue.capacity());
+            let full =
+                depth as f64 >= capacity as f64 * config.queue_saturation;
+            if full != saturated {
+                saturated = full;
+                let title = if full {
+                    "Queue saturated"
+                } else {
+                    "Queue drained"
+                };
+                report(
+                    &app,
+                    url,
+                    title,
+                    format!("{} of {} notifications queued.", depth, capacity),
+                    !full,
+                );
+            }
+
+            let failures = metrics::get(&app.metrics.template_reload_failures);
+            if failures > reload_failures {
+                reload_failures = failures;
+                report(
+                    &app,
+                    url,
+                    "Template reload failed",
+                    String::from(
+                        "The changed templates are invalid, the previous \
+                        ones are still in use. See the logs for details.",
+                    ),
+                    false,
+                );
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn recovers_only_once_closed() {
+        assert!(still_open(State::Open(Instant::now()), false));
+        assert!(still_open(State::HalfOpen, true));
+        assert!(!still_open(State::HalfOpen, false));
+        assert!(!still_open(State::Closed, true));
+    }
+}
@@ -0,0 +1,201 @@
+use std::fmt::Write;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+
+/// Self-metrics of the bridge, exposed in the Prometheus text format.
+#[derive(Default)]
+pub struct Metrics {
+    pub requests: AtomicU64,
+    pub rejected_requests: AtomicU64,
+    pub messages_sent: AtomicU64,
+    pub delivery_failures: AtomicU64,
+    pub messages_dropped: AtomicU64,
+    pub template_reload_failures: AtomicU64,
+    pub unmatched: AtomicU64,
+    pub sink_failures: AtomicU64,
+    pub sink_dropped: AtomicU64,
+    pub partial_deliveries: AtomicU64,
+    pub repeated_notifications: AtomicU64,
+    pub reminders: AtomicU64,
+    pub maintenance_muted: AtomicU64,
+    pub tickets_opened: AtomicU64,
+    pub ticket_failures: AtomicU64,
+    pub sms_sent: AtomicU64,
+    pub sms_capped: AtomicU64,
+    /// Unix time of the last message delivered to Discord, 0 before any.
+    pub last_delivery: AtomicU64,
+}
+
+pub fn inc(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn add(counter: &AtomicU64, count: u64) {
+    counter.fetch_add(count, Ordering::Relaxed);
+}
+
+pub fn set(gauge: &AtomicU64, value: u64) {
+    gauge.store(value, Ordering::Relaxed);
+}
+
+pub fn get(counter: &AtomicU64) -> u64 {
+    counter.load(Ordering::Relaxed)
+}
+
+/// Formats a label set like `{alertname="X",severity="critical"}`.
+pub fn labels(pairs: &[(&str, &str)]) -> String {
+    let pairs: Vec<_> = pairs
+        .iter()
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", name, value)
+        })
+        .collect();
+    format!("{{{}}}", pairs.join(","))
+}
+
+/// Builds a text exposition one metric family at a time.
+#[derive(Default)]
+pub struct Exposition(String);
+
+impl Exposition {
+    pub fn metric(&mut self, name: &str, kind: &str, help: &str, value: f64) {
+        self.family(name, kind, help, &[(String::new(), value)]);
+    }
+
+    /// Writes a family whose samples are already-formatted label sets like
+    /// `{alertname="X"}` with their value.
+    pub fn family(
+        &mut self,
+        name: &str,
+        kind: &str,
+        help: &str,
+        samples: &[(String, f64)],
+    ) {
+        let _ = writeln!(self.0, "# HELP {} {}", name, help);
+        let _ = writeln!(self.0, "# TYPE {} {}", name, kind);
+        for (labels, value) in samples {
+            let _ = writeln!(self.0, "{}{} {}", name, labels, value);
+        }
+    }
+
+    pub fn finish(self) -> String {
+        self.0
+    }
+}
+
+impl Metrics {
+    pub fn expose(&self, out: &mut Exposition) {
+        out.metric(
+            "bridge_requests_total",
+            "counter",
+            "Alertmanager notifications received.",
+            get(&self.requests) as f64,
+        );
+        out.metric(
+            "bridge_rejected_requests_total",
+            "counter",
+            "Notifications turned away while the circuit was open.",
+            get(&self.rejected_requests) as f64,
+        );
+        out.metric(
+            "bridge_messages_sent_total",
+            "counter",
+            "Messages delivered to Discord.",
+            get(&self.messages_sent) as f64,
+        );
+        out.metric(
+            "bridge_delivery_failures_total",
+            "counter",
+            "Failed delivery attempts, retries included.",
+            get(&self.delivery_failures) as f64,
+        );
+        out.metric(
+            "bridge_messages_dropped_total",
+            "counter",
+            "Messages given up on or shed from a full queue.",
+            get(&self.messages_dropped) as f64,
+        );
+        out.metric(
+            "bridge_template_reload_failures_total",
+            "counter",
+            "Template changes rejected, the previous templates being kept.",
+            get(&self.template_reload_failures) as f64,
+        );
+        out.metric(
+            "bridge_unmatched_total",
+            "counter",
+            "Notifications matching no route.",
+            get(&self.unmatched) as f64,
+        );
+        out.metric(
+            "bridge_sink_failures_total",
+            "counter",
+            "Failed attempts to deliver to sinks other than Discord.",
+            get(&self.sink_failures) as f64,
+        );
+        out.metric(
+            "bridge_sink_deliveries_dropped_total",
+            "counter",
+            "Sink deliveries dropped, more being pending than queue.capacity.",
+            get(&self.sink_dropped) as f64,
+        );
+        out.metric(
+            "bridge_partial_deliveries_total",
+            "counter",
+            "Notifications only some of the messages of were sent.",
+            get(&self.partial_deliveries) as f64,
+        );
+        out.metric(
+            "bridge_repeated_notifications_total",
+            "counter",
+            "Notifications skipped as already handled.",
+            get(&self.repeated_notifications) as f64,
+        );
+        out.metric(
+            "bridge_reminders_total",
+            "counter",
+            "Reminders queued for long-firing alerts.",
+            get(&self.reminders) as f64,
+        );
+        out.metric(
+            "bridge_maintenance_muted_total",
+            "counter",
+            "Notifications muted during a maintenance window.",
+            get(&self.maintenance_muted) as f64,
+        );
+        out.metric(
+            "bridge_tickets_opened_total",
+            "counter",
+            "Issues opened for alerts.",
+            get(&self.tickets_opened) as f64,
+        );
+        out.metric(
+            "bridge_ticket_failures_total",
+            "counter",
+            "Issues that could not be opened.",
+            get(&self.ticket_failures) as f64,
+        );
+        out.metric(
+            "bridge_sms_sent_total",
+            "counter",
+            "SMS sent as Discord could not be reached.",
+            get(&self.sms_sent) as f64,
+        );
+        out.metric(
+            "bridge_sms_capped_total",
+            "counter",
+            "SMS escalations not sent because of the rate cap.",
+            get(&self.sms_capped) as f64,
+        );
+        out.metric(
+            "bridge_last_delivery_timestamp_seconds",
+            "gauge",
+            "When a message was last delivered to Discord, 0 before any.",
+            get(&self.last_delivery) as f64,
+        );
+    }
+}
@@ -0,0 +1,126 @@
+use crate::sync::Recover;
+use chrono::DateTime;
+use chrono::Utc;
+use rouille::Response;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+
+/// The webhook of routes without one, with `--mock-discord`.
+pub const WEBHOOK_URL: &str = "https://discord.com/api/webhooks/0/mock";
+/// Messages kept, the oldest being dropped first.
+const CAPACITY: usize = 1000;
+
+/// A message that would have been posted to Discord.
+#[derive(Serialize, Debug, Clone)]
+pub struct Message {
+    pub id: String,
+    /// The thread it was posted in, a new one taking the message's ID.
+    pub channel_id: String,
+    pub received_at: DateTime<Utc>,
+    /// The webhook, its token left out.
+    pub webhook: String,
+    pub payload: Value,
+}
+
+#[derive(Default)]
+struct Mock {
+    messages: Mutex<VecDeque<Message>>,
+    next: AtomicU64,
+}
+
+static MOCK: OnceLock<Mock> = OnceLock::new();
+
+/// Captures the messages from now on instead of posting them.
+pub fn enable() {
+    let _ = MOCK.set(Mock::default());
+}
+
+/// Captures the message if the mock is enabled, returning what Discord
+/// would have answered. The body is only serialized then.
+pub fn intercept(
+    url: &str,
+    query: &[(&str, &str)],
+    body: &impl Serialize,
+) -> Option<Value> {
+    let mock = MOCK.get()?;
+    let payload = serde_json::to_value(body).ok()?;
+    let id = (1001 + mock.next.fetch_add(1, Ordering::Relaxed)).to_string();
+    let thread = query.iter().find(|(k, _)| *k == "thread_id");
+    let channel_id = thread.map_or(id.clone(), |(_, v)| v.to_string());
+    let webhook = match url.rsplit_once('/') {
+        Some((webhook, _token)) => webhook.to_string(),
+        None => url.to_string(),
+    };
+    let message = Message {
+        id: id.clone(),
+        channel_id: channel_id.clone(),
+        received_at: Utc::now(),
+        webhook,
+        payload,
+    };
+    let mut messages = mock.messages.lock().recover();
+    if messages.len() >= CAPACITY {
+        messages.pop_front();
+    }
+    messages.push_back(message);
+    Some(json!({"id": id, "channel_id": channel_id}))
+}
+
+pub fn messages() -> Response {
+    match MOCK.get() {
+        Some(mock) => Response::json(&*mock.messages.lock().recover()),
+        None => Response::empty_404(),
+    }
+}
+
+pub fn clear() -> Response {
+    match MOCK.get() {
+        Some(mock) => {
+            mock.messages.lock().recover().clear();
+            Response::empty_204()
+        }
+        None => Response::empty_404(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn captures_messages_once_enabled() {
+        let url = "https://discord.com/api/webhooks/1/token";
+        let body = json!({"content": "hi"});
+        // The only test enabling the mock, which is global.
+        assert_eq!(MOCK.get().map(|_| ()), None);
+        assert_eq!(intercept(url, &[], &body), None);
+        enable();
+
+        let answer = intercept(url, &[], &body).unwrap();
+        assert_eq!(answer, json!({"id": "1001", "channel_id": "1001"}));
+        let answer = intercept(url, &[("thread_id", "7")], &body).unwrap();
+        assert_eq!(answer, json!({"id": "1002", "channel_id": "7"}));
+        let mock = MOCK.get().unwrap();
+        {
+            let messages = mock.messages.lock().recover();
+            assert_eq!(
+                messages[0].webhook,
+                "https://discord.com/api/webhooks/1"
+            );
+            assert_eq!(messages[1].payload, body);
+        }
+
+        for _ in 0..CAPACITY {
+            intercept(url, &[], &body);
+        }
+        let messages = mock.messages.lock().recover();
+        assert_eq!(messages.len(), CAPACITY);
+        assert_eq!(messages[0].id, "1003");
+    }
+}
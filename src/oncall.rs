@@ -0,0 +1,211 @@
+use crate::config::Route;
+use crate::log;
+use crate::sync::Recover;
+use crate::App;
+use anyhow::Context;
+use anyhow::Result;
+use chrono::DateTime;
+use chrono::Utc;
+use reqwest::blocking::Client;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+/// How often the schedules of on-call APIs are fetched.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Who is on call, mapped to Discord user IDs.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ScheduleConfig {
+    /// `users` take turns of `shift` each, the first one from `start`.
+    Rota {
+        start: DateTime<Utc>,
+        #[serde(with = "humantime_serde")]
+        shift: Duration,
+        users: Vec<String>,
+    },
+    Pagerduty {
+        #[serde(default = "default_pagerduty_url")]
+        url: String,
+        schedule_id: String,
+        token: String,
+        /// Discord user IDs by PagerDuty user ID.
+        users: HashMap<String, String>,
+    },
+    GrafanaOncall {
+        /// Base URL of Grafana OnCall, e.g. https://oncall.example.com.
+        url: String,
+        schedule_id: String,
+        token: String,
+        /// Discord user IDs by Grafana OnCall user ID.
+        users: HashMap<String, String>,
+    },
+}
+
+fn default_pagerduty_url() -> String {
+    String::from("https://api.pagerduty.com")
+}
+
+impl ScheduleConfig {
+    /// URL of the API the schedule is fetched from, if any.
+    pub fn url(&self) -> Option<&str> {
+        match self {
+            ScheduleConfig::Rota { .. } => None,
+            ScheduleConfig::Pagerduty { url, .. }
+            | ScheduleConfig::GrafanaOncall { url, .. } => Some(url),
+        }
+    }
+
+    /// The users of the API on call now, as Discord user IDs.
+    fn fetch(&self, client: &Client) -> Result<Vec<String>> {
+        #[derive(Deserialize)]
+        struct PagerDutyOncalls {
+            oncalls: Vec<PagerDutyOncall>,
+        }
+        #[derive(Deserialize)]
+        struct PagerDutyOncall {
+            user: PagerDutyUser,
+        }
+        #[derive(Deserialize)]
+        struct PagerDutyUser {
+            id: String,
+        }
+        #[derive(Deserialize)]
+        struct GrafanaSchedule {
+            on_call_now: Vec<String>,
+        }
+
+        let (ids, users) = match self {
+            ScheduleConfig::Rota { .. } => return Ok(Vec::new()),
+            ScheduleConfig::Pagerduty {
+                url,
+                schedule_id,
+                token,
+                users,
+            } => {
+                let url = format!("{}/oncalls", url.trim_end_matches('/'));
+                let oncalls: PagerDutyOncalls = client
+                    .get(&url)
+                    .query(&[("schedule_ids[]", schedule_id.as_str())])
+                    .header("Authorization", format!("Token token={}", token))
+                    .header(
+                        "Accept",
+                        "application/vnd.pagerduty+json;version=2",
+                    )
+                    .send()
+                    .and_then(|r| r.error_for_status())
+                    .and_then(|r| r.json())
+                    .with_context(|| format!("cannot query {}", url))?;
+                let ids = oncalls.oncalls.into_iter().map(|o| o.user.id);
+                (ids.collect::<Vec<_>>(), users)
+            }
+            ScheduleConfig::GrafanaOncall {
+                url,
+                schedule_id,
+                token,
+                users,
+            } => {
+                let url = format!(
+                    "{}/api/v1/schedules/{}/",
+                    url.trim_end_matches('/'),
+                    schedule_id
+                );
+                let schedule: GrafanaSchedule = client
+                    .get(&url)
+                    .header("Authorization", token)
+                    .send()
+                    .and_then(|r| r.error_for_status())
+                    .and_then(|r| r.json())
+                    .with_context(|| format!("cannot query {}", url))?;
+                (schedule.on_call_now, users)
+            }
+        };
+        let mut discord = Vec::new();
+        for id in ids {
+            match users.get(&id) {
+                Some(user) if !discord.contains(user) => {
+                    discord.push(user.clone())
+                }
+                Some(_) => {}
+                None => log::warning(format!(
+                    "on-call user {} has no Discord user",
+                    id
+                )),
+            }
+        }
+        Ok(discord)
+    }
+}
+
+/// The on-call schedules, those of APIs as last fetched.
+pub struct Schedules {
+    configs: HashMap<String, ScheduleConfig>,
+    fetched: Mutex<HashMap<String, Vec<String>>>,
+}
+
+impl Schedules {
+    pub fn new(configs: HashMap<String, ScheduleConfig>) -> Schedules {
+        Schedules {
+            configs,
+            fetched: Mutex::default(),
+        }
+    }
+
+    /// Discord user IDs of whoever is on call for the route now.
+    pub fn current(&self, route: &Route) -> Vec<String> {
+        let Some(name) = &route.oncall else {
+            return Vec::new();
+        };
+        match self.configs.get(name) {
+            Some(ScheduleConfig::Rota {
+                start,
+                shift,
+                users,
+            }) => {
+                let shift = shift.as_secs().max(1) as i64;
+                let turn =
+                    (Utc::now() - *start).num_seconds().div_euclid(shift);
+                let index = turn.rem_euclid(users.len().max(1) as i64);
+                users.get(index as usize).cloned().into_iter().collect()
+            }
+            Some(_) => self
+                .fetched
+                .lock()
+                .recover()
+                .get(name)
+                .cloned()
+                .unwrap_or_default(),
+            None => Vec::new(),
+        }
+    }
+
+    fn refresh(&self, client: &Client) {
+        for (name, config) in &self.configs {
+            if config.url().is_none() {
+                continue;
+            }
+            match config.fetch(client) {
+                Ok(users) => {
+                    self.fetched.lock().recover().insert(name.clone(), users);
+                }
+                // The last known on-call stays mentioned meanwhile.
+                Err(e) => log::error(format!("schedule {}: {:#}", name, e)),
+            }
+        }
+    }
+}
+
+/// Starts the thread fetching the schedules of on-call APIs, if any.
+pub fn start(app: Arc<App>) {
+    if app.schedules.configs.values().all(|c| c.url().is_none()) {
+        return;
+    }
+    thread::spawn(move || loop {
+        app.schedules.refresh(&app.client);
+        thread::sleep(REFRESH_INTERVAL);
+    });
+}
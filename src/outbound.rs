@@ -0,0 +1,170 @@
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use reqwest::blocking::Client;
+use reqwest::header::HeaderMap;
+use reqwest::header::HeaderName;
+use reqwest::header::HeaderValue;
+use reqwest::Certificate;
+use reqwest::Identity;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Settings of the HTTP client used for every outgoing request.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutboundConfig {
+    pub user_agent: String,
+    pub headers: HashMap<String, String>,
+    /// Hosts webhooks may point to; `*.example.com` also matches
+    /// subdomains.
+    pub allowed_hosts: Vec<String>,
+    /// Lets URLs use plain HTTP, e.g. for a relay on the same host.
+    pub allow_http: bool,
+    /// PEM bundle of CAs trusted on top of the system ones, e.g. the one
+    /// of a TLS-intercepting proxy.
+    pub ca_bundle: Option<String>,
+    /// PKCS#12 file holding the client certificate and its key.
+    pub client_identity: Option<String>,
+    pub client_identity_password: String,
+}
+
+impl Default for OutboundConfig {
+    fn default() -> Self {
+        OutboundConfig {
+            user_agent: format!(
+                "{}/{}",
+                env!("CARGO_PKG_NAME"),
+                env!("CARGO_PKG_VERSION")
+            ),
+            headers: HashMap::new(),
+            allowed_hosts: vec![
+                String::from("discord.com"),
+                String::from("*.discord.com"),
+                String::from("discordapp.com"),
+                String::from("*.discordapp.com"),
+            ],
+            allow_http: false,
+            ca_bundle: None,
+            client_identity: None,
+            client_identity_password: String::new(),
+        }
+    }
+}
+
+impl OutboundConfig {
+    /// Fails unless the URL points to one of the allowed hosts, over HTTPS
+    /// unless `allow_http`. The URL is left out of the errors, its path
+    /// often being a secret.
+    pub fn check_url(&self, url: &str) -> Result<()> {
+        let parsed = url::Url::parse(url).context("invalid webhook URL")?;
+        match parsed.scheme() {
+            "https" => {}
+            "http" if self.allow_http => {}
+            "http" => bail!(
+                "webhook URL uses plain HTTP, set outbound.allow_http to \
+                allow it"
+            ),
+            // The host of email sinks, which have their own TLS setting.
+            "smtp" => {}
+            scheme => bail!("unsupported webhook URL scheme {}", scheme),
+        }
+        let host = parsed.host_str().unwrap_or_default().to_ascii_lowercase();
+        let allowed = self.allowed_hosts.iter().any(|pattern| {
+            let pattern = pattern.to_ascii_lowercase();
+            match pattern.strip_prefix("*.") {
+                Some(domain) => host
+                    .strip_suffix(domain)
+                    .is_some_and(|sub| sub.ends_with('.')),
+                None => host == pattern,
+            }
+        });
+        if !allowed {
+            bail!("webhook host {} is not in outbound.allowed_hosts", host);
+        }
+        Ok(())
+    }
+
+    pub fn client(&self) -> Result<Client> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            headers.insert(
+                HeaderName::from_bytes(name.as_bytes())
+                    .with_context(|| format!("invalid header name {}", name))?,
+                HeaderValue::from_str(value)
+                    .with_context(|| format!("invalid value for {}", name))?,
+            );
+        }
+        let mut builder = Client::builder()
+            .user_agent(&self.user_agent)
+            .default_headers(headers);
+        if let Some(path) = &self.ca_bundle {
+            for cert in certificates(path)? {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        if let Some(path) = &self.client_identity {
+            let der = std::fs::read(path)
+                .with_context(|| format!("cannot read {}", path))?;
+            let identity =
+                Identity::from_pkcs12_der(&der, &self.client_identity_password)
+                    .with_context(|| {
+                        format!("cannot load identity {}", path)
+                    })?;
+            builder = builder.identity(identity);
+        }
+        Ok(builder.build()?)
+    }
+}
+
+/// The certificates of a PEM bundle, which reqwest only parses one at a
+/// time.
+fn certificates(path: &str) -> Result<Vec<Certificate>> {
+    const END: &str = "-----END CERTIFICATE-----";
+    let pem = std::fs::read_to_string(path)
+        .with_context(|| format!("cannot read {}", path))?;
+    let certs: Vec<_> = pem
+        .split_inclusive(END)
+        .filter(|block| block.contains(END))
+        .map(|block| {
+            Certificate::from_pem(block.as_bytes())
+                .with_context(|| format!("invalid certificate in {}", path))
+        })
+        .collect::<Result<_>>()?;
+    if certs.is_empty() {
+        bail!("no certificate in {}", path);
+    }
+    Ok(certs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allows_listed_hosts_over_https() {
+        let config = OutboundConfig {
+            allowed_hosts: vec![String::from("*.Example.com")],
+            ..OutboundConfig::default()
+        };
+        assert!(config.check_url("https://hooks.EXAMPLE.com/a").is_ok());
+        assert!(config.check_url("https://example.com/a").is_err());
+        assert!(config.check_url("https://badexample.com/a").is_err());
+        assert!(config.check_url("smtp://mail.example.com").is_ok());
+        assert!(config.check_url("ftp://files.example.com/a").is_err());
+        let e = config.check_url("http://hooks.example.com/a").unwrap_err();
+        assert!(e.to_string().contains("allow_http"));
+        let config = OutboundConfig {
+            allow_http: true,
+            ..config
+        };
+        assert!(config.check_url("http://hooks.example.com/a").is_ok());
+    }
+
+    #[test]
+    fn leaves_invalid_urls_out_of_errors() {
+        let config = OutboundConfig::default();
+        let e = config.check_url("secret-token/discord.com").unwrap_err();
+        assert!(!format!("{:#}", e).contains("secret-token"));
+    }
+}
@@ -0,0 +1,367 @@
+use crate::alert::Alert;
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+/// Exporters whose usual labels are shown as `details` in `field_value`.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Preset {
+    NodeExporter,
+    KubeStateMetrics,
+    BlackboxExporter,
+    Kubernetes,
+}
+
+/// Settings of the `kubernetes` preset.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct KubernetesConfig {
+    /// minijinja template of a link to the pod, seeing its labels.
+    pub dashboard_url: Option<String>,
+    #[serde(default = "default_dashboard_text")]
+    pub dashboard_text: String,
+    /// Adds `namespace` to the `regroup_by` of every route.
+    #[serde(default)]
+    pub group_by_namespace: bool,
+}
+
+fn default_dashboard_text() -> String {
+    String::from("dashboard")
+}
+
+/// What a preset makes of an alert's labels.
+#[derive(Debug, Default)]
+pub struct Enrichment {
+    /// Replaces the instance, when it is the exporter's rather than what
+    /// the alert is about.
+    pub instance: Option<String>,
+    pub details: String,
+    /// Made by the `kubernetes` preset, which links to the dashboard.
+    pub kubernetes: bool,
+}
+
+/// The Kubernetes objects kube-state-metrics labels its series with, in
+/// the order they are shown.
+const KUBE_OBJECTS: [(&str, &str); 10] = [
+    ("pod", "pod"),
+    ("container", "container"),
+    ("deployment", "deployment"),
+    ("statefulset", "statefulset"),
+    ("daemonset", "daemonset"),
+    ("job_name", "job"),
+    ("cronjob", "cronjob"),
+    ("persistentvolumeclaim", "PVC"),
+    ("horizontalpodautoscaler", "HPA"),
+    ("node", "node"),
+];
+
+/// The `job` of the exporters, as usually named: `node`, `node-exporter`
+/// or `node_exporter`, `blackbox` or `blackbox-<module>`.
+static NODE_JOB: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new("^node(?:[-_]exporter)?$").unwrap());
+static KUBE_STATE_METRICS_JOB: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new("^kube-state-metrics$").unwrap());
+static BLACKBOX_JOB: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new("^blackbox(?:[-_]exporter)?(?:[-_][[:alnum:]_-]+)?$").unwrap()
+});
+
+fn job_is(labels: &HashMap<String, String>, job: &Regex) -> bool {
+    labels.get("job").is_some_and(|j| job.is_match(j))
+}
+
+/// Whether the alert is about a blackbox probe.
+pub fn is_probe(labels: &HashMap<String, String>) -> bool {
+    job_is(labels, &BLACKBOX_JOB)
+}
+
+/// Escapes what Discord would read as markdown in a label value.
+fn escape(value: &str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        if "\\*_~`|[]()<>#".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// A label value as inline code, its backticks, which would end it,
+/// replaced.
+fn code(value: &str) -> String {
+    format!("`{}`", value.replace('`', "'"))
+}
+
+impl Preset {
+    fn enrich(self, alert: &Alert) -> Option<Enrichment> {
+        let labels = &alert.labels;
+        let label = |name: &str| labels.get(name).filter(|v| !v.is_empty());
+        match self {
+            Preset::NodeExporter => {
+                if !job_is(labels, &NODE_JOB) {
+                    return None;
+                }
+                let details = match (label("mountpoint"), label("device")) {
+                    (Some(mountpoint), device) => {
+                        let mut details = code(mountpoint);
+                        if let Some(device) = device {
+                            details += &format!(" on {}", code(device));
+                        }
+                        if let Some(fstype) = label("fstype") {
+                            details += &format!(" ({})", escape(fstype));
+                        }
+                        details
+                    }
+                    (None, Some(device)) => format!("device {}", code(device)),
+                    (None, None) => return None,
+                };
+                Some(Enrichment {
+                    instance: None,
+                    details,
+                    kubernetes: false,
+                })
+            }
+            Preset::KubeStateMetrics => {
+                if !job_is(labels, &KUBE_STATE_METRICS_JOB) {
+                    return None;
+                }
+                let objects: Vec<_> = KUBE_OBJECTS
+                    .iter()
+                    .filter_map(|(name, kind)| {
+                        label(name)
+                            .map(|value| format!("{} {}", kind, code(value)))
+                    })
+                    .collect();
+                if objects.is_empty() {
+                    return None;
+                }
+                // The instance is the kube-state-metrics pod's address.
+                let object = KUBE_OBJECTS
+                    .iter()
+                    .filter(|(name, _)| *name != "container")
+                    .find_map(|(name, _)| label(name));
+                let instance = match (label("namespace"), object) {
+                    (Some(namespace), Some(object)) => Some(format!(
+                        "{}/{}",
+                        escape(namespace),
+                        escape(object)
+                    )),
+                    (None, object) => object.map(|o| escape(o)),
+                    (Some(namespace), None) => Some(escape(namespace)),
+                };
+                Some(Enrichment {
+                    instance,
+                    details: objects.join(", "),
+                    kubernetes: false,
+                })
+            }
+            Preset::BlackboxExporter => {
+                if !is_probe(labels) {
+                    return None;
+                }
+                let mut details = match label("module") {
+                    Some(module) => format!("probe {}", code(module)),
+                    None => String::from("probe"),
+                };
+                // The target, once relabeled as the instance.
+                let target = label("instance").and_then(|i| {
+                    let url = url::Url::parse(i).ok()?;
+                    matches!(url.scheme(), "http" | "https").then_some((i, url))
+                });
+                if let Some((target, url)) = target {
+                    // Parentheses would end the link early.
+                    let url =
+                        url.as_str().replace('(', "%28").replace(')', "%29");
+                    details += &format!(" of [{}]({})", escape(target), url);
+                }
+                let probe = alert.probe.unwrap_or_default();
+                match (probe.status_code, probe.duration) {
+                    (Some(code), Some(duration)) => {
+                        details +=
+                            &format!(", HTTP {} in {:.2}s", code, duration)
+                    }
+                    (Some(code), None) => {
+                        details += &format!(", HTTP {}", code)
+                    }
+                    (None, Some(duration)) => {
+                        details += &format!(", last took {:.2}s", duration)
+                    }
+                    (None, None) => {}
+                }
+                Some(Enrichment {
+                    instance: None,
+                    details,
+                    kubernetes: false,
+                })
+            }
+            Preset::Kubernetes => {
+                let (namespace, pod) = (label("namespace")?, label("pod")?);
+                let (namespace, pod) = (escape(namespace), escape(pod));
+                let instance = match label("container") {
+                    Some(container) => {
+                        format!("{}/{}[{}]", namespace, pod, escape(container))
+                    }
+                    None => format!("{}/{}", namespace, pod),
+                };
+                Some(Enrichment {
+                    instance: Some(instance),
+                    details: String::new(),
+                    kubernetes: true,
+                })
+            }
+        }
+    }
+}
+
+/// The enrichment of the first preset recognizing the alert.
+pub fn enrich(presets: &[Preset], alert: &Alert) -> Enrichment {
+    presets
+        .iter()
+        .find_map(|preset| preset.enrich(alert))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::StoredAlert;
+    use chrono::Utc;
+
+    type Labels<'a> = &'a [(&'a str, &'a str)];
+
+    fn enrichment(preset: Preset, labels: Labels) -> Option<Enrichment> {
+        let alert = StoredAlert {
+            receiver: String::new(),
+            labels: labels
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            annotations: None,
+            starts_at: Utc::now(),
+        }
+        .firing("a");
+        preset.enrich(&alert)
+    }
+
+    #[test]
+    fn details_node_exporter_alerts() {
+        let cases: [(Labels, Option<&str>); 6] = [
+            (
+                &[
+                    ("job", "node"),
+                    ("mountpoint", "/var"),
+                    ("device", "/dev/sda1"),
+                    ("fstype", "ext4"),
+                ],
+                Some("`/var` on `/dev/sda1` (ext4)"),
+            ),
+            (
+                &[("job", "node-exporter"), ("device", "eth0")],
+                Some("device `eth0`"),
+            ),
+            (
+                &[("job", "node_exporter"), ("device", "a`b")],
+                Some("device `a'b`"),
+            ),
+            (
+                &[("job", "node"), ("mountpoint", "/"), ("fstype", "*x*")],
+                Some("`/` (\\*x\\*)"),
+            ),
+            (&[("job", "nodejs"), ("device", "eth0")], None),
+            (&[("job", "node")], None),
+        ];
+        for (labels, details) in cases {
+            let found = enrichment(Preset::NodeExporter, labels);
+            assert_eq!(
+                found.map(|e| e.details).as_deref(),
+                details,
+                "{:?}",
+                labels
+            );
+        }
+    }
+
+    #[test]
+    fn details_kube_state_metrics_alerts() {
+        let cases: [(Labels, Option<(&str, &str)>); 4] = [
+            (
+                &[
+                    ("job", "kube-state-metrics"),
+                    ("namespace", "ops"),
+                    ("pod", "web-1"),
+                    ("container", "app"),
+                ],
+                Some(("ops/web-1", "pod `web-1`, container `app`")),
+            ),
+            (
+                &[
+                    ("job", "kube-state-metrics"),
+                    ("persistentvolumeclaim", "data_1"),
+                ],
+                Some(("data\\_1", "PVC `data_1`")),
+            ),
+            (&[("job", "kube-state-metrics-old"), ("pod", "p")], None),
+            (&[("job", "kube-state-metrics"), ("namespace", "ops")], None),
+        ];
+        for (labels, expected) in cases {
+            let found = enrichment(Preset::KubeStateMetrics, labels);
+            let found =
+                found.map(|e| (e.instance.unwrap_or_default(), e.details));
+            assert_eq!(
+                found.as_ref().map(|(i, d)| (i.as_str(), d.as_str())),
+                expected,
+                "{:?}",
+                labels
+            );
+        }
+    }
+
+    #[test]
+    fn details_blackbox_alerts() {
+        let cases: [(Labels, Option<&str>); 5] = [
+            (
+                &[("job", "blackbox"), ("module", "http_2xx"), ("instance", "https://a.example/x_(1)")],
+                Some("probe `http_2xx` of [https://a.example/x\\_\\(1\\)](https://a.example/x_%281%29)"),
+            ),
+            (&[("job", "blackbox-icmp"), ("instance", "10.0.0.1")], Some("probe")),
+            (&[("job", "blackbox_exporter"), ("module", "tcp")], Some("probe `tcp`")),
+            (&[("job", "blackbox"), ("instance", "ftp://a.example")], Some("probe")),
+            (&[("job", "not-blackbox"), ("module", "tcp")], None),
+        ];
+        for (labels, details) in cases {
+            let found = enrichment(Preset::BlackboxExporter, labels);
+            assert_eq!(
+                found.map(|e| e.details).as_deref(),
+                details,
+                "{:?}",
+                labels
+            );
+        }
+    }
+
+    #[test]
+    fn names_kubernetes_pods() {
+        let cases: [(Labels, Option<&str>); 3] = [
+            (
+                &[("namespace", "ops"), ("pod", "web-1"), ("container", "app")],
+                Some("ops/web-1[app]"),
+            ),
+            (
+                &[("namespace", "ops"), ("pod", "web_1")],
+                Some("ops/web\\_1"),
+            ),
+            (&[("pod", "web-1")], None),
+        ];
+        for (labels, instance) in cases {
+            let found = enrichment(Preset::Kubernetes, labels);
+            assert_eq!(
+                found.and_then(|e| e.instance).as_deref(),
+                instance,
+                "{:?}",
+                labels
+            );
+        }
+    }
+}
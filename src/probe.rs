@@ -0,0 +1,179 @@
+use crate::alert::Alert;
+use crate::alert::AlertGroup;
+use crate::alert::Status;
+use crate::log;
+use crate::preset;
+use crate::preset::Preset;
+use crate::sync::Recover;
+use crate::App;
+use anyhow::Context;
+use anyhow::Result;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+/// The Prometheus the last results of blackbox probes are queried from.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ProbeConfig {
+    pub prometheus_url: String,
+}
+
+/// The last result of a blackbox probe.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Probe {
+    pub duration: Option<f64>,
+    pub status_code: Option<u16>,
+}
+
+#[derive(Deserialize)]
+struct Sample {
+    metric: HashMap<String, String>,
+    value: (f64, String),
+}
+
+#[derive(Deserialize)]
+struct Data {
+    result: Vec<Sample>,
+}
+
+#[derive(Deserialize)]
+struct QueryResponse {
+    data: Data,
+}
+
+/// How long the results of a probe are reused for the following alerts
+/// of its target.
+const TTL: Duration = Duration::from_secs(30);
+
+/// How long Prometheus is given to answer, the notification waiting on it.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A probed target, by the `instance` and `job` of its alerts.
+type Target = (String, String);
+
+fn target(labels: &HashMap<String, String>) -> Target {
+    let label = |name| labels.get(name).cloned().unwrap_or_default();
+    (label("instance"), label("job"))
+}
+
+/// The last probe results by target, as recently queried.
+#[derive(Default)]
+pub struct Probes(Mutex<HashMap<Target, (Instant, Probe)>>);
+
+impl Probes {
+    fn get(&self, target: &Target) -> Option<Probe> {
+        let mut probes = self.0.lock().recover();
+        probes.retain(|_, (at, _)| at.elapsed() < TTL);
+        probes.get(target).map(|(_, probe)| *probe)
+    }
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Matches any of the values exactly.
+fn any_of<'a>(values: impl Iterator<Item = &'a str>) -> String {
+    let mut values: Vec<_> = values.map(regex::escape).collect();
+    values.sort();
+    values.dedup();
+    quote(&values.join("|"))
+}
+
+/// The last results of the probes of the targets, in a single query.
+fn query(
+    app: &App,
+    base_url: &str,
+    targets: &[Target],
+) -> Result<HashMap<Target, Probe>> {
+    let selector = format!(
+        "{{__name__=~\"probe_duration_seconds|probe_http_status_code\",\
+         instance=~{},job=~{}}}",
+        any_of(targets.iter().map(|(instance, _)| instance.as_str())),
+        any_of(targets.iter().map(|(_, job)| job.as_str())),
+    );
+    let url = format!("{}/api/v1/query", base_url.trim_end_matches('/'));
+    let response: QueryResponse = app
+        .client
+        .get(&url)
+        .query(&[("query", selector)])
+        .timeout(QUERY_TIMEOUT)
+        .send()
+        .and_then(|r| r.error_for_status())
+        .and_then(|r| r.json())
+        .with_context(|| format!("cannot query {}", url))?;
+    // Targets without results are remembered all the same.
+    let mut probes: HashMap<Target, Probe> = targets
+        .iter()
+        .map(|t| (t.clone(), Probe::default()))
+        .collect();
+    for sample in response.data.result {
+        let Some(probe) = probes.get_mut(&target(&sample.metric)) else {
+            continue;
+        };
+        let value = sample.value.1.parse::<f64>().ok();
+        match sample.metric.get("__name__").map(String::as_str) {
+            Some("probe_duration_seconds") => probe.duration = value,
+            // 0 when the probe got no response at all.
+            Some("probe_http_status_code") => {
+                probe.status_code = value.map(|v| v as u16).filter(|c| *c > 0)
+            }
+            _ => {}
+        }
+    }
+    Ok(probes)
+}
+
+/// Notes the last probe results of the group's firing blackbox alerts,
+/// querying those of the targets not looked up recently at once.
+pub fn attach(app: &App, group: &mut AlertGroup) {
+    let Some(config) = &app.config.probes else {
+        return;
+    };
+    if !app.config.presets.contains(&Preset::BlackboxExporter) {
+        return;
+    }
+    let probed = |alert: &Alert| {
+        alert.status == Status::Firing && preset::is_probe(&alert.labels)
+    };
+    let mut missing: Vec<Target> = Vec::new();
+    for alert in group.alerts.iter_mut().filter(|a| probed(a)) {
+        let target = target(&alert.labels);
+        alert.probe = app.probes.get(&target);
+        if alert.probe.is_none() && !missing.contains(&target) {
+            missing.push(target);
+        }
+    }
+    if missing.is_empty() {
+        return;
+    }
+    let probes = match query(app, &config.prometheus_url, &missing) {
+        Ok(probes) => probes,
+        Err(e) => return log::warning(format!("{:#}", e)),
+    };
+    for alert in group.alerts.iter_mut().filter(|a| probed(a)) {
+        if let Some(probe) = probes.get(&target(&alert.labels)) {
+            alert.probe = Some(*probe);
+        }
+    }
+    let now = Instant::now();
+    let mut cached = app.probes.0.lock().recover();
+    cached.extend(probes.into_iter().map(|(t, probe)| (t, (now, probe))));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_any_target_exactly() {
+        assert_eq!(
+            any_of(["https://a.example/?q=1", "b", "b"].into_iter()),
+            r#""b|https://a\\.example/\\?q=1""#
+        );
+        assert_eq!(any_of([r#"say "hi""#].into_iter()), r#""say \"hi\"""#);
+    }
+}
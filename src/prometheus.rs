@@ -0,0 +1,86 @@
+use crate::alert::fingerprint;
+use crate::alert::Alert;
+use crate::alert::AlertGroup;
+use crate::alert::Annotations;
+use crate::alert::Status;
+use crate::log;
+use crate::state::Store;
+use crate::App;
+use chrono::DateTime;
+use chrono::Utc;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// An alert as Prometheus posts it to Alertmanager's `/api/v2/alerts`.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PostableAlert {
+    labels: HashMap<String, String>,
+    #[serde(default)]
+    annotations: HashMap<String, String>,
+    starts_at: Option<DateTime<Utc>>,
+    ends_at: Option<DateTime<Utc>>,
+}
+
+impl From<PostableAlert> for Alert {
+    fn from(alert: PostableAlert) -> Alert {
+        let now = Utc::now();
+        // Prometheus keeps pushing firing alerts with an end a few
+        // evaluation intervals out, and resolved ones with an end in the past.
+        let status = match alert.ends_at {
+            Some(ends_at) if ends_at <= now => Status::Resolved,
+            _ => Status::Firing,
+        };
+        Alert {
+            status,
+            fingerprint: fingerprint(&alert.labels),
+            labels: alert.labels,
+            annotations: Annotations::from_map(alert.annotations),
+            starts_at: alert.starts_at.unwrap_or(now),
+            ends_at: alert.ends_at.unwrap_or_default(),
+            values: None,
+            value_string: None,
+            ticket: None,
+            silences: None,
+            probe: None,
+            host: None,
+            suppressed: false,
+        }
+    }
+}
+
+/// Groups the alerts whose status changed. Prometheus resends the active
+/// alerts on every evaluation and the resolved ones for a while.
+pub fn groups(alerts: Vec<PostableAlert>, store: &Store) -> Vec<AlertGroup> {
+    let alerts: Vec<Alert> = alerts.into_iter().map(Alert::from).collect();
+    store.extend(&alerts);
+    let alerts = alerts
+        .into_iter()
+        .filter(|a| {
+            store.is_firing(&a.fingerprint) != (a.status == Status::Firing)
+        })
+        .collect();
+    AlertGroup::by_alertname(String::new(), String::new(), alerts)
+}
+
+/// Resolves the alerts whose end passed without Prometheus sending them
+/// again, as Alertmanager would.
+pub fn start(app: Arc<App>) {
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs(10));
+        let expired = app.store.expired();
+        if expired.is_empty() {
+            continue;
+        }
+        for group in
+            AlertGroup::by_alertname(String::new(), String::new(), expired)
+        {
+            if let Err(e) = crate::forward_alert(&app, group) {
+                log::error(format!("cannot resolve expired alerts: {:#}", e));
+            }
+        }
+    });
+}
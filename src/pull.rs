@@ -0,0 +1,248 @@
+use crate::alert::Alert;
+use crate::alert::AlertGroup;
+use crate::alert::Annotations;
+use crate::alert::Status;
+use crate::log;
+use crate::App;
+use anyhow::Context;
+use anyhow::Result;
+use chrono::DateTime;
+use chrono::TimeDelta;
+use chrono::Utc;
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PullConfig {
+    /// Base URL of the Alertmanager, e.g. http://alertmanager:9093.
+    pub url: String,
+    #[serde(with = "humantime_serde", default = "default_interval")]
+    pub interval: Duration,
+    /// Only alerts routed to this Alertmanager receiver (a regex).
+    pub receiver: Option<String>,
+    #[serde(default)]
+    pub suppressed: Suppressed,
+}
+
+fn default_interval() -> Duration {
+    Duration::from_secs(60)
+}
+
+/// Catches up with Alertmanager once at startup and then, if set, every
+/// `interval`, so that alerts that changed while the bridge was down are
+/// still notified.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ReconcileConfig {
+    pub url: String,
+    #[serde(with = "humantime_serde", default)]
+    pub interval: Option<Duration>,
+    pub receiver: Option<String>,
+    #[serde(default)]
+    pub suppressed: Suppressed,
+    /// Alertmanager's `group_wait`: alerts that started firing more
+    /// recently, plus a margin, are left for Alertmanager to send.
+    #[serde(with = "humantime_serde", default = "default_group_wait")]
+    pub group_wait: Duration,
+}
+
+fn default_group_wait() -> Duration {
+    Duration::from_secs(30)
+}
+
+/// Allowance for Alertmanager's evaluation and delivery after `group_wait`.
+const GROUP_WAIT_MARGIN: Duration = Duration::from_secs(15);
+
+/// What is done with the alerts Alertmanager silenced or inhibited.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Suppressed {
+    /// Not notified, nor resolved while they stay suppressed.
+    #[default]
+    Skip,
+    /// Notified like the others, marked and greyed out.
+    Show,
+}
+
+#[derive(Deserialize)]
+struct ApiReceiver {
+    name: String,
+}
+
+#[derive(Deserialize, Default)]
+struct ApiStatus {
+    /// `active`, `suppressed` or `unprocessed`.
+    state: String,
+}
+
+/// An alert as returned by `GET /api/v2/alerts`.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiAlert {
+    labels: HashMap<String, String>,
+    #[serde(default)]
+    annotations: HashMap<String, String>,
+    starts_at: DateTime<Utc>,
+    ends_at: DateTime<Utc>,
+    fingerprint: String,
+    #[serde(default)]
+    receivers: Vec<ApiReceiver>,
+    #[serde(default)]
+    status: ApiStatus,
+}
+
+impl ApiAlert {
+    fn receiver(&self) -> String {
+        self.receivers
+            .first()
+            .map_or_else(String::new, |r| r.name.clone())
+    }
+}
+
+impl From<ApiAlert> for Alert {
+    fn from(alert: ApiAlert) -> Alert {
+        Alert {
+            status: Status::Firing,
+            labels: alert.labels,
+            annotations: Annotations::from_map(alert.annotations),
+            starts_at: alert.starts_at,
+            ends_at: alert.ends_at,
+            fingerprint: alert.fingerprint,
+            values: None,
+            value_string: None,
+            ticket: None,
+            silences: None,
+            probe: None,
+            host: None,
+            suppressed: alert.status.state == "suppressed",
+        }
+    }
+}
+
+/// The alerts currently firing, silenced and inhibited ones included,
+/// with the receiver they are routed to.
+fn active_alerts(
+    app: &App,
+    base_url: &str,
+    receiver: Option<&str>,
+) -> Result<Vec<(String, Alert)>> {
+    let mut query = vec![
+        ("active", "true"),
+        ("silenced", "true"),
+        ("inhibited", "true"),
+    ];
+    if let Some(receiver) = receiver {
+        query.push(("receiver", receiver));
+    }
+    let url = format!("{}/api/v2/alerts", base_url.trim_end_matches('/'));
+    let alerts: Vec<ApiAlert> = app
+        .client
+        .get(&url)
+        .query(&query)
+        .send()
+        .and_then(|r| r.error_for_status())
+        .and_then(|r| r.json())
+        .with_context(|| format!("cannot query {}", url))?;
+    Ok(alerts
+        .into_iter()
+        .map(|a| (a.receiver(), Alert::from(a)))
+        .collect())
+}
+
+/// Notifies the alerts that started firing since the state store was last
+/// updated, at least `min_age` ago, and resolves those that are no longer
+/// active, limited to the receivers matching `receiver` like Alertmanager
+/// does.
+pub fn sync(
+    app: &App,
+    base_url: &str,
+    receiver: Option<&str>,
+    suppressed: Suppressed,
+    min_age: Duration,
+) -> Result<()> {
+    let scope = receiver
+        .map(|r| Regex::new(&format!("^(?:{})$", r)))
+        .transpose()
+        .context("invalid receiver regex")?;
+    let active = active_alerts(app, base_url, receiver)?;
+    let stored = app.store.firing();
+    let notified_suppressed = app.store.suppressed();
+
+    let now = Utc::now();
+    let min_age = TimeDelta::from_std(min_age).unwrap_or(TimeDelta::MAX);
+    let fingerprints: HashSet<_> =
+        active.iter().map(|(_, a)| a.fingerprint.clone()).collect();
+    app.reminders.set_suppressed(
+        active
+            .iter()
+            .map(|(_, a)| (a.fingerprint.as_str(), a.suppressed)),
+    );
+    let mut by_receiver: HashMap<String, Vec<Alert>> = HashMap::new();
+    for (receiver, alert) in active {
+        if alert.suppressed && suppressed == Suppressed::Skip {
+            continue;
+        }
+        if now - alert.starts_at < min_age {
+            continue;
+        }
+        // Shown while suppressed, they are notified again once active.
+        let unsuppressed = !alert.suppressed
+            && notified_suppressed.contains(&alert.fingerprint);
+        if !stored.contains_key(&alert.fingerprint) || unsuppressed {
+            by_receiver.entry(receiver).or_default().push(alert);
+        }
+    }
+    for (fingerprint, alert) in &stored {
+        let in_scope =
+            scope.as_ref().is_none_or(|s| s.is_match(&alert.receiver));
+        if in_scope && !fingerprints.contains(fingerprint) {
+            by_receiver
+                .entry(alert.receiver.clone())
+                .or_default()
+                .push(alert.resolved(fingerprint, now));
+        }
+    }
+
+    for (receiver, alerts) in by_receiver {
+        let groups =
+            AlertGroup::by_alertname(receiver, base_url.to_string(), alerts);
+        for group in groups {
+            crate::forward_alert(app, group)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn start(app: Arc<App>, config: PullConfig) {
+    thread::spawn(move || loop {
+        let receiver = config.receiver.as_deref();
+        let suppressed = config.suppressed;
+        let synced =
+            sync(&app, &config.url, receiver, suppressed, Duration::ZERO);
+        if let Err(e) = synced {
+            log::error(format!("{:#}", e));
+        }
+        thread::sleep(config.interval);
+    });
+}
+
+pub fn start_reconcile(app: Arc<App>, config: ReconcileConfig) {
+    thread::spawn(move || loop {
+        let receiver = config.receiver.as_deref();
+        let min_age = config.group_wait + GROUP_WAIT_MARGIN;
+        match sync(&app, &config.url, receiver, config.suppressed, min_age) {
+            Ok(()) => log::info(format!("reconciled with {}", config.url)),
+            Err(e) => log::error(format!("cannot reconcile: {:#}", e)),
+        }
+        match config.interval {
+            Some(interval) => thread::sleep(interval),
+            None => return,
+        }
+    });
+}
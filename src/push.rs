@@ -0,0 +1,128 @@
+use crate::log;
+use crate::status;
+use crate::App;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// A Pushgateway the self-metrics are pushed to, for bridges that cannot
+/// be scraped.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PushConfig {
+    /// Base URL of the Pushgateway, e.g. http://pushgateway:9091.
+    pub url: String,
+    #[serde(default = "default_job")]
+    pub job: String,
+    /// Grouping labels, usually telling the instances apart.
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    #[serde(with = "humantime_serde", default = "default_interval")]
+    pub interval: Duration,
+}
+
+fn default_job() -> String {
+    String::from(env!("CARGO_PKG_NAME"))
+}
+
+fn default_interval() -> Duration {
+    Duration::from_secs(60)
+}
+
+impl PushConfig {
+    /// `<url>/metrics/job/<job>/<label>/<value>...`, each part escaped.
+    pub fn group_url(&self) -> Result<url::Url> {
+        // The Pushgateway would need those base64 encoded.
+        let mut values = self.labels.values().chain([&self.job]);
+        if let Some(value) = values.find(|v| v.is_empty() || v.contains('/')) {
+            bail!("Pushgateway label value {:?} is empty or has a /", value);
+        }
+        let mut url = url::Url::parse(&self.url)
+            .with_context(|| format!("invalid Pushgateway URL {}", self.url))?;
+        url.path_segments_mut()
+            .map_err(|_| {
+                anyhow::anyhow!("invalid Pushgateway URL {}", self.url)
+            })?
+            .pop_if_empty()
+            .extend(["metrics", "job", &self.job])
+            .extend(self.labels.iter().flat_map(|(k, v)| [k, v]));
+        Ok(url)
+    }
+}
+
+/// Replaces the metrics of the group with the current ones.
+fn push(app: &App, url: &url::Url) -> Result<()> {
+    app.client
+        .put(url.clone())
+        .header("Content-Type", "text/plain; version=0.0.4")
+        .body(status::exposition(app))
+        .send()
+        .and_then(|r| r.error_for_status())
+        .with_context(|| format!("cannot push metrics to {}", url))?;
+    Ok(())
+}
+
+/// Pushes the metrics every `interval`.
+pub fn start(app: Arc<App>) -> Result<()> {
+    let Some(config) = app.config.push.clone() else {
+        return Ok(());
+    };
+    let url = config.group_url()?;
+    thread::spawn(move || loop {
+        if let Err(e) = push(&app, &url) {
+            log::error(format!("{:#}", e));
+        }
+        thread::sleep(config.interval);
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(url: &str, labels: &[(&str, &str)]) -> PushConfig {
+        PushConfig {
+            url: url.to_string(),
+            job: String::from("bridge"),
+            labels: labels
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            interval: default_interval(),
+        }
+    }
+
+    #[test]
+    fn escapes_the_grouping_labels() {
+        let url = config(
+            "http://pushgateway:9091/",
+            &[("zone", "eu west"), ("instance", "a:9094")],
+        )
+        .group_url()
+        .unwrap();
+        assert_eq!(
+            url.as_str(),
+            "http://pushgateway:9091/metrics/job/bridge\
+             /instance/a:9094/zone/eu%20west"
+        );
+        let url = config("http://gw/prefix", &[]).group_url().unwrap();
+        assert_eq!(url.as_str(), "http://gw/prefix/metrics/job/bridge");
+    }
+
+    #[test]
+    fn rejects_values_the_pushgateway_would_misread() {
+        for value in ["", "a/b"] {
+            let e = config("http://gw", &[("zone", value)])
+                .group_url()
+                .unwrap_err();
+            assert!(e.to_string().contains("is empty or has a /"), "{}", e);
+        }
+        assert!(config("gw:9091", &[]).group_url().is_err());
+    }
+}
@@ -0,0 +1,722 @@
+use crate::batch::Batch;
+use crate::breaker::Breaker;
+use crate::chaos::Chaos;
+use crate::dedup::Dedup;
+use crate::discord;
+use crate::discord::DeliveryError;
+use crate::discord::DiscordContent;
+use crate::incident::Thread;
+use crate::log;
+use crate::metrics;
+use crate::metrics::Metrics;
+use crate::receipt;
+#[cfg(feature = "twilio")]
+use crate::sink::twilio::Sms;
+use crate::state::Store;
+use crate::sync::Recover;
+use anyhow::Context;
+use anyhow::Result;
+use chrono::Utc;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Longest wait between two attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(600);
+
+/// How long to wait before the attempt after `attempt`, doubling `base`
+/// each time up to `MAX_BACKOFF`.
+pub fn backoff(base: Duration, attempt: u32) -> Duration {
+    let factor = 2u32.checked_pow(attempt).unwrap_or(u32::MAX);
+    base.saturating_mul(factor).min(MAX_BACKOFF)
+}
+
+/// What happens to new notifications while the queue is full.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Overflow {
+    /// Hold the incoming request until there is room, pushing back on
+    /// Alertmanager.
+    Block,
+    /// Drop the least severe notification, queued or incoming.
+    Shed,
+}
+
+/// The error of a notification the queue had no room for in time.
+#[derive(Debug)]
+pub struct Full;
+
+impl fmt::Display for Full {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("the delivery queue is full")
+    }
+}
+
+impl std::error::Error for Full {}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct QueueConfig {
+    pub workers: usize,
+    pub capacity: usize,
+    pub overflow: Overflow,
+    /// Attempts after the first failed one, waiting `retry_backoff`,
+    /// then twice as long and so on, or as long as Discord asks to.
+    pub retries: u32,
+    #[serde(with = "humantime_serde")]
+    pub retry_backoff: Duration,
+    /// Bytes of queued messages kept in memory, the next ones being
+    /// written to `spill_dir` until the queue drains.
+    pub max_memory: Option<usize>,
+    pub spill_dir: Option<String>,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        QueueConfig {
+            workers: 4,
+            capacity: 1024,
+            overflow: Overflow::Block,
+            retries: 3,
+            retry_backoff: Duration::from_secs(1),
+            max_memory: None,
+            spill_dir: None,
+        }
+    }
+}
+
+/// A message to deliver, as also written to the spill directory.
+#[derive(Serialize, Deserialize)]
+pub struct Job {
+    pub url: String,
+    pub content: DiscordContent,
+    /// Severity rank of the notification, 0 being the most severe.
+    pub rank: usize,
+    /// Told the outcome of the delivery, for receipts.
+    #[serde(skip)]
+    pub receipt: Option<Sender<receipt::Outcome>>,
+    /// The batch of the notification and the chunk the job is.
+    #[serde(skip)]
+    pub batch: Option<(Arc<Batch>, u64)>,
+    pub thread: Option<Thread>,
+    /// Texted when Discord cannot be reached, for severe alerts.
+    #[serde(default)]
+    pub sms: Option<String>,
+    /// The dedup key of the message, recorded once it is sent.
+    #[serde(default)]
+    pub dedup: Option<u64>,
+    /// The next messages of the notification, sent after this one by the
+    /// same worker so that they arrive in order.
+    pub then: Vec<Job>,
+}
+
+impl Job {
+    /// The messages of the job, this one and those sent after it.
+    pub fn messages(&self) -> usize {
+        1 + self.then.iter().map(Job::messages).sum::<usize>()
+    }
+
+    /// Jobs waited on by a receipt or tracked by a batch stay in memory.
+    fn spillable(&self) -> bool {
+        self.receipt.is_none()
+            && self.batch.is_none()
+            && self.then.iter().all(Job::spillable)
+    }
+
+    fn report(&self, outcome: receipt::Outcome) {
+        if let (Some((batch, chunk)), Ok(_)) = (&self.batch, &outcome) {
+            batch.sent(*chunk);
+        }
+        if let Some(receipt) = &self.receipt {
+            // The notification may have stopped waiting.
+            let _ = receipt.send(outcome);
+        }
+    }
+}
+
+#[derive(Default)]
+struct Jobs {
+    /// The jobs in memory with their size, counted when spilling is on.
+    queued: VecDeque<(Job, usize)>,
+    bytes: usize,
+    /// The files of the spilled jobs with their rank, oldest first.
+    spilled: VecDeque<(PathBuf, usize)>,
+    next_spill: u64,
+    /// Jobs being written to the spill directory, holding their place.
+    writing: usize,
+    /// Jobs taken by a worker and not delivered yet.
+    active: usize,
+}
+
+impl Jobs {
+    fn len(&self) -> usize {
+        self.queued.len() + self.spilled.len() + self.writing
+    }
+
+    /// Takes the least severe job, the last queued of its rank, if less
+    /// severe than `rank`.
+    fn shed(&mut self, rank: usize) -> Option<Shed> {
+        let queued = self.queued.iter().map(|(job, _)| job.rank);
+        let spilled = self.spilled.iter().map(|(_, rank)| *rank);
+        let (index, least) = queued
+            .chain(spilled)
+            .enumerate()
+            .max_by_key(|(i, rank)| (*rank, *i))?;
+        if least <= rank {
+            return None;
+        }
+        if let Some(index) = index.checked_sub(self.queued.len()) {
+            let (path, _) = self.spilled.remove(index)?;
+            return Some(Shed::Spilled(path));
+        }
+        let (job, size) = self.queued.remove(index)?;
+        self.bytes -= size;
+        Some(Shed::Queued(Box::new(job)))
+    }
+}
+
+/// A job dropped to make room, read back once the lock is released when
+/// it was spilled.
+enum Shed {
+    Queued(Box<Job>),
+    Spilled(PathBuf),
+}
+
+impl Shed {
+    fn job(self) -> Option<Job> {
+        match self {
+            Shed::Queued(job) => Some(*job),
+            Shed::Spilled(path) => unspill(&path)
+                .map_err(|e| log::error(format!("{:#}", e)))
+                .ok(),
+        }
+    }
+}
+
+/// The sequence number and rank of a spilled job, from its file name,
+/// files of older versions having no rank and never being shed.
+fn spilled(path: &Path) -> Option<(u64, usize)> {
+    let stem = path.file_stem()?.to_str()?;
+    match stem.split_once('-') {
+        Some((sequence, rank)) => {
+            Some((sequence.parse().ok()?, rank.parse().ok()?))
+        }
+        None => Some((stem.parse().ok()?, 0)),
+    }
+}
+
+pub struct Queue {
+    config: QueueConfig,
+    jobs: Mutex<Jobs>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    idle: Condvar,
+    spills: AtomicU64,
+}
+
+impl Queue {
+    /// Picks up the jobs spilled by a previous run, if any.
+    pub fn new(config: QueueConfig) -> Result<Queue> {
+        let mut jobs = Jobs::default();
+        if let Some(dir) = &config.spill_dir {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("cannot create {}", dir))?;
+            let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+                .with_context(|| format!("cannot read {}", dir))?
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|path| path.extension().is_some_and(|e| e == "json"))
+                .collect();
+            files.sort();
+            let files: Vec<_> = files
+                .into_iter()
+                .filter_map(|path| Some((spilled(&path)?, path)))
+                .collect();
+            jobs.next_spill = files
+                .iter()
+                .map(|((sequence, _), _)| sequence + 1)
+                .max()
+                .unwrap_or(0);
+            jobs.spilled = files
+                .into_iter()
+                .map(|((_, rank), path)| (path, rank))
+                .collect();
+        }
+        Ok(Queue {
+            config,
+            jobs: Mutex::new(jobs),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            idle: Condvar::new(),
+            spills: AtomicU64::new(0),
+        })
+    }
+
+    /// Queues a job, returning the job dropped to make room, if any.
+    pub fn push(&self, job: Job) -> Option<Job> {
+        self.push_until(job, None).unwrap_or_else(|job| Some(*job))
+    }
+
+    /// Like `push`, but a full queue blocking gives the job back, not
+    /// queued, once the deadline is past.
+    pub fn push_until(
+        &self,
+        job: Job,
+        deadline: Option<Instant>,
+    ) -> Result<Option<Job>, Box<Job>> {
+        // Sized, and serialized in case it is spilled, outside of the lock.
+        let spill_to = match (&self.config.spill_dir, self.config.max_memory) {
+            (Some(dir), Some(max)) => {
+                Some((dir, max, serde_json::to_vec(&job).unwrap_or_default()))
+            }
+            _ => None,
+        };
+        let size = spill_to.as_ref().map_or(0, |(_, _, data)| data.len());
+        let mut jobs = self.jobs.lock().recover();
+        let mut shed = None;
+        match self.config.overflow {
+            Overflow::Block => {
+                while jobs.len() >= self.config.capacity {
+                    let Some(deadline) = deadline else {
+                        jobs = self.not_full.wait(jobs).recover();
+                        continue;
+                    };
+                    let timeout =
+                        deadline.saturating_duration_since(Instant::now());
+                    if timeout.is_zero() {
+                        return Err(Box::new(job));
+                    }
+                    jobs =
+                        self.not_full.wait_timeout(jobs, timeout).recover().0;
+                }
+            }
+            Overflow::Shed if jobs.len() >= self.config.capacity => {
+                match jobs.shed(job.rank) {
+                    Some(least) => shed = Some(least),
+                    None => return Ok(Some(job)),
+                }
+            }
+            Overflow::Shed => {}
+        }
+        if let Some((dir, max, data)) = &spill_to {
+            // Once spilling, until the spilled jobs are picked up, so that
+            // they keep their order.
+            let spilling = !jobs.spilled.is_empty() || jobs.writing > 0;
+            if (jobs.bytes + size > *max || spilling) && job.spillable() {
+                let path = Path::new(dir)
+                    .join(format!("{:020}-{}.json", jobs.next_spill, job.rank));
+                jobs.next_spill += 1;
+                jobs.writing += 1;
+                drop(jobs);
+                let written = spill(&path, data);
+                jobs = self.jobs.lock().recover();
+                jobs.writing -= 1;
+                match written {
+                    Ok(()) => {
+                        let at =
+                            jobs.spilled.partition_point(|(p, _)| *p < path);
+                        jobs.spilled.insert(at, (path, job.rank));
+                        metrics::inc(&self.spills);
+                        self.not_empty.notify_one();
+                        drop(jobs);
+                        return Ok(shed.and_then(Shed::job));
+                    }
+                    Err(e) => log::error(format!(
+                        "cannot spill to {}, keeping in memory: {}",
+                        path.display(),
+                        e
+                    )),
+                }
+            }
+        }
+        jobs.bytes += size;
+        jobs.queued.push_back((job, size));
+        self.not_empty.notify_one();
+        drop(jobs);
+        Ok(shed.and_then(Shed::job))
+    }
+
+    pub fn pop(&self) -> Job {
+        let mut jobs = self.jobs.lock().recover();
+        loop {
+            if let Some((job, size)) = jobs.queued.pop_front() {
+                jobs.bytes -= size;
+                jobs.active += 1;
+                self.not_full.notify_one();
+                return job;
+            }
+            if let Some((path, _)) = jobs.spilled.pop_front() {
+                jobs.active += 1;
+                self.not_full.notify_one();
+                // Other workers go on while the file is read.
+                drop(jobs);
+                match unspill(&path) {
+                    Ok(job) => return job,
+                    Err(e) => log::error(format!("{:#}", e)),
+                }
+                jobs = self.jobs.lock().recover();
+                jobs.active -= 1;
+                continue;
+            }
+            jobs = self.not_empty.wait(jobs).recover();
+        }
+    }
+
+    /// Tells that the job last popped by a worker was delivered.
+    fn done(&self) {
+        let mut jobs = self.jobs.lock().recover();
+        jobs.active -= 1;
+        if jobs.active == 0 && jobs.len() == 0 {
+            self.idle.notify_all();
+        }
+    }
+
+    /// Waits for every queued job to be delivered, false if the deadline
+    /// came first.
+    pub fn drain(&self, deadline: Instant) -> bool {
+        let mut jobs = self.jobs.lock().recover();
+        while jobs.active > 0 || jobs.len() > 0 {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                return false;
+            }
+            jobs = self.idle.wait_timeout(jobs, timeout).recover().0;
+        }
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.jobs.lock().recover().len()
+    }
+
+    /// Jobs waiting in the spill directory.
+    pub fn spilled(&self) -> usize {
+        self.jobs.lock().recover().spilled.len()
+    }
+
+    /// Jobs written to the spill directory since startup.
+    pub fn spills(&self) -> u64 {
+        metrics::get(&self.spills)
+    }
+
+    /// Size of the jobs in memory, when spilling is on.
+    pub fn memory_bytes(&self) -> usize {
+        self.jobs.lock().recover().bytes
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.config.capacity
+    }
+}
+
+/// Writes a spilled job, readable by the bridge only as it may carry
+/// mentions and links.
+fn spill(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(data)
+}
+
+/// Reads a spilled job, removing its file.
+fn unspill(path: &Path) -> Result<Job> {
+    let data = std::fs::read(path)
+        .with_context(|| format!("cannot read {}", path.display()))?;
+    let job = serde_json::from_slice(&data)
+        .with_context(|| format!("invalid spilled job {}", path.display()));
+    // The job is delivered all the same, again after a restart.
+    if let Err(e) = std::fs::remove_file(path) {
+        log::error(format!("cannot remove {}: {}", path.display(), e));
+    }
+    job
+}
+
+pub struct Workers {
+    pub client: reqwest::blocking::Client,
+    pub breaker: Arc<Breaker>,
+    pub metrics: Arc<Metrics>,
+    pub store: Arc<Store>,
+    pub dedup: Option<Arc<Dedup>>,
+    pub chaos: Chaos,
+    #[cfg(feature = "twilio")]
+    pub sms: Option<Arc<Sms>>,
+}
+
+/// Starts the threads delivering queued notifications.
+pub fn start_workers(queue: Arc<Queue>, workers: Workers) {
+    let workers = Arc::new(workers);
+    for _ in 0..queue.config.workers.max(1) {
+        let queue = queue.clone();
+        let workers = workers.clone();
+        thread::spawn(move || loop {
+            let job = queue.pop();
+            workers.deliver(&queue.config, &job);
+            for job in &job.then {
+                workers.deliver(&queue.config, job);
+            }
+            // Its batch is settled before the queue may be seen drained.
+            drop(job);
+            queue.done();
+        });
+    }
+}
+
+impl Workers {
+    fn deliver(&self, config: &QueueConfig, job: &Job) {
+        let mut attempt = 0;
+        loop {
+            self.breaker.wait();
+            let sent = match (self.chaos.inject(), &job.thread) {
+                (Err(error), _) => Err(error),
+                (Ok(()), Some(thread)) => self.send_in_thread(job, thread),
+                (Ok(()), None) => {
+                    let wait = job.receipt.is_some();
+                    discord::send(&self.client, &job.url, &job.content, wait)
+                }
+            };
+            let error = match sent {
+                Ok(id) => {
+                    self.breaker.success();
+                    metrics::inc(&self.metrics.messages_sent);
+                    let now = Utc::now().timestamp().max(0) as u64;
+                    metrics::set(&self.metrics.last_delivery, now);
+                    if let (Some(dedup), Some(key)) = (&self.dedup, job.dedup) {
+                        dedup.record(key);
+                    }
+                    job.report(Ok(id));
+                    return;
+                }
+                Err(error) => error,
+            };
+            metrics::inc(&self.metrics.delivery_failures);
+
+            let retry_after = match &error {
+                DeliveryError::Transient { retry_after, .. } => {
+                    self.breaker.failure();
+                    *retry_after
+                }
+                DeliveryError::Permanent(_) => {
+                    // Discord is up, it just does not want this message.
+                    self.breaker.success();
+                    attempt = config.retries;
+                    None
+                }
+            };
+            if attempt >= config.retries {
+                log::error(format!("giving up on notification: {}", error));
+                metrics::inc(&self.metrics.messages_dropped);
+                #[cfg(feature = "twilio")]
+                if let (
+                    Some(sms),
+                    Some(text),
+                    DeliveryError::Transient { .. },
+                ) = (&self.sms, &job.sms, &error)
+                {
+                    sms.escalate(text);
+                }
+                job.report(Err(error.to_string()));
+                return;
+            }
+            log::warning(format!(
+                "cannot deliver notification, retrying: {}",
+                error
+            ));
+            thread::sleep(
+                retry_after
+                    .unwrap_or_else(|| backoff(config.retry_backoff, attempt)),
+            );
+            attempt += 1;
+        }
+    }
+
+    fn send_in_thread(
+        &self,
+        job: &Job,
+        thread: &Thread,
+    ) -> Result<Option<String>, DeliveryError> {
+        let open = self.store.thread(&thread.key);
+        let sent = discord::send_in_thread(
+            &self.client,
+            &job.url,
+            &job.content,
+            open.as_deref(),
+            &thread.name,
+        );
+        match sent {
+            Ok((id, thread_id)) => {
+                if thread.close {
+                    self.store.set_thread(&thread.key, None);
+                } else if open.is_none() {
+                    self.store.set_thread(&thread.key, Some(&thread_id));
+                }
+                Ok(id)
+            }
+            // Deleted or locked: the retry opens a new thread.
+            Err(DeliveryError::Permanent(error)) if open.is_some() => {
+                self.store.set_thread(&thread.key, None);
+                Err(DeliveryError::Transient {
+                    error,
+                    retry_after: None,
+                })
+            }
+            Err(error) => Err(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn job(rank: usize, then: usize) -> Job {
+        Job {
+            url: String::from("https://discord.invalid/api/webhooks/1/a"),
+            content: DiscordContent {
+                content: Some(format!("rank {}", rank)),
+                embeds: Vec::new(),
+                allowed_mentions: None,
+                tts: false,
+                components: Vec::new(),
+            },
+            rank,
+            receipt: None,
+            batch: None,
+            thread: None,
+            sms: None,
+            dedup: None,
+            then: (0..then).map(|_| job(rank, 0)).collect(),
+        }
+    }
+
+    #[test]
+    fn sheds_the_least_severe_notification() {
+        let queue = Queue::new(QueueConfig {
+            capacity: 2,
+            overflow: Overflow::Shed,
+            ..QueueConfig::default()
+        })
+        .unwrap();
+        assert!(queue.push(job(1, 2)).is_none());
+        assert!(queue.push(job(0, 0)).is_none());
+        let shed = queue.push(job(0, 1)).unwrap();
+        assert_eq!((shed.rank, shed.messages()), (1, 3));
+        let shed = queue.push(job(2, 0)).unwrap();
+        assert_eq!((shed.rank, shed.messages()), (2, 1));
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.pop().messages(), 1);
+        assert_eq!(queue.pop().messages(), 2);
+    }
+
+    #[test]
+    fn gives_jobs_back_past_the_deadline() {
+        let queue = Queue::new(QueueConfig {
+            capacity: 1,
+            overflow: Overflow::Block,
+            ..QueueConfig::default()
+        })
+        .unwrap();
+        assert!(queue.push(job(0, 0)).is_none());
+        let deadline = Instant::now() + Duration::from_millis(10);
+        let full = queue.push_until(job(1, 0), Some(deadline));
+        assert_eq!(full.err().map(|job| job.rank), Some(1));
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn sheds_spilled_notifications() {
+        let dir = std::env::temp_dir()
+            .join(format!("bridge-shed-{}", std::process::id()));
+        let queue = Queue::new(QueueConfig {
+            capacity: 2,
+            overflow: Overflow::Shed,
+            max_memory: Some(1),
+            spill_dir: Some(dir.to_string_lossy().into_owned()),
+            ..QueueConfig::default()
+        })
+        .unwrap();
+        assert!(queue.push(job(2, 0)).is_none());
+        assert!(queue.push(job(0, 0)).is_none());
+        assert_eq!(queue.spilled(), 2);
+        let shed = queue.push(job(1, 0)).unwrap();
+        assert_eq!(shed.rank, 2);
+        assert!(queue.push(job(1, 0)).is_some());
+        drop(queue);
+
+        // The ranks are known again after a restart.
+        let queue = Queue::new(QueueConfig {
+            capacity: 2,
+            overflow: Overflow::Shed,
+            spill_dir: Some(dir.to_string_lossy().into_owned()),
+            ..QueueConfig::default()
+        })
+        .unwrap();
+        let shed = queue.push(job(0, 0)).unwrap();
+        assert_eq!(shed.rank, 1);
+        assert_eq!(queue.pop().rank, 0);
+        assert_eq!(queue.pop().rank, 0);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        std::fs::remove_dir(&dir).unwrap();
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let base = Duration::from_secs(1);
+        assert_eq!(backoff(base, 0), base);
+        assert_eq!(backoff(base, 3), Duration::from_secs(8));
+        assert_eq!(backoff(base, 31), MAX_BACKOFF);
+        assert_eq!(backoff(base, 32), MAX_BACKOFF);
+        assert_eq!(backoff(Duration::MAX, 1), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn spills_over_max_memory_in_order() {
+        let dir = std::env::temp_dir()
+            .join(format!("bridge-spill-{}", std::process::id()));
+        let queue = Queue::new(QueueConfig {
+            max_memory: Some(1),
+            spill_dir: Some(dir.to_string_lossy().into_owned()),
+            ..QueueConfig::default()
+        })
+        .unwrap();
+        let batch = Arc::new(Batch::new(
+            String::new(),
+            String::new(),
+            Default::default(),
+            Arc::default(),
+            Arc::default(),
+        ));
+        queue.push(job(0, 0));
+        queue.push(job(1, 1));
+        let mut tracked = job(2, 0);
+        tracked.batch = Some((batch, 0));
+        queue.push(tracked);
+        assert_eq!(queue.spilled(), 2);
+        for entry in std::fs::read_dir(&dir).unwrap() {
+            let mode = entry.unwrap().metadata().unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        // The batch job stays in memory, ahead of the spilled ones.
+        assert!(queue.pop().batch.is_some());
+        assert_eq!(queue.pop().rank, 0);
+        assert_eq!(queue.pop().messages(), 2);
+        assert_eq!(queue.len(), 0);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        std::fs::remove_dir(&dir).unwrap();
+    }
+}
@@ -0,0 +1,316 @@
+use crate::discord::AllowedMentions;
+use crate::discord::Color;
+use crate::discord::DiscordContent;
+use crate::discord::DiscordEmbed;
+use crate::log;
+use crate::queue::Job;
+use crate::queue::Queue;
+use crate::render::DESCRIPTION_LIMIT;
+use crate::sync::Recover;
+use regex::Regex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+const WINDOW: Duration = Duration::from_secs(60);
+/// Held notifications listed by title in a rollup, the rest are counted.
+const ROLLUP_TITLES: usize = 20;
+const MAX_CONTENT_CHARS: usize = 2000;
+
+struct Window {
+    start: Instant,
+    sent: u32,
+    held: Vec<Job>,
+}
+
+/// Caps the messages sent to each webhook per minute. Messages over the
+/// cap are held and sent as a single rollup when the minute is over.
+pub struct Limiter {
+    queue: Arc<Queue>,
+    windows: Mutex<HashMap<String, Window>>,
+}
+
+impl Limiter {
+    pub fn start(queue: Arc<Queue>) -> Arc<Limiter> {
+        let limiter = Arc::new(Limiter {
+            queue,
+            windows: Mutex::new(HashMap::new()),
+        });
+        let flusher = limiter.clone();
+        thread::spawn(move || loop {
+            thread::sleep(Duration::from_secs(1));
+            flusher.flush(false);
+        });
+        limiter
+    }
+
+    /// Returns the job if it may be sent now, holding it otherwise.
+    pub fn admit(&self, job: Job, per_minute: Option<u32>) -> Option<Job> {
+        let Some(limit) = per_minute else {
+            return Some(job);
+        };
+        let mut windows = self.windows.lock().recover();
+        let window = windows.entry(job.url.clone()).or_insert(Window {
+            start: Instant::now(),
+            sent: 0,
+            held: Vec::new(),
+        });
+        if window.sent < limit {
+            window.sent += 1;
+            Some(job)
+        } else {
+            window.held.push(job);
+            None
+        }
+    }
+
+    /// Queues the rollups of the held messages right away, on shutdown.
+    pub fn release(&self) {
+        self.flush(true);
+    }
+
+    /// Queues the rollups of the windows that are over, or of all of them.
+    fn flush(&self, all: bool) {
+        let mut rollups = Vec::new();
+        {
+            let mut windows = self.windows.lock().recover();
+            windows.retain(|url, window| {
+                if window.start.elapsed() < WINDOW && !all {
+                    return true;
+                }
+                if window.held.is_empty() {
+                    return false;
+                }
+                rollups.push(rollup(url, std::mem::take(&mut window.held)));
+                window.start = Instant::now();
+                window.sent = 1;
+                true
+            });
+        }
+        for job in rollups {
+            if self.queue.push(job).is_some() {
+                log::warning("queue full, dropped a rate limit rollup");
+            }
+        }
+    }
+}
+
+/// The mentions of the held messages, which the rollup pings instead.
+fn mentions(held: &[Job]) -> (Option<String>, Option<AllowedMentions>) {
+    let mention = Regex::new(r"<@[!&]?\d+>|@everyone|@here").unwrap();
+    let mut found: Vec<&str> = Vec::new();
+    let contents = held.iter().filter_map(|job| job.content.content.as_deref());
+    for m in contents.flat_map(|c| mention.find_iter(c)) {
+        if !found.contains(&m.as_str()) {
+            found.push(m.as_str());
+        }
+    }
+    let mut content = String::new();
+    for m in found {
+        if content.chars().count() + m.len() + 1 > MAX_CONTENT_CHARS {
+            break;
+        }
+        if !content.is_empty() {
+            content.push(' ');
+        }
+        content.push_str(m);
+    }
+    // Messages without allowed mentions ping whatever they mention.
+    let allowed = held
+        .iter()
+        .map(|job| job.content.allowed_mentions.as_ref())
+        .try_fold(AllowedMentions::default(), |mut all, allowed| {
+            let allowed = allowed?;
+            for parse in &allowed.parse {
+                if !all.parse.contains(parse) {
+                    all.parse.push(*parse);
+                }
+            }
+            for role in &allowed.roles {
+                if !all.roles.contains(role) {
+                    all.roles.push(role.clone());
+                }
+            }
+            for user in &allowed.users {
+                if !all.users.contains(user) {
+                    all.users.push(user.clone());
+                }
+            }
+            Some(all)
+        });
+    (Some(content).filter(|c| !c.is_empty()), allowed)
+}
+
+/// The titles of the held messages, fitting in an embed description.
+fn description(held: &[Job]) -> String {
+    let titles: Vec<_> = held
+        .iter()
+        .flat_map(|job| std::iter::once(job).chain(&job.then))
+        .flat_map(|job| job.content.embeds.iter())
+        .map(|embed| format!("- {}", embed.title))
+        .collect();
+    let more = |count: usize| format!("\n… and {} more", count);
+    let mut listed = Vec::new();
+    let mut length = 0;
+    for title in &titles {
+        let next = length + title.chars().count() + 1;
+        let rest = titles.len() - listed.len() - 1;
+        let room = match rest {
+            0 => 0,
+            _ => more(rest).chars().count(),
+        };
+        if listed.len() == ROLLUP_TITLES || next + room > DESCRIPTION_LIMIT {
+            break;
+        }
+        listed.push(title.as_str());
+        length = next;
+    }
+    let mut description = listed.join("\n");
+    if listed.len() < titles.len() {
+        description += &more(titles.len() - listed.len());
+    }
+    description
+}
+
+fn rollup(url: &str, mut held: Vec<Job>) -> Job {
+    held.sort_by_key(|job| job.rank);
+    let description = description(&held);
+    let (content, allowed_mentions) = mentions(&held);
+    let color = held
+        .first()
+        .and_then(|job| job.content.embeds.first())
+        .map_or(Color::Orange, |embed| embed.color);
+    Job {
+        url: url.to_string(),
+        content: DiscordContent {
+            content,
+            embeds: vec![DiscordEmbed {
+                title: format!(
+                    "{} notifications held back by the rate limit",
+                    held.len()
+                ),
+                url: None,
+                description,
+                color,
+                author: None,
+                thumbnail: None,
+                fields: Vec::new(),
+                footer: None,
+            }],
+            allowed_mentions,
+            tts: false,
+            components: Vec::new(),
+        },
+        rank: held.first().map_or(usize::MAX, |job| job.rank),
+        receipt: None,
+        batch: None,
+        thread: None,
+        sms: held.iter().find_map(|job| job.sms.clone()),
+        dedup: None,
+        then: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::discord::MentionType;
+
+    fn job(title: &str, content: Option<&str>) -> Job {
+        Job {
+            url: String::from("http://discord/hook"),
+            content: DiscordContent {
+                content: content.map(String::from),
+                embeds: vec![DiscordEmbed {
+                    title: title.to_string(),
+                    url: None,
+                    description: String::new(),
+                    color: Color::Red,
+                    author: None,
+                    thumbnail: None,
+                    fields: Vec::new(),
+                    footer: None,
+                }],
+                allowed_mentions: None,
+                tts: false,
+                components: Vec::new(),
+            },
+            rank: 0,
+            receipt: None,
+            batch: None,
+            thread: None,
+            sms: None,
+            dedup: None,
+            then: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn lists_a_few_titles() {
+        let held = vec![job("a", None), job("b", None)];
+        assert_eq!(description(&held), "- a\n- b");
+        let held: Vec<_> = (0..25).map(|i| job(&i.to_string(), None)).collect();
+        let description = description(&held);
+        assert_eq!(description.lines().count(), ROLLUP_TITLES + 1);
+        assert!(description.ends_with("… and 5 more"));
+    }
+
+    #[test]
+    fn fits_long_titles_in_the_description() {
+        let title = "x".repeat(256);
+        let held: Vec<_> = (0..19).map(|_| job(&title, None)).collect();
+        let description = description(&held);
+        assert!(description.chars().count() <= DESCRIPTION_LIMIT);
+        assert!(description.ends_with("more"));
+    }
+
+    #[test]
+    fn carries_mentions_over() {
+        let mut held = vec![
+            job("a", Some("<@&123> disk full")),
+            job("b", Some("<@&123> <@456> and @here")),
+        ];
+        held[0].content.allowed_mentions = Some(AllowedMentions {
+            parse: vec![MentionType::Roles],
+            roles: Vec::new(),
+            users: Vec::new(),
+        });
+        held[1].content.allowed_mentions = Some(AllowedMentions {
+            parse: vec![MentionType::Users],
+            roles: Vec::new(),
+            users: Vec::new(),
+        });
+        let (content, allowed) = mentions(&held);
+        assert_eq!(content.as_deref(), Some("<@&123> <@456> @here"));
+        let allowed = allowed.unwrap();
+        assert_eq!(allowed.parse, [MentionType::Roles, MentionType::Users]);
+
+        held.push(job("c", None));
+        assert!(mentions(&held).1.is_none());
+    }
+
+    #[test]
+    fn holds_messages_over_the_limit_for_a_rollup() {
+        let queue = Arc::new(Queue::new(Default::default()).unwrap());
+        let limiter = Limiter::start(queue);
+        assert!(limiter.admit(job("a", None), None).is_some());
+        assert!(limiter.admit(job("a", None), Some(1)).is_some());
+        assert!(limiter.admit(job("b", None), Some(1)).is_none());
+        assert!(limiter.admit(job("c", None), Some(1)).is_none());
+
+        let windows = limiter.windows.lock().recover();
+        let held: Vec<_> = windows["http://discord/hook"]
+            .held
+            .iter()
+            .map(|job| job.content.embeds[0].title.clone())
+            .map(|title| job(&title, None))
+            .collect();
+        let rollup = rollup("http://discord/hook", held);
+        let embed = &rollup.content.embeds[0];
+        assert_eq!(embed.title, "2 notifications held back by the rate limit");
+        assert_eq!(embed.description, "- b\n- c");
+    }
+}
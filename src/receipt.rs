@@ -0,0 +1,168 @@
+use serde::Serialize;
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::mpsc::Sender;
+use std::sync::mpsc::TryRecvError;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How long a notification asking for a receipt waits for its messages.
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The ID of the message Discord created, or why it was given up on.
+pub type Outcome = Result<Option<String>, String>;
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum Message {
+    Sent {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    Failed {
+        error: String,
+    },
+    /// Identical to one sent within the dedup window.
+    Duplicate,
+    /// Over the route's rate limit, to be sent in the next rollup.
+    Held,
+    /// Shed from the full queue.
+    Dropped,
+    /// Sent by a previous attempt that was only partially delivered.
+    Skipped,
+    /// Still queued or retried when the receipt was written.
+    Pending,
+}
+
+/// What was done for one route of a notification.
+#[derive(Serialize, Debug)]
+pub struct RouteReceipt {
+    pub route: String,
+    pub alerts: usize,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub paused: bool,
+    /// The maintenance event the route was in.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub maintenance: Option<String>,
+    /// Deliveries handed to the sinks.
+    pub sinks: usize,
+    pub messages: Vec<Message>,
+    #[serde(skip)]
+    waiting: Vec<(usize, Receiver<Outcome>)>,
+}
+
+impl RouteReceipt {
+    pub fn new(route: &str, alerts: usize) -> RouteReceipt {
+        RouteReceipt {
+            route: route.to_string(),
+            alerts,
+            paused: false,
+            maintenance: None,
+            sinks: 0,
+            messages: Vec::new(),
+            waiting: Vec::new(),
+        }
+    }
+
+    /// Records a queued message, returning the sender its delivery is to
+    /// be reported to when `wait` is set.
+    pub fn queued(&mut self, wait: bool) -> Option<Sender<Outcome>> {
+        let index = self.messages.len();
+        self.messages.push(Message::Pending);
+        if !wait {
+            return None;
+        }
+        let (sender, receiver) = mpsc::channel();
+        self.waiting.push((index, receiver));
+        Some(sender)
+    }
+
+    /// Replaces the status of the last message, no longer waiting for it.
+    pub fn set_last(&mut self, message: Message) {
+        self.set(self.messages.len() - 1, message);
+    }
+
+    /// Replaces the status of the `index`th message, no longer waiting for
+    /// it.
+    pub fn set(&mut self, index: usize, message: Message) {
+        self.waiting.retain(|(i, _)| *i != index);
+        self.messages[index] = message;
+    }
+
+    /// Notes the outcomes reported so far, without waiting for the others.
+    fn poll(&mut self) {
+        let messages = &mut self.messages;
+        self.waiting.retain(|(index, receiver)| {
+            messages[*index] = match receiver.try_recv() {
+                Ok(Ok(id)) => Message::Sent { id },
+                Ok(Err(error)) => Message::Failed { error },
+                Err(TryRecvError::Empty) => return true,
+                Err(TryRecvError::Disconnected) => Message::Dropped,
+            };
+            false
+        });
+    }
+
+    fn wait(&mut self, deadline: Instant) {
+        for (index, receiver) in self.waiting.drain(..) {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            self.messages[index] = match receiver.recv_timeout(timeout) {
+                Ok(Ok(id)) => Message::Sent { id },
+                Ok(Err(error)) => Message::Failed { error },
+                Err(RecvTimeoutError::Timeout) => Message::Pending,
+                // The job was dropped along with its sender.
+                Err(RecvTimeoutError::Disconnected) => Message::Dropped,
+            };
+        }
+    }
+}
+
+/// The answer to a notification sent with `?receipt=true`.
+#[derive(Serialize, Debug, Default)]
+pub struct Receipt {
+    pub routes: Vec<RouteReceipt>,
+    /// Groups skipped as already handled.
+    #[serde(skip_serializing_if = "is_zero")]
+    pub repeated: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
+impl Receipt {
+    /// Waits up to `TIMEOUT`, or until the deadline if sooner, for the
+    /// queued messages to be delivered.
+    pub fn wait(&mut self, deadline: Option<Instant>) {
+        let timeout = Instant::now() + TIMEOUT;
+        let deadline = deadline.map_or(timeout, |d| d.min(timeout));
+        for route in &mut self.routes {
+            route.wait(deadline);
+        }
+    }
+
+    pub fn poll(&mut self) {
+        for route in &mut self.routes {
+            route.poll();
+        }
+    }
+
+    /// Whether messages are still queued or retried.
+    pub fn pending(&self) -> bool {
+        self.routes.iter().any(|route| {
+            route.messages.iter().any(|m| matches!(m, Message::Pending))
+        })
+    }
+
+    pub fn failed(&self) -> bool {
+        self.routes.iter().any(|route| {
+            route
+                .messages
+                .iter()
+                .any(|m| matches!(m, Message::Failed { .. } | Message::Dropped))
+        })
+    }
+}
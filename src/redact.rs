@@ -0,0 +1,215 @@
+use crate::alert::Alert;
+use crate::alert::AlertGroup;
+use crate::alert::Annotations;
+use crate::state::ResolvedAlert;
+use anyhow::Context;
+use anyhow::Result;
+use regex::NoExpand;
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedactConfig {
+    /// Labels whose whole value is masked.
+    pub labels: Vec<String>,
+    /// Regular expressions masked in every label value and annotation.
+    pub patterns: Vec<String>,
+    pub replacement: String,
+}
+
+impl Default for RedactConfig {
+    fn default() -> Self {
+        RedactConfig {
+            labels: Vec::new(),
+            patterns: Vec::new(),
+            replacement: String::from("[REDACTED]"),
+        }
+    }
+}
+
+pub struct Redactor {
+    config: RedactConfig,
+    patterns: Vec<Regex>,
+}
+
+impl Redactor {
+    pub fn new(config: RedactConfig) -> Result<Redactor> {
+        let patterns = config
+            .patterns
+            .iter()
+            .map(|p| {
+                Regex::new(p)
+                    .with_context(|| format!("invalid redaction pattern {}", p))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Redactor { config, patterns })
+    }
+
+    /// Masks the sensitive parts of everything rendered from the group.
+    pub fn redact(&self, group: &mut AlertGroup) {
+        if self.config.labels.is_empty() && self.patterns.is_empty() {
+            return;
+        }
+        for alert in &mut group.alerts {
+            self.redact_alert(alert);
+        }
+        self.redact_labels(&mut group.group_labels);
+        self.redact_labels(&mut group.common_labels);
+        if let Some(annotations) = &mut group.common_annotations {
+            self.redact_annotations(annotations);
+        }
+    }
+
+    /// Masks an alert of the history, which is kept as received, before it
+    /// is exported or archived.
+    pub fn redact_resolved(&self, alert: &mut ResolvedAlert) {
+        self.redact_labels(&mut alert.labels);
+        if let Some(annotations) = &mut alert.annotations {
+            self.redact_annotations(annotations);
+        }
+    }
+
+    /// Masks an arbitrary JSON document, such as a raw payload, treating
+    /// every object key as a label name.
+    pub fn redact_json(&self, value: &mut Value) {
+        match value {
+            Value::String(text) => self.redact_text(text),
+            Value::Array(values) => {
+                values.iter_mut().for_each(|v| self.redact_json(v))
+            }
+            Value::Object(map) => {
+                for (name, value) in map.iter_mut() {
+                    if value.is_string() && self.config.labels.contains(name) {
+                        *value = Value::from(self.config.replacement.clone());
+                    } else {
+                        self.redact_json(value);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn redact_alert(&self, alert: &mut Alert) {
+        self.redact_labels(&mut alert.labels);
+        if let Some(annotations) = &mut alert.annotations {
+            self.redact_annotations(annotations);
+        }
+        if let Some(value_string) = &mut alert.value_string {
+            self.redact_text(value_string);
+        }
+    }
+
+    fn redact_labels(&self, labels: &mut HashMap<String, String>) {
+        for (name, value) in labels.iter_mut() {
+            if self.config.labels.contains(name) {
+                *value = self.config.replacement.clone();
+            } else {
+                self.redact_text(value);
+            }
+        }
+    }
+
+    fn redact_annotations(&self, annotations: &mut Annotations) {
+        self.redact_text(&mut annotations.summary);
+        if let Some(description) = &mut annotations.description {
+            self.redact_text(description);
+        }
+    }
+
+    fn redact_text(&self, text: &mut String) {
+        for pattern in &self.patterns {
+            if pattern.is_match(text) {
+                *text = pattern
+                    .replace_all(text, NoExpand(&self.config.replacement))
+                    .into_owned();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::StoredAlert;
+    use chrono::Utc;
+    use serde_json::json;
+
+    fn redactor() -> Redactor {
+        Redactor::new(RedactConfig {
+            labels: vec![String::from("customer")],
+            patterns: vec![String::from(r"\d+\.\d+\.\d+\.\d+")],
+            ..RedactConfig::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn masks_labels_and_patterns() {
+        let alert = StoredAlert {
+            receiver: String::new(),
+            labels: HashMap::from([
+                ("alertname".into(), "Down".into()),
+                ("customer".into(), "acme".into()),
+                ("instance".into(), "10.0.0.1:9100".into()),
+            ]),
+            annotations: Some(Annotations {
+                summary: String::from("10.0.0.1 is down"),
+                description: Some(String::from("$1 from 10.0.0.2")),
+            }),
+            starts_at: Utc::now(),
+        }
+        .firing("a");
+        let mut group =
+            AlertGroup::by_alertname(String::new(), String::new(), vec![alert])
+                .remove(0);
+        redactor().redact(&mut group);
+        let alert = &group.alerts[0];
+        assert_eq!(alert.labels["customer"], "[REDACTED]");
+        assert_eq!(alert.labels["instance"], "[REDACTED]:9100");
+        let annotations = alert.annotations.as_ref().unwrap();
+        assert_eq!(annotations.summary, "[REDACTED] is down");
+        assert_eq!(
+            annotations.description.as_deref(),
+            Some("$1 from [REDACTED]")
+        );
+        assert_eq!(group.common_labels["customer"], "[REDACTED]");
+    }
+
+    #[test]
+    fn masks_raw_payloads() {
+        let mut payload = json!({
+            "alerts": [{
+                "labels": { "customer": "acme", "instance": "10.0.0.1" },
+                "startsAt": "2026-10-14T10:00:00Z",
+            }],
+            "customer": { "nested": "kept" },
+        });
+        redactor().redact_json(&mut payload);
+        assert_eq!(
+            payload,
+            json!({
+                "alerts": [{
+                    "labels": {
+                        "customer": "[REDACTED]",
+                        "instance": "[REDACTED]",
+                    },
+                    "startsAt": "2026-10-14T10:00:00Z",
+                }],
+                "customer": { "nested": "kept" },
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        let config = RedactConfig {
+            patterns: vec![String::from("(")],
+            ..RedactConfig::default()
+        };
+        assert!(Redactor::new(config).is_err());
+    }
+}
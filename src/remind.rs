@@ -0,0 +1,316 @@
+use crate::alert::Alert;
+use crate::alert::Status;
+use crate::config::Route;
+use crate::config::Unmatched;
+use crate::log;
+use crate::maintenance;
+use crate::metrics;
+use crate::queue::Job;
+use crate::render::Renderer;
+use crate::state::StoredAlert;
+use crate::sync::Recover;
+use crate::App;
+use anyhow::Result;
+use chrono::Utc;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Reminds of alerts firing for longer than `after`, every `interval`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ReminderConfig {
+    #[serde(with = "humantime_serde")]
+    pub after: Duration,
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+    /// Reminders per alert, unlimited if unset.
+    pub max: Option<u32>,
+}
+
+/// How many reminders were sent for each alert still firing, and when the
+/// last one was, by fingerprint.
+#[derive(Default)]
+pub struct Reminders {
+    sent: Mutex<HashMap<String, (u32, Instant)>>,
+    /// The alerts Alertmanager last reported suppressed when polled.
+    suppressed: Mutex<HashSet<String>>,
+    /// The route each firing alert was last routed to, by fingerprint.
+    routes: Mutex<HashMap<String, String>>,
+}
+
+impl Reminders {
+    /// Records whether Alertmanager silences or inhibits the alerts.
+    pub fn set_suppressed<'a>(
+        &self,
+        alerts: impl IntoIterator<Item = (&'a str, bool)>,
+    ) {
+        let mut suppressed = self.suppressed.lock().recover();
+        for (fingerprint, is) in alerts {
+            if is {
+                suppressed.insert(fingerprint.to_string());
+            } else {
+                suppressed.remove(fingerprint);
+            }
+        }
+    }
+
+    fn is_suppressed(&self, fingerprint: &str) -> bool {
+        self.suppressed.lock().recover().contains(fingerprint)
+    }
+
+    /// Records the route the alerts of a notification were routed to.
+    pub fn routed(&self, route: &str, alerts: &[Alert]) {
+        let mut routes = self.routes.lock().recover();
+        for alert in alerts {
+            match alert.status {
+                Status::Firing => {
+                    routes.insert(alert.fingerprint.clone(), route.to_string())
+                }
+                Status::Resolved => routes.remove(&alert.fingerprint),
+            };
+        }
+    }
+
+    fn route_of(&self, fingerprint: &str) -> Option<String> {
+        self.routes.lock().recover().get(fingerprint).cloned()
+    }
+
+    /// Whether a reminder is due for the alert.
+    fn due(&self, config: &ReminderConfig, alert: &Alert) -> bool {
+        let age = (Utc::now() - alert.starts_at).to_std().unwrap_or_default();
+        if age < config.after {
+            return false;
+        }
+        let sent = self.sent.lock().recover();
+        sent.get(&alert.fingerprint).is_none_or(|(count, last)| {
+            last.elapsed() >= config.interval
+                && config.max.is_none_or(|max| *count < max)
+        })
+    }
+
+    /// Counts a reminder of the alerts, once queued.
+    fn sent(&self, alerts: &[Alert]) {
+        let mut sent = self.sent.lock().recover();
+        for alert in alerts {
+            let count = sent.get(&alert.fingerprint).map_or(0, |(c, _)| *c);
+            sent.insert(alert.fingerprint.clone(), (count + 1, Instant::now()));
+        }
+    }
+
+    /// Forgets the alerts that are no longer firing.
+    fn retain(&self, firing: &HashMap<String, StoredAlert>) {
+        self.sent
+            .lock()
+            .recover()
+            .retain(|f, _| firing.contains_key(f));
+        self.suppressed
+            .lock()
+            .recover()
+            .retain(|f| firing.contains_key(f));
+        self.routes
+            .lock()
+            .recover()
+            .retain(|f, _| firing.contains_key(f));
+    }
+}
+
+/// The route an alert of `receiver` was last routed to or, unknown since
+/// a restart, the one it goes to on its own, whose labels are those of its
+/// group with either `routing`.
+pub fn route<'a>(
+    app: &'a App,
+    receiver: &str,
+    alert: &Alert,
+) -> Option<&'a Route> {
+    let config = &app.config;
+    let mut routes = config.routes.iter();
+    if let Some(name) = app.reminders.route_of(&alert.fingerprint) {
+        let known = routes.clone().chain([&config.default_route]);
+        if let Some(route) = known.into_iter().find(|r| r.name == name) {
+            return Some(route);
+        }
+    }
+    match routes.find(|r| r.matches_labels(receiver, &alert.labels)) {
+        Some(route) => Some(route),
+        None if config.unmatched == Unmatched::Default => {
+            Some(&config.default_route)
+        }
+        None => None,
+    }
+}
+
+/// Whether the alert would not be notified now: inhibited, silenced or in
+/// a maintenance muting its route.
+fn is_muted(app: &App, route: &Route, alert: &Alert) -> bool {
+    let maintenance = route.maintenance.as_ref().filter(|m| {
+        m.action == maintenance::Action::Mute
+            && app.calendars.ongoing(m).is_some()
+    });
+    maintenance.is_some()
+        || app.inhibitor.is_inhibited(alert)
+        || app.silences.is_silenced(&alert.labels)
+        || app.reminders.is_suppressed(&alert.fingerprint)
+        || acknowledged(app, alert)
+}
+
+/// Whether someone acknowledged the alert from Discord.
+#[cfg(feature = "interactions")]
+fn acknowledged(app: &App, alert: &Alert) -> bool {
+    app.interactions.is_acknowledged(&alert.fingerprint)
+}
+
+#[cfg(not(feature = "interactions"))]
+fn acknowledged(_: &App, _: &Alert) -> bool {
+    false
+}
+
+fn remind(app: &App, config: &ReminderConfig) {
+    let firing = app.store.firing();
+    app.reminders.retain(&firing);
+    #[cfg(feature = "interactions")]
+    app.interactions.retain(&firing);
+
+    let mut routes: Vec<(&Route, Vec<Alert>)> = Vec::new();
+    for (fingerprint, stored) in firing {
+        let alert = stored.firing(&fingerprint);
+        let Some(route) = route(app, &stored.receiver, &alert) else {
+            continue;
+        };
+        if app.store.is_paused(&route.name) || !route.discord() {
+            continue;
+        }
+        if is_muted(app, route, &alert) {
+            continue;
+        }
+        if !app.reminders.due(config, &alert) {
+            continue;
+        }
+        match routes.iter_mut().find(|(r, _)| r.name == route.name) {
+            Some((_, alerts)) => alerts.push(alert),
+            None => routes.push((route, vec![alert])),
+        }
+    }
+
+    let renderer = app.renderer.read().recover().clone();
+    for (route, mut alerts) in routes {
+        alerts.sort_by_key(|a| a.starts_at);
+        if let Err(e) = queue(app, &renderer, route, &alerts) {
+            log::error(format!(
+                "cannot send reminders to route {}: {:#}",
+                route.name, e
+            ));
+        }
+    }
+}
+
+/// Queues the reminder of the alerts of a route.
+fn queue(
+    app: &App,
+    renderer: &Renderer,
+    route: &Route,
+    alerts: &[Alert],
+) -> Result<()> {
+    let url = route.webhook_url()?;
+    app.config.outbound.check_url(&url)?;
+    let rank = alerts
+        .iter()
+        .map(|a| app.config.severity.rank(&a.labels))
+        .min()
+        .unwrap_or(usize::MAX);
+    let job = Job {
+        url,
+        content: renderer.render_reminder(route, alerts)?,
+        rank,
+        receipt: None,
+        batch: None,
+        thread: None,
+        sms: None,
+        dedup: None,
+        then: Vec::new(),
+    };
+    app.reminders.sent(alerts);
+    metrics::inc(&app.metrics.reminders);
+    if let Some(shed) = app.queue.push(job) {
+        metrics::add(&app.metrics.messages_dropped, shed.messages() as u64);
+        log::warning("queue full, dropped the least severe notification");
+    }
+    Ok(())
+}
+
+/// Starts the thread sending reminders, if enabled.
+pub fn start(app: Arc<App>) {
+    let Some(config) = app.config.reminders.clone() else {
+        return;
+    };
+    thread::spawn(move || loop {
+        thread::sleep(CHECK_INTERVAL);
+        remind(&app, &config);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alert(age: Duration) -> StoredAlert {
+        StoredAlert {
+            receiver: String::new(),
+            labels: HashMap::new(),
+            annotations: None,
+            starts_at: Utc::now() - chrono::TimeDelta::from_std(age).unwrap(),
+        }
+    }
+
+    #[test]
+    fn reminds_at_most_max_times() {
+        let config = ReminderConfig {
+            after: Duration::from_secs(3600),
+            interval: Duration::ZERO,
+            max: Some(2),
+        };
+        let reminders = Reminders::default();
+        let young = alert(Duration::from_secs(60)).firing("a");
+        assert!(!reminders.due(&config, &young));
+        let old = alert(Duration::from_secs(7200)).firing("a");
+        assert!(reminders.due(&config, &old));
+        // Only counted once queued.
+        assert!(reminders.due(&config, &old));
+        reminders.sent(std::slice::from_ref(&old));
+        assert!(reminders.due(&config, &old));
+        reminders.sent(std::slice::from_ref(&old));
+        assert!(!reminders.due(&config, &old));
+    }
+
+    #[test]
+    fn remembers_routes_while_firing() {
+        let reminders = Reminders::default();
+        let mut firing = alert(Duration::ZERO).firing("a");
+        reminders.routed("db", std::slice::from_ref(&firing));
+        assert_eq!(reminders.route_of("a").as_deref(), Some("db"));
+        firing.status = Status::Resolved;
+        reminders.routed("db", std::slice::from_ref(&firing));
+        assert_eq!(reminders.route_of("a"), None);
+    }
+
+    #[test]
+    fn tracks_suppressed_alerts() {
+        let reminders = Reminders::default();
+        reminders.set_suppressed([("a", true), ("b", true)]);
+        reminders.set_suppressed([("b", false)]);
+        assert!(reminders.is_suppressed("a"));
+        assert!(!reminders.is_suppressed("b"));
+        reminders.retain(&HashMap::from([(
+            String::from("b"),
+            alert(Duration::ZERO),
+        )]));
+        assert!(!reminders.is_suppressed("a"));
+    }
+}
@@ -0,0 +1,1283 @@
+use crate::alert::Alert;
+use crate::alert::AlertGroup;
+use crate::alert::Status;
+use crate::config::AlertmanagerLink;
+use crate::config::Config;
+use crate::config::GroupLabels;
+use crate::config::Layout;
+use crate::config::Messages;
+use crate::config::Route;
+use crate::console;
+use crate::discord::DiscordContent;
+use crate::discord::DiscordEmbed;
+use crate::discord::DiscordEmbedField;
+use crate::discord::DiscordEmbedFooter;
+use crate::discord::DiscordEmbedThumbnail;
+use crate::escalate;
+use crate::escalate::Escalation;
+use crate::gotmpl;
+use crate::i18n::Catalog;
+use crate::matcher;
+use crate::preset;
+use crate::preset::Preset;
+use crate::severity::Severity;
+use crate::table;
+use crate::theme::Theme;
+use anyhow::anyhow;
+use anyhow::Result;
+use chrono::DateTime;
+use chrono::Utc;
+use chrono_tz::Tz;
+use minijinja::context;
+use minijinja::Environment;
+use minijinja::Error;
+use minijinja::ErrorKind;
+use minijinja::State;
+use minijinja::Template;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Longest footer text Discord accepts.
+const FOOTER_LIMIT: usize = 2048;
+pub const DESCRIPTION_LIMIT: usize = 4096;
+/// Discord's limit on the fields of an embed.
+const REMINDER_FIELDS: usize = 25;
+
+/// Links to the Alertmanager UI filtered down to the alerts of the group.
+fn alertmanager_url(group: &AlertGroup) -> Option<String> {
+    if group.external_url.is_empty() {
+        return None;
+    }
+    let mut matchers: Vec<_> = group
+        .group_labels
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, matcher::quote(value)))
+        .collect();
+    matchers.sort();
+    let mut query = url::form_urlencoded::Serializer::new(String::new());
+    if !group.receiver.is_empty() {
+        query.append_pair("receiver", &group.receiver);
+    }
+    // Alertmanager refuses an empty filter.
+    if !matchers.is_empty() {
+        query.append_pair("filter", &format!("{{{}}}", matchers.join(",")));
+    }
+    Some(format!(
+        "{}/#/alerts?{}",
+        group.external_url.trim_end_matches('/'),
+        query.finish()
+    ))
+}
+
+pub struct Renderer {
+    env: Environment<'static>,
+    messages: Messages,
+    severity: Severity,
+    escalation: Vec<Escalation>,
+    presets: Vec<Preset>,
+    /// Text of the links made by `kubernetes.dashboard_url`.
+    dashboard_text: Option<String>,
+    max_alerts: Option<usize>,
+    follow_ups: bool,
+    catalog: Arc<Catalog>,
+}
+
+fn current_locale(state: &State) -> String {
+    state
+        .lookup("locale")
+        .and_then(|l| l.as_str().map(String::from))
+        .unwrap_or_default()
+}
+
+fn current_timezone(state: &State) -> Tz {
+    state
+        .lookup("timezone")
+        .and_then(|l| l.as_str().and_then(|tz| tz.parse().ok()))
+        .unwrap_or(Tz::UTC)
+}
+
+impl Renderer {
+    pub fn new(config: &Config, catalog: Arc<Catalog>) -> Result<Renderer> {
+        let messages = &config.messages;
+        let mut env = Environment::new();
+        gotmpl::register(&mut env);
+
+        let c = catalog.clone();
+        env.add_function(
+            "t",
+            move |state: &State, key: String, count: Option<i64>| {
+                let locale = current_locale(state);
+                let message =
+                    c.message(&locale, &key, count).ok_or_else(|| {
+                        Error::new(
+                            ErrorKind::InvalidOperation,
+                            format!("no message {} in locale {}", key, locale),
+                        )
+                    })?;
+                state.env().render_str(&message, context! { count })
+            },
+        );
+        let c = catalog.clone();
+        env.add_filter("datetime", move |state: &State, value: String| {
+            let time = DateTime::parse_from_rfc3339(&value).map_err(|e| {
+                Error::new(ErrorKind::InvalidOperation, e.to_string())
+            })?;
+            Ok::<_, Error>(c.format_datetime(
+                &current_locale(state),
+                current_timezone(state),
+                &time.to_utc(),
+            ))
+        });
+
+        env.add_template_owned("title", messages.title.clone())?;
+        env.add_template_owned("content", messages.content.clone())?;
+        for route in &config.routes {
+            let templates = [
+                ("content", &route.content),
+                ("content_prefix", &route.content_prefix),
+                ("content_suffix", &route.content_suffix),
+            ];
+            for (name, source) in templates {
+                if let Some(source) = source {
+                    env.add_template_owned(
+                        format!("{}/{}", name, route.name),
+                        source.clone(),
+                    )?;
+                }
+            }
+        }
+        for route in config.routes.iter().chain([&config.default_route]) {
+            for (i, link) in route.console_links().iter().enumerate() {
+                env.add_template_owned(
+                    console::template_name(&route.name, i),
+                    link.url.clone(),
+                )?;
+            }
+        }
+        for (set, templates) in &config.template_sets {
+            let templates = [
+                ("title", &templates.title),
+                ("content", &templates.content),
+                ("field_name", &templates.field_name),
+                ("field_value", &templates.field_value),
+            ];
+            for (name, source) in templates {
+                if let Some(source) = source {
+                    env.add_template_owned(
+                        format!("set/{}/{}", set, name),
+                        source.clone(),
+                    )?;
+                }
+            }
+        }
+        env.add_template_owned("field_name", messages.field_name.clone())?;
+        env.add_template_owned("field_value", messages.field_value.clone())?;
+        env.add_template_owned(
+            "no_annotations",
+            messages.no_annotations.clone(),
+        )?;
+        env.add_template_owned("transition", messages.transition.clone())?;
+        env.add_template_owned("more_alerts", messages.more_alerts.clone())?;
+        env.add_template_owned(
+            "group_summary",
+            messages.group_summary.clone(),
+        )?;
+        env.add_template_owned(
+            "flapping_title",
+            messages.flapping_title.clone(),
+        )?;
+        env.add_template_owned(
+            "flapping_field",
+            messages.flapping_field.clone(),
+        )?;
+        env.add_template_owned(
+            "reminder_title",
+            messages.reminder_title.clone(),
+        )?;
+        env.add_template_owned(
+            "reminder_field",
+            messages.reminder_field.clone(),
+        )?;
+        env.add_template_owned("thread_name", messages.thread_name.clone())?;
+        env.add_template_owned("maintenance", messages.maintenance.clone())?;
+        env.add_template_owned("ticket_title", messages.ticket_title.clone())?;
+        env.add_template_owned("ticket_body", messages.ticket_body.clone())?;
+        env.add_template_owned("silence", messages.silence.clone())?;
+        let kubernetes = config.kubernetes.as_ref();
+        let dashboard = kubernetes.and_then(|k| k.dashboard_url.clone());
+        if let Some(dashboard) = dashboard {
+            env.add_template_owned("kubernetes_dashboard", dashboard)?;
+        }
+        Ok(Renderer {
+            env,
+            messages: messages.clone(),
+            severity: config.severity.clone(),
+            escalation: config.escalation.clone(),
+            presets: config.presets.clone(),
+            dashboard_text: kubernetes
+                .filter(|k| k.dashboard_url.is_some())
+                .map(|k| k.dashboard_text.clone()),
+            max_alerts: config.max_alerts_per_message,
+            follow_ups: config.follow_up_messages,
+            catalog,
+        })
+    }
+
+    /// Name of the template rendering the content of the route's messages.
+    pub fn content_template(&self, route: &Route) -> String {
+        let names = [
+            route
+                .template
+                .as_ref()
+                .map(|set| format!("set/{}/content", set)),
+            Some(format!("content/{}", route.name)),
+        ];
+        names
+            .into_iter()
+            .flatten()
+            .find(|name| self.env.get_template(name).is_ok())
+            .unwrap_or_else(|| String::from("content"))
+    }
+
+    /// The template of that name in the route's template set, if it has
+    /// one, or the default one.
+    fn template(&self, route: &Route, name: &str) -> Result<Template<'_, '_>> {
+        if let Some(set) = &route.template {
+            let name = format!("set/{}/{}", set, name);
+            if let Ok(template) = self.env.get_template(&name) {
+                return Ok(template);
+            }
+        }
+        Ok(self.env.get_template(name)?)
+    }
+
+    fn render_title(
+        &self,
+        route: &Route,
+        status: Status,
+        count: usize,
+        alert_name: &str,
+    ) -> Result<String> {
+        let locale = route.locale();
+        let status_text = self.catalog.status(locale, status);
+        Ok(self.template(route, "title")?.render(context! {
+            locale,
+            timezone => route.timezone().name(),
+            status => &status_text,
+            count,
+            alertname => alert_name,
+        })?)
+    }
+
+    /// The title of the group's alerts of a status, as in its messages.
+    #[cfg(feature = "chat")]
+    pub fn title(
+        &self,
+        route: &Route,
+        group: &AlertGroup,
+        status: Status,
+        count: usize,
+    ) -> Result<String> {
+        let alert_name = group
+            .common_labels
+            .get("alertname")
+            .map_or(self.messages.unnamed.as_str(), String::as_str);
+        self.render_title(route, status, count, alert_name)
+    }
+
+    /// The line telling how many more alerts are left out.
+    pub fn more_alerts(&self, route: &Route, count: usize) -> Result<String> {
+        Ok(self.env.get_template("more_alerts")?.render(context! {
+            locale => route.locale(),
+            timezone => route.timezone().name(),
+            count,
+        })?)
+    }
+
+    /// `oncall` are the Discord user IDs of whoever is on call for the
+    /// route, mentioned along with the severity level's mention.
+    pub fn render(
+        &self,
+        route: &Route,
+        group: AlertGroup,
+        oncall: &[String],
+    ) -> Result<Vec<DiscordContent>> {
+        let m = &self.messages;
+        let locale = route.locale();
+        let alertmanager_url = alertmanager_url(&group);
+        let short_key =
+            Some(group.short_key()).filter(|_| !group.group_key.is_empty());
+        let timezone = route.timezone().name();
+
+        let alert_name = group
+            .common_labels
+            .get("alertname")
+            .map_or(m.unnamed.clone(), |l| l.clone());
+
+        let common_labels = group.common_labels;
+        let has_summary = group.common_annotations.is_some();
+        let alert_summary = group
+            .common_annotations
+            .map_or(m.no_summary.clone(), |a| a.summary);
+
+        let mut group_labels: Vec<_> = group
+            .group_labels
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect();
+        group_labels.sort();
+        let group_labels = group_labels.join(", ");
+
+        // Firing first, so that what still needs attention comes on top.
+        let (firing, resolved): (Vec<_>, Vec<_>) = group
+            .alerts
+            .into_iter()
+            .partition(|a| a.status == Status::Firing);
+        let counts = (firing.len(), resolved.len());
+        let alert_by_status =
+            [(Status::Firing, firing), (Status::Resolved, resolved)]
+                .into_iter()
+                .filter(|(_, alerts)| !alerts.is_empty());
+
+        let mut messages = Vec::new();
+        for (status, mut alerts) in alert_by_status {
+            alerts.sort_by_key(|a| self.severity.rank(&a.labels));
+            let level =
+                alerts.first().and_then(|a| self.severity.level(&a.labels));
+
+            let status_text = self.catalog.status(locale, status);
+            let title =
+                self.render_title(route, status, alerts.len(), &alert_name)?;
+            let mut description = alert_summary.clone();
+            let min = m.group_summary_min;
+            if min > 0 && alerts.len() >= min {
+                let line = self.group_summary(route, &alerts)?;
+                description = format!("{}\n{}", line, description);
+            }
+            if description.chars().count() > DESCRIPTION_LIMIT {
+                description =
+                    description.chars().take(DESCRIPTION_LIMIT - 1).collect();
+                description.push('…');
+            }
+
+            let escalated = alerts
+                .iter()
+                .filter_map(|a| escalate::escalation(&self.escalation, a))
+                .filter(|e| e.color.is_some())
+                .max_by_key(|e| e.after);
+            let theme = route.theme();
+            let suppressed = alerts.iter().all(|a| a.suppressed);
+            let color = match status {
+                Status::Firing if suppressed => theme.suppressed(),
+                Status::Firing => escalated
+                    .and_then(|e| e.color)
+                    .or_else(|| level.and_then(|l| l.color))
+                    .unwrap_or(theme.color(status)),
+                Status::Resolved => theme.color(status),
+            };
+            let title = Theme::decorate(theme.emoji(status), title);
+
+            let style = match level {
+                Some(level) => level.embed.or(&route.embed()),
+                None => route.embed(),
+            };
+            let mut embed = DiscordEmbed {
+                title,
+                url: None,
+                description,
+                color,
+                author: style.author,
+                thumbnail: style
+                    .thumbnail
+                    .map(|url| DiscordEmbedThumbnail { url }),
+                fields: Vec::new(),
+                footer: style.footer,
+            };
+
+            if !group_labels.is_empty() {
+                match m.group_labels {
+                    GroupLabels::None => {}
+                    GroupLabels::Author => {
+                        embed.author.get_or_insert_default().name =
+                            group_labels.clone();
+                    }
+                    GroupLabels::Field => {
+                        embed.fields.push(DiscordEmbedField {
+                            name: m.group_labels_name.clone(),
+                            value: group_labels.clone(),
+                        })
+                    }
+                }
+            }
+
+            let oncall: Vec<_> =
+                oncall.iter().map(|id| format!("<@{}>", id)).collect();
+            let oncall = oncall.join(" ");
+            let mention = match status {
+                // Silenced and inhibited alerts page nobody.
+                Status::Firing if suppressed => String::new(),
+                Status::Firing => level
+                    .and_then(|l| l.mention.clone())
+                    .into_iter()
+                    .chain(Some(oncall.clone()).filter(|o| !o.is_empty()))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                Status::Resolved => String::new(),
+            };
+            let ctx = context! {
+                locale,
+                timezone,
+                status => &status_text,
+                count => alerts.len(),
+                alertname => &alert_name,
+                labels => &common_labels,
+                mention,
+                oncall,
+                summary => if has_summary { &alert_summary } else { "" },
+            };
+            let mut parts = Vec::new();
+            for name in [
+                format!("content_prefix/{}", route.name),
+                self.content_template(route),
+                format!("content_suffix/{}", route.name),
+            ] {
+                // The prefix and suffix are optional.
+                let Ok(template) = self.env.get_template(&name) else {
+                    continue;
+                };
+                let part = template.render(&ctx)?;
+                if !part.trim().is_empty() {
+                    parts.push(part);
+                }
+            }
+            let content = Some(parts.join("\n")).filter(|c| !c.is_empty());
+
+            if let Some(url) = &alertmanager_url {
+                match m.alertmanager_link {
+                    AlertmanagerLink::None => {}
+                    AlertmanagerLink::Title => embed.url = Some(url.clone()),
+                    AlertmanagerLink::Field => {
+                        embed.fields.push(DiscordEmbedField {
+                            name: m.alertmanager_link_text.clone(),
+                            value: format!(
+                                "[{}]({})",
+                                m.alertmanager_link_text, url
+                            ),
+                        })
+                    }
+                }
+            }
+
+            if m.trace_footer {
+                let fingerprints: Vec<_> =
+                    alerts.iter().map(|a| a.fingerprint.as_str()).collect();
+                let mut trace = fingerprints.join(", ");
+                if let Some(key) = &short_key {
+                    trace = format!("group {} · {}", key, trace);
+                }
+                let footer = embed.footer.get_or_insert(DiscordEmbedFooter {
+                    text: String::new(),
+                    icon_url: None,
+                });
+                if !footer.text.is_empty() {
+                    footer.text.push_str(" · ");
+                }
+                footer.text.push_str(&trace);
+                if footer.text.chars().count() > FOOTER_LIMIT {
+                    footer.text =
+                        footer.text.chars().take(FOOTER_LIMIT - 1).collect();
+                    footer.text.push('…');
+                }
+            }
+
+            let mut rest = match self.max_alerts {
+                Some(max) if alerts.len() > max => alerts.split_off(max),
+                _ => Vec::new(),
+            };
+            self.list(route, &status_text, alerts, &mut embed)?;
+            let mut follow_ups = Vec::new();
+            let max = self.max_alerts.unwrap_or_default();
+            if !rest.is_empty() && !self.follow_ups {
+                let name = self.more_alerts(route, rest.len())?;
+                let value = match &alertmanager_url {
+                    Some(url) => {
+                        format!("[{}]({})", m.alertmanager_link_text, url)
+                    }
+                    None => m.no_description.clone(),
+                };
+                embed.fields.push(DiscordEmbedField { name, value });
+            } else if !rest.is_empty() {
+                // Continued in further messages of at most `max` alerts.
+                let parts = rest.len().div_ceil(max) + 1;
+                while !rest.is_empty() {
+                    let tail = rest.split_off(max.min(rest.len()));
+                    let mut follow_up = DiscordEmbed {
+                        title: format!(
+                            "{} ({}/{})",
+                            embed.title,
+                            follow_ups.len() + 2,
+                            parts
+                        ),
+                        url: embed.url.clone(),
+                        description: String::new(),
+                        color,
+                        author: None,
+                        thumbnail: None,
+                        fields: Vec::new(),
+                        footer: None,
+                    };
+                    self.list(route, &status_text, rest, &mut follow_up)?;
+                    follow_ups.push(follow_up);
+                    rest = tail;
+                }
+                embed.title = format!("{} (1/{})", embed.title, parts);
+            }
+
+            let embeds = vec![embed];
+            messages.push(DiscordContent {
+                content,
+                embeds,
+                allowed_mentions: route.allowed_mentions.clone(),
+                tts: false,
+                components: Vec::new(),
+            });
+            messages.extend(follow_ups.into_iter().map(|embed| {
+                DiscordContent {
+                    content: None,
+                    embeds: vec![embed],
+                    allowed_mentions: route.allowed_mentions.clone(),
+                    tts: false,
+                    components: Vec::new(),
+                }
+            }));
+        }
+        if counts.0 > 0 && counts.1 > 0 {
+            let header =
+                self.env.get_template("transition")?.render(context! {
+                    locale,
+                    timezone,
+                    alertname => &alert_name,
+                    firing => counts.0,
+                    resolved => counts.1,
+                })?;
+            let first = &mut messages[0];
+            let content = match &first.content {
+                Some(content) => format!("{}\n{}", header, content),
+                None => header,
+            };
+            first.content = Some(content);
+            // Unless continued in follow-ups or too long together, both go
+            // in a single message.
+            if messages.len() == 2 {
+                let resolved = messages.pop().expect("two messages");
+                let rest = messages[0].absorb(resolved);
+                messages.extend(rest);
+            }
+        }
+        Ok(messages)
+    }
+
+    #[cfg(feature = "pagerduty")]
+    pub fn severity(&self) -> &Severity {
+        &self.severity
+    }
+
+    /// The instance of the alert as shown in messages.
+    pub fn instance(&self, alert: &Alert) -> String {
+        match (
+            alert.labels.get("instance"),
+            alert.labels.get("exported_instance"),
+        ) {
+            (None, Some(exported)) => exported.clone(),
+            (Some(i), Some(exported)) if i == "localhost" || i == "unknown" => {
+                exported.clone()
+            }
+            (Some(i), _) => match &alert.host {
+                Some(host) => host.display(i),
+                None => i.clone(),
+            },
+            (None, None) => self.messages.unknown_instance.clone(),
+        }
+    }
+
+    pub fn alert_name(&self, alert: &Alert) -> String {
+        alert
+            .labels
+            .get("alertname")
+            .map_or(self.messages.unknown_alertname.clone(), |l| l.clone())
+    }
+
+    /// The `alert_on` message of the catalog, naming the alert and its
+    /// instance in the fields of flapping notices and reminders.
+    fn alert_on(&self, locale: &str, alert: &Alert) -> Result<String> {
+        let message =
+            self.catalog.message(locale, "alert_on", None).ok_or_else(
+                || anyhow!("no message alert_on in locale {}", locale),
+            )?;
+        Ok(self.env.render_str(
+            &message,
+            context! {
+                alertname => self.alert_name(alert),
+                instance => self.instance(alert),
+            },
+        )?)
+    }
+
+    /// Lists the alerts in the embed, as fields or as a table.
+    fn list(
+        &self,
+        route: &Route,
+        status_text: &str,
+        alerts: Vec<Alert>,
+        embed: &mut DiscordEmbed,
+    ) -> Result<()> {
+        let m = &self.messages;
+        let locale = route.locale();
+        let timezone = route.timezone().name();
+        // A table lists the alerts in the description instead of fields.
+        let fields = match route.layout() {
+            Layout::Fields => alerts,
+            Layout::Table => {
+                let now = Utc::now();
+                let rows: Vec<_> = alerts
+                    .iter()
+                    .map(|alert| {
+                        let name = self.alert_name(alert);
+                        let name =
+                            match escalate::escalation(&self.escalation, alert)
+                            {
+                                Some(e) => format!("{} {}", e.label(), name),
+                                None => name,
+                            };
+                        let name = self.mark_suppressed(route, alert, name);
+                        let instance = preset::enrich(&self.presets, alert)
+                            .instance
+                            .unwrap_or_else(|| self.instance(alert));
+                        [
+                            name,
+                            instance,
+                            self.severity.display(&alert.labels),
+                            table::age(alert.starts_at, now),
+                        ]
+                    })
+                    .collect();
+                let limit = DESCRIPTION_LIMIT
+                    .saturating_sub(embed.description.chars().count() + 1);
+                let table = table::code_block(
+                    ["ALERT", "INSTANCE", "SEVERITY", "SINCE"],
+                    &rows,
+                    limit,
+                );
+                if !table.is_empty() {
+                    embed.description =
+                        format!("{}\n{}", embed.description, table);
+                }
+                Vec::new()
+            }
+        };
+
+        for alert in fields {
+            let mut enrichment = preset::enrich(&self.presets, &alert);
+            if let Some(text) = &self.dashboard_text {
+                if enrichment.kubernetes {
+                    let url = self
+                        .env
+                        .get_template("kubernetes_dashboard")?
+                        .render(&alert.labels)?;
+                    enrichment.details = format!("[{}]({})", text, url.trim());
+                }
+            }
+            let location =
+                alert.host.as_ref().and_then(|h| h.location.as_ref());
+            if let Some(location) = location {
+                if !enrichment.details.is_empty() {
+                    enrichment.details.push_str(" · ");
+                }
+                enrichment.details.push_str(location);
+            }
+            for (i, link) in route.console_links().iter().enumerate() {
+                if !link.applies(&alert.labels) {
+                    continue;
+                }
+                let url = self
+                    .env
+                    .get_template(&console::template_name(&route.name, i))?
+                    .render(&alert.labels)?;
+                let link = format!("[{}]({})", link.text, url.trim());
+                if !enrichment.details.is_empty() {
+                    enrichment.details.push_str(" · ");
+                }
+                enrichment.details.push_str(&link);
+            }
+            let instance = match enrichment.instance {
+                Some(instance) => instance,
+                None => self.instance(&alert),
+            };
+
+            let alert_name = self.alert_name(&alert);
+            let name =
+                self.template(route, "field_name")?.render(context! {
+                    locale,
+                    timezone,
+                    status => status_text,
+                    alertname => alert_name,
+                    instance => instance,
+                    starts_at => alert.starts_at.to_rfc3339(),
+                    ends_at => alert.ends_at.to_rfc3339(),
+                })?;
+            let name = match escalate::escalation(&self.escalation, &alert) {
+                Some(e) => format!("{} {}", e.label(), name),
+                None => name,
+            };
+            let name = self.mark_suppressed(route, &alert, name);
+
+            let summary = match alert.annotations {
+                Some(a) => a.description.unwrap_or(a.summary),
+                None => self.no_annotations(&alert)?,
+            };
+            let severity = self.severity.display(&alert.labels);
+            let job = alert
+                .labels
+                .get("job")
+                .map_or(m.no_job.clone(), |l| l.clone());
+            let values = match (&alert.values, &alert.value_string) {
+                (Some(values), _) if !values.is_empty() => {
+                    let mut values: Vec<_> = values
+                        .iter()
+                        .map(|(name, value)| format!("{}={}", name, value))
+                        .collect();
+                    values.sort();
+                    values.join(", ")
+                }
+                (_, Some(value_string)) => value_string.clone(),
+                _ => String::new(),
+            };
+            let silence = match &alert.silences {
+                Some(silenced) => {
+                    self.env.get_template("silence")?.render(context! {
+                        locale,
+                        timezone,
+                        count => silenced.count,
+                        expires_in => table::age(Utc::now(), silenced.until),
+                        until => silenced.until.to_rfc3339(),
+                    })?
+                }
+                None => String::new(),
+            };
+            let value =
+                self.template(route, "field_value")?.render(context! {
+                    values,
+                    locale,
+                    timezone,
+                    starts_at => alert.starts_at.to_rfc3339(),
+                    ends_at => alert.ends_at.to_rfc3339(),
+                    severity => severity,
+                    job => job,
+                    summary => summary,
+                    details => enrichment.details,
+                    ticket => alert.ticket.as_deref().unwrap_or_default(),
+                    silence,
+                })?;
+
+            embed.fields.push(DiscordEmbedField { name, value });
+        }
+        Ok(())
+    }
+    fn mark_suppressed(
+        &self,
+        route: &Route,
+        alert: &Alert,
+        name: String,
+    ) -> String {
+        match alert.suppressed {
+            true => Theme::decorate(route.theme().suppressed_emoji(), name),
+            false => name,
+        }
+    }
+
+    /// The summary of an alert without annotations, built from its labels.
+    fn no_annotations(&self, alert: &Alert) -> Result<String> {
+        // Listed in order.
+        let labels: BTreeMap<String, String> =
+            alert.labels.clone().into_iter().collect();
+        let standard = ["alertname", "instance", "job", &self.severity.label];
+        let extra_labels: Vec<_> = labels
+            .iter()
+            .filter(|(name, _)| !standard.contains(&name.as_str()))
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect();
+        let summary =
+            self.env.get_template("no_annotations")?.render(context! {
+                labels,
+                extra_labels => extra_labels.join(", "),
+            })?;
+        Ok(match summary.trim() {
+            "" => self.messages.no_description.clone(),
+            summary => summary.to_string(),
+        })
+    }
+
+    /// Counts the alerts by name and the namespaces and jobs they span.
+    fn group_summary(&self, route: &Route, alerts: &[Alert]) -> Result<String> {
+        let mut names: Vec<(String, usize)> = Vec::new();
+        for alert in alerts {
+            let name = self.alert_name(alert);
+            match names.iter_mut().find(|(n, _)| *n == name) {
+                Some((_, count)) => *count += 1,
+                None => names.push((name, 1)),
+            }
+        }
+        names.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        let breakdown: Vec<_> = names
+            .iter()
+            .map(|(name, count)| format!("{}×{}", count, name))
+            .collect();
+        let distinct = |label: &str| {
+            let mut values: Vec<_> =
+                alerts.iter().filter_map(|a| a.labels.get(label)).collect();
+            values.sort();
+            values.dedup();
+            values.len()
+        };
+        Ok(self.env.get_template("group_summary")?.render(context! {
+            locale => route.locale(),
+            timezone => route.timezone().name(),
+            count => alerts.len(),
+            breakdown => breakdown.join(", "),
+            alertnames => names.len(),
+            namespaces => distinct("namespace"),
+            jobs => distinct("job"),
+        })?)
+    }
+
+    /// Renders the one-off notice sent when alerts start flapping, each
+    /// given with the number of status changes seen within `window`.
+    pub fn render_flapping(
+        &self,
+        route: &Route,
+        alerts: &[(Alert, usize)],
+        window: Duration,
+    ) -> Result<DiscordContent> {
+        let locale = route.locale();
+        let timezone = route.timezone().name();
+        let window = humantime::format_duration(window).to_string();
+
+        let mut names: Vec<_> =
+            alerts.iter().map(|(a, _)| self.alert_name(a)).collect();
+        names.sort();
+        names.dedup();
+        let title =
+            self.env.get_template("flapping_title")?.render(context! {
+                locale,
+                timezone,
+                count => alerts.len(),
+                alertname => names.join(", "),
+            })?;
+        let theme = route.theme();
+        let title = Theme::decorate(theme.flapping_emoji(), title);
+
+        let mut fields = Vec::new();
+        for (alert, flips) in alerts {
+            let name = self.alert_on(locale, alert)?;
+            let value =
+                self.env.get_template("flapping_field")?.render(context! {
+                    locale,
+                    timezone,
+                    flips,
+                    window => &window,
+                    status => self.catalog.status(locale, alert.status),
+                })?;
+            fields.push(DiscordEmbedField { name, value });
+        }
+
+        Ok(DiscordContent {
+            content: None,
+            allowed_mentions: None,
+            tts: false,
+            components: Vec::new(),
+            embeds: vec![DiscordEmbed {
+                title,
+                url: None,
+                description: String::new(),
+                color: theme.warning(),
+                author: None,
+                thumbnail: None,
+                fields,
+                footer: None,
+            }],
+        })
+    }
+
+    /// Renders the line heading messages sent during the maintenance
+    /// `event`.
+    pub fn render_maintenance(
+        &self,
+        route: &Route,
+        event: &str,
+    ) -> Result<String> {
+        Ok(self.env.get_template("maintenance")?.render(context! {
+            locale => route.locale(),
+            timezone => route.timezone().name(),
+            event,
+        })?)
+    }
+
+    /// Renders the title and body of the issue opened for the alert.
+    pub fn render_ticket(
+        &self,
+        route: &Route,
+        alert: &Alert,
+    ) -> Result<(String, String)> {
+        let locale = route.locale();
+        let timezone = route.timezone().name();
+        let (summary, description) = match &alert.annotations {
+            Some(a) => (a.summary.clone(), a.description.clone()),
+            None => (self.messages.no_summary.clone(), None),
+        };
+        // Listed in order.
+        let labels: BTreeMap<String, String> =
+            alert.labels.clone().into_iter().collect();
+        let context = context! {
+            locale,
+            timezone,
+            alertname => self.alert_name(alert),
+            instance => self.instance(alert),
+            severity => self.severity.display(&alert.labels),
+            summary,
+            description => description.unwrap_or_default(),
+            labels,
+            starts_at => alert.starts_at.to_rfc3339(),
+        };
+        let title = self.env.get_template("ticket_title")?.render(&context)?;
+        let body = self.env.get_template("ticket_body")?.render(&context)?;
+        Ok((title, body))
+    }
+
+    /// Renders the name of the incident thread opened for the group.
+    pub fn render_thread_name(
+        &self,
+        route: &Route,
+        group: &AlertGroup,
+    ) -> Result<String> {
+        let mut names: Vec<_> =
+            group.alerts.iter().map(|a| self.alert_name(a)).collect();
+        names.sort();
+        names.dedup();
+        let severity = group
+            .alerts
+            .iter()
+            .min_by_key(|a| self.severity.rank(&a.labels))
+            .map(|a| self.severity.display(&a.labels))
+            .unwrap_or_default();
+        Ok(self.env.get_template("thread_name")?.render(context! {
+            locale => route.locale(),
+            timezone => route.timezone().name(),
+            count => group.alerts.len(),
+            alertname => names.join(", "),
+            severity,
+            common_labels => group.common_labels.clone(),
+        })?)
+    }
+
+    /// Renders a reminder of alerts still firing long after they started.
+    pub fn render_reminder(
+        &self,
+        route: &Route,
+        alerts: &[Alert],
+    ) -> Result<DiscordContent> {
+        let locale = route.locale();
+        let timezone = route.timezone().name();
+        let now = Utc::now();
+
+        let mut names: Vec<_> =
+            alerts.iter().map(|a| self.alert_name(a)).collect();
+        names.sort();
+        names.dedup();
+        let title =
+            self.env.get_template("reminder_title")?.render(context! {
+                locale,
+                timezone,
+                count => alerts.len(),
+                alertname => names.join(", "),
+            })?;
+        let theme = route.theme();
+        let title = Theme::decorate(theme.emoji(Status::Firing), title);
+
+        // Past the limit, the last field counts the alerts left out.
+        let shown = if alerts.len() > REMINDER_FIELDS {
+            REMINDER_FIELDS - 1
+        } else {
+            alerts.len()
+        };
+        let (shown, rest) = alerts.split_at(shown);
+        let mut fields = Vec::new();
+        for alert in shown {
+            let name = self.alert_on(locale, alert)?;
+            let summary =
+                alert.annotations.as_ref().map(|a| a.summary.as_str());
+            let value =
+                self.env.get_template("reminder_field")?.render(context! {
+                    locale,
+                    timezone,
+                    age => table::age(alert.starts_at, now),
+                    starts_at => alert.starts_at.to_rfc3339(),
+                    summary => summary.unwrap_or_default(),
+                })?;
+            fields.push(DiscordEmbedField { name, value });
+        }
+        if !rest.is_empty() {
+            let name = self.more_alerts(route, rest.len())?;
+            fields.push(DiscordEmbedField {
+                name,
+                value: String::from("-"),
+            });
+        }
+
+        Ok(DiscordContent {
+            content: None,
+            allowed_mentions: None,
+            tts: false,
+            components: Vec::new(),
+            embeds: vec![DiscordEmbed {
+                title,
+                url: None,
+                description: String::new(),
+                color: theme.color(Status::Firing),
+                author: None,
+                thumbnail: None,
+                fields,
+                footer: None,
+            }],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alert::Annotations;
+    use crate::console::ConsoleLink;
+    use crate::discord::Color;
+    use crate::preset::KubernetesConfig;
+    use crate::state::StoredAlert;
+    use std::collections::HashMap;
+
+    fn renderer(config: &Config) -> Renderer {
+        let catalog = Catalog::new(&HashMap::new()).unwrap();
+        Renderer::new(config, Arc::new(catalog)).unwrap()
+    }
+
+    fn alert(fingerprint: &str, summary: &str) -> Alert {
+        StoredAlert {
+            receiver: String::new(),
+            labels: HashMap::from([
+                (String::from("alertname"), String::from("Down")),
+                (String::from("instance"), format!("{}:9100", fingerprint)),
+            ]),
+            annotations: Some(Annotations {
+                summary: summary.to_string(),
+                description: None,
+            }),
+            starts_at: Utc::now(),
+        }
+        .firing(fingerprint)
+    }
+
+    fn group(alerts: Vec<Alert>) -> AlertGroup {
+        AlertGroup::by_alertname(String::from("team"), String::new(), alerts)
+            .remove(0)
+    }
+
+    #[test]
+    fn truncates_descriptions_with_their_summary() {
+        let mut config = Config::default();
+        config.messages.group_summary_min = 1;
+        let summary = "x".repeat(DESCRIPTION_LIMIT);
+        let messages = renderer(&config)
+            .render(
+                &config.default_route,
+                group(vec![alert("a", &summary), alert("b", &summary)]),
+                &[],
+            )
+            .unwrap();
+        let description = &messages[0].embeds[0].description;
+        assert_eq!(description.chars().count(), DESCRIPTION_LIMIT);
+        assert!(description.ends_with("x…"));
+    }
+
+    #[test]
+    fn puts_the_prefix_and_suffix_around_the_content() {
+        let mut config = Config::default();
+        config.routes.push(Route {
+            name: String::from("team"),
+            content_prefix: Some(String::from("Runbooks: {{ alertname }}")),
+            content_suffix: Some(String::from("  ")),
+            ..config.default_route.clone()
+        });
+        let renderer = renderer(&config);
+        let messages = renderer
+            .render(&config.routes[0], group(vec![alert("a", "Down")]), &[])
+            .unwrap();
+        assert_eq!(
+            messages[0].content.as_deref(),
+            Some("Runbooks: Down\nDown")
+        );
+
+        // Other routes go without.
+        let messages = renderer
+            .render(&config.default_route, group(vec![alert("a", "Down")]), &[])
+            .unwrap();
+        assert_eq!(messages[0].content.as_deref(), Some("Down"));
+    }
+
+    #[test]
+    fn describes_alerts_without_annotations_by_their_labels() {
+        let value = |config: &Config| {
+            let mut bare = alert("a", "");
+            bare.annotations = None;
+            bare.labels.insert(String::from("zone"), String::from("b"));
+            bare.labels.insert(String::from("team"), String::from("db"));
+            let messages = renderer(config)
+                .render(&config.default_route, group(vec![bare]), &[])
+                .unwrap();
+            messages[0].embeds[0].fields[0].value.clone()
+        };
+        let mut config = Config::default();
+        assert_eq!(value(&config), "INFO - -");
+        config.messages.no_annotations = String::from("{{ extra_labels }}");
+        assert_eq!(value(&config), "INFO - team=db, zone=b");
+    }
+
+    #[test]
+    fn greys_suppressed_alerts_out() {
+        let config = Config::default();
+        let renderer = renderer(&config);
+        let suppressed = || {
+            let mut alert = alert("a", "Down");
+            alert.suppressed = true;
+            alert
+        };
+        let messages = renderer
+            .render(&config.default_route, group(vec![suppressed()]), &[])
+            .unwrap();
+        let embed = &messages[0].embeds[0];
+        assert_eq!(embed.color, Color::Grey);
+        assert_eq!(embed.fields[0].name, "\u{1F507} [Firing]: Down on a:9100");
+
+        // Only the suppressed alerts are marked when some are not.
+        let messages = renderer
+            .render(
+                &config.default_route,
+                group(vec![suppressed(), alert("b", "Down")]),
+                &[],
+            )
+            .unwrap();
+        let embed = &messages[0].embeds[0];
+        assert_ne!(embed.color, Color::Grey);
+        assert!(embed.fields[0].name.starts_with('\u{1F507}'));
+        assert!(!embed.fields[1].name.starts_with('\u{1F507}'));
+    }
+
+    #[test]
+    fn links_pods_to_the_kubernetes_dashboard() {
+        let config = Config {
+            presets: vec![Preset::Kubernetes],
+            kubernetes: Some(KubernetesConfig {
+                dashboard_url: Some(String::from(
+                    "https://lens.example/{{ namespace }}/{{ pod }}",
+                )),
+                dashboard_text: String::from("dashboard"),
+                group_by_namespace: false,
+            }),
+            ..Config::default()
+        };
+        let mut pod = alert("a", "Down");
+        pod.labels
+            .insert(String::from("namespace"), String::from("ops"));
+        pod.labels
+            .insert(String::from("pod"), String::from("web-1"));
+        let messages = renderer(&config)
+            .render(&config.default_route, group(vec![pod]), &[])
+            .unwrap();
+        let field = &messages[0].embeds[0].fields[0];
+        assert_eq!(field.name, "[Firing]: Down on ops/web-1");
+        assert_eq!(
+            field.value,
+            "INFO - Down · [dashboard](https://lens.example/ops/web-1)"
+        );
+    }
+
+    #[test]
+    fn links_resources_to_their_console() {
+        let mut config = Config::default();
+        config.default_route.console_links = Some(vec![ConsoleLink {
+            text: String::from("EC2"),
+            labels: vec![String::from("instance_id")],
+            url: String::from(
+                "https://console.aws.amazon.com/ec2/home#Instances:\
+                 instanceId={{ instance_id }}",
+            ),
+        }]);
+        let mut instance = alert("a", "Down");
+        instance
+            .labels
+            .insert(String::from("instance_id"), String::from("i-0abc"));
+        let messages = renderer(&config)
+            .render(
+                &config.default_route,
+                group(vec![instance, alert("b", "Down")]),
+                &[],
+            )
+            .unwrap();
+        let fields = &messages[0].embeds[0].fields;
+        assert_eq!(
+            fields[0].value,
+            "INFO - Down · [EC2](https://console.aws.amazon.com/ec2/home\
+             #Instances:instanceId=i-0abc)"
+        );
+        assert_eq!(fields[1].value, "INFO - Down");
+    }
+
+    #[test]
+    fn names_flapping_alerts_in_the_locale() {
+        let mut config = Config::default();
+        config.default_route.locale = Some(String::from("de"));
+        let content = renderer(&config)
+            .render_flapping(
+                &config.default_route,
+                &[(alert("a", "Down"), 5)],
+                Duration::from_secs(1800),
+            )
+            .unwrap();
+        assert_eq!(content.embeds[0].fields[0].name, "Down auf a:9100");
+    }
+
+    #[test]
+    fn names_reminded_alerts_in_the_locale() {
+        let mut config = Config::default();
+        config.default_route.locale = Some(String::from("fr"));
+        let content = renderer(&config)
+            .render_reminder(&config.default_route, &[alert("a", "Down")])
+            .unwrap();
+        assert_eq!(content.embeds[0].fields[0].name, "Down sur a:9100");
+    }
+
+    #[test]
+    fn filters_alertmanager_by_the_group_labels() {
+        let mut group = group(vec![alert("a", "Down")]);
+        group.external_url = String::from("http://alertmanager:9093/");
+        group.group_labels =
+            HashMap::from([(String::from("summary"), String::from("a \"b\""))]);
+        assert_eq!(
+            alertmanager_url(&group).unwrap(),
+            "http://alertmanager:9093/#/alerts?receiver=team\
+             &filter=%7Bsummary%3D%22a+%5C%22b%5C%22%22%7D"
+        );
+        group.group_labels.clear();
+        assert_eq!(
+            alertmanager_url(&group).unwrap(),
+            "http://alertmanager:9093/#/alerts?receiver=team"
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use crate::log;
+use crate::sync::Pending;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use rouille::Request;
+use rouille::Response;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::fs::PermissionsExt;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+/// Binds a unix socket, replacing a stale socket left by a previous run
+/// but no other kind of file, and sets its permissions so a local reverse
+/// proxy can connect.
+pub fn bind_unix(path: &str, mode: u32) -> Result<tiny_http::Server> {
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            bail!("{} exists and is not a socket", path);
+        }
+        std::fs::remove_file(path)
+            .with_context(|| format!("cannot remove stale socket {}", path))?;
+    }
+    let server = tiny_http::Server::http_unix(Path::new(path))
+        .map_err(|e| anyhow::anyhow!("cannot listen on {}: {}", path, e))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    Ok(server)
+}
+
+pub fn bind_tcp(addr: &str) -> Result<tiny_http::Server> {
+    tiny_http::Server::http(addr)
+        .map_err(|e| anyhow::anyhow!("cannot listen on {}: {}", addr, e))
+}
+
+/// Which endpoints a listener serves, all of them unless `admin_listen`
+/// splits them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Endpoints {
+    All,
+    Ingest,
+    Admin,
+}
+
+/// The header the TLS listener names the client of a relayed request in,
+/// trusted on its private socket only.
+pub const PEER_HEADER: &str = "X-Bridge-Peer";
+
+/// A server and the endpoints it serves.
+pub struct Listener {
+    pub server: tiny_http::Server,
+    pub endpoints: Endpoints,
+    /// Whether its connections are relayed by the TLS listener, which
+    /// names their client in `PEER_HEADER`.
+    pub relayed: bool,
+}
+
+/// The listeners being served, until stopped.
+pub struct Serving {
+    servers: Vec<Arc<tiny_http::Server>>,
+    threads: Vec<thread::JoinHandle<()>>,
+    requests: Arc<Pending>,
+}
+
+impl Serving {
+    /// Stops accepting requests and waits for those being handled, false
+    /// if the deadline came first.
+    pub fn stop(self, deadline: Instant) -> bool {
+        for server in &self.servers {
+            server.unblock();
+        }
+        for thread in self.threads {
+            let _ = thread.join();
+        }
+        // Dropping the servers closes their sockets.
+        drop(self.servers);
+        self.requests.wait(deadline)
+    }
+}
+
+/// Serves every listener with the same handler, one thread per request
+/// like `rouille::start_server` does. The handler is given the body apart,
+/// to be read off the connection as it is parsed, the request having none.
+pub fn serve<F>(listeners: Vec<Listener>, handler: F) -> Serving
+where
+    F: Fn(&Request, &mut dyn Read, Endpoints) -> Response
+        + Send
+        + Sync
+        + 'static,
+{
+    let handler = Arc::new(handler);
+    let requests = Arc::new(Pending::default());
+    let mut servers = Vec::new();
+    let mut threads = Vec::new();
+    for listener in listeners {
+        let Listener {
+            server,
+            endpoints,
+            relayed,
+        } = listener;
+        let server = Arc::new(server);
+        servers.push(server.clone());
+        let (handler, requests) = (handler.clone(), requests.clone());
+        threads.push(thread::spawn(move || {
+            // Ends once the server is unblocked.
+            for request in server.incoming_requests() {
+                let (handler, requests) = (handler.clone(), requests.clone());
+                requests.start();
+                thread::spawn(move || {
+                    handle(request, relayed, |r, body| {
+                        handler(r, body, endpoints)
+                    });
+                    requests.finish();
+                });
+            }
+        }));
+    }
+    Serving {
+        servers,
+        threads,
+        requests,
+    }
+}
+
+fn handle<F>(mut request: tiny_http::Request, relayed: bool, handler: F)
+where
+    F: Fn(&Request, &mut dyn Read) -> Response,
+{
+    let peer = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv(PEER_HEADER))
+        .and_then(|h| h.value.as_str().parse::<SocketAddr>().ok());
+    // Unix socket clients have no address, they are always local. Those of
+    // the TLS listener are named by it, or not let through as local.
+    let remote_addr = match (request.remote_addr(), relayed) {
+        (_, true) => peer.unwrap_or_else(|| SocketAddr::from(([0; 4], 0))),
+        (Some(addr), false) => *addr,
+        (None, false) => SocketAddr::from(([127, 0, 0, 1], 0)),
+    };
+    let headers = request
+        .headers()
+        .iter()
+        .filter(|h| !h.field.equiv(PEER_HEADER))
+        .map(|h| (h.field.to_string(), h.value.to_string()))
+        .collect();
+    let rouille_request = Request::fake_http_from(
+        remote_addr,
+        request.method().as_str(),
+        request.url(),
+        headers,
+        Vec::new(),
+    );
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        handler(&rouille_request, request.as_reader())
+    }));
+    let rouille_response = result.unwrap_or_else(|_| {
+        log::error(format!("panic while handling {}", request.url()));
+        Response::text("Internal Server Error").with_status_code(500)
+    });
+
+    let (data, length) = rouille_response.data.into_reader_and_size();
+    let mut response = tiny_http::Response::empty(rouille_response.status_code)
+        .with_data(data, length);
+    for (key, value) in rouille_response.headers {
+        if key.eq_ignore_ascii_case("Content-Length") {
+            continue;
+        }
+        if let Ok(header) =
+            tiny_http::Header::from_bytes(key.as_bytes(), value.as_bytes())
+        {
+            response.add_header(header);
+        }
+    }
+    let _ = request.respond(response);
+}
@@ -0,0 +1,293 @@
+use crate::alert::Alert;
+use crate::alert::AlertGroup;
+use crate::alert::Status;
+use crate::discord::Color;
+use crate::discord::EmbedStyle;
+use crate::matcher::Matchers;
+use crate::state::Store;
+use anyhow::bail;
+use anyhow::Result;
+use chrono::Utc;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::Duration;
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Level {
+    pub name: String,
+    /// Embed color of firing alerts at this level.
+    pub color: Option<Color>,
+    /// Prepended to the message content when alerts at this level fire.
+    pub mention: Option<String>,
+    #[serde(default)]
+    pub embed: EmbedStyle,
+}
+
+impl Level {
+    fn named(name: &str) -> Level {
+        Level {
+            name: name.to_string(),
+            color: None,
+            mention: None,
+            embed: EmbedStyle::default(),
+        }
+    }
+}
+
+/// Which label carries the severity and which values it can take, most
+/// severe first.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Severity {
+    pub label: String,
+    pub default: String,
+    pub levels: Vec<Level>,
+}
+
+impl Default for Severity {
+    fn default() -> Self {
+        Severity {
+            label: String::from("severity"),
+            default: String::from("info"),
+            levels: vec![
+                Level::named("critical"),
+                Level::named("warning"),
+                Level::named("info"),
+            ],
+        }
+    }
+}
+
+impl Severity {
+    pub fn validate(&self) -> Result<()> {
+        if self.position(&self.default).is_none() {
+            bail!("severity: default level {} is not listed", self.default);
+        }
+        Ok(())
+    }
+
+    pub fn position(&self, value: &str) -> Option<usize> {
+        self.levels
+            .iter()
+            .position(|l| l.name.eq_ignore_ascii_case(value))
+    }
+
+    /// Rank of the alert's level, 0 being the most severe. Missing and
+    /// unrecognized values rank as the default level.
+    pub fn rank(&self, labels: &HashMap<String, String>) -> usize {
+        labels
+            .get(&self.label)
+            .and_then(|value| self.position(value))
+            .or_else(|| self.position(&self.default))
+            .unwrap_or(self.levels.len())
+    }
+
+    pub fn level(&self, labels: &HashMap<String, String>) -> Option<&Level> {
+        self.levels.get(self.rank(labels))
+    }
+
+    /// The severity as shown in messages: the label value, or the default
+    /// level when the label is missing.
+    pub fn display(&self, labels: &HashMap<String, String>) -> String {
+        labels
+            .get(&self.label)
+            .unwrap_or(&self.default)
+            .to_uppercase()
+    }
+
+    /// Whether a message about `alerts` is read aloud: when one of them
+    /// fires at the `tts` level of the route or a more severe one.
+    pub fn audible(&self, tts: Option<&str>, alerts: &[Alert]) -> bool {
+        tts.and_then(|t| self.position(t)).is_some_and(|least| {
+            alerts.iter().any(|a| {
+                a.status == Status::Firing && self.rank(&a.labels) <= least
+            })
+        })
+    }
+}
+
+/// Sets the severity of the alerts matching `matchers`, and when set
+/// firing for at least `firing_for`, to `severity`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SeverityRule {
+    #[serde(default)]
+    pub matchers: Matchers,
+    #[serde(with = "humantime_serde", default)]
+    pub firing_for: Option<Duration>,
+    pub severity: String,
+}
+
+impl SeverityRule {
+    fn applies(&self, alert: &Alert) -> bool {
+        if !self.matchers.matches(&alert.labels) {
+            return false;
+        }
+        let Some(firing_for) = self.firing_for else {
+            return true;
+        };
+        alert.status == Status::Firing
+            && (Utc::now() - alert.starts_at)
+                .to_std()
+                .is_ok_and(|age| age >= firing_for)
+    }
+}
+
+impl Severity {
+    /// Rewrites the severity of the group's alerts by the first rule each
+    /// matches, before anything depends on it. Resolved alerts no rule
+    /// matches any longer, like those rewritten for firing for long, keep
+    /// the severity they were firing with, as remembered by the store,
+    /// which a dry run goes without.
+    pub fn rewrite(
+        &self,
+        rules: &[SeverityRule],
+        store: Option<&Store>,
+        group: &mut AlertGroup,
+    ) {
+        let mut changed = false;
+        for alert in &mut group.alerts {
+            let rule = rules.iter().find(|r| r.applies(alert));
+            let firing = alert.status == Status::Firing;
+            let rewritten = match rule {
+                Some(rule) => Some(rule.severity.clone()),
+                None if firing => None,
+                None => {
+                    store.and_then(|s| s.rewritten_severity(&alert.fingerprint))
+                }
+            };
+            if let (Some(store), true) = (store, firing) {
+                let severity = rewritten.as_deref();
+                store.set_rewritten_severity(&alert.fingerprint, severity);
+            }
+            if let Some(severity) = rewritten {
+                changed |= alert.labels.get(&self.label) != Some(&severity);
+                alert.labels.insert(self.label.clone(), severity);
+            }
+        }
+        if !changed {
+            return;
+        }
+        let mut values = group.alerts.iter().map(|a| a.labels.get(&self.label));
+        let first = values.next().flatten().cloned();
+        match first.filter(|first| values.all(|v| v == Some(first))) {
+            Some(value) => {
+                group.common_labels.insert(self.label.clone(), value);
+            }
+            None => {
+                group.common_labels.remove(&self.label);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::HistoryPolicy;
+    use crate::state::StoredAlert;
+    use chrono::TimeDelta;
+
+    fn alert(fingerprint: &str, severity: &str, age: i64) -> Alert {
+        StoredAlert {
+            receiver: String::new(),
+            labels: HashMap::from([
+                ("alertname".into(), "DiskFull".into()),
+                ("severity".into(), severity.into()),
+            ]),
+            annotations: None,
+            starts_at: Utc::now() - TimeDelta::minutes(age),
+        }
+        .firing(fingerprint)
+    }
+
+    fn rule(
+        matchers: &str,
+        firing_for: Option<u64>,
+        severity: &str,
+    ) -> SeverityRule {
+        SeverityRule {
+            matchers: serde_yaml::from_str(matchers).unwrap(),
+            firing_for: firing_for.map(|m| Duration::from_secs(m * 60)),
+            severity: severity.to_string(),
+        }
+    }
+
+    #[test]
+    fn ranks_unknown_levels_as_the_default() {
+        let severity = Severity::default();
+        let labels = |s: &str| HashMap::from([("severity".into(), s.into())]);
+        assert_eq!(severity.rank(&labels("CRITICAL")), 0);
+        assert_eq!(severity.rank(&labels("page")), 2);
+        assert_eq!(severity.rank(&HashMap::new()), 2);
+        assert_eq!(severity.display(&HashMap::new()), "INFO");
+    }
+
+    #[test]
+    fn reads_severe_firing_alerts_aloud() {
+        let severity = Severity::default();
+        let warning = [alert("a", "warning", 0)];
+        assert!(severity.audible(Some("warning"), &warning));
+        assert!(severity.audible(Some("info"), &warning));
+        assert!(!severity.audible(Some("critical"), &warning));
+        assert!(!severity.audible(None, &warning));
+        assert!(!severity.audible(Some("page"), &warning));
+        let mut resolved = [alert("a", "critical", 0)];
+        resolved[0].status = Status::Resolved;
+        assert!(!severity.audible(Some("critical"), &resolved));
+    }
+
+    #[test]
+    fn rewrites_by_the_first_matching_rule() {
+        let severity = Severity::default();
+        let rules = [
+            rule("[\"severity=warning\"]", Some(30), "critical"),
+            rule("[\"severity=warning\"]", None, "info"),
+        ];
+        let mut group = AlertGroup::by_alertname(
+            String::new(),
+            String::new(),
+            vec![alert("a", "warning", 60), alert("b", "warning", 5)],
+        )
+        .remove(0);
+        let store = Store::open(None, HistoryPolicy::default()).unwrap();
+        assert_eq!(group.common_labels["severity"], "warning");
+        severity.rewrite(&rules, Some(&store), &mut group);
+        let levels: Vec<_> =
+            group.alerts.iter().map(|a| &a.labels["severity"]).collect();
+        assert_eq!(levels, ["critical", "info"]);
+        assert!(!group.common_labels.contains_key("severity"));
+
+        // Both end up critical as they get older.
+        group.alerts[1].starts_at -= TimeDelta::hours(1);
+        for alert in &mut group.alerts {
+            alert.labels.insert("severity".into(), "warning".into());
+        }
+        severity.rewrite(&rules, Some(&store), &mut group);
+        assert_eq!(group.common_labels["severity"], "critical");
+
+        // Resolved, they are no longer firing for long but stay critical.
+        let fingerprint = group.alerts[0].fingerprint.clone();
+        let resolved = StoredAlert {
+            receiver: String::new(),
+            labels: HashMap::from([
+                ("alertname".into(), "DiskFull".into()),
+                ("severity".into(), "warning".into()),
+            ]),
+            annotations: None,
+            starts_at: Utc::now() - TimeDelta::hours(2),
+        }
+        .resolved(&fingerprint, Utc::now());
+        let mut group = AlertGroup::by_alertname(
+            String::new(),
+            String::new(),
+            vec![resolved],
+        )
+        .remove(0);
+        severity.rewrite(&rules[..1], Some(&store), &mut group);
+        assert_eq!(group.alerts[0].labels["severity"], "critical");
+        store.observe("", &group.alerts);
+        assert_eq!(store.rewritten_severity(&fingerprint), None);
+    }
+}
@@ -0,0 +1,441 @@
+use crate::admin;
+use crate::alert::AlertGroup;
+use crate::alert::Status;
+use crate::input;
+use crate::log;
+use crate::matcher;
+use crate::matcher::Matchers;
+use crate::matcher::Op;
+use crate::sync::Recover;
+use crate::App;
+use anyhow::Context;
+use anyhow::Result;
+use chrono::DateTime;
+use chrono::TimeDelta;
+use chrono::Utc;
+use rouille::Request;
+use rouille::Response;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Read;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+/// The Alertmanager whose silences are shown on the alerts they match.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SilenceConfig {
+    /// Base URL of the Alertmanager, e.g. http://alertmanager:9093.
+    pub url: String,
+    #[serde(with = "humantime_serde", default = "default_refresh")]
+    pub refresh: Duration,
+}
+
+fn default_refresh() -> Duration {
+    Duration::from_secs(60)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Matcher {
+    name: String,
+    value: String,
+    #[serde(default)]
+    is_regex: bool,
+    #[serde(default = "default_equal")]
+    is_equal: bool,
+}
+
+fn default_equal() -> bool {
+    true
+}
+
+impl Matcher {
+    #[cfg(feature = "interactions")]
+    pub fn equal(name: &str, value: &str) -> Matcher {
+        Matcher {
+            name: name.to_string(),
+            value: value.to_string(),
+            is_regex: false,
+            is_equal: true,
+        }
+    }
+
+    fn op(&self) -> Op {
+        match (self.is_equal, self.is_regex) {
+            (true, false) => Op::Equal,
+            (false, false) => Op::NotEqual,
+            (true, true) => Op::Regex,
+            (false, true) => Op::NotRegex,
+        }
+    }
+
+    /// The matcher matching labels, its regex compiled.
+    fn compile(&self) -> Result<matcher::Matcher> {
+        matcher::Matcher::new(&self.name, self.op(), &self.value)
+    }
+}
+
+impl fmt::Display for Matcher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}{}{}",
+            self.name,
+            self.op(),
+            matcher::quote(&self.value)
+        )
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct SilenceStatus {
+    state: String,
+}
+
+/// A silence as returned by `GET /api/v2/silences`.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Silence {
+    id: String,
+    matchers: Vec<Matcher>,
+    ends_at: DateTime<Utc>,
+    status: SilenceStatus,
+}
+
+/// A silence created through the bridge, tracked until it ends.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CreatedSilence {
+    pub id: String,
+    pub matchers: Vec<Matcher>,
+    pub created_by: String,
+    pub comment: String,
+    pub created_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+}
+
+/// The active silences matching an alert.
+#[derive(Debug, Clone, Copy)]
+pub struct Silenced {
+    pub count: usize,
+    /// When the last of them expires.
+    pub until: DateTime<Utc>,
+}
+
+/// An active silence, its matchers compiled once.
+struct Active {
+    id: String,
+    matchers: Matchers,
+    ends_at: DateTime<Utc>,
+}
+
+impl Active {
+    fn new(
+        id: &str,
+        matchers: &[Matcher],
+        ends_at: DateTime<Utc>,
+    ) -> Result<Active> {
+        let matchers: Result<_> =
+            matchers.iter().map(Matcher::compile).collect();
+        Ok(Active {
+            id: id.to_string(),
+            matchers: Matchers(
+                matchers.with_context(|| format!("silence {}", id))?,
+            ),
+            ends_at,
+        })
+    }
+}
+
+/// The active silences, as last fetched.
+#[derive(Default)]
+pub struct Silences(Mutex<Vec<Active>>);
+
+impl Silences {
+    /// Whether an active Alertmanager silence matches the labels.
+    pub fn is_silenced(&self, labels: &HashMap<String, String>) -> bool {
+        self.matching(labels).is_some()
+    }
+
+    fn matching(&self, labels: &HashMap<String, String>) -> Option<Silenced> {
+        let silences = self.0.lock().recover();
+        let now = Utc::now();
+        let ends: Vec<_> = silences
+            .iter()
+            .filter(|s| s.ends_at > now)
+            .filter(|s| s.matchers.matches(labels))
+            .map(|s| s.ends_at)
+            .collect();
+        Some(Silenced {
+            count: ends.len(),
+            until: ends.into_iter().max()?,
+        })
+    }
+}
+
+/// Notes the silences matching the firing alerts of the group.
+pub fn attach(app: &App, group: &mut AlertGroup) {
+    if app.config.silences.is_none() {
+        return;
+    }
+    for alert in &mut group.alerts {
+        if alert.status == Status::Firing {
+            alert.silences = app.silences.matching(&alert.labels);
+        }
+    }
+}
+
+fn fetch(app: &App, base_url: &str) -> Result<()> {
+    let started = Utc::now();
+    let url = format!("{}/api/v2/silences", base_url.trim_end_matches('/'));
+    let silences: Vec<Silence> = app
+        .client
+        .get(&url)
+        .send()
+        .and_then(|r| r.error_for_status())
+        .and_then(|r| r.json())
+        .with_context(|| format!("cannot query {}", url))?;
+    let active: Vec<_> = silences
+        .iter()
+        .filter(|s| s.status.state == "active")
+        .filter_map(|s| match Active::new(&s.id, &s.matchers, s.ends_at) {
+            Ok(active) => Some(active),
+            // Alertmanager checks its silences, this is unlikely.
+            Err(e) => {
+                log::warning(format!("ignoring {:#}", e));
+                None
+            }
+        })
+        .collect();
+    // Those expired from Alertmanager itself are forgotten too.
+    app.store.retain_silences(|created| {
+        created.created_at >= started
+            || active.iter().any(|s| s.id == created.id)
+    });
+    *app.silences.0.lock().recover() = active;
+    Ok(())
+}
+
+/// The body of `POST /api/silences`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NewSilence {
+    matchers: Vec<Matcher>,
+    #[serde(with = "humantime_serde")]
+    duration: std::time::Duration,
+    comment: String,
+}
+
+#[derive(Deserialize)]
+struct Created {
+    #[serde(rename = "silenceID")]
+    silence_id: String,
+}
+
+fn create_silence(
+    app: &App,
+    base_url: &str,
+    created_by: &str,
+    new: NewSilence,
+) -> Result<CreatedSilence> {
+    if new.matchers.is_empty() {
+        anyhow::bail!(
+            "a silence needs matchers, or it would silence every alert"
+        );
+    }
+    let url = format!("{}/api/v2/silences", base_url.trim_end_matches('/'));
+    let now = Utc::now();
+    let ends_at = now + TimeDelta::from_std(new.duration)?;
+    // Checked before Alertmanager has it.
+    let mut active = Active::new("", &new.matchers, ends_at)?;
+    let body = serde_json::json!({
+        "matchers": new.matchers,
+        "startsAt": now,
+        "endsAt": ends_at,
+        "createdBy": created_by,
+        "comment": new.comment,
+    });
+    let created: Created = app
+        .client
+        .post(&url)
+        .json(&body)
+        .send()
+        .and_then(|r| r.error_for_status())
+        .and_then(|r| r.json())
+        .with_context(|| format!("cannot create silence at {}", url))?;
+    let created = CreatedSilence {
+        id: created.silence_id,
+        matchers: new.matchers,
+        created_by: created_by.to_string(),
+        comment: new.comment,
+        created_at: now,
+        ends_at,
+    };
+    // Shown on the alerts right away rather than after the next refresh.
+    active.id = created.id.clone();
+    app.silences.0.lock().recover().push(active);
+    app.store.track_silence(created.clone());
+    Ok(created)
+}
+
+/// Creates a silence on behalf of `created_by`, as `POST /api/silences`
+/// does, failing when silences are not configured.
+#[cfg(feature = "interactions")]
+pub fn silence(
+    app: &App,
+    created_by: &str,
+    matchers: Vec<Matcher>,
+    duration: Duration,
+    comment: String,
+) -> Result<CreatedSilence> {
+    let Some(config) = &app.config.silences else {
+        anyhow::bail!("silences are not configured");
+    };
+    let new = NewSilence {
+        matchers,
+        duration,
+        comment,
+    };
+    create_silence(app, &config.url, created_by, new)
+}
+
+/// `POST /api/silences`, creating a silence in Alertmanager on behalf of
+/// the `X-Actor` of the request.
+pub fn create(app: &App, request: &Request, body: &mut dyn Read) -> Response {
+    if let Some(response) = admin::check(app, request) {
+        return response;
+    }
+    let Some(config) = &app.config.silences else {
+        return Response::empty_404();
+    };
+    let new: NewSilence =
+        match input::json_input(request, body, app.config.max_body_size) {
+            Ok(new) => new,
+            Err(e) => return input::error(e),
+        };
+    if new.matchers.is_empty() {
+        return Response::text("no matchers").with_status_code(400);
+    }
+    let created_by = request
+        .header("X-Actor")
+        .unwrap_or(env!("CARGO_PKG_NAME"))
+        .to_string();
+    let created = match create_silence(app, &config.url, &created_by, new) {
+        Ok(created) => created,
+        Err(e) => {
+            log::error(format!("{:#}", e));
+            return Response::text(format!("{:#}", e)).with_status_code(502);
+        }
+    };
+    admin::audit(app, request, "create_silence", &created.id);
+    Response::json(&created).with_status_code(201)
+}
+
+/// `GET /api/silences`, the silences created through the bridge.
+pub fn list(app: &App, request: &Request) -> Response {
+    if let Some(response) = admin::check(app, request) {
+        return response;
+    }
+    if app.config.silences.is_none() {
+        return Response::empty_404();
+    }
+    Response::json(&app.store.created_silences())
+}
+
+/// `DELETE /api/silences/<id>`, expiring a silence created through the
+/// bridge before it ends.
+pub fn expire(app: &App, request: &Request, id: &str) -> Response {
+    if let Some(response) = admin::check(app, request) {
+        return response;
+    }
+    let Some(config) = &app.config.silences else {
+        return Response::empty_404();
+    };
+    if !app.store.created_silences().iter().any(|s| s.id == id) {
+        return Response::text("No such silence").with_status_code(404);
+    }
+    let url =
+        format!("{}/api/v2/silence/{}", config.url.trim_end_matches('/'), id);
+    let result = app
+        .client
+        .delete(&url)
+        .send()
+        .and_then(|r| r.error_for_status());
+    if let Err(e) = result {
+        log::error(format!("cannot expire silence at {}: {}", url, e));
+        return Response::text(e.to_string()).with_status_code(502);
+    }
+    admin::audit(app, request, "expire_silence", id);
+    app.silences.0.lock().recover().retain(|s| s.id != id);
+    app.store.retain_silences(|s| s.id != id);
+    Response::text("OK")
+}
+
+/// Fetches the silences every `refresh`.
+pub fn start(app: Arc<App>) {
+    let Some(config) = app.config.silences.clone() else {
+        return;
+    };
+    thread::spawn(move || loop {
+        // The last silences fetched stay in use meanwhile.
+        if let Err(e) = fetch(&app, &config.url) {
+            log::error(format!("{:#}", e));
+        }
+        thread::sleep(config.refresh);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher(
+        name: &str,
+        value: &str,
+        is_regex: bool,
+        is_equal: bool,
+    ) -> Matcher {
+        Matcher {
+            name: name.to_string(),
+            value: value.to_string(),
+            is_regex,
+            is_equal,
+        }
+    }
+
+    #[test]
+    fn matches_like_alertmanager() {
+        let silence = Active::new(
+            "1",
+            &[
+                matcher("job", "node|db", true, true),
+                matcher("env", "dev", false, false),
+            ],
+            Utc::now() + TimeDelta::hours(1),
+        )
+        .unwrap();
+        let labels = |job: &str, env: &str| {
+            HashMap::from([
+                (String::from("job"), job.to_string()),
+                (String::from("env"), env.to_string()),
+            ])
+        };
+        assert!(silence.matchers.matches(&labels("node", "prod")));
+        assert!(!silence.matchers.matches(&labels("node-exporter", "prod")));
+        assert!(!silence.matchers.matches(&labels("db", "dev")));
+        let invalid = [matcher("job", "(", true, true)];
+        assert!(Active::new("2", &invalid, Utc::now()).is_err());
+        assert_eq!(
+            matcher("job", "db", true, false).to_string(),
+            "job!~\"db\""
+        );
+        assert_eq!(
+            matcher("summary", "say \"hi\"\n", false, true).to_string(),
+            r#"summary="say \"hi\"\n""#
+        );
+    }
+}
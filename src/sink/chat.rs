@@ -0,0 +1,114 @@
+use super::Delivery;
+use crate::alert::Alert;
+use crate::alert::AlertGroup;
+use crate::alert::Status;
+use crate::config::Route;
+use crate::render::Renderer;
+use crate::theme::Theme;
+use anyhow::bail;
+use anyhow::Result;
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::Value;
+use url::Url;
+
+/// An incoming webhook of Mattermost or Rocket.Chat, both taking
+/// Slack-style attachments.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ChatConfig {
+    pub url: String,
+    /// Overrides of the channel, name and avatar set on the webhook.
+    pub channel: Option<String>,
+    pub username: Option<String>,
+    pub icon_url: Option<String>,
+}
+
+impl ChatConfig {
+    /// `mmost://host/token` or `rocket://host/token`, `mmosts` and
+    /// `rockets` posting over TLS, with overrides as query parameters.
+    pub fn from_url(url: &Url) -> Result<ChatConfig> {
+        if url.path().trim_matches('/').is_empty() {
+            bail!("{} URL needs the webhook token as path", url.scheme());
+        }
+        Ok(ChatConfig {
+            url: super::http_url(url, "/hooks")?,
+            channel: super::param(url, "channel"),
+            username: super::param(url, "username"),
+            icon_url: super::param(url, "icon_url"),
+        })
+    }
+}
+
+/// Longest attachment text, well within what Mattermost and Rocket.Chat
+/// accept.
+const ATTACHMENT_LIMIT: usize = 4000;
+
+/// One message per notification, with an attachment colored by the theme
+/// for each status.
+pub fn deliveries(
+    config: &ChatConfig,
+    group: &AlertGroup,
+    route: &Route,
+    renderer: &Renderer,
+    rocketchat: bool,
+) -> Result<Vec<Delivery>> {
+    let theme = route.theme();
+    let mut attachments: Vec<Value> = Vec::new();
+    for status in [Status::Firing, Status::Resolved] {
+        let alerts: Vec<&Alert> =
+            group.alerts.iter().filter(|a| a.status == status).collect();
+        if alerts.is_empty() {
+            continue;
+        }
+        // Titled like the Discord messages, in the route's locale.
+        let title = Theme::decorate(
+            theme.emoji(status),
+            renderer.title(route, group, status, alerts.len())?,
+        );
+        let mut text = String::new();
+        for (shown, alert) in alerts.iter().enumerate() {
+            let instance = renderer.instance(alert);
+            let line = match &alert.annotations {
+                Some(a) => format!("- **{}** {}\n", instance, a.summary),
+                None => format!("- **{}**\n", instance),
+            };
+            let more = renderer.more_alerts(route, alerts.len() - shown)?;
+            if text.len() + line.len() + more.len() > ATTACHMENT_LIMIT {
+                text.push_str(&more);
+                break;
+            }
+            text.push_str(&line);
+        }
+        let mut attachment = json!({
+            "fallback": title,
+            "color": format!("#{:06X}", theme.color(status).value()),
+            "title": title,
+            "text": text.trim_end(),
+        });
+        if !group.external_url.is_empty() {
+            attachment["title_link"] = json!(group.external_url);
+        }
+        attachments.push(attachment);
+    }
+
+    let mut body = json!({ "attachments": attachments });
+    let (username, icon) = if rocketchat {
+        ("alias", "avatar")
+    } else {
+        ("username", "icon_url")
+    };
+    if let Some(channel) = &config.channel {
+        body["channel"] = json!(channel);
+    }
+    if let Some(name) = &config.username {
+        body[username] = json!(name);
+    }
+    if let Some(url) = &config.icon_url {
+        body[icon] = json!(url);
+    }
+    Ok(vec![Delivery::Json {
+        url: config.url.clone(),
+        body,
+    }])
+}
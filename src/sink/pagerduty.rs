@@ -0,0 +1,165 @@
+use super::Delivery;
+use crate::alert::Alert;
+use crate::alert::AlertGroup;
+use crate::alert::Status;
+use crate::severity::Severity;
+use anyhow::bail;
+use anyhow::Result;
+use serde::Deserialize;
+use serde_json::json;
+use url::Url;
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PagerDutyConfig {
+    /// Integration key of the Events API v2 integration of a service.
+    pub routing_key: String,
+    #[serde(default = "default_url")]
+    pub url: String,
+}
+
+fn default_url() -> String {
+    String::from("https://events.pagerduty.com/v2/enqueue")
+}
+
+impl PagerDutyConfig {
+    /// `pagerduty://routing-key`.
+    pub fn from_url(url: &Url) -> Result<PagerDutyConfig> {
+        let Some(routing_key) = url.host_str().filter(|k| !k.is_empty()) else {
+            bail!("pagerduty URL needs a routing key");
+        };
+        Ok(PagerDutyConfig {
+            routing_key: routing_key.to_string(),
+            url: default_url(),
+        })
+    }
+}
+
+/// PagerDuty's severities.
+const SEVERITIES: [&str; 4] = ["critical", "error", "warning", "info"];
+
+/// The PagerDuty severity of the alert's level: its name when PagerDuty
+/// has it, otherwise the one of its rank among the levels, spread from
+/// `critical` for the most severe to `info` for the least.
+fn severity(config: &Severity, alert: &Alert) -> &'static str {
+    let rank = config.rank(&alert.labels);
+    if let Some(level) = config.levels.get(rank) {
+        let known = SEVERITIES
+            .iter()
+            .find(|s| level.name.eq_ignore_ascii_case(s));
+        if let Some(severity) = known {
+            return severity;
+        }
+    }
+    let last = config.levels.len().saturating_sub(1);
+    if last == 0 {
+        return "error";
+    }
+    let last_severity = SEVERITIES.len() - 1;
+    let index = (rank.min(last) * last_severity + last / 2) / last;
+    SEVERITIES[index]
+}
+
+/// One Events API v2 event per alert, deduplicated by fingerprint so that
+/// resolutions resolve the incident the alert triggered.
+pub fn deliveries(
+    config: &PagerDutyConfig,
+    group: &AlertGroup,
+    severity: &Severity,
+) -> Vec<Delivery> {
+    group
+        .alerts
+        .iter()
+        .map(|alert| {
+            let name = alert
+                .labels
+                .get("alertname")
+                .map_or("alert", String::as_str);
+            let summary = match &alert.annotations {
+                Some(a) => format!("{}: {}", name, a.summary),
+                None => name.to_string(),
+            };
+            let summary: String = summary.chars().take(1024).collect();
+            let action = match alert.status {
+                Status::Firing => "trigger",
+                Status::Resolved => "resolve",
+            };
+            let source = alert
+                .labels
+                .get("instance")
+                .or_else(|| alert.labels.get("job"))
+                .map_or("prometheus", String::as_str);
+            let mut body = json!({
+                "routing_key": config.routing_key,
+                "event_action": action,
+                "dedup_key": alert.fingerprint,
+                "client": "prometheus-discord-alert",
+                "payload": {
+                    "summary": summary,
+                    "source": source,
+                    "severity": self::severity(severity, alert),
+                    "timestamp": alert.starts_at.to_rfc3339(),
+                    "custom_details": {
+                        "labels": alert.labels,
+                        "annotations": alert.annotations,
+                    },
+                },
+            });
+            if !group.external_url.is_empty() {
+                body["client_url"] = json!(group.external_url);
+            }
+            Delivery::Json {
+                url: config.url.clone(),
+                body,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alert::Alert;
+    use crate::state::StoredAlert;
+    use chrono::Utc;
+    use std::collections::HashMap;
+
+    fn alert(label: &str, value: &str) -> Alert {
+        StoredAlert {
+            receiver: String::new(),
+            labels: HashMap::from([(label.to_string(), value.to_string())]),
+            annotations: None,
+            starts_at: Utc::now(),
+        }
+        .firing("a")
+    }
+
+    #[test]
+    fn maps_the_configured_levels() {
+        let levels: Severity = serde_yaml::from_str(
+            "{label: priority, default: p3, levels: [{name: p1}, {name: p2}, \
+             {name: P3}, {name: warning}, {name: p5}]}",
+        )
+        .unwrap();
+        let cases = [
+            ("priority", "p1", "critical"),
+            ("priority", "P2", "error"),
+            ("priority", "p3", "warning"),
+            ("priority", "p2", "error"),
+            ("priority", "warning", "warning"),
+            ("priority", "p5", "info"),
+            ("priority", "unknown", "warning"),
+            ("severity", "critical", "warning"),
+        ];
+        for (label, value, expected) in cases {
+            let alert = alert(label, value);
+            assert_eq!(severity(&levels, &alert), expected, "{}", value);
+        }
+        let default = Severity::default();
+        assert_eq!(severity(&default, &alert("severity", "x")), "info");
+        assert_eq!(
+            severity(&default, &alert("severity", "warning")),
+            "warning"
+        );
+    }
+}
@@ -0,0 +1,80 @@
+use crate::alert::AlertGroup;
+use crate::alert::Status;
+use crate::gotmpl;
+use minijinja::context;
+use minijinja::value::ValueKind;
+use minijinja::Environment;
+use minijinja::Error;
+use minijinja::Value;
+use serde_json::json;
+
+/// minijinja values as JSON, there being no `tojson` without its serde
+/// support.
+fn to_json(value: &Value) -> Result<serde_json::Value, Error> {
+    Ok(match value.kind() {
+        ValueKind::Undefined | ValueKind::None => serde_json::Value::Null,
+        ValueKind::Bool => serde_json::Value::Bool(value.is_true()),
+        ValueKind::Number => match i64::try_from(value.clone()) {
+            Ok(n) => json!(n),
+            Err(_) => json!(f64::try_from(value.clone())?),
+        },
+        ValueKind::Seq | ValueKind::Iterable => serde_json::Value::Array(
+            value
+                .try_iter()?
+                .map(|v| to_json(&v))
+                .collect::<Result<_, _>>()?,
+        ),
+        ValueKind::Map => {
+            let mut map = serde_json::Map::new();
+            for key in value.try_iter()? {
+                let item = value.get_item(&key)?;
+                map.insert(key.to_string(), to_json(&item)?);
+            }
+            serde_json::Value::Object(map)
+        }
+        _ => serde_json::Value::String(value.to_string()),
+    })
+}
+
+pub fn environment() -> Environment<'static> {
+    let mut env = Environment::new();
+    gotmpl::register(&mut env);
+    env.add_filter("tojson", |value: Value| {
+        let json = to_json(&value)?;
+        Ok::<_, Error>(Value::from_safe_string(json.to_string()))
+    });
+    env
+}
+
+/// The variables of email and webhook templates.
+pub fn context(group: &AlertGroup) -> Value {
+    let alerts: Vec<_> = group
+        .alerts
+        .iter()
+        .map(|alert| {
+            let status = match alert.status {
+                Status::Firing => "firing",
+                Status::Resolved => "resolved",
+            };
+            let annotations = alert.annotations.as_ref();
+            context! {
+                status,
+                labels => &alert.labels,
+                summary => annotations.map(|a| a.summary.clone()),
+                description => annotations.and_then(|a| a.description.clone()),
+                starts_at => alert.starts_at.to_rfc3339(),
+                ends_at => alert.ends_at.to_rfc3339(),
+                fingerprint => &alert.fingerprint,
+            }
+        })
+        .collect();
+    context! {
+        status => group.status.to_string(),
+        alertname => group.common_labels.get("alertname"),
+        receiver => &group.receiver,
+        count => alerts.len(),
+        external_url => &group.external_url,
+        common_labels => &group.common_labels,
+        alerts,
+    }
+}
@@ -0,0 +1,207 @@
+use super::Delivery;
+use super::Sinks;
+use crate::alert::AlertGroup;
+use crate::alert::Status;
+use crate::log;
+use crate::metrics;
+use crate::metrics::Metrics;
+use crate::severity::Severity;
+use crate::sync::Recover;
+use anyhow::bail;
+use anyhow::Result;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Text messages sent through Twilio when Discord cannot be reached for
+/// severe alerts.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SmsConfig {
+    pub account_sid: String,
+    pub auth_token: String,
+    /// Twilio number the messages are sent from.
+    pub from: String,
+    pub to: Vec<String>,
+    /// Least severe level escalated, of the `severity` levels.
+    pub severity: String,
+    /// Messages sent at most per `per`, counting each recipient.
+    #[serde(default = "default_max_messages")]
+    pub max_messages: usize,
+    #[serde(with = "humantime_serde", default = "default_per")]
+    pub per: Duration,
+    #[serde(default = "default_url")]
+    pub url: String,
+}
+
+fn default_max_messages() -> usize {
+    10
+}
+
+fn default_per() -> Duration {
+    Duration::from_secs(3600)
+}
+
+fn default_url() -> String {
+    String::from("https://api.twilio.com")
+}
+
+/// SMS are limited to 1600 characters, long ones costing several.
+const MAX_LENGTH: usize = 300;
+
+impl SmsConfig {
+    pub fn validate(&self, severity: &Severity) -> Result<()> {
+        if self.to.is_empty() {
+            bail!("no recipients");
+        }
+        if self.to.len() > self.max_messages {
+            bail!(
+                "{} recipients but max_messages is {}, nobody would be texted",
+                self.to.len(),
+                self.max_messages
+            );
+        }
+        if severity.position(&self.severity).is_none() {
+            bail!("unknown severity {}", self.severity);
+        }
+        Ok(())
+    }
+
+    fn messages_url(&self) -> String {
+        format!(
+            "{}/2010-04-01/Accounts/{}/Messages.json",
+            self.url.trim_end_matches('/'),
+            self.account_sid
+        )
+    }
+
+    /// The text sent if the group's messages cannot be delivered, when it
+    /// has firing alerts at least as severe as `severity`.
+    pub fn text(
+        &self,
+        severity: &Severity,
+        group: &AlertGroup,
+    ) -> Option<String> {
+        let least = severity.position(&self.severity)?;
+        let severe: Vec<_> = group
+            .alerts
+            .iter()
+            .filter(|a| a.status == Status::Firing)
+            .filter(|a| severity.rank(&a.labels) <= least)
+            .collect();
+        let first = severe.first()?;
+        let name = first.labels.get("alertname").map_or("alert", |n| n);
+        let mut text = format!("Discord unreachable, firing: {}", name);
+        if let Some(instance) = first.labels.get("instance") {
+            text.push_str(&format!(" on {}", instance));
+        }
+        let summary = first.annotations.as_ref().map(|a| &a.summary);
+        if let Some(summary) = summary.filter(|s| !s.is_empty()) {
+            text.push_str(&format!(": {}", summary));
+        }
+        if severe.len() > 1 {
+            text.push_str(&format!(" (+{} more)", severe.len() - 1));
+        }
+        Some(text.chars().take(MAX_LENGTH).collect())
+    }
+}
+
+/// The messages of the last `per`.
+#[derive(Default)]
+struct Recent {
+    /// When each was sent.
+    sent: VecDeque<Instant>,
+    /// When each text was last escalated.
+    texts: HashMap<String, Instant>,
+}
+
+/// Sends the escalations, within the rate cap.
+pub struct Sms {
+    config: SmsConfig,
+    sinks: Sinks,
+    metrics: Arc<Metrics>,
+    recent: Mutex<Recent>,
+}
+
+impl Sms {
+    pub fn new(config: SmsConfig, sinks: Sinks, metrics: Arc<Metrics>) -> Sms {
+        Sms {
+            config,
+            sinks,
+            metrics,
+            recent: Mutex::default(),
+        }
+    }
+
+    /// Texts every recipient, unless that would exceed the cap or the
+    /// same text was already sent in the last `per`, as when Alertmanager
+    /// retries a notification turned away.
+    pub fn escalate(&self, text: &str) {
+        let mut recent = self.recent.lock().recover();
+        let now = Instant::now();
+        let per = self.config.per;
+        recent.texts.retain(|_, t| now - *t < per);
+        if recent.texts.contains_key(text) {
+            return;
+        }
+        let sent = &mut recent.sent;
+        while sent.front().is_some_and(|t| now - *t >= per) {
+            sent.pop_front();
+        }
+        if sent.len() + self.config.to.len() > self.config.max_messages {
+            metrics::inc(&self.metrics.sms_capped);
+            log::warning(format!(
+                "not sending SMS, {} already sent in the last {}: {}",
+                sent.len(),
+                humantime::format_duration(self.config.per),
+                text
+            ));
+            return;
+        }
+        log::notice(format!("escalating by SMS: {}", text));
+        recent.texts.insert(text.to_string(), now);
+        for to in &self.config.to {
+            recent.sent.push_back(now);
+            metrics::inc(&self.metrics.sms_sent);
+            self.sinks.push(Delivery::Form {
+                url: self.config.messages_url(),
+                credentials: (
+                    self.config.account_sid.clone(),
+                    self.config.auth_token.clone(),
+                ),
+                form: vec![
+                    (String::from("From"), self.config.from.clone()),
+                    (String::from("To"), to.clone()),
+                    (String::from("Body"), text.to_string()),
+                ],
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn needs_room_for_every_recipient() {
+        let mut config = SmsConfig {
+            account_sid: String::from("AC1"),
+            auth_token: String::from("secret"),
+            from: String::from("+15550000000"),
+            to: vec![String::from("+15551111111"); 3],
+            severity: String::from("critical"),
+            max_messages: 3,
+            per: default_per(),
+            url: default_url(),
+        };
+        let severity = Severity::default();
+        assert!(config.validate(&severity).is_ok());
+        config.max_messages = 2;
+        assert!(config.validate(&severity).is_err());
+    }
+}
@@ -0,0 +1,125 @@
+use super::template;
+use super::Delivery;
+use crate::alert::AlertGroup;
+use crate::discord::DeliveryError;
+use anyhow::Context;
+use anyhow::Result;
+use reqwest::blocking::Client;
+use reqwest::header::HeaderName;
+use reqwest::header::HeaderValue;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Method;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use url::form_urlencoded;
+use url::Url;
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub url: String,
+    #[serde(default = "default_method")]
+    pub method: String,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default = "default_content_type")]
+    pub content_type: String,
+    /// minijinja template seeing the same variables as emails, with a
+    /// `tojson` filter.
+    #[serde(default = "default_body")]
+    pub body: String,
+}
+
+fn default_method() -> String {
+    String::from("POST")
+}
+
+fn default_content_type() -> String {
+    String::from("application/json")
+}
+
+fn default_body() -> String {
+    String::from(
+        "{\"status\": {{ status|tojson }}, \
+         \"alertname\": {{ alertname|tojson }}, \
+         \"receiver\": {{ receiver|tojson }}, \
+         \"external_url\": {{ external_url|tojson }}, \
+         \"common_labels\": {{ common_labels|tojson }}, \
+         \"alerts\": {{ alerts|tojson }}}",
+    )
+}
+
+impl WebhookConfig {
+    /// `json://host/path`, `jsons` posting over TLS, with `+Name=value`
+    /// query parameters as headers.
+    pub fn from_url(url: &Url) -> Result<WebhookConfig> {
+        // Decoding the query would turn the `+` into a space.
+        let headers = url
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .filter_map(|pair| {
+                let pair = pair.strip_prefix('+')?;
+                let (name, value) =
+                    form_urlencoded::parse(pair.as_bytes()).next()?;
+                Some((name.into_owned(), value.into_owned()))
+            })
+            .collect();
+        Ok(WebhookConfig {
+            url: super::http_url(url, "")?,
+            method: default_method(),
+            headers,
+            content_type: default_content_type(),
+            body: default_body(),
+        })
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        Method::from_bytes(self.method.as_bytes()).with_context(|| {
+            format!("invalid webhook method {}", self.method)
+        })?;
+        for (name, value) in &self.headers {
+            HeaderName::from_bytes(name.as_bytes())
+                .with_context(|| format!("invalid header {}", name))?;
+            HeaderValue::from_str(value)
+                .with_context(|| format!("invalid value of header {}", name))?;
+        }
+        template::environment()
+            .template_from_str(&self.body)
+            .context("invalid webhook body")?;
+        Ok(())
+    }
+}
+
+/// One request per notification, with the rendered body.
+pub fn deliveries(
+    config: &WebhookConfig,
+    group: &AlertGroup,
+) -> Result<Vec<Delivery>> {
+    let body = template::environment()
+        .render_str(&config.body, template::context(group))?;
+    if config.content_type.contains("json") {
+        serde_json::from_str::<Value>(&body)
+            .with_context(|| format!("webhook body is not JSON: {}", body))?;
+    }
+    Ok(vec![Delivery::Webhook(Box::new(config.clone()), body)])
+}
+
+pub fn send(
+    client: &Client,
+    config: &WebhookConfig,
+    body: &str,
+) -> Result<(), DeliveryError> {
+    // Checked by validate.
+    let method =
+        Method::from_bytes(config.method.as_bytes()).unwrap_or(Method::POST);
+    let mut builder = client
+        .request(method, &config.url)
+        .header(CONTENT_TYPE, &config.content_type)
+        .body(body.to_string());
+    for (name, value) in &config.headers {
+        builder = builder.header(name, value);
+    }
+    super::request(&config.url, builder)
+}
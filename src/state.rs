@@ -0,0 +1,741 @@
+use crate::alert::Alert;
+use crate::alert::Annotations;
+use crate::alert::Status;
+use crate::log;
+use crate::silence::CreatedSilence;
+use crate::sync::Recover;
+use anyhow::Context;
+use anyhow::Result;
+use chrono::DateTime;
+use chrono::TimeDelta;
+use chrono::Utc;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+/// A firing alert as last notified.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StoredAlert {
+    pub receiver: String,
+    pub labels: HashMap<String, String>,
+    pub annotations: Option<Annotations>,
+    pub starts_at: DateTime<Utc>,
+}
+
+impl StoredAlert {
+    /// The alert as still firing.
+    pub fn firing(&self, fingerprint: &str) -> Alert {
+        Alert {
+            status: Status::Firing,
+            labels: self.labels.clone(),
+            annotations: self.annotations.clone(),
+            starts_at: self.starts_at,
+            ends_at: DateTime::default(),
+            fingerprint: fingerprint.to_string(),
+            values: None,
+            value_string: None,
+            ticket: None,
+            silences: None,
+            probe: None,
+            host: None,
+            suppressed: false,
+        }
+    }
+
+    /// The alert as resolved at `ends_at`.
+    pub fn resolved(&self, fingerprint: &str, ends_at: DateTime<Utc>) -> Alert {
+        Alert {
+            status: Status::Resolved,
+            labels: self.labels.clone(),
+            annotations: self.annotations.clone(),
+            starts_at: self.starts_at,
+            ends_at,
+            fingerprint: fingerprint.to_string(),
+            values: None,
+            value_string: None,
+            ticket: None,
+            silences: None,
+            probe: None,
+            host: None,
+            suppressed: false,
+        }
+    }
+}
+
+/// An alert as it resolved, kept in the history.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ResolvedAlert {
+    pub fingerprint: String,
+    pub receiver: String,
+    pub labels: HashMap<String, String>,
+    pub annotations: Option<Annotations>,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+}
+
+/// An operator action, or a change the bridge picked up by itself.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AuditEntry {
+    pub at: DateTime<Utc>,
+    pub action: String,
+    /// What was acted on, e.g. the route paused.
+    pub target: String,
+    /// The `X-Actor` the client named itself with, if any.
+    pub actor: Option<String>,
+    /// Where the request came from, none for the bridge's own changes.
+    pub address: Option<String>,
+}
+
+/// Resolved alerts kept at most, the oldest being dropped beyond.
+const HISTORY_LIMIT: usize = 100_000;
+/// Audit entries kept at most, the oldest being dropped beyond.
+const AUDIT_LIMIT: usize = 10_000;
+
+/// Which resolved alerts the history keeps.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HistoryPolicy {
+    /// Until uploaded by the archival.
+    pub archive: bool,
+    /// For that long after they resolved.
+    pub retention: Option<Duration>,
+}
+
+impl HistoryPolicy {
+    fn enabled(&self) -> bool {
+        self.archive || self.retention.is_some()
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+struct State {
+    /// The alerts currently firing by fingerprint.
+    alerts: HashMap<String, StoredAlert>,
+    /// Names of the routes that deliver nothing until resumed.
+    paused: HashSet<String>,
+    /// When notifications were handled, by idempotency key.
+    handled: HashMap<String, DateTime<Utc>>,
+    /// IDs of the open incident threads, by route and group.
+    threads: HashMap<String, String>,
+    /// Links to the issues of firing alerts, by fingerprint.
+    tickets: HashMap<String, String>,
+    /// The severities firing alerts were rewritten to, by fingerprint, for
+    /// their resolution to keep them.
+    severities: HashMap<String, String>,
+    /// The firing alerts last notified while silenced or inhibited.
+    suppressed: HashSet<String>,
+    /// The alerts whose issue is being opened.
+    #[serde(skip)]
+    opening: HashSet<String>,
+    /// Resolved alerts, oldest first.
+    history: Vec<ResolvedAlert>,
+    /// How many alerts were ever dropped from the start of the history,
+    /// which the positions of `archived` count from.
+    dropped: u64,
+    /// The position of the first alert of the history not archived, among
+    /// all those ever kept, so that pruning does not move it.
+    archived: u64,
+    /// Operator actions, oldest first.
+    audit: Vec<AuditEntry>,
+    /// The silences created through the bridge that did not end yet.
+    silences: Vec<CreatedSilence>,
+    /// When the firing alerts Prometheus sent directly end unless sent
+    /// again, by fingerprint.
+    ends: HashMap<String, DateTime<Utc>>,
+}
+
+impl State {
+    /// The index in the history of the first alert not archived.
+    fn first_unarchived(&self) -> usize {
+        let start = self.archived.saturating_sub(self.dropped);
+        (start as usize).min(self.history.len())
+    }
+}
+
+/// How long changes wait to be saved, so that a burst of notifications
+/// writes the state once.
+const SAVE_DELAY: Duration = Duration::from_secs(1);
+
+/// What the bridge remembers, saved to `state_file` shortly after it
+/// changes when one is configured so it survives restarts.
+pub struct Store {
+    path: Option<PathBuf>,
+    state: Mutex<State>,
+    history: HistoryPolicy,
+    /// Whether the state changed since it was last written.
+    dirty: Mutex<bool>,
+    changed: Condvar,
+}
+
+impl Store {
+    pub fn open(path: Option<&str>, history: HistoryPolicy) -> Result<Store> {
+        let path = path.map(PathBuf::from);
+        let state = match &path {
+            Some(path) if path.exists() => {
+                let raw = std::fs::read_to_string(path).with_context(|| {
+                    format!("cannot read state file {}", path.display())
+                })?;
+                serde_json::from_str(&raw).with_context(|| {
+                    format!("invalid state file {}", path.display())
+                })?
+            }
+            _ => State::default(),
+        };
+        Ok(Store {
+            path,
+            state: Mutex::new(state),
+            history,
+            dirty: Mutex::new(false),
+            changed: Condvar::new(),
+        })
+    }
+
+    pub fn firing(&self) -> HashMap<String, StoredAlert> {
+        self.state.lock().recover().alerts.clone()
+    }
+
+    /// The firing alerts last notified while suppressed, by fingerprint.
+    pub fn suppressed(&self) -> HashSet<String> {
+        self.state.lock().recover().suppressed.clone()
+    }
+
+    pub fn is_firing(&self, fingerprint: &str) -> bool {
+        self.state.lock().recover().alerts.contains_key(fingerprint)
+    }
+
+    /// Records the status of alerts received for `receiver`.
+    pub fn observe(&self, receiver: &str, alerts: &[Alert]) {
+        let mut state = self.state.lock().recover();
+        let state = &mut *state;
+        let stored = &mut state.alerts;
+        let mut changed = false;
+        for alert in alerts {
+            match alert.status {
+                Status::Firing => {
+                    changed |= match alert.suppressed {
+                        true => {
+                            state.suppressed.insert(alert.fingerprint.clone())
+                        }
+                        false => state.suppressed.remove(&alert.fingerprint),
+                    };
+                    changed |= stored
+                        .insert(
+                            alert.fingerprint.clone(),
+                            StoredAlert {
+                                receiver: receiver.to_string(),
+                                labels: alert.labels.clone(),
+                                annotations: alert.annotations.clone(),
+                                starts_at: alert.starts_at,
+                            },
+                        )
+                        .is_none();
+                }
+                Status::Resolved => {
+                    changed |=
+                        state.severities.remove(&alert.fingerprint).is_some();
+                    changed |= state.suppressed.remove(&alert.fingerprint);
+                    let Some(resolved) = stored.remove(&alert.fingerprint)
+                    else {
+                        continue;
+                    };
+                    state.ends.remove(&alert.fingerprint);
+                    changed = true;
+                    if self.history.enabled() {
+                        // Some sources leave the end of resolved alerts out.
+                        let ends_at = match alert.ends_at {
+                            t if t > resolved.starts_at => t,
+                            _ => Utc::now(),
+                        };
+                        state.history.push(ResolvedAlert {
+                            fingerprint: alert.fingerprint.clone(),
+                            receiver: resolved.receiver,
+                            labels: resolved.labels,
+                            annotations: resolved.annotations,
+                            starts_at: resolved.starts_at,
+                            ends_at,
+                        });
+                    }
+                }
+            }
+        }
+        if changed {
+            self.prune(state);
+            self.save();
+        }
+    }
+
+    /// Records when alerts Prometheus keeps sending end, unless sent again.
+    pub fn extend(&self, alerts: &[Alert]) {
+        let mut state = self.state.lock().recover();
+        let mut changed = false;
+        for alert in alerts {
+            if alert.status != Status::Firing || alert.ends_at <= Utc::now() {
+                continue;
+            }
+            let previous =
+                state.ends.insert(alert.fingerprint.clone(), alert.ends_at);
+            changed |= previous.is_none();
+        }
+        if changed {
+            self.save();
+        }
+    }
+
+    /// The alerts Prometheus stopped sending, resolved as they ended.
+    pub fn expired(&self) -> Vec<Alert> {
+        let mut state = self.state.lock().recover();
+        let state = &mut *state;
+        let now = Utc::now();
+        let mut expired = Vec::new();
+        state.ends.retain(|fingerprint, ends_at| {
+            if *ends_at > now {
+                return true;
+            }
+            // Those still firing are forgotten once notified resolved.
+            match state.alerts.get(fingerprint) {
+                Some(alert) => {
+                    expired.push(alert.resolved(fingerprint, *ends_at));
+                    true
+                }
+                None => false,
+            }
+        });
+        expired
+    }
+
+    pub fn is_paused(&self, route: &str) -> bool {
+        self.state.lock().recover().paused.contains(route)
+    }
+
+    /// Pauses or resumes a route, returning whether that changed anything.
+    pub fn set_paused(&self, route: &str, paused: bool) -> bool {
+        let mut state = self.state.lock().recover();
+        let changed = if paused {
+            state.paused.insert(route.to_string())
+        } else {
+            state.paused.remove(route)
+        };
+        if changed {
+            self.save();
+        }
+        changed
+    }
+
+    pub fn created_silences(&self) -> Vec<CreatedSilence> {
+        let mut state = self.state.lock().recover();
+        let now = Utc::now();
+        state.silences.retain(|s| s.ends_at > now);
+        state.silences.clone()
+    }
+
+    pub fn track_silence(&self, silence: CreatedSilence) {
+        let mut state = self.state.lock().recover();
+        state.silences.push(silence);
+        self.save();
+    }
+
+    /// Forgets the created silences `keep` returns false for.
+    pub fn retain_silences(&self, keep: impl Fn(&CreatedSilence) -> bool) {
+        let mut state = self.state.lock().recover();
+        let count = state.silences.len();
+        state.silences.retain(keep);
+        if state.silences.len() != count {
+            self.save();
+        }
+    }
+
+    pub fn audit(&self, entry: AuditEntry) {
+        let mut state = self.state.lock().recover();
+        state.audit.push(entry);
+        let excess = state.audit.len().saturating_sub(AUDIT_LIMIT);
+        state.audit.drain(..excess);
+        self.save();
+    }
+
+    pub fn audit_log(&self) -> Vec<AuditEntry> {
+        self.state.lock().recover().audit.clone()
+    }
+
+    /// Records the notification as handled, unless it already was within
+    /// the window, forgetting the ones handled before.
+    pub fn claim(&self, key: &str, window: Duration) -> bool {
+        let mut state = self.state.lock().recover();
+        let window = TimeDelta::from_std(window).unwrap_or(TimeDelta::MAX);
+        let now = Utc::now();
+        state.handled.retain(|_, at| now - *at < window);
+        if state.handled.contains_key(key) {
+            return false;
+        }
+        state.handled.insert(key.to_string(), now);
+        self.save();
+        true
+    }
+
+    /// Forgets notifications that could not be handled after all.
+    pub fn release(&self, keys: &[String]) {
+        let mut state = self.state.lock().recover();
+        for key in keys {
+            state.handled.remove(key);
+        }
+        self.save();
+    }
+
+    pub fn thread(&self, key: &str) -> Option<String> {
+        self.state.lock().recover().threads.get(key).cloned()
+    }
+
+    /// Records the thread of an incident, or forgets it when `id` is None.
+    pub fn set_thread(&self, key: &str, id: Option<&str>) {
+        let mut state = self.state.lock().recover();
+        match id {
+            Some(id) => state.threads.insert(key.to_string(), id.to_string()),
+            None => state.threads.remove(key),
+        };
+        self.save();
+    }
+
+    pub fn ticket(&self, fingerprint: &str) -> Option<String> {
+        self.state
+            .lock()
+            .recover()
+            .tickets
+            .get(fingerprint)
+            .cloned()
+    }
+
+    /// Claims the opening of the alert's issue, false if it has one or
+    /// another notification is opening it.
+    pub fn claim_ticket(&self, fingerprint: &str) -> bool {
+        let mut state = self.state.lock().recover();
+        !state.tickets.contains_key(fingerprint)
+            && state.opening.insert(fingerprint.to_string())
+    }
+
+    /// Gives up the opening of the alert's issue, for another attempt.
+    pub fn release_ticket(&self, fingerprint: &str) {
+        self.state.lock().recover().opening.remove(fingerprint);
+    }
+
+    /// Records the issue of an alert, or forgets it when `url` is None.
+    pub fn set_ticket(&self, fingerprint: &str, url: Option<&str>) {
+        let mut state = self.state.lock().recover();
+        state.opening.remove(fingerprint);
+        match url {
+            Some(url) => state
+                .tickets
+                .insert(fingerprint.to_string(), url.to_string()),
+            None => state.tickets.remove(fingerprint),
+        };
+        self.save();
+    }
+
+    /// The severity a firing alert was rewritten to.
+    pub fn rewritten_severity(&self, fingerprint: &str) -> Option<String> {
+        let state = self.state.lock().recover();
+        state.severities.get(fingerprint).cloned()
+    }
+
+    /// Records the severity a firing alert is rewritten to, or that it no
+    /// longer is when `severity` is None.
+    pub fn set_rewritten_severity(
+        &self,
+        fingerprint: &str,
+        severity: Option<&str>,
+    ) {
+        let mut state = self.state.lock().recover();
+        let known = state.severities.get(fingerprint).map(String::as_str);
+        if known == severity {
+            return;
+        }
+        match severity {
+            Some(severity) => state
+                .severities
+                .insert(fingerprint.to_string(), severity.to_string()),
+            None => state.severities.remove(fingerprint),
+        };
+        self.save();
+    }
+
+    pub fn history(&self) -> Vec<ResolvedAlert> {
+        self.state.lock().recover().history.clone()
+    }
+
+    /// The alerts of the history firing at some point between `from` and
+    /// `to`, copying only those out of the history.
+    pub fn history_between(
+        &self,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Vec<ResolvedAlert> {
+        let state = self.state.lock().recover();
+        state
+            .history
+            .iter()
+            .filter(|a| from.is_none_or(|from| a.ends_at >= from))
+            .filter(|a| to.is_none_or(|to| a.starts_at < to))
+            .cloned()
+            .collect()
+    }
+
+    /// The alerts of the history not archived yet, and the position to
+    /// tell `archived` once they are.
+    #[cfg(feature = "archive")]
+    pub fn unarchived(&self) -> (Vec<ResolvedAlert>, u64) {
+        let state = self.state.lock().recover();
+        let start = state.first_unarchived();
+        let until = state.dropped + state.history.len() as u64;
+        (state.history[start..].to_vec(), until)
+    }
+
+    /// Records that the alerts of the history before `until`, as given by
+    /// `unarchived`, were archived.
+    #[cfg(feature = "archive")]
+    pub fn archived(&self, until: u64) {
+        let mut state = self.state.lock().recover();
+        state.archived = state.archived.max(until);
+        self.prune(&mut state);
+        self.save();
+    }
+
+    /// Drops the oldest alerts of the history, once neither waiting for
+    /// the archival nor within the retention.
+    fn prune(&self, state: &mut State) {
+        let mut excess = state.history.len().saturating_sub(HISTORY_LIMIT);
+        if excess > 0 {
+            log::warning(format!(
+                "alert history full, dropping {} alerts",
+                excess
+            ));
+        }
+        let cutoff = self
+            .history
+            .retention
+            .and_then(|r| TimeDelta::from_std(r).ok())
+            .map(|r| Utc::now() - r);
+        for (index, alert) in state.history.iter().enumerate().skip(excess) {
+            let archiving =
+                self.history.archive && index >= state.first_unarchived();
+            let retained = cutoff.is_some_and(|cutoff| alert.ends_at >= cutoff);
+            if archiving || retained {
+                break;
+            }
+            excess += 1;
+        }
+        state.history.drain(..excess);
+        state.dropped += excess as u64;
+    }
+
+    fn save(&self) {
+        *self.dirty.lock().recover() = true;
+        self.changed.notify_one();
+    }
+
+    /// Writes the state if it changed since last written.
+    pub fn flush(&self) {
+        let state = self.state.lock().recover();
+        let mut dirty = self.dirty.lock().recover();
+        if !*dirty {
+            return;
+        }
+        if let Err(e) = self.write(&state) {
+            log::error(format!("{:#}", e));
+            return;
+        }
+        *dirty = false;
+    }
+
+    fn write(&self, state: &State) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let temp = path.with_extension("tmp");
+        std::fs::write(&temp, serde_json::to_vec(state)?)
+            .and_then(|()| std::fs::rename(&temp, path))
+            .with_context(|| {
+                format!("cannot write state file {}", path.display())
+            })
+    }
+}
+
+/// Starts the thread saving the changes to `state_file`.
+pub fn start(store: Arc<Store>) {
+    if store.path.is_none() {
+        return;
+    }
+    thread::spawn(move || loop {
+        {
+            let mut dirty = store.dirty.lock().recover();
+            while !*dirty {
+                dirty = store.changed.wait(dirty).recover();
+            }
+        }
+        thread::sleep(SAVE_DELAY);
+        store.flush();
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn alert(status: Status, ends_at: DateTime<Utc>) -> Alert {
+        let stored = StoredAlert {
+            receiver: String::new(),
+            labels: HashMap::from([("alertname".into(), "Down".into())]),
+            annotations: None,
+            starts_at: Utc::now() - TimeDelta::minutes(5),
+        };
+        match status {
+            Status::Firing => Alert {
+                ends_at,
+                ..stored.firing("a")
+            },
+            Status::Resolved => stored.resolved("a", ends_at),
+        }
+    }
+
+    #[test]
+    fn expires_alerts_not_sent_again() {
+        let store = Store::open(None, HistoryPolicy::default()).unwrap();
+        let soon = Utc::now() + TimeDelta::milliseconds(50);
+        let firing = [alert(Status::Firing, soon)];
+        store.extend(&firing);
+        store.observe("", &firing);
+        assert!(store.expired().is_empty());
+        std::thread::sleep(Duration::from_millis(60));
+        let expired = store.expired();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].status, Status::Resolved);
+        assert_eq!(expired[0].ends_at, soon);
+        store.observe("", &expired);
+        assert!(!store.is_firing("a"));
+        assert!(store.expired().is_empty());
+    }
+
+    #[test]
+    fn keeps_alerts_sent_again() {
+        let store = Store::open(None, HistoryPolicy::default()).unwrap();
+        let firing = [alert(Status::Firing, Utc::now())];
+        store.observe("", &firing);
+        store
+            .extend(&[alert(Status::Firing, Utc::now() + TimeDelta::hours(1))]);
+        assert!(store.expired().is_empty());
+        assert!(store.is_firing("a"));
+    }
+
+    #[test]
+    fn remembers_alerts_notified_while_suppressed() {
+        let store = Store::open(None, HistoryPolicy::default()).unwrap();
+        let mut firing = [alert(Status::Firing, DateTime::default())];
+        firing[0].suppressed = true;
+        store.observe("", &firing);
+        assert!(store.suppressed().contains("a"));
+        firing[0].suppressed = false;
+        store.observe("", &firing);
+        assert!(store.suppressed().is_empty());
+        assert!(store.is_firing("a"));
+    }
+
+    #[test]
+    fn saves_on_flush() {
+        let path = std::env::temp_dir().join(format!(
+            "prometheus-discord-alert-state-{}.json",
+            std::process::id()
+        ));
+        let path = path.to_str().unwrap();
+        let store = Store::open(Some(path), HistoryPolicy::default()).unwrap();
+        store.set_paused("ops", true);
+        assert!(!Path::new(path).exists());
+        store.flush();
+        let reopened =
+            Store::open(Some(path), HistoryPolicy::default()).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert!(reopened.is_paused("ops"));
+    }
+
+    #[test]
+    fn copies_the_history_in_range() {
+        let policy = HistoryPolicy {
+            archive: false,
+            retention: Some(Duration::from_secs(86400)),
+        };
+        let store = Store::open(None, policy).unwrap();
+        let start = Utc::now() - TimeDelta::hours(3);
+        for (fingerprint, hours) in [("a", 2), ("b", 1)] {
+            let stored = StoredAlert {
+                receiver: String::new(),
+                labels: HashMap::new(),
+                annotations: None,
+                starts_at: start,
+            };
+            let end = start + TimeDelta::hours(hours);
+            store.observe("", &[stored.firing(fingerprint)]);
+            store.observe("", &[stored.resolved(fingerprint, end)]);
+        }
+        let between = |from: i64, to: i64| {
+            store
+                .history_between(
+                    Some(start + TimeDelta::minutes(from)),
+                    Some(start + TimeDelta::minutes(to)),
+                )
+                .into_iter()
+                .map(|a| a.fingerprint)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(between(0, 30), ["a", "b"]);
+        assert_eq!(between(90, 150), ["a"]);
+        assert!(between(150, 180).is_empty());
+        assert_eq!(store.history_between(None, None).len(), 2);
+    }
+
+    #[cfg(feature = "archive")]
+    #[test]
+    fn archives_what_pruning_leaves() {
+        let policy = HistoryPolicy {
+            archive: true,
+            retention: None,
+        };
+        let store = Store::open(None, policy).unwrap();
+        store.state.lock().recover().history = (0..HISTORY_LIMIT)
+            .map(|_| ResolvedAlert {
+                fingerprint: String::from("old"),
+                receiver: String::new(),
+                labels: HashMap::new(),
+                annotations: None,
+                starts_at: Utc::now(),
+                ends_at: Utc::now(),
+            })
+            .collect();
+        let (alerts, until) = store.unarchived();
+        assert_eq!(alerts.len(), HISTORY_LIMIT);
+        // Pushing out the oldest alert while the others are uploaded.
+        store.observe("", &[alert(Status::Firing, DateTime::default())]);
+        store.observe("", &[alert(Status::Resolved, Utc::now())]);
+        store.archived(until);
+        let (alerts, _) = store.unarchived();
+        let fingerprints: Vec<_> =
+            alerts.iter().map(|a| a.fingerprint.as_str()).collect();
+        assert_eq!(fingerprints, ["a"]);
+    }
+
+    #[test]
+    fn claims_the_opening_of_an_issue_once() {
+        let store = Store::open(None, HistoryPolicy::default()).unwrap();
+        assert!(store.claim_ticket("a"));
+        assert!(!store.claim_ticket("a"));
+        store.release_ticket("a");
+        assert!(store.claim_ticket("a"));
+        store.set_ticket("a", Some("https://issues/1"));
+        assert!(!store.claim_ticket("a"));
+        store.set_ticket("a", None);
+        assert!(store.claim_ticket("a"));
+    }
+}
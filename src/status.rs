@@ -0,0 +1,219 @@
+use crate::breaker::State;
+use crate::metrics;
+use crate::metrics::Exposition;
+use crate::App;
+use chrono::DateTime;
+use chrono::Utc;
+use rouille::Response;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+
+pub fn metrics(app: &App) -> Response {
+    Response::from_data("text/plain; version=0.0.4", exposition(app))
+}
+
+/// The self-metrics in the Prometheus text format.
+pub fn exposition(app: &App) -> String {
+    let mut out = Exposition::default();
+    app.metrics.expose(&mut out);
+    out.metric(
+        "bridge_queue_depth",
+        "gauge",
+        "Notifications waiting for a worker.",
+        app.queue.len() as f64,
+    );
+    if app.config.queue.max_memory.is_some() {
+        out.metric(
+            "bridge_queue_memory_bytes",
+            "gauge",
+            "Size of the notifications queued in memory.",
+            app.queue.memory_bytes() as f64,
+        );
+        out.metric(
+            "bridge_queue_spilled",
+            "gauge",
+            "Notifications waiting in the spill directory.",
+            app.queue.spilled() as f64,
+        );
+        out.metric(
+            "bridge_queue_spills_total",
+            "counter",
+            "Notifications written to the spill directory.",
+            app.queue.spills() as f64,
+        );
+    }
+    out.metric(
+        "bridge_circuit_state",
+        "gauge",
+        "State of the Discord circuit breaker: 0 closed, 1 open, 2 half-open.",
+        app.breaker.state().gauge() as f64,
+    );
+    alert_statistics(app, &mut out);
+    out.finish()
+}
+
+/// Gauges about the alerts currently firing, as notified to Discord.
+fn alert_statistics(app: &App, out: &mut Exposition) {
+    let firing = app.store.firing();
+    let mut counts: BTreeMap<(String, String), usize> = BTreeMap::new();
+    for alert in firing.values() {
+        let alertname = alert.labels.get("alertname").cloned();
+        let severity = app.config.severity.level(&alert.labels);
+        let key = (
+            alertname.unwrap_or_default(),
+            severity.map(|l| l.name.clone()).unwrap_or_default(),
+        );
+        *counts.entry(key).or_default() += 1;
+    }
+    let samples: Vec<_> = counts
+        .into_iter()
+        .map(|((alertname, severity), count)| {
+            let labels = metrics::labels(&[
+                ("alertname", &alertname),
+                ("severity", &severity),
+            ]);
+            (labels, count as f64)
+        })
+        .collect();
+    out.family(
+        "bridge_active_alerts",
+        "gauge",
+        "Alerts firing, by alert name and severity level.",
+        &samples,
+    );
+
+    let longest = firing
+        .values()
+        .map(|a| (Utc::now() - a.starts_at).num_milliseconds())
+        .max()
+        .unwrap_or(0);
+    out.metric(
+        "bridge_longest_firing_seconds",
+        "gauge",
+        "How long the oldest firing alert has been firing.",
+        longest.max(0) as f64 / 1000.0,
+    );
+}
+
+pub fn page(app: &App) -> Response {
+    let m = &app.metrics;
+    let rows = [
+        ("Version", env!("CARGO_PKG_VERSION").to_string()),
+        ("Circuit", app.breaker.state().name().to_string()),
+        (
+            "Queue",
+            format!("{} / {}", app.queue.len(), app.queue.capacity()),
+        ),
+        ("Requests", metrics::get(&m.requests).to_string()),
+        ("Rejected", metrics::get(&m.rejected_requests).to_string()),
+        ("Sent", metrics::get(&m.messages_sent).to_string()),
+        ("Failures", metrics::get(&m.delivery_failures).to_string()),
+        ("Dropped", metrics::get(&m.messages_dropped).to_string()),
+    ];
+    let silences = app.store.created_silences().into_iter().map(|s| {
+        let matchers: Vec<_> =
+            s.matchers.iter().map(|m| m.to_string()).collect();
+        let value = format!(
+            "{} until {} by {}: {} ({})",
+            matchers.join(", "),
+            s.ends_at.to_rfc3339(),
+            s.created_by,
+            s.comment,
+            s.id
+        );
+        ("Silence", value)
+    });
+    let rows: String = rows
+        .into_iter()
+        .chain(silences)
+        .map(|(name, value)| {
+            format!("<tr><th>{}</th><td>{}</td></tr>\n", name, escape(&value))
+        })
+        .collect();
+    Response::html(format!(
+        "<!DOCTYPE html>\n<html><head><title>prometheus-discord-alert</title>\
+        </head><body>\n<h1>prometheus-discord-alert</h1>\n<table>\n{}\
+        </table>\n</body></html>\n",
+        rows
+    ))
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Health of the bridge and the alerts it sees firing, for dashboards.
+#[derive(Serialize)]
+struct Summary {
+    /// `ok`, `degraded` while the circuit is half-open or the queue
+    /// almost full, or `down` while the circuit is open.
+    status: &'static str,
+    version: &'static str,
+    circuit: &'static str,
+    queue_depth: usize,
+    queue_capacity: usize,
+    firing: usize,
+    /// Firing alerts by severity level.
+    severities: BTreeMap<String, usize>,
+    last_delivery: Option<String>,
+}
+
+fn summary(app: &App) -> Summary {
+    let circuit = app.breaker.state();
+    let (depth, capacity) = (app.queue.len(), app.queue.capacity());
+    let status = match circuit {
+        State::Open(_) => "down",
+        State::HalfOpen => "degraded",
+        State::Closed if depth * 10 >= capacity * 9 => "degraded",
+        State::Closed => "ok",
+    };
+    let firing = app.store.firing();
+    let mut severities = BTreeMap::new();
+    for alert in firing.values() {
+        let level = app.config.severity.level(&alert.labels);
+        let name = level.map(|l| l.name.clone()).unwrap_or_default();
+        *severities.entry(name).or_default() += 1;
+    }
+    let last_delivery = metrics::get(&app.metrics.last_delivery);
+    let last_delivery = i64::try_from(last_delivery)
+        .ok()
+        .filter(|t| *t > 0)
+        .and_then(|t| DateTime::from_timestamp(t, 0))
+        .map(|t| t.to_rfc3339());
+    Summary {
+        status,
+        version: env!("CARGO_PKG_VERSION"),
+        circuit: circuit.name(),
+        queue_depth: depth,
+        queue_capacity: capacity,
+        firing: firing.len(),
+        severities,
+        last_delivery,
+    }
+}
+
+/// `GET /api/status.json`.
+pub fn json(app: &App) -> Response {
+    Response::json(&summary(app))
+}
+
+/// `GET /api/status/badge`, a shields.io endpoint badge counting the
+/// firing alerts.
+pub fn badge(app: &App) -> Response {
+    let summary = summary(app);
+    let (message, color) = match (summary.status, summary.firing) {
+        ("down", _) => (String::from("Discord unavailable"), "lightgrey"),
+        (_, 0) => (String::from("none firing"), "brightgreen"),
+        (_, firing) => (format!("{} firing", firing), "red"),
+    };
+    Response::json(&json!({
+        "schemaVersion": 1,
+        "label": "alerts",
+        "message": message,
+        "color": color,
+    }))
+}
@@ -0,0 +1,82 @@
+use std::sync::Condvar;
+use std::sync::LockResult;
+use std::sync::Mutex;
+use std::sync::PoisonError;
+use std::time::Instant;
+
+/// Takes the guard of a poisoned lock rather than panicking. Handler
+/// panics are caught, and the stores they leave behind stay usable by
+/// the next requests instead of failing every one of them.
+pub trait Recover<G> {
+    fn recover(self) -> G;
+}
+
+impl<G> Recover<G> for LockResult<G> {
+    fn recover(self) -> G {
+        self.unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Counts the work in progress, for shutting down once it is done.
+#[derive(Default)]
+pub struct Pending {
+    count: Mutex<usize>,
+    done: Condvar,
+}
+
+impl Pending {
+    pub fn start(&self) {
+        *self.count.lock().recover() += 1;
+    }
+
+    pub fn finish(&self) {
+        let mut count = self.count.lock().recover();
+        *count = count.saturating_sub(1);
+        if *count == 0 {
+            self.done.notify_all();
+        }
+    }
+
+    /// Waits for the work in progress, false if the deadline came first.
+    pub fn wait(&self, deadline: Instant) -> bool {
+        let mut count = self.count.lock().recover();
+        while *count > 0 {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                return false;
+            }
+            count = self.done.wait_timeout(count, timeout).recover().0;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn recovers_poisoned_locks() {
+        let mutex = Mutex::new(1);
+        let _ = std::panic::catch_unwind(|| {
+            let _guard = mutex.lock().unwrap();
+            panic!("handler panic");
+        });
+        assert!(mutex.is_poisoned());
+        *mutex.lock().recover() += 1;
+        assert_eq!(*mutex.lock().recover(), 2);
+    }
+
+    #[test]
+    fn waits_for_pending_work() {
+        let pending = std::sync::Arc::new(Pending::default());
+        let soon = || Instant::now() + std::time::Duration::from_millis(50);
+        assert!(pending.wait(soon()));
+        pending.start();
+        assert!(!pending.wait(soon()));
+        let worker = pending.clone();
+        std::thread::spawn(move || worker.finish());
+        assert!(pending.wait(soon() + std::time::Duration::from_secs(5)));
+    }
+}
@@ -0,0 +1,78 @@
+use crate::log;
+use anyhow::anyhow;
+use anyhow::Result;
+use std::net::TcpListener;
+use std::os::fd::FromRawFd;
+use std::os::fd::IntoRawFd;
+use std::os::unix::net::SocketAddr;
+use std::os::unix::net::UnixDatagram;
+use std::os::unix::net::UnixListener;
+
+/// First file descriptor passed by systemd, see sd_listen_fds(3).
+const LISTEN_FDS_START: i32 = 3;
+
+/// Takes over the listening sockets passed by systemd socket activation,
+/// if any were passed to this process, with their `FileDescriptorName`.
+pub fn listeners() -> Result<Vec<(String, tiny_http::Server)>> {
+    let pid = std::env::var("LISTEN_PID").ok();
+    let fds = std::env::var("LISTEN_FDS").ok();
+    let names = std::env::var("LISTEN_FDNAMES").unwrap_or_default();
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+
+    if pid.and_then(|p| p.parse::<u32>().ok()) != Some(std::process::id()) {
+        return Ok(Vec::new());
+    }
+    let count = fds.and_then(|n| n.parse::<i32>().ok()).unwrap_or(0);
+    let mut names = names.split(':');
+
+    (LISTEN_FDS_START..LISTEN_FDS_START + count)
+        .map(|fd| {
+            let name = names.next().unwrap_or_default().to_string();
+            // SAFETY: systemd hands these descriptors over to us and
+            // nothing else in the process uses them.
+            let unix = unsafe { UnixListener::from_raw_fd(fd) };
+            let server = if unix.local_addr().is_ok() {
+                tiny_http::Server::from_listener(unix, None)
+            } else {
+                let fd = unix.into_raw_fd();
+                let tcp = unsafe { TcpListener::from_raw_fd(fd) };
+                tiny_http::Server::from_listener(tcp, None)
+            };
+            server
+                .map(|server| (name, server))
+                .map_err(|e| anyhow!("inherited socket {}: {}", fd, e))
+        })
+        .collect()
+}
+
+/// Sends a state like `READY=1` to the service manager, see sd_notify(3).
+/// Does nothing when not started by systemd with `Type=notify`.
+pub fn notify(state: &str) {
+    let Ok(path) = std::env::var("NOTIFY_SOCKET") else {
+        return;
+    };
+    let Ok(socket) = UnixDatagram::unbound() else {
+        return;
+    };
+    let result = match path.strip_prefix('@') {
+        Some(name) => abstract_addr(name)
+            .and_then(|addr| socket.send_to_addr(state.as_bytes(), &addr)),
+        None => socket.send_to(state.as_bytes(), &path),
+    };
+    if let Err(e) = result {
+        log::warning(format!("cannot notify systemd: {}", e));
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn abstract_addr(name: &str) -> std::io::Result<SocketAddr> {
+    use std::os::linux::net::SocketAddrExt;
+    SocketAddr::from_abstract_name(name)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn abstract_addr(_: &str) -> std::io::Result<SocketAddr> {
+    Err(std::io::Error::other("abstract sockets need linux"))
+}
@@ -0,0 +1,106 @@
+use chrono::DateTime;
+use chrono::Utc;
+
+/// Longest value shown in a column, longer ones being cut.
+const MAX_WIDTH: usize = 32;
+
+/// The two largest units of the time elapsed since `since`: "2d 3h",
+/// "3h 12m", "45s".
+pub fn age(since: DateTime<Utc>, now: DateTime<Utc>) -> String {
+    let s = (now - since).num_seconds().max(0) as u64;
+    let (days, hours) = (s / 86400, s / 3600 % 24);
+    let (minutes, secs) = (s / 60 % 60, s % 60);
+    match (days, hours, minutes) {
+        (0, 0, 0) => format!("{}s", secs),
+        (0, 0, _) => format!("{}m {}s", minutes, secs),
+        (0, _, _) => format!("{}h {}m", hours, minutes),
+        _ => format!("{}d {}h", days, hours),
+    }
+}
+
+fn cut(value: &str) -> String {
+    let value = value.replace('`', "'");
+    if value.chars().count() <= MAX_WIDTH {
+        return value;
+    }
+    let mut cut: String = value.chars().take(MAX_WIDTH - 1).collect();
+    cut.push('…');
+    cut
+}
+
+/// Formats the rows as an aligned table in a code block of at most
+/// `limit` characters, rows that do not fit being counted on a last line.
+/// Nothing is returned when not even the header and that line fit.
+pub fn code_block<const N: usize>(
+    header: [&str; N],
+    rows: &[[String; N]],
+    limit: usize,
+) -> String {
+    let rows: Vec<[String; N]> = rows
+        .iter()
+        .map(|row| row.each_ref().map(|v| cut(v)))
+        .collect();
+    let mut widths = header.map(|h| h.chars().count());
+    for row in &rows {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(value.chars().count());
+        }
+    }
+    let line = |values: [&str; N]| {
+        let cells: Vec<_> = values
+            .iter()
+            .zip(widths)
+            .map(|(value, width)| format!("{:<width$}", value))
+            .collect();
+        format!("{}\n", cells.join("  ").trim_end())
+    };
+
+    // The fences and, unless on the last row, the line counting the rest.
+    let fences = "```\n```".len();
+    let more = |count: usize| format!("… and {} more\n", count);
+    let mut table = line(header);
+    let least = match rows.len() {
+        0 => 0,
+        count => more(count).chars().count(),
+    };
+    if table.chars().count() + least + fences > limit {
+        return String::new();
+    }
+    for (shown, row) in rows.iter().enumerate() {
+        let row = line(row.each_ref().map(String::as_str));
+        let rest = rows.len() - shown - 1;
+        let reserved = if rest > 0 {
+            more(rest).chars().count()
+        } else {
+            0
+        };
+        let length = table.chars().count() + row.chars().count();
+        if length + reserved + fences > limit {
+            table.push_str(&more(rows.len() - shown));
+            break;
+        }
+        table.push_str(&row);
+    }
+    format!("```\n{}```", table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_the_rows_that_do_not_fit() {
+        let rows = [
+            [String::from("a"), String::from("1")],
+            [String::from("b"), String::from("2")],
+            [String::from("c"), String::from("3")],
+        ];
+        let table = code_block(["NAME", "N"], &rows, 36);
+        assert_eq!(table, "```\nNAME  N\na     1\n… and 2 more\n```");
+        let table = code_block(["NAME", "N"], &rows, 28);
+        assert_eq!(table, "```\nNAME  N\n… and 3 more\n```");
+        assert_eq!(code_block(["NAME", "N"], &rows, 27), "");
+        assert_eq!(code_block(["NAME", "N"], &rows, 0), "");
+        assert_eq!(code_block(["NAME", "N"], &[], 15), "```\nNAME  N\n```");
+    }
+}
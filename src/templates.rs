@@ -0,0 +1,260 @@
+use crate::config::Config;
+use crate::i18n::Catalog;
+use crate::log;
+use crate::metrics;
+use crate::render::Renderer;
+use crate::state::AuditEntry;
+use crate::sync::Recover;
+use crate::App;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use chrono::Utc;
+use serde::Serialize;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use std::time::SystemTime;
+
+/// Lists the `.j2` files of the directory and its subdirectories with
+/// their modification time, which changes whenever a template does.
+/// Links are followed, and hidden entries skipped, so that a version of
+/// the templates can be swapped in at once by pointing a link to it, as
+/// Kubernetes does with the `..data` link of ConfigMap volumes.
+fn scan(dir: &Path) -> Result<Vec<(PathBuf, SystemTime)>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = std::fs::read_dir(&dir)
+            .with_context(|| format!("cannot read {}", dir.display()))?;
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let path = entry.path();
+            let metadata = std::fs::metadata(&path)
+                .with_context(|| format!("cannot read {}", path.display()))?;
+            if metadata.is_dir() {
+                dirs.push(path);
+            } else if path.extension().is_some_and(|e| e == "j2") {
+                files.push((path, metadata.modified()?));
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// What changed between two scans, file names relative to the directory.
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+struct Changes {
+    added: Vec<String>,
+    changed: Vec<String>,
+    removed: Vec<String>,
+}
+
+fn changes(
+    dir: &Path,
+    old: &[(PathBuf, SystemTime)],
+    new: &[(PathBuf, SystemTime)],
+) -> Changes {
+    let name = |path: &PathBuf| {
+        path.strip_prefix(dir).unwrap_or(path).display().to_string()
+    };
+    let mut changes = Changes::default();
+    for (path, modified) in new {
+        match old.iter().find(|(p, _)| p == path) {
+            None => changes.added.push(name(path)),
+            Some((_, m)) if m != modified => changes.changed.push(name(path)),
+            Some(_) => {}
+        }
+    }
+    for (path, _) in old {
+        if !new.iter().any(|(p, _)| p == path) {
+            changes.removed.push(name(path));
+        }
+    }
+    changes
+}
+
+/// Replaces the templates of the config by the files of the directory,
+/// named after the template they replace: `title.j2`, `field_name.j2`,
+/// `content/<route>.j2` and so on.
+fn apply(config: &mut Config, dir: &Path) -> Result<()> {
+    for (path, _) in scan(dir)? {
+        let name = path
+            .strip_prefix(dir)?
+            .with_extension("")
+            .to_string_lossy()
+            .into_owned();
+        let source = std::fs::read_to_string(&path)
+            .with_context(|| format!("cannot read {}", path.display()))?;
+        let m = &mut config.messages;
+        let template = match name.as_str() {
+            "title" => &mut m.title,
+            "content" => &mut m.content,
+            "field_name" => &mut m.field_name,
+            "field_value" => &mut m.field_value,
+            "no_annotations" => &mut m.no_annotations,
+            "transition" => &mut m.transition,
+            "group_summary" => &mut m.group_summary,
+            "more_alerts" => &mut m.more_alerts,
+            "flapping_title" => &mut m.flapping_title,
+            "flapping_field" => &mut m.flapping_field,
+            "reminder_title" => &mut m.reminder_title,
+            "reminder_field" => &mut m.reminder_field,
+            "thread_name" => &mut m.thread_name,
+            "maintenance" => &mut m.maintenance,
+            "ticket_title" => &mut m.ticket_title,
+            "ticket_body" => &mut m.ticket_body,
+            "silence" => &mut m.silence,
+            _ => {
+                let (kind, route) = name.split_once('/').unwrap_or_default();
+                let route = config.routes.iter_mut().find(|c| c.name == route);
+                match (kind, route) {
+                    ("content", Some(route)) => {
+                        route.content.insert(String::new())
+                    }
+                    ("content_prefix", Some(route)) => {
+                        route.content_prefix.insert(String::new())
+                    }
+                    ("content_suffix", Some(route)) => {
+                        route.content_suffix.insert(String::new())
+                    }
+                    _ => bail!("unknown template {}", path.display()),
+                }
+            }
+        };
+        *template = source;
+    }
+    Ok(())
+}
+
+/// Builds the renderer from the config and the templates directory.
+pub fn renderer(config: &Config, catalog: &Arc<Catalog>) -> Result<Renderer> {
+    let Some(dir) = &config.templates_dir else {
+        return Renderer::new(config, catalog.clone());
+    };
+    let mut config = config.clone();
+    apply(&mut config, Path::new(dir))?;
+    Renderer::new(&config, catalog.clone())
+        .with_context(|| format!("invalid template in {}", dir))
+}
+
+/// Rebuilds the renderer whenever a template changes. A renderer that
+/// fails to build is logged and the previous one kept.
+pub fn watch(app: Arc<App>) {
+    let Some(dir) = app.config.templates_dir.clone() else {
+        return;
+    };
+    thread::spawn(move || {
+        let mut last = scan(Path::new(&dir)).ok();
+        loop {
+            thread::sleep(Duration::from_secs(2));
+            let current = scan(Path::new(&dir)).ok();
+            if current == last {
+                continue;
+            }
+            let changed = changes(
+                Path::new(&dir),
+                last.as_deref().unwrap_or_default(),
+                current.as_deref().unwrap_or_default(),
+            );
+            let changed = serde_json::to_string(&changed).unwrap_or_default();
+            last = current;
+            match renderer(&app.config, &app.catalog) {
+                Ok(renderer) => {
+                    *app.renderer.write().recover() = Arc::new(renderer);
+                    log::info(format!(
+                        "reloaded templates from {}: {}",
+                        dir, changed
+                    ));
+                    app.store.audit(AuditEntry {
+                        at: Utc::now(),
+                        action: String::from("reload_templates"),
+                        target: changed,
+                        actor: None,
+                        address: None,
+                    });
+                }
+                Err(e) => {
+                    metrics::inc(&app.metrics.template_reload_failures);
+                    log::error(format!(
+                        "keeping the previous templates: {:#}",
+                        e
+                    ));
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tells_the_files_changed() {
+        let dir = Path::new("/templates");
+        let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        let old = [
+            (dir.join("content/ops.j2"), at(1)),
+            (dir.join("title.j2"), at(1)),
+            (dir.join("silence.j2"), at(1)),
+        ];
+        let new = [
+            (dir.join("content/db.j2"), at(2)),
+            (dir.join("content/ops.j2"), at(1)),
+            (dir.join("title.j2"), at(2)),
+        ];
+        let changes = changes(dir, &old, &new);
+        assert_eq!(
+            changes,
+            Changes {
+                added: vec![String::from("content/db.j2")],
+                changed: vec![String::from("title.j2")],
+                removed: vec![String::from("silence.j2")],
+            }
+        );
+        assert_eq!(
+            serde_json::to_string(&changes).unwrap(),
+            r#"{"added":["content/db.j2"],"changed":["title.j2"],"removed":["silence.j2"]}"#
+        );
+    }
+
+    #[test]
+    fn follows_links_to_the_current_version() {
+        use std::os::unix::fs::symlink;
+        let dir = std::env::temp_dir().join(format!(
+            "prometheus-discord-alert-templates-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        for (version, title) in [("..v1", "one"), ("..v2", "two")] {
+            std::fs::create_dir_all(dir.join(version)).unwrap();
+            std::fs::write(dir.join(version).join("title.j2"), title).unwrap();
+        }
+        symlink("..v1", dir.join("..data")).unwrap();
+        symlink("..data/title.j2", dir.join("title.j2")).unwrap();
+        let title = || {
+            let mut config = Config::default();
+            apply(&mut config, &dir).unwrap();
+            config.messages.title
+        };
+        let first = scan(&dir).unwrap();
+        assert_eq!(first.len(), 1);
+        assert_eq!(title(), "one");
+
+        // Swapped the way kubelet does, renaming a new link over the old.
+        std::thread::sleep(Duration::from_millis(10));
+        std::fs::write(dir.join("..v2/title.j2"), "two").unwrap();
+        symlink("..v2", dir.join("..data_tmp")).unwrap();
+        std::fs::rename(dir.join("..data_tmp"), dir.join("..data")).unwrap();
+        assert_ne!(scan(&dir).unwrap(), first);
+        assert_eq!(title(), "two");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,131 @@
+use crate::alert::Status;
+use crate::discord::Color;
+use serde::Deserialize;
+use serde::Deserializer;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Preset {
+    #[default]
+    Default,
+    /// Blue and orange rather than green and red, with emoji telling
+    /// statuses apart by shape.
+    Colorblind,
+    Monochrome,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThemeEmoji {
+    pub firing: Option<String>,
+    pub resolved: Option<String>,
+    pub flapping: Option<String>,
+    pub suppressed: Option<String>,
+}
+
+/// Embed colors and title emoji: a preset, optionally with some of its
+/// colors and emoji replaced.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Palette {
+    pub preset: Preset,
+    pub firing: Option<Color>,
+    pub resolved: Option<Color>,
+    /// Flapping notices, rollups and bridge problems.
+    pub warning: Option<Color>,
+    /// Embeds of silenced or inhibited alerts only.
+    pub suppressed: Option<Color>,
+    pub emoji: ThemeEmoji,
+}
+
+/// Configured as a preset name or as a palette.
+#[derive(Debug, Clone, Default)]
+pub struct Theme(pub Palette);
+
+impl<'de> Deserialize<'de> for Theme {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Theme, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Preset(Preset),
+            Palette(Palette),
+        }
+        Ok(Theme(match Raw::deserialize(d)? {
+            Raw::Preset(preset) => Palette {
+                preset,
+                ..Palette::default()
+            },
+            Raw::Palette(palette) => palette,
+        }))
+    }
+}
+
+impl Theme {
+    pub fn color(&self, status: Status) -> Color {
+        let palette = &self.0;
+        let (firing, resolved) = match palette.preset {
+            Preset::Default => (Color::Red, Color::Green),
+            Preset::Colorblind => {
+                (Color::Custom(0xD55E00), Color::Custom(0x0072B2))
+            }
+            Preset::Monochrome => {
+                (Color::Custom(0x202225), Color::Custom(0xB9BBBE))
+            }
+        };
+        match status {
+            Status::Firing => palette.firing.unwrap_or(firing),
+            Status::Resolved => palette.resolved.unwrap_or(resolved),
+        }
+    }
+
+    pub fn warning(&self) -> Color {
+        let preset = match self.0.preset {
+            Preset::Default => Color::Orange,
+            Preset::Colorblind => Color::Custom(0xE69F00),
+            Preset::Monochrome => Color::Custom(0x72767D),
+        };
+        self.0.warning.unwrap_or(preset)
+    }
+
+    pub fn suppressed(&self) -> Color {
+        self.0.suppressed.unwrap_or(Color::Grey)
+    }
+
+    /// Marks the silenced or inhibited alerts, whatever the preset.
+    pub fn suppressed_emoji(&self) -> &str {
+        self.0.emoji.suppressed.as_deref().unwrap_or("\u{1F507}")
+    }
+
+    fn preset_emoji(&self) -> [&'static str; 3] {
+        match self.0.preset {
+            Preset::Default => ["", "", ""],
+            Preset::Colorblind | Preset::Monochrome => {
+                ["\u{1F525}", "\u{2705}", "\u{1F501}"]
+            }
+        }
+    }
+
+    /// Emoji prepended to the titles of embeds with that status.
+    pub fn emoji(&self, status: Status) -> &str {
+        let [firing, resolved, _] = self.preset_emoji();
+        let emoji = &self.0.emoji;
+        match status {
+            Status::Firing => emoji.firing.as_deref().unwrap_or(firing),
+            Status::Resolved => emoji.resolved.as_deref().unwrap_or(resolved),
+        }
+    }
+
+    pub fn flapping_emoji(&self) -> &str {
+        let [_, _, flapping] = self.preset_emoji();
+        self.0.emoji.flapping.as_deref().unwrap_or(flapping)
+    }
+
+    /// The title with the emoji in front, if there is one.
+    pub fn decorate(emoji: &str, title: String) -> String {
+        if emoji.is_empty() {
+            title
+        } else {
+            format!("{} {}", emoji, title)
+        }
+    }
+}
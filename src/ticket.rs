@@ -0,0 +1,269 @@
+use crate::alert::AlertGroup;
+use crate::alert::Status;
+use crate::config::Route;
+use crate::log;
+use crate::matcher::Matchers;
+use crate::metrics;
+use crate::metrics::Metrics;
+use crate::state::Store;
+use crate::sync::Recover;
+use crate::App;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use reqwest::blocking::Client;
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::mpsc;
+use std::sync::mpsc::SyncSender;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Opens an issue for each firing alert matching `matchers`, linked from
+/// its messages until it resolves.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TicketConfig {
+    pub matchers: Matchers,
+    pub tracker: Tracker,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Tracker {
+    Github {
+        #[serde(default = "default_github_url")]
+        url: String,
+        /// `owner/name`.
+        repo: String,
+        token: String,
+        #[serde(default)]
+        labels: Vec<String>,
+    },
+    /// Jira Cloud, authenticated with the `user`'s API token.
+    Jira {
+        url: String,
+        user: String,
+        token: String,
+        /// Key of the project.
+        project: String,
+        #[serde(default = "default_issue_type")]
+        issue_type: String,
+    },
+}
+
+fn default_github_url() -> String {
+    String::from("https://api.github.com")
+}
+
+fn default_issue_type() -> String {
+    String::from("Bug")
+}
+
+impl Tracker {
+    pub fn url(&self) -> &str {
+        match self {
+            Tracker::Github { url, .. } | Tracker::Jira { url, .. } => url,
+        }
+    }
+
+    /// Opens the issue, returning its web page.
+    fn open(&self, client: &Client, title: &str, body: &str) -> Result<String> {
+        #[derive(Deserialize)]
+        struct GithubIssue {
+            html_url: String,
+        }
+        #[derive(Deserialize)]
+        struct JiraIssue {
+            key: String,
+        }
+
+        match self {
+            Tracker::Github {
+                url,
+                repo,
+                token,
+                labels,
+            } => {
+                let url = format!(
+                    "{}/repos/{}/issues",
+                    url.trim_end_matches('/'),
+                    repo
+                );
+                let issue: GithubIssue = client
+                    .post(&url)
+                    .bearer_auth(token)
+                    .header("Accept", "application/vnd.github+json")
+                    .header("User-Agent", env!("CARGO_PKG_NAME"))
+                    .json(&json!({
+                        "title": title,
+                        "body": body,
+                        "labels": labels,
+                    }))
+                    .send()
+                    .and_then(|r| r.error_for_status())
+                    .and_then(|r| r.json())
+                    .with_context(|| {
+                        format!("cannot open an issue at {}", url)
+                    })?;
+                Ok(issue.html_url)
+            }
+            Tracker::Jira {
+                url,
+                user,
+                token,
+                project,
+                issue_type,
+            } => {
+                let base = url.trim_end_matches('/');
+                let url = format!("{}/rest/api/2/issue", base);
+                let issue: JiraIssue = client
+                    .post(&url)
+                    .basic_auth(user, Some(token))
+                    .json(&json!({
+                        "fields": {
+                            "project": { "key": project },
+                            "summary": title,
+                            "description": body,
+                            "issuetype": { "name": issue_type },
+                        },
+                    }))
+                    .send()
+                    .and_then(|r| r.error_for_status())
+                    .and_then(|r| r.json())
+                    .with_context(|| {
+                        format!("cannot open an issue at {}", url)
+                    })?;
+                Ok(format!("{}/browse/{}", base, issue.key))
+            }
+        }
+    }
+}
+
+/// Issues waiting to be opened, at most.
+const CAPACITY: usize = 256;
+
+/// How long a notification waits for the issues of its alerts, linked
+/// from its own messages when opened in time.
+const WAIT: Duration = Duration::from_secs(2);
+
+/// An issue to open for the alert of `fingerprint`, the `index`th of its
+/// notification, told through `opened`.
+struct Opening {
+    fingerprint: String,
+    title: String,
+    body: String,
+    index: usize,
+    opened: mpsc::Sender<(usize, String)>,
+}
+
+/// Opens the issues on a thread, off the path of the notifications.
+pub struct Tickets {
+    sender: Option<SyncSender<Opening>>,
+}
+
+impl Tickets {
+    pub fn start(
+        config: Option<&TicketConfig>,
+        client: Client,
+        store: Arc<Store>,
+        metrics: Arc<Metrics>,
+    ) -> Tickets {
+        let Some(config) = config else {
+            return Tickets { sender: None };
+        };
+        let tracker = config.tracker.clone();
+        let (sender, receiver) = mpsc::sync_channel::<Opening>(CAPACITY);
+        thread::spawn(move || {
+            for opening in receiver {
+                match tracker.open(&client, &opening.title, &opening.body) {
+                    Ok(url) => {
+                        metrics::inc(&metrics.tickets_opened);
+                        store.set_ticket(&opening.fingerprint, Some(&url));
+                        // The notification may have stopped waiting.
+                        let _ = opening.opened.send((opening.index, url));
+                    }
+                    // Another attempt is made with the alert's next
+                    // notification.
+                    Err(e) => {
+                        metrics::inc(&metrics.ticket_failures);
+                        store.release_ticket(&opening.fingerprint);
+                        log::error(format!("{:#}", e));
+                    }
+                }
+            }
+        });
+        Tickets {
+            sender: Some(sender),
+        }
+    }
+}
+
+/// Links the alerts of the group to their issue, having one opened for
+/// those that just started firing. The issue of a resolved alert is
+/// forgotten, the next time it fires getting a new one. The issues opened
+/// within `WAIT` are linked from this notification, the others from the
+/// next ones.
+pub fn attach(app: &App, route: &Route, group: &mut AlertGroup) {
+    let (Some(config), Some(sender)) =
+        (&app.config.tickets, &app.tickets.sender)
+    else {
+        return;
+    };
+    let renderer = app.renderer.read().recover().clone();
+    let (opened, openings) = mpsc::channel();
+    let mut waiting = 0;
+    for (index, alert) in group.alerts.iter_mut().enumerate() {
+        alert.ticket = app.store.ticket(&alert.fingerprint);
+        if alert.status == Status::Resolved {
+            if alert.ticket.is_some() {
+                app.store.set_ticket(&alert.fingerprint, None);
+            }
+            continue;
+        }
+        if alert.ticket.is_some() || !config.matchers.matches(&alert.labels) {
+            continue;
+        }
+        // Only one notification of the alert has its issue opened.
+        if !app.store.claim_ticket(&alert.fingerprint) {
+            continue;
+        }
+        let opening =
+            renderer
+                .render_ticket(route, alert)
+                .map(|(title, body)| Opening {
+                    fingerprint: alert.fingerprint.clone(),
+                    title,
+                    body,
+                    index,
+                    opened: opened.clone(),
+                });
+        let queued = match opening {
+            Ok(opening) => sender
+                .try_send(opening)
+                .map_err(|_| anyhow!("too many issues to open, skipped one")),
+            Err(e) => Err(e),
+        };
+        match queued {
+            Ok(()) => waiting += 1,
+            Err(e) => {
+                metrics::inc(&app.metrics.ticket_failures);
+                app.store.release_ticket(&alert.fingerprint);
+                log::error(format!("{:#}", e));
+            }
+        }
+    }
+    // Only the thread holds senders now, dropped with failed openings.
+    drop(opened);
+    let deadline = Instant::now() + WAIT;
+    while waiting > 0 {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let Ok((index, url)) = openings.recv_timeout(left) else {
+            break;
+        };
+        group.alerts[index].ticket = Some(url);
+        waiting -= 1;
+    }
+}
@@ -0,0 +1,269 @@
+use crate::log;
+use crate::server::PEER_HEADER;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use openssl::ssl::SslAcceptor;
+use openssl::ssl::SslFiletype;
+use openssl::ssl::SslMethod;
+use openssl::ssl::SslStream;
+use openssl::ssl::SslVerifyMode;
+use serde::Deserialize;
+use std::fs::DirBuilder;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// A listener terminating TLS, optionally requiring client certificates.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    pub listen: String,
+    /// PEM certificate chain and private key of the bridge.
+    pub cert: String,
+    pub key: String,
+    /// PEM bundle of the CAs client certificates must be signed by, every
+    /// client having to present one when set.
+    pub client_ca: Option<String>,
+}
+
+/// Connections idle for that long are closed.
+const IDLE: Duration = Duration::from_secs(60);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Longest request head relayed, longer ones closing the connection.
+const MAX_HEAD: usize = 65536;
+
+/// Creates the directory of the socket the decrypted connections are
+/// relayed to, new and private to the bridge's user so that no one else
+/// can connect to it nor put a file in its place, and returns the path of
+/// the socket. `XDG_RUNTIME_DIR` is preferred to the temporary directory.
+pub fn socket_path() -> Result<PathBuf> {
+    let parent = std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir);
+    let mut attempt = 0;
+    loop {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .subsec_nanos();
+        let dir = parent.join(format!(
+            "prometheus-discord-alert-{}-{:08x}",
+            std::process::id(),
+            nanos
+        ));
+        match DirBuilder::new().mode(0o700).create(&dir) {
+            Ok(()) => return Ok(dir.join("relay.sock")),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists && attempt < 10 => {
+                attempt += 1;
+            }
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!("cannot create {}", dir.display())
+                })
+            }
+        }
+    }
+}
+
+/// Removes the relay socket and its directory.
+pub fn remove_socket(path: &Path) {
+    let _ = std::fs::remove_file(path);
+    if let Some(dir) = path.parent() {
+        let _ = std::fs::remove_dir(dir);
+    }
+}
+
+fn acceptor(config: &TlsConfig) -> Result<SslAcceptor> {
+    let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls())?;
+    builder
+        .set_certificate_chain_file(&config.cert)
+        .with_context(|| format!("cannot load certificate {}", config.cert))?;
+    builder
+        .set_private_key_file(&config.key, SslFiletype::PEM)
+        .with_context(|| format!("cannot load key {}", config.key))?;
+    builder.check_private_key()?;
+    if let Some(ca) = &config.client_ca {
+        builder
+            .set_ca_file(ca)
+            .with_context(|| format!("cannot load client CA {}", ca))?;
+        builder.set_verify(
+            SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT,
+        );
+    }
+    Ok(builder.build())
+}
+
+/// Reads the head of the first request off the connection, returning it
+/// apart from whatever of the body came along.
+fn read_head(tls: &mut SslStream<TcpStream>) -> Result<(Vec<u8>, Vec<u8>)> {
+    let mut read = Vec::new();
+    let mut buffer = [0; 4096];
+    loop {
+        if let Some(end) = read.windows(4).position(|w| w == b"\r\n\r\n") {
+            let rest = read.split_off(end + 4);
+            return Ok((read, rest));
+        }
+        if read.len() > MAX_HEAD {
+            bail!("request head longer than {} bytes", MAX_HEAD);
+        }
+        match tls.read(&mut buffer)? {
+            0 => bail!("connection closed before the request head"),
+            n => read.extend_from_slice(&buffer[..n]),
+        }
+    }
+}
+
+/// The head with the client's address in `PEER_HEADER`, in place of any
+/// the client sent, and `Connection: close`, so that each request comes
+/// on a connection of its own and no later head goes through unnamed.
+fn rewrite(head: &[u8], peer: SocketAddr) -> Vec<u8> {
+    let head = String::from_utf8_lossy(head);
+    let mut lines = head.trim_end().split("\r\n");
+    let mut rewritten = String::from(lines.next().unwrap_or_default());
+    for line in lines {
+        let name = line.split(':').next().unwrap_or_default().trim();
+        if name.eq_ignore_ascii_case(PEER_HEADER)
+            || name.eq_ignore_ascii_case("Connection")
+        {
+            continue;
+        }
+        rewritten.push_str("\r\n");
+        rewritten.push_str(line);
+    }
+    let extra =
+        format!("\r\nConnection: close\r\n{}: {}\r\n\r\n", PEER_HEADER, peer);
+    rewritten.push_str(&extra);
+    rewritten.into_bytes()
+}
+
+/// Waits until either stream can be read, returning which, or neither
+/// once idle for `IDLE`.
+fn wait(tls: &TcpStream, plain: &UnixStream) -> Result<(bool, bool)> {
+    let pollfd = |fd| libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let mut fds = [pollfd(tls.as_raw_fd()), pollfd(plain.as_raw_fd())];
+    loop {
+        // SAFETY: fds is an array of 2 initialized pollfd structs.
+        let ready =
+            unsafe { libc::poll(fds.as_mut_ptr(), 2, IDLE.as_millis() as i32) };
+        if ready >= 0 {
+            break;
+        }
+        let e = std::io::Error::last_os_error();
+        if e.kind() != ErrorKind::Interrupted {
+            return Err(e.into());
+        }
+    }
+    let readable = |fd: &libc::pollfd| fd.revents != 0;
+    Ok((readable(&fds[0]), readable(&fds[1])))
+}
+
+/// Relays the request to the server with the client named, then copies
+/// bytes both ways until either side closes or the connection stays
+/// idle. An `SslStream` cannot be read and written from two threads, so
+/// both sides are polled from one.
+fn relay(
+    mut tls: SslStream<TcpStream>,
+    socket: &Path,
+    peer: SocketAddr,
+) -> Result<()> {
+    let (head, rest) = read_head(&mut tls)?;
+    tls.get_ref().set_read_timeout(None)?;
+    let mut plain = UnixStream::connect(socket)?;
+    plain.write_all(&rewrite(&head, peer))?;
+    plain.write_all(&rest)?;
+    let mut buffer = [0; 16384];
+    loop {
+        // Data already decrypted is not seen by poll.
+        let (from_client, from_server) = match tls.ssl().pending() {
+            0 => wait(tls.get_ref(), &plain)?,
+            _ => (true, false),
+        };
+        if !from_client && !from_server {
+            break;
+        }
+        if from_client {
+            match tls.read(&mut buffer)? {
+                0 => break,
+                n => plain.write_all(&buffer[..n])?,
+            }
+        }
+        if from_server {
+            match plain.read(&mut buffer)? {
+                0 => break,
+                n => tls.write_all(&buffer[..n])?,
+            }
+        }
+    }
+    let _ = tls.shutdown();
+    Ok(())
+}
+
+/// Listens on `listen`, relaying the connections that complete the
+/// handshake to the server on `socket`.
+pub fn start(config: &TlsConfig, socket: PathBuf) -> Result<()> {
+    let acceptor = Arc::new(acceptor(config)?);
+    let listener = TcpListener::bind(&config.listen)
+        .with_context(|| format!("cannot listen on {}", config.listen))?;
+    let socket = Arc::new(socket);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            let (acceptor, socket) = (acceptor.clone(), socket.clone());
+            thread::spawn(move || {
+                let Ok(peer) = stream.peer_addr() else {
+                    return;
+                };
+                // Also bounds the time the request head takes to come.
+                let _ = stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT));
+                let result = match acceptor.accept(stream) {
+                    Ok(tls) => relay(tls, &socket, peer),
+                    Err(e) => Err(anyhow::anyhow!("TLS handshake: {}", e)),
+                };
+                if let Err(e) = result {
+                    log::warning(format!(
+                        "TLS connection from {}: {:#}",
+                        peer, e
+                    ));
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_the_client_in_the_head() {
+        let head = b"POST / HTTP/1.1\r\nHost: bridge\r\nConnection: \
+            keep-alive\r\nx-bridge-peer: 127.0.0.1:1\r\n\r\n";
+        let peer = SocketAddr::from(([192, 0, 2, 7], 4321));
+        assert_eq!(
+            String::from_utf8(rewrite(head, peer)).unwrap(),
+            "POST / HTTP/1.1\r\nHost: bridge\r\nConnection: close\r\n\
+            X-Bridge-Peer: 192.0.2.7:4321\r\n\r\n"
+        );
+    }
+}